@@ -2,12 +2,12 @@ use hir::{db::HirDatabase, HasSource, HasVisibility, PathResolution};
 use ide_db::base_db::FileId;
 use syntax::{
     ast::{self, VisibilityOwner},
-    AstNode, TextRange, TextSize,
+    AstNode, SyntaxNode, TextRange, TextSize,
 };
 
 use crate::{utils::vis_offset, AssistContext, AssistId, AssistKind, Assists};
 
-// FIXME: this really should be a fix for diagnostic, rather than an assist.
+// This is also offered as the fix of the `private-item` and `private-field` diagnostics.
 
 // Assist: fix_visibility
 //
@@ -33,6 +33,7 @@ use crate::{utils::vis_offset, AssistContext, AssistId, AssistKind, Assists};
 pub(crate) fn fix_visibility(acc: &mut Assists, ctx: &AssistContext) -> Option<()> {
     add_vis_to_referenced_module_def(acc, ctx)
         .or_else(|| add_vis_to_referenced_record_field(acc, ctx))
+        .or_else(|| add_vis_to_accessed_field(acc, ctx))
 }
 
 fn add_vis_to_referenced_module_def(acc: &mut Assists, ctx: &AssistContext) -> Option<()> {
@@ -86,8 +87,22 @@ fn add_vis_to_referenced_module_def(acc: &mut Assists, ctx: &AssistContext) -> O
 fn add_vis_to_referenced_record_field(acc: &mut Assists, ctx: &AssistContext) -> Option<()> {
     let record_field: ast::RecordExprField = ctx.find_node_at_offset()?;
     let (record_field_def, _) = ctx.sema.resolve_record_field(&record_field)?;
+    add_vis_to_field(acc, ctx, record_field_def, record_field.syntax())
+}
+
+fn add_vis_to_accessed_field(acc: &mut Assists, ctx: &AssistContext) -> Option<()> {
+    let field_expr: ast::FieldExpr = ctx.find_node_at_offset()?;
+    let field_def = ctx.sema.resolve_field(&field_expr)?;
+    add_vis_to_field(acc, ctx, field_def, field_expr.syntax())
+}
 
-    let current_module = ctx.sema.scope(record_field.syntax()).module()?;
+fn add_vis_to_field(
+    acc: &mut Assists,
+    ctx: &AssistContext,
+    record_field_def: hir::Field,
+    usage: &SyntaxNode,
+) -> Option<()> {
+    let current_module = ctx.sema.scope(usage).module()?;
     let visibility = record_field_def.visibility(ctx.db());
     if visibility.is_visible_from(ctx.db(), current_module.into()) {
         return None;
@@ -324,6 +339,22 @@ pub struct Foo { pub bar: () }
         );
     }
 
+    #[test]
+    fn fix_visibility_of_accessed_field() {
+        check_assist(
+            fix_visibility,
+            r"mod foo { pub struct Foo { bar: (), } }
+              fn main(foo: foo::Foo) { foo.<|>bar; } ",
+            r"mod foo { pub struct Foo { $0pub(crate) bar: (), } }
+              fn main(foo: foo::Foo) { foo.bar; } ",
+        );
+        check_assist_not_applicable(
+            fix_visibility,
+            r"mod foo { pub struct Foo { pub bar: (), } }
+              fn main(foo: foo::Foo) { foo.<|>bar; } ",
+        );
+    }
+
     #[test]
     fn fix_visibility_of_enum_variant_field() {
         // Enum variants, as well as their fields, always get the enum's visibility. In fact, rustc
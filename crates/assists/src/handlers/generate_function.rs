@@ -327,6 +327,13 @@ fn next_space_for_fn_in_module(
                 GeneratedFunctionTarget::InEmptyItemList(it.item_list()?)
             }
         }
+        hir::ModuleSource::BlockExpr(it) => {
+            let last_item = it.statements().filter_map(|stmt| match stmt {
+                ast::Stmt::Item(it) => Some(it),
+                _ => None,
+            });
+            GeneratedFunctionTarget::BehindItem(last_item.last()?.syntax().clone())
+        }
     };
     Some((file, assist_item))
 }
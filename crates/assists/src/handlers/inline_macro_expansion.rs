@@ -0,0 +1,261 @@
+use ide_db::helpers::insert_whitespaces;
+use syntax::{
+    ast::{self, edit::IndentLevel, AstNode},
+    TextRange,
+};
+use test_utils::mark;
+
+use crate::{AssistContext, AssistId, AssistKind, Assists};
+
+// Assist: inline_macro_expansion
+//
+// Replaces a macro call with its expansion. Macro calls in the expansion are not expanded.
+//
+// ```
+// macro_rules! double {
+//     ($e:expr) => { $e * 2 };
+// }
+// fn main() {
+//     let x = dou<|>ble!(21);
+// }
+// ```
+// ->
+// ```
+// macro_rules! double {
+//     ($e:expr) => { $e * 2 };
+// }
+// fn main() {
+//     let x = 21*2;
+// }
+// ```
+pub(crate) fn inline_macro_expansion(acc: &mut Assists, ctx: &AssistContext) -> Option<()> {
+    let macro_call = ctx.find_node_at_offset::<ast::MacroCall>()?;
+    let path = macro_call.path()?;
+    let bang = macro_call.excl_token()?;
+    let target = TextRange::new(path.syntax().text_range().start(), bang.text_range().end());
+    if !target.contains_inclusive(ctx.offset()) {
+        return None;
+    }
+
+    let expansion = ctx.sema.expand(&macro_call)?;
+    let mut text = insert_whitespaces(expansion.clone()).trim_end().to_string();
+
+    if text.contains("$crate") {
+        let def_crate = ctx.sema.resolve_macro_call(&macro_call)?.module(ctx.db())?.krate();
+        let dollar_crate = if Some(def_crate) == ctx.sema.scope(macro_call.syntax()).krate() {
+            "crate".to_string()
+        } else {
+            format!("::{}", def_crate.display_name(ctx.db())?)
+        };
+        text = text.replace("$crate", &dollar_crate);
+    }
+
+    // Expansions to items bring their own semicolons, if they need them.
+    let keep_semicolon = !ast::MacroItems::can_cast(expansion.kind()) && !text.ends_with(';');
+    let range = match macro_call.semicolon_token() {
+        Some(semi) if keep_semicolon => {
+            TextRange::new(macro_call.syntax().text_range().start(), semi.text_range().start())
+        }
+        _ => macro_call.syntax().text_range(),
+    };
+
+    // Macro expansions behave as if they were parenthesized.
+    let parent = macro_call.syntax().parent().and_then(ast::Expr::cast);
+    let in_expr = !matches!(parent, None | Some(ast::Expr::BlockExpr(_)));
+    let needs_parens = matches!(
+        ast::Expr::cast(expansion),
+        Some(ast::Expr::BinExpr(_))
+            | Some(ast::Expr::RangeExpr(_))
+            | Some(ast::Expr::CastExpr(_))
+            | Some(ast::Expr::PrefixExpr(_))
+            | Some(ast::Expr::ClosureExpr(_))
+    );
+    if in_expr && needs_parens {
+        mark::hit!(inline_macro_expansion_parens);
+        text = format!("({})", text);
+    }
+
+    let indent = IndentLevel::from_node(macro_call.syntax());
+    let text = reindent(&text, indent);
+
+    acc.add(
+        AssistId("inline_macro_expansion", AssistKind::RefactorInline),
+        "Inline macro expansion",
+        target,
+        |builder| builder.replace(range, text),
+    )
+}
+
+/// `insert_whitespaces` indents with two spaces, starting at column zero, and leaves blank lines
+/// before closing braces.
+fn reindent(text: &str, indent: IndentLevel) -> String {
+    let mut res = String::new();
+    for line in text.lines() {
+        let trimmed = line.trim_start_matches(' ');
+        if trimmed.is_empty() {
+            continue;
+        }
+        if !res.is_empty() {
+            let level = (line.len() - trimmed.len()) / 2;
+            res += &format!("\n{}{}", indent, "    ".repeat(level));
+        }
+        res += trimmed;
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use test_utils::mark;
+
+    use crate::tests::{check_assist, check_assist_not_applicable, check_assist_target};
+
+    use super::*;
+
+    #[test]
+    fn inline_expression() {
+        check_assist(
+            inline_macro_expansion,
+            r#"
+macro_rules! five { () => { 5 } }
+fn f() -> i32 {
+    fi<|>ve!()
+}
+"#,
+            r#"
+macro_rules! five { () => { 5 } }
+fn f() -> i32 {
+    5
+}
+"#,
+        );
+    }
+
+    #[test]
+    fn inline_wraps_in_parens() {
+        mark::check!(inline_macro_expansion_parens);
+        check_assist(
+            inline_macro_expansion,
+            r#"
+macro_rules! sum { ($a:expr, $b:expr) => { $a + $b } }
+fn f() -> i32 {
+    2 * <|>sum!(1, 2)
+}
+"#,
+            r#"
+macro_rules! sum { ($a:expr, $b:expr) => { $a + $b } }
+fn f() -> i32 {
+    2 * (1+2)
+}
+"#,
+        );
+    }
+
+    #[test]
+    fn inline_items_one_level() {
+        check_assist(
+            inline_macro_expansion,
+            r#"
+macro_rules! unit { ($i:ident) => { struct $i; } }
+macro_rules! units { ($a:ident, $b:ident) => { unit!($a); unit!($b); } }
+mod m {
+    un<|>its!(A, B);
+}
+"#,
+            r#"
+macro_rules! unit { ($i:ident) => { struct $i; } }
+macro_rules! units { ($a:ident, $b:ident) => { unit!($a); unit!($b); } }
+mod m {
+    unit!(A);
+    unit!(B);
+}
+"#,
+        );
+    }
+
+    #[test]
+    fn inline_keeps_statement_semicolon() {
+        check_assist(
+            inline_macro_expansion,
+            r#"
+macro_rules! call { ($f:ident) => { $f() } }
+fn g() {}
+fn f() {
+    call<|>!(g);
+}
+"#,
+            r#"
+macro_rules! call { ($f:ident) => { $f() } }
+fn g() {}
+fn f() {
+    g();
+}
+"#,
+        );
+    }
+
+    #[test]
+    fn inline_reindents_blocks() {
+        check_assist(
+            inline_macro_expansion,
+            r#"
+macro_rules! func { ($i:ident) => { fn $i() { let x = 1; } } }
+mod m {
+    f<|>unc!(foo);
+}
+"#,
+            r#"
+macro_rules! func { ($i:ident) => { fn $i() { let x = 1; } } }
+mod m {
+    fn foo(){
+        let x = 1;
+    }
+}
+"#,
+        );
+    }
+
+    #[test]
+    fn inline_replaces_dollar_crate() {
+        check_assist(
+            inline_macro_expansion,
+            r#"
+pub struct S;
+macro_rules! s { () => { $crate::S } }
+fn f() -> S {
+    <|>s!()
+}
+"#,
+            r#"
+pub struct S;
+macro_rules! s { () => { $crate::S } }
+fn f() -> S {
+    crate::S
+}
+"#,
+        );
+    }
+
+    #[test]
+    fn target_is_macro_path() {
+        check_assist_target(
+            inline_macro_expansion,
+            r#"
+macro_rules! five { () => { 5 } }
+fn f() -> i32 { fi<|>ve!() }
+"#,
+            "five!",
+        );
+    }
+
+    #[test]
+    fn not_applicable_in_arguments() {
+        check_assist_not_applicable(
+            inline_macro_expansion,
+            r#"
+macro_rules! id { ($e:expr) => { $e } }
+fn f() -> i32 { id!(1 + <|>2) }
+"#,
+        );
+    }
+}
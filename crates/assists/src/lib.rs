@@ -100,6 +100,21 @@ impl Assist {
         });
         acc.finish()
     }
+
+    /// Returns the `fix_visibility` assist at the given position, if it applies.
+    ///
+    /// This is used as the fix for privacy diagnostics, so it only runs that single handler.
+    pub fn fix_visibility(
+        db: &RootDatabase,
+        config: &AssistConfig,
+        range: FileRange,
+    ) -> Option<Assist> {
+        let sema = Semantics::new(db);
+        let ctx = AssistContext::new(sema, config, range);
+        let mut acc = Assists::new(&ctx, true);
+        handlers::fix_visibility(&mut acc, &ctx);
+        acc.finish().pop()
+    }
 }
 
 mod handlers {
@@ -132,6 +147,7 @@ mod handlers {
     mod generate_new;
     mod infer_function_return_type;
     mod inline_local_variable;
+    mod inline_macro_expansion;
     mod introduce_named_lifetime;
     mod invert_if;
     mod merge_imports;
@@ -156,6 +172,8 @@ mod handlers {
     mod unwrap_block;
     mod wrap_return_type_in_result;
 
+    pub(crate) use self::fix_visibility::fix_visibility;
+
     pub(crate) fn all() -> &'static [Handler] {
         &[
             // These are alphabetic for the foolish consistency
@@ -183,6 +201,7 @@ mod handlers {
             generate_new::generate_new,
             infer_function_return_type::infer_function_return_type,
             inline_local_variable::inline_local_variable,
+            inline_macro_expansion::inline_macro_expansion,
             introduce_named_lifetime::introduce_named_lifetime,
             invert_if::invert_if,
             merge_imports::merge_imports,
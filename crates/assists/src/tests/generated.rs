@@ -548,6 +548,29 @@ fn main() {
     )
 }
 
+#[test]
+fn doctest_inline_macro_expansion() {
+    check_doc_test(
+        "inline_macro_expansion",
+        r#####"
+macro_rules! double {
+    ($e:expr) => { $e * 2 };
+}
+fn main() {
+    let x = dou<|>ble!(21);
+}
+"#####,
+        r#####"
+macro_rules! double {
+    ($e:expr) => { $e * 2 };
+}
+fn main() {
+    let x = 21*2;
+}
+"#####,
+    )
+}
+
 #[test]
 fn doctest_introduce_named_lifetime() {
     check_doc_test(
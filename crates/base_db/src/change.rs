@@ -7,14 +7,18 @@ use rustc_hash::FxHashSet;
 use salsa::Durability;
 use vfs::FileId;
 
-use crate::{CrateGraph, SourceDatabaseExt, SourceRoot, SourceRootId};
+use crate::{CrateGraph, Env, SourceDatabaseExt, SourceRoot, SourceRootId};
 
 /// Encapsulate a bunch of raw `.set` calls on the database.
 #[derive(Default)]
 pub struct Change {
     pub roots: Option<Vec<SourceRoot>>,
     pub files_changed: Vec<(FileId, Option<Arc<String>>)>,
+    pub binary_files_changed: Vec<(FileId, Option<Arc<Vec<u8>>>)>,
     pub crate_graph: Option<CrateGraph>,
+    /// The environment of the proc macro server. This is stored by `hir_expand`, so `apply`
+    /// leaves it to the database which knows about it.
+    pub proc_macro_env: Option<Env>,
 }
 
 impl fmt::Debug for Change {
@@ -26,9 +30,15 @@ impl fmt::Debug for Change {
         if !self.files_changed.is_empty() {
             d.field("files_changed", &self.files_changed.len());
         }
+        if !self.binary_files_changed.is_empty() {
+            d.field("binary_files_changed", &self.binary_files_changed.len());
+        }
         if self.crate_graph.is_some() {
             d.field("crate_graph", &self.crate_graph);
         }
+        if self.proc_macro_env.is_some() {
+            d.field("proc_macro_env", &self.proc_macro_env);
+        }
         d.finish()
     }
 }
@@ -46,10 +56,19 @@ impl Change {
         self.files_changed.push((file_id, new_text))
     }
 
+    /// Changes the contents of a file which is not Rust source code.
+    pub fn change_binary_file(&mut self, file_id: FileId, new_contents: Option<Arc<Vec<u8>>>) {
+        self.binary_files_changed.push((file_id, new_contents))
+    }
+
     pub fn set_crate_graph(&mut self, graph: CrateGraph) {
         self.crate_graph = Some(graph);
     }
 
+    pub fn set_proc_macro_env(&mut self, env: Env) {
+        self.proc_macro_env = Some(env);
+    }
+
     pub fn apply(self, db: &mut dyn SourceDatabaseExt) {
         let _p = profile::span("RootDatabase::apply_change");
         // db.request_cancellation();
@@ -80,7 +99,15 @@ impl Change {
             let durability = durability(&source_root);
             // XXX: can't actually remove the file, just reset the text
             let text = text.unwrap_or_default();
-            db.set_file_text_with_durability(file_id, text, durability)
+            db.set_file_text_with_durability(file_id, text, durability);
+            db.set_file_bytes_with_durability(file_id, None, durability);
+        }
+        for (file_id, contents) in self.binary_files_changed {
+            let source_root_id = db.file_source_root(file_id);
+            let source_root = db.source_root(source_root_id);
+            let durability = durability(&source_root);
+            db.set_file_text_with_durability(file_id, Default::default(), durability);
+            db.set_file_bytes_with_durability(file_id, contents, durability);
         }
         if let Some(crate_graph) = self.crate_graph {
             db.set_crate_graph_with_durability(Arc::new(crate_graph), Durability::HIGH)
@@ -65,7 +65,7 @@ use test_utils::{extract_range_or_offset, Fixture, RangeOrOffset, CURSOR_MARKER}
 use vfs::{file_set::FileSet, VfsPath};
 
 use crate::{
-    input::CrateName, Change, CrateGraph, CrateId, Edition, Env, FileId, FilePosition,
+    input::CrateName, Change, CrateGraph, CrateId, CrateKind, Edition, Env, FileId, FilePosition,
     SourceDatabaseExt, SourceRoot, SourceRootId,
 };
 
@@ -133,6 +133,7 @@ impl ChangeFixture {
         let mut crate_deps = Vec::new();
         let mut default_crate_root: Option<FileId> = None;
         let mut default_cfg = CfgOptions::default();
+        let mut default_kind = CrateKind::Lib;
 
         let mut file_set = FileSet::default();
         let source_root_prefix = "/".to_string();
@@ -158,6 +159,7 @@ impl ChangeFixture {
                 let crate_id = crate_graph.add_crate_root(
                     file_id,
                     meta.edition,
+                    meta.kind,
                     Some(crate_name.clone().into()),
                     meta.cfg,
                     meta.env,
@@ -173,6 +175,7 @@ impl ChangeFixture {
                 assert!(default_crate_root.is_none());
                 default_crate_root = Some(file_id);
                 default_cfg = meta.cfg;
+                default_kind = meta.kind;
             }
 
             change.change_file(file_id, Some(Arc::new(text)));
@@ -187,6 +190,7 @@ impl ChangeFixture {
             crate_graph.add_crate_root(
                 crate_root,
                 Edition::Edition2018,
+                default_kind,
                 Some(CrateName::new("test").unwrap().into()),
                 default_cfg,
                 Env::default(),
@@ -213,6 +217,7 @@ struct FileMeta {
     deps: Vec<String>,
     cfg: CfgOptions,
     edition: Edition,
+    kind: CrateKind,
     env: Env,
 }
 
@@ -231,6 +236,7 @@ impl From<Fixture> for FileMeta {
                 .edition
                 .as_ref()
                 .map_or(Edition::Edition2018, |v| Edition::from_str(&v).unwrap()),
+            kind: f.kind.as_ref().map_or(CrateKind::Lib, |v| CrateKind::from_str(&v).unwrap()),
             env: f.env.into_iter().collect(),
         }
     }
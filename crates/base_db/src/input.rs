@@ -6,12 +6,14 @@
 //! actual IO. See `vfs` and `project_model` in the `rust-analyzer` crate for how
 //! actual IO is done and lowered to input.
 
-use std::{fmt, iter::FromIterator, ops, panic::RefUnwindSafe, str::FromStr, sync::Arc};
+use std::{
+    fmt, iter::FromIterator, ops, panic::RefUnwindSafe, path::PathBuf, str::FromStr, sync::Arc,
+};
 
 use cfg::CfgOptions;
 use rustc_hash::{FxHashMap, FxHashSet};
 use syntax::SmolStr;
-use tt::{ExpansionError, Subtree};
+use tt::{Diagnostic, ExpansionError, Subtree};
 use vfs::{file_set::FileSet, FileId, VfsPath};
 
 /// Files are grouped into source roots. A source root is a directory on the
@@ -143,7 +145,7 @@ impl CrateDisplayName {
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub struct ProcMacroId(pub u32);
 
-#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Hash)]
 pub enum ProcMacroKind {
     CustomDerive,
     FuncLike,
@@ -156,7 +158,18 @@ pub trait ProcMacroExpander: fmt::Debug + Send + Sync + RefUnwindSafe {
         subtree: &Subtree,
         attrs: Option<&Subtree>,
         env: &Env,
-    ) -> Result<Subtree, ExpansionError>;
+    ) -> Result<ProcMacroExpansion, ExpansionError>;
+}
+
+/// The output of a proc macro and what it read to produce it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProcMacroExpansion {
+    pub subtree: Subtree,
+    pub diagnostics: Vec<Diagnostic>,
+    /// Environment variables read through `proc_macro::tracked_env`, with the values read.
+    pub tracked_env: Vec<(String, Option<String>)>,
+    /// Files read by the macro, registered through `proc_macro::tracked_path`.
+    pub tracked_paths: Vec<PathBuf>,
 }
 
 #[derive(Debug, Clone)]
@@ -177,6 +190,7 @@ impl PartialEq for ProcMacro {
 pub struct CrateData {
     pub root_file_id: FileId,
     pub edition: Edition,
+    pub kind: CrateKind,
     /// A name used in the package's project declaration: for Cargo projects,
     /// it's [package].name, can be different for other project types or even
     /// absent (a dummy crate for the code snippet, for example).
@@ -196,6 +210,23 @@ pub enum Edition {
     Edition2015,
 }
 
+/// The kind of a crate, as reported by the build system.
+///
+/// For Cargo projects, this corresponds to the kind of the target the crate was created from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CrateKind {
+    Lib,
+    /// A `proc-macro = true` library. Only the crate root of such a crate can define procedural
+    /// macros, and it exports nothing else.
+    ProcMacro,
+    Bin,
+    Example,
+    Test,
+    Bench,
+    /// Build scripts and anything else we don't know about.
+    Other,
+}
+
 #[derive(Default, Debug, Clone, PartialEq, Eq)]
 pub struct Env {
     entries: FxHashMap<String, String>,
@@ -212,6 +243,7 @@ impl CrateGraph {
         &mut self,
         file_id: FileId,
         edition: Edition,
+        kind: CrateKind,
         display_name: Option<CrateDisplayName>,
         cfg_options: CfgOptions,
         env: Env,
@@ -220,6 +252,7 @@ impl CrateGraph {
         let data = CrateData {
             root_file_id: file_id,
             edition,
+            kind,
             display_name,
             cfg_options,
             env,
@@ -408,6 +441,24 @@ impl fmt::Display for Edition {
     }
 }
 
+impl FromStr for CrateKind {
+    type Err = ParseCrateKindError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let res = match s {
+            "lib" => CrateKind::Lib,
+            "proc-macro" => CrateKind::ProcMacro,
+            "bin" => CrateKind::Bin,
+            "example" => CrateKind::Example,
+            "test" => CrateKind::Test,
+            "bench" => CrateKind::Bench,
+            "other" => CrateKind::Other,
+            _ => return Err(ParseCrateKindError { invalid_input: s.to_string() }),
+        };
+        Ok(res)
+    }
+}
+
 impl FromIterator<(String, String)> for Env {
     fn from_iter<T: IntoIterator<Item = (String, String)>>(iter: T) -> Self {
         Env { entries: FromIterator::from_iter(iter) }
@@ -441,6 +492,19 @@ impl fmt::Display for ParseEditionError {
 
 impl std::error::Error for ParseEditionError {}
 
+#[derive(Debug)]
+pub struct ParseCrateKindError {
+    invalid_input: String,
+}
+
+impl fmt::Display for ParseCrateKindError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid crate kind: {:?}", self.invalid_input)
+    }
+}
+
+impl std::error::Error for ParseCrateKindError {}
+
 #[derive(Debug)]
 pub struct CyclicDependenciesError {
     from: (CrateId, Option<CrateDisplayName>),
@@ -459,7 +523,9 @@ impl fmt::Display for CyclicDependenciesError {
 
 #[cfg(test)]
 mod tests {
-    use super::{CfgOptions, CrateGraph, CrateName, Dependency, Edition::Edition2018, Env, FileId};
+    use super::{
+        CfgOptions, CrateGraph, CrateKind, CrateName, Dependency, Edition::Edition2018, Env, FileId,
+    };
 
     #[test]
     fn detect_cyclic_dependency_indirect() {
@@ -467,6 +533,7 @@ mod tests {
         let crate1 = graph.add_crate_root(
             FileId(1u32),
             Edition2018,
+            CrateKind::Lib,
             None,
             CfgOptions::default(),
             Env::default(),
@@ -475,6 +542,7 @@ mod tests {
         let crate2 = graph.add_crate_root(
             FileId(2u32),
             Edition2018,
+            CrateKind::Lib,
             None,
             CfgOptions::default(),
             Env::default(),
@@ -483,6 +551,7 @@ mod tests {
         let crate3 = graph.add_crate_root(
             FileId(3u32),
             Edition2018,
+            CrateKind::Lib,
             None,
             CfgOptions::default(),
             Env::default(),
@@ -499,6 +568,7 @@ mod tests {
         let crate1 = graph.add_crate_root(
             FileId(1u32),
             Edition2018,
+            CrateKind::Lib,
             None,
             CfgOptions::default(),
             Env::default(),
@@ -507,6 +577,7 @@ mod tests {
         let crate2 = graph.add_crate_root(
             FileId(2u32),
             Edition2018,
+            CrateKind::Lib,
             None,
             CfgOptions::default(),
             Env::default(),
@@ -522,6 +593,7 @@ mod tests {
         let crate1 = graph.add_crate_root(
             FileId(1u32),
             Edition2018,
+            CrateKind::Lib,
             None,
             CfgOptions::default(),
             Env::default(),
@@ -530,6 +602,7 @@ mod tests {
         let crate2 = graph.add_crate_root(
             FileId(2u32),
             Edition2018,
+            CrateKind::Lib,
             None,
             CfgOptions::default(),
             Env::default(),
@@ -538,6 +611,7 @@ mod tests {
         let crate3 = graph.add_crate_root(
             FileId(3u32),
             Edition2018,
+            CrateKind::Lib,
             None,
             CfgOptions::default(),
             Env::default(),
@@ -553,6 +627,7 @@ mod tests {
         let crate1 = graph.add_crate_root(
             FileId(1u32),
             Edition2018,
+            CrateKind::Lib,
             None,
             CfgOptions::default(),
             Env::default(),
@@ -561,6 +636,7 @@ mod tests {
         let crate2 = graph.add_crate_root(
             FileId(2u32),
             Edition2018,
+            CrateKind::Lib,
             None,
             CfgOptions::default(),
             Env::default(),
@@ -13,8 +13,9 @@ pub use crate::{
     cancellation::Canceled,
     change::Change,
     input::{
-        CrateData, CrateDisplayName, CrateGraph, CrateId, CrateName, Dependency, Edition, Env,
-        ProcMacro, ProcMacroExpander, ProcMacroId, ProcMacroKind, SourceRoot, SourceRootId,
+        CrateData, CrateDisplayName, CrateGraph, CrateId, CrateKind, CrateName, Dependency,
+        Edition, Env, ProcMacro, ProcMacroExpander, ProcMacroExpansion, ProcMacroId, ProcMacroKind,
+        SourceRoot, SourceRootId,
     },
 };
 pub use salsa;
@@ -74,7 +75,7 @@ impl<T: salsa::Database> CheckCanceled for T {
     }
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct FilePosition {
     pub file_id: FileId,
     pub offset: TextSize,
@@ -91,7 +92,12 @@ pub const DEFAULT_LRU_CAP: usize = 128;
 pub trait FileLoader {
     /// Text of the file.
     fn file_text(&self, file_id: FileId) -> Arc<String>;
+    /// Raw contents of a non-Rust file, `None` for Rust source files.
+    fn file_bytes(&self, file_id: FileId) -> Option<Arc<Vec<u8>>>;
     fn resolve_path(&self, path: AnchoredPath) -> Option<FileId>;
+    /// Asks for the file at `path`, which failed to resolve, to be loaded. Only Rust files are
+    /// loaded up front, the files read by `include_str!` and the like are loaded on demand.
+    fn request_file(&self, _path: AnchoredPath) {}
     fn relevant_crates(&self, file_id: FileId) -> Arc<FxHashSet<CrateId>>;
 }
 
@@ -120,6 +126,11 @@ fn parse_query(db: &dyn SourceDatabase, file_id: FileId) -> Parse<ast::SourceFil
 pub trait SourceDatabaseExt: SourceDatabase {
     #[salsa::input]
     fn file_text(&self, file_id: FileId) -> Arc<String>;
+    /// Contents of files which are not Rust source code, like the ones read by
+    /// `include_bytes!`. These are kept as is, without line ending
+    /// normalization, and their `file_text` is empty.
+    #[salsa::input]
+    fn file_bytes(&self, file_id: FileId) -> Option<Arc<Vec<u8>>>;
     /// Path to a file, relative to the root of its source root.
     /// Source root of the file.
     #[salsa::input]
@@ -150,6 +161,9 @@ impl<T: SourceDatabaseExt> FileLoader for FileLoaderDelegate<&'_ T> {
     fn file_text(&self, file_id: FileId) -> Arc<String> {
         SourceDatabaseExt::file_text(self.0, file_id)
     }
+    fn file_bytes(&self, file_id: FileId) -> Option<Arc<Vec<u8>>> {
+        SourceDatabaseExt::file_bytes(self.0, file_id)
+    }
     fn resolve_path(&self, path: AnchoredPath) -> Option<FileId> {
         // FIXME: this *somehow* should be platform agnostic...
         let source_root = self.0.file_source_root(path.anchor);
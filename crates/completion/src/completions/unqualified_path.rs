@@ -581,6 +581,21 @@ fn main() { let v = <|> }
         );
     }
 
+    #[test]
+    fn completes_only_macros_defined_before() {
+        check(
+            r#"
+macro_rules! foo { () => {} }
+fn main() { <|> }
+macro_rules! bar { () => {} }
+"#,
+            expect![[r#"
+                fn main()  fn main()
+                ma foo!(…) macro_rules! foo
+            "#]],
+        );
+    }
+
     #[test]
     fn completes_both_macro_and_value() {
         check(
@@ -1,9 +1,12 @@
 //! FIXME: write short doc here
-pub use hir_def::diagnostics::{InactiveCode, UnresolvedModule, UnresolvedProcMacro};
-pub use hir_expand::diagnostics::{
-    Diagnostic, DiagnosticCode, DiagnosticSink, DiagnosticSinkBuilder,
+pub use hir_def::diagnostics::{
+    InactiveCode, MacroError, UnresolvedMacroCall, UnresolvedModule, UnresolvedProcMacro,
+};
+pub use hir_expand::{
+    diagnostics::{Diagnostic, DiagnosticCode, DiagnosticSink, DiagnosticSinkBuilder},
+    DiagnosticLevel,
 };
 pub use hir_ty::diagnostics::{
     IncorrectCase, MismatchedArgCount, MissingFields, MissingMatchArms, MissingOkInTailExpr,
-    NoSuchField, RemoveThisSemicolon,
+    NoSuchField, PrivateField, PrivateItem, RemoveThisSemicolon,
 };
@@ -47,6 +47,7 @@ pub use hir_def::{
     attr::{Attrs, Documentation},
     body::scope::ExprScopes,
     builtin_type::BuiltinType,
+    dump,
     find_path::PrefixKind,
     import_map,
     item_scope::ItemInNs,
@@ -57,8 +58,8 @@ pub use hir_def::{
 };
 pub use hir_expand::{
     name::{known, AsName, Name},
-    ExpandResult, HirFileId, InFile, MacroCallId, MacroCallLoc, /* FIXME */ MacroDefId,
-    MacroFile, Origin,
+    ExpandResult, HirFileId, InFile, MacroBacktraceFrame, MacroBinding, MacroCallId, MacroCallLoc,
+    /* FIXME */ MacroDefId, MacroExpansionTrace, MacroFile, MacroRuleTrace, Origin,
 };
 pub use hir_ty::display::HirDisplay;
 
@@ -4,19 +4,23 @@ mod source_to_def;
 
 use std::{cell::RefCell, fmt, iter::successors};
 
-use base_db::{FileId, FileRange};
+use base_db::{FileId, FilePosition, FileRange};
 use hir_def::{
     resolver::{self, HasResolver, Resolver, TypeNs},
     AsMacroCall, FunctionId, TraitId, VariantId,
 };
-use hir_expand::{hygiene::Hygiene, name::AsName, ExpansionInfo};
+use hir_expand::{
+    hygiene::{Hygiene, SyntaxContext},
+    name::AsName,
+    ExpansionInfo,
+};
 use hir_ty::associated_type_shorthand_candidates;
 use itertools::Itertools;
 use rustc_hash::{FxHashMap, FxHashSet};
 use syntax::{
-    algo::find_node_at_offset,
+    algo::{find_covering_element, find_node_at_offset},
     ast::{self, GenericParamsOwner, LoopBodyOwner},
-    match_ast, AstNode, SyntaxNode, SyntaxToken, TextSize,
+    match_ast, AstNode, NodeOrToken, SyntaxNode, SyntaxToken, TextRange, TextSize,
 };
 
 use crate::{
@@ -26,8 +30,8 @@ use crate::{
     semantics::source_to_def::{ChildContainer, SourceToDefCache, SourceToDefCtx},
     source_analyzer::{resolve_hir_path, SourceAnalyzer},
     AssocItem, Callable, ConstParam, Crate, Field, Function, HirFileId, Impl, InFile, Label,
-    LifetimeParam, Local, MacroDef, Module, ModuleDef, Name, Path, ScopeDef, Trait, Type,
-    TypeAlias, TypeParam, VariantDef,
+    LifetimeParam, Local, MacroDef, MacroExpansionTrace, ModPath, Module, ModuleDef, Name, Path,
+    ScopeDef, Trait, Type, TypeAlias, TypeParam, VariantDef,
 };
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -116,6 +120,21 @@ impl<'db, DB: HirDatabase> Semantics<'db, DB> {
     pub fn expand(&self, macro_call: &ast::MacroCall) -> Option<SyntaxNode> {
         self.imp.expand(macro_call)
     }
+
+    /// For calls of `macro_rules!` and `macro` macros, returns which rules were tried to expand
+    /// `macro_call` and what their meta variables were bound to.
+    pub fn trace_macro_expansion(
+        &self,
+        macro_call: &ast::MacroCall,
+    ) -> Option<MacroExpansionTrace> {
+        self.imp.trace_macro_expansion(macro_call)
+    }
+
+    /// Expands the derive macro `derive` applied to `item`, like `Clone` in `#[derive(Clone)]`.
+    pub fn expand_derive(&self, item: &ast::Item, derive: &ModPath) -> Option<SyntaxNode> {
+        self.imp.expand_derive(item, derive)
+    }
+
     pub fn speculative_expand(
         &self,
         actual_macro_call: &ast::MacroCall,
@@ -141,6 +160,17 @@ impl<'db, DB: HirDatabase> Semantics<'db, DB> {
         self.imp.original_range(node)
     }
 
+    /// Maps `range`, which might be inside a macro expansion, to a range in a source file.
+    pub fn original_file_range(&self, range: InFile<TextRange>) -> FileRange {
+        self.imp.original_file_range(range)
+    }
+
+    /// Maps `token`, which might be inside a macro expansion, to the token in a source file it
+    /// was copied from. This can be a token of a macro call or of a macro definition.
+    pub fn original_token_range(&self, token: &SyntaxToken) -> Option<FileRange> {
+        self.imp.original_token_range(token)
+    }
+
     pub fn diagnostics_display_range(&self, diagnostics: &dyn Diagnostic) -> FileRange {
         self.imp.diagnostics_display_range(diagnostics)
     }
@@ -317,6 +347,20 @@ impl<'db> SemanticsImpl<'db> {
         Some(node)
     }
 
+    fn trace_macro_expansion(&self, macro_call: &ast::MacroCall) -> Option<MacroExpansionTrace> {
+        let sa = self.analyze(macro_call.syntax());
+        let file_id = sa.expand(self.db, InFile::new(sa.file_id, macro_call))?;
+        file_id.trace_expansion(self.db.upcast())
+    }
+
+    fn expand_derive(&self, item: &ast::Item, derive: &ModPath) -> Option<SyntaxNode> {
+        let sa = self.analyze(item.syntax());
+        let file_id = sa.expand_derive(self.db, InFile::new(sa.file_id, item), derive)?;
+        let node = self.db.parse_or_expand(file_id)?;
+        self.cache(node.clone(), file_id);
+        Some(node)
+    }
+
     fn speculative_expand(
         &self,
         actual_macro_call: &ast::MacroCall,
@@ -326,9 +370,13 @@ impl<'db> SemanticsImpl<'db> {
         let sa = self.analyze(actual_macro_call.syntax());
         let macro_call = InFile::new(sa.file_id, actual_macro_call);
         let krate = sa.resolver.krate()?;
-        let macro_call_id = macro_call.as_call_id(self.db.upcast(), krate, |path| {
-            sa.resolver.resolve_path_as_macro(self.db.upcast(), &path)
-        })?;
+        let module = sa.resolver.module()?;
+        let module_path =
+            || module.def_map(self.db.upcast()).module_path(self.db.upcast(), module.local_id);
+        let macro_call_id =
+            macro_call.as_call_id(self.db.upcast(), krate, &module_path, |path| {
+                sa.resolve_macro_call_path(self.db, macro_call, &path)
+            })?;
         hir_expand::db::expand_hypothetical(
             self.db.upcast(),
             macro_call_id,
@@ -385,6 +433,37 @@ impl<'db> SemanticsImpl<'db> {
         node.as_ref().original_file_range(self.db.upcast())
     }
 
+    fn original_file_range(&self, range: InFile<TextRange>) -> FileRange {
+        let file_id = range.file_id.original_file(self.db.upcast());
+        if range.file_id == file_id.into() {
+            return FileRange { file_id, range: range.value };
+        }
+        let root = match self.db.parse_or_expand(range.file_id) {
+            Some(it) => it,
+            // Point at the whole macro call if its expansion failed.
+            None => match range.file_id.call_node(self.db.upcast()) {
+                Some(call) => return call.as_ref().original_file_range(self.db.upcast()),
+                None => return FileRange { file_id, range: range.value },
+            },
+        };
+        self.cache(root.clone(), range.file_id);
+        let node = match find_covering_element(&root, range.value) {
+            NodeOrToken::Node(it) => it,
+            NodeOrToken::Token(it) => it.parent(),
+        };
+        range.with_value(&node).original_file_range(self.db.upcast())
+    }
+
+    fn original_token_range(&self, token: &SyntaxToken) -> Option<FileRange> {
+        let mut token = self.find_file(token.parent()).with_value(token.clone());
+        while let Some(info) = token.file_id.expansion_info(self.db.upcast()) {
+            let (mapped, _origin) = info.map_token_up(token.as_ref())?;
+            token = mapped;
+        }
+        let file_id = token.file_id.original_file(self.db.upcast());
+        Some(FileRange { file_id, range: token.value.text_range() })
+    }
+
     fn diagnostics_display_range(&self, diagnostics: &dyn Diagnostic) -> FileRange {
         let src = diagnostics.display_source();
         let root = self.db.parse_or_expand(src.file_id).unwrap();
@@ -546,18 +625,34 @@ impl<'db> SemanticsImpl<'db> {
 
     fn scope(&self, node: &SyntaxNode) -> SemanticsScope<'db> {
         let sa = self.analyze(node);
-        SemanticsScope { db: self.db, file_id: sa.file_id, resolver: sa.resolver }
+        let range = InFile::new(sa.file_id, node).original_file_range(self.db.upcast());
+        let position = FilePosition { file_id: range.file_id, offset: range.range.start() };
+        SemanticsScope {
+            db: self.db,
+            file_id: sa.file_id,
+            resolver: sa.resolver,
+            position: Some(position),
+        }
     }
 
     fn scope_at_offset(&self, node: &SyntaxNode, offset: TextSize) -> SemanticsScope<'db> {
         let sa = self.analyze_with_offset(node, offset);
-        SemanticsScope { db: self.db, file_id: sa.file_id, resolver: sa.resolver }
+        let range = InFile::new(sa.file_id, node).original_file_range(self.db.upcast());
+        // The offset can only be used as is if `node` isn't inside a macro expansion.
+        let offset = if sa.file_id == range.file_id.into() { offset } else { range.range.start() };
+        let position = FilePosition { file_id: range.file_id, offset };
+        SemanticsScope {
+            db: self.db,
+            file_id: sa.file_id,
+            resolver: sa.resolver,
+            position: Some(position),
+        }
     }
 
     fn scope_for_def(&self, def: Trait) -> SemanticsScope<'db> {
         let file_id = self.db.lookup_intern_trait(def.id).id.file_id;
         let resolver = def.id.resolver(self.db.upcast());
-        SemanticsScope { db: self.db, file_id, resolver }
+        SemanticsScope { db: self.db, file_id, resolver, position: None }
     }
 
     fn analyze(&self, node: &SyntaxNode) -> SourceAnalyzer {
@@ -781,6 +876,9 @@ pub struct SemanticsScope<'a> {
     pub db: &'a dyn HirDatabase,
     file_id: HirFileId,
     resolver: Resolver,
+    /// The position in the original file the scope is at, used to tell which `macro_rules!`
+    /// definitions are visible.
+    position: Option<FilePosition>,
 }
 
 impl<'a> SemanticsScope<'a> {
@@ -802,7 +900,7 @@ impl<'a> SemanticsScope<'a> {
     pub fn process_all_names(&self, f: &mut dyn FnMut(Name, ScopeDef)) {
         let resolver = &self.resolver;
 
-        resolver.process_all_names(self.db.upcast(), &mut |name, def| {
+        let mut process = |name: Name, def: resolver::ScopeDef| {
             let def = match def {
                 resolver::ScopeDef::PerNs(it) => {
                     let items = ScopeDef::all_items(it);
@@ -820,7 +918,13 @@ impl<'a> SemanticsScope<'a> {
                 }
             };
             f(name, def)
-        })
+        };
+        match self.position {
+            Some(position) => {
+                resolver.process_all_names_at(self.db.upcast(), position, &mut process)
+            }
+            None => resolver.process_all_names(self.db.upcast(), &mut process),
+        }
     }
 
     /// Resolve a path as-if it was written at the given scope. This is
@@ -828,6 +932,6 @@ impl<'a> SemanticsScope<'a> {
     pub fn speculative_resolve(&self, path: &ast::Path) -> Option<PathResolution> {
         let hygiene = Hygiene::new(self.db.upcast(), self.file_id);
         let path = Path::from_src(path.clone(), &hygiene)?;
-        resolve_hir_path(self.db, &self.resolver, &path)
+        resolve_hir_path(self.db, &self.resolver, &path, SyntaxContext::ROOT)
     }
 }
@@ -15,23 +15,27 @@ use hir_def::{
     expr::{ExprId, Pat, PatId},
     path::{ModPath, Path, PathKind},
     resolver::{resolver_for_scope, Resolver, TypeNs, ValueNs},
-    AsMacroCall, DefWithBodyId, FieldId, FunctionId, LocalFieldId, VariantId,
+    AsMacroCall, AstIdWithPath, DefWithBodyId, FieldId, FunctionId, LocalFieldId, VariantId,
+};
+use hir_expand::{
+    hygiene::{Hygiene, SyntaxContext},
+    name::AsName,
+    HirFileId, InFile, MacroDefId,
 };
-use hir_expand::{hygiene::Hygiene, name::AsName, HirFileId, InFile};
 use hir_ty::{
     diagnostics::{record_literal_missing_fields, record_pattern_missing_fields},
     InferenceResult, Substs, Ty,
 };
 use syntax::{
     ast::{self, AstNode},
-    SyntaxNode, TextRange, TextSize,
+    SyntaxNode, SyntaxToken, TextRange, TextSize,
 };
 
 use crate::{
     db::HirDatabase, semantics::PathResolution, Adt, Const, Field, Function, Local, MacroDef,
     ModuleDef, Static, Struct, Trait, Type, TypeAlias, TypeParam, Variant,
 };
-use base_db::CrateId;
+use base_db::{CrateId, FilePosition};
 
 /// `SourceAnalyzer` is a convenience wrapper which exposes HIR API in terms of
 /// original source files. It should not be used inside the HIR itself.
@@ -166,9 +170,10 @@ impl SourceAnalyzer {
         let local = if field.name_ref().is_some() {
             None
         } else {
-            let local_name = field.field_name()?.as_name();
-            let path = ModPath::from_segments(PathKind::Plain, once(local_name));
-            match self.resolver.resolve_path_in_value_ns_fully(db.upcast(), &path) {
+            let name_ref = field.field_name()?;
+            let hygiene = self.syntax_context(db, name_ref.ident_token()?);
+            let path = ModPath::from_segments(PathKind::Plain, once(name_ref.as_name()));
+            match self.resolver.resolve_path_in_value_ns_fully(db.upcast(), &path, hygiene) {
                 Some(ValueNs::LocalBinding(pat_id)) => {
                     Some(Local { pat_id, parent: self.resolver.body_owner()? })
                 }
@@ -196,7 +201,20 @@ impl SourceAnalyzer {
     ) -> Option<MacroDef> {
         let hygiene = Hygiene::new(db.upcast(), macro_call.file_id);
         let path = macro_call.value.path().and_then(|ast| Path::from_src(ast, &hygiene))?;
-        self.resolver.resolve_path_as_macro(db.upcast(), path.mod_path()).map(|it| it.into())
+        self.resolve_macro_call_path(db, macro_call, path.mod_path()).map(|it| it.into())
+    }
+
+    /// Resolves the path of `macro_call`, which only sees the `macro_rules!` definitions
+    /// preceding it.
+    pub(crate) fn resolve_macro_call_path(
+        &self,
+        db: &dyn HirDatabase,
+        macro_call: InFile<&ast::MacroCall>,
+        path: &ModPath,
+    ) -> Option<MacroDefId> {
+        let range = macro_call.map(|it| it.syntax()).original_file_range(db.upcast());
+        let position = FilePosition { file_id: range.file_id, offset: range.range.start() };
+        self.resolver.resolve_path_as_macro_at(db.upcast(), path, position)
     }
 
     pub(crate) fn resolve_bind_pat_to_const(
@@ -210,7 +228,7 @@ impl SourceAnalyzer {
             Pat::Path(path) => path,
             _ => return None,
         };
-        let res = resolve_hir_path(db, &self.resolver, &path)?;
+        let res = resolve_hir_path(db, &self.resolver, &path, SyntaxContext::ROOT)?;
         match res {
             PathResolution::Def(def) => Some(def),
             _ => None,
@@ -277,7 +295,16 @@ impl SourceAnalyzer {
             }
         }
 
-        resolve_hir_path(db, &self.resolver, &hir_path)
+        let hygiene = path
+            .segment()
+            .filter(|_| path.qualifier().is_none())
+            .and_then(|segment| segment.name_ref()?.ident_token())
+            .map_or(SyntaxContext::ROOT, |token| self.syntax_context(db, token));
+        resolve_hir_path(db, &self.resolver, &hir_path, hygiene)
+    }
+
+    fn syntax_context(&self, db: &dyn HirDatabase, token: SyntaxToken) -> SyntaxContext {
+        SyntaxContext::of_token(db.upcast(), InFile::new(self.file_id, &token))
     }
 
     pub(crate) fn record_literal_missing_fields(
@@ -348,12 +375,31 @@ impl SourceAnalyzer {
         macro_call: InFile<&ast::MacroCall>,
     ) -> Option<HirFileId> {
         let krate = self.resolver.krate()?;
-        let macro_call_id = macro_call.as_call_id(db.upcast(), krate, |path| {
-            self.resolver.resolve_path_as_macro(db.upcast(), &path)
+        let module = self.resolver.module()?;
+        let module_path = || module.def_map(db.upcast()).module_path(db.upcast(), module.local_id);
+        let macro_call_id = macro_call.as_call_id(db.upcast(), krate, &module_path, |path| {
+            self.resolve_macro_call_path(db, macro_call, &path)
         })?;
         Some(macro_call_id.as_file()).filter(|it| it.expansion_level(db.upcast()) < 64)
     }
 
+    pub(crate) fn expand_derive(
+        &self,
+        db: &dyn HirDatabase,
+        item: InFile<&ast::Item>,
+        derive: &ModPath,
+    ) -> Option<HirFileId> {
+        let krate = self.resolver.krate()?;
+        let module = self.resolver.module()?;
+        let module_path = || module.def_map(db.upcast()).module_path(db.upcast(), module.local_id);
+        let ast_id = db.ast_id_map(item.file_id).ast_id(item.value);
+        let derive = AstIdWithPath::new(item.file_id, ast_id, derive.clone());
+        let macro_call_id = derive.as_call_id(db.upcast(), krate, &module_path, |path| {
+            self.resolver.resolve_path_as_macro(db.upcast(), &path)
+        })?;
+        Some(macro_call_id.as_file())
+    }
+
     pub(crate) fn resolve_variant(
         &self,
         db: &dyn HirDatabase,
@@ -451,6 +497,7 @@ pub(crate) fn resolve_hir_path(
     db: &dyn HirDatabase,
     resolver: &Resolver,
     path: &Path,
+    hygiene: SyntaxContext,
 ) -> Option<PathResolution> {
     let types =
         resolver.resolve_path_in_type_ns_fully(db.upcast(), path.mod_path()).map(|ty| match ty {
@@ -466,8 +513,9 @@ pub(crate) fn resolve_hir_path(
         });
 
     let body_owner = resolver.body_owner();
-    let values =
-        resolver.resolve_path_in_value_ns_fully(db.upcast(), path.mod_path()).and_then(|val| {
+    let values = resolver
+        .resolve_path_in_value_ns_fully(db.upcast(), path.mod_path(), hygiene)
+        .and_then(|val| {
             let res = match val {
                 ValueNs::LocalBinding(pat_id) => {
                     let var = Local { parent: body_owner?.into(), pat_id };
@@ -9,13 +9,15 @@ pub mod scope;
 use std::{mem, ops::Index, sync::Arc};
 
 use arena::{map::ArenaMap, Arena};
-use base_db::CrateId;
+use base_db::{CrateId, FilePosition};
 use cfg::CfgOptions;
 use drop_bomb::DropBomb;
 use either::Either;
 use hir_expand::{
-    ast_id_map::AstIdMap, diagnostics::DiagnosticSink, hygiene::Hygiene, AstId, ExpandResult,
-    HirFileId, InFile, MacroDefId,
+    ast_id_map::AstIdMap,
+    diagnostics::DiagnosticSink,
+    hygiene::{Hygiene, SyntaxContext},
+    AstId, ExpandResult, HirFileId, InFile, MacroCallId, MacroDefId,
 };
 use rustc_hash::FxHashMap;
 use syntax::{ast, AstNode, AstPtr};
@@ -27,12 +29,10 @@ use crate::{
     attr::{Attrs, RawAttrs},
     db::DefDatabase,
     expr::{Expr, ExprId, Label, LabelId, Pat, PatId},
-    item_scope::BuiltinShadowMode,
-    item_scope::ItemScope,
     nameres::CrateDefMap,
     path::{ModPath, Path},
     src::HasSource,
-    AsMacroCall, DefWithBodyId, HasModule, Lookup, ModuleId,
+    AsMacroCall, BlockId, DefWithBodyId, HasModule, Lookup, ModuleId, UnresolvedMacro,
 };
 
 /// A subset of Expander that only deals with cfg attributes. We only need it to
@@ -45,11 +45,11 @@ pub(crate) struct CfgExpander {
 
 pub(crate) struct Expander {
     cfg_expander: CfgExpander,
-    crate_def_map: Arc<CrateDefMap>,
+    def_map: Arc<CrateDefMap>,
     current_file_id: HirFileId,
     ast_id_map: Arc<AstIdMap>,
     module: ModuleId,
-    recursion_limit: usize,
+    recursion_depth: usize,
 }
 
 #[cfg(test)]
@@ -86,52 +86,54 @@ impl Expander {
         module: ModuleId,
     ) -> Expander {
         let cfg_expander = CfgExpander::new(db, current_file_id, module.krate);
-        let crate_def_map = db.crate_def_map(module.krate);
+        let def_map = module.def_map(db);
         let ast_id_map = db.ast_id_map(current_file_id);
-        Expander {
-            cfg_expander,
-            crate_def_map,
-            current_file_id,
-            ast_id_map,
-            module,
-            recursion_limit: 0,
-        }
+        Expander { cfg_expander, def_map, current_file_id, ast_id_map, module, recursion_depth: 0 }
     }
 
+    /// Expands `macro_call` and enters the expansion. Also returns the id of the call, so that
+    /// errors can be reported with a macro backtrace.
     pub(crate) fn enter_expand<T: ast::AstNode>(
         &mut self,
         db: &dyn DefDatabase,
-        local_scope: Option<&ItemScope>,
         macro_call: ast::MacroCall,
-    ) -> ExpandResult<Option<(Mark, T)>> {
-        if self.recursion_limit + 1 > EXPANSION_RECURSION_LIMIT {
+    ) -> Result<(ExpandResult<Option<(Mark, T)>>, Option<MacroCallId>), UnresolvedMacro> {
+        let limit =
+            self.def_map.recursion_limit().map_or(EXPANSION_RECURSION_LIMIT, |it| it as usize);
+        if self.recursion_depth + 1 > limit {
             mark::hit!(your_stack_belongs_to_me);
-            return ExpandResult::str_err("reached recursion limit during macro expansion".into());
+            return Ok((
+                ExpandResult::str_err("reached recursion limit during macro expansion".into()),
+                None,
+            ));
         }
 
         let macro_call = InFile::new(self.current_file_id, &macro_call);
 
+        let range = macro_call.map(|it| it.syntax()).original_file_range(db.upcast());
+        let position = FilePosition { file_id: range.file_id, offset: range.range.start() };
         let resolver = |path: ModPath| -> Option<MacroDefId> {
-            if let Some(local_scope) = local_scope {
-                if let Some(def) = path.as_ident().and_then(|n| local_scope.get_legacy_macro(n)) {
-                    return Some(def);
-                }
-            }
-            self.resolve_path_as_macro(db, &path)
+            self.resolve_path_as_macro(db, &path, position)
         };
 
+        let module_path = || self.def_map.module_path(db, self.module.local_id);
         let mut err = None;
-        let call_id =
-            macro_call.as_call_id_with_errors(db, self.crate_def_map.krate, resolver, &mut |e| {
+        let call_id = macro_call.as_call_id_with_errors(
+            db,
+            self.def_map.krate,
+            &module_path,
+            resolver,
+            &mut |e| {
                 err.get_or_insert(e);
-            });
+            },
+        )?;
         let call_id = match call_id {
             Some(it) => it,
             None => {
                 if err.is_none() {
                     eprintln!("no error despite `as_call_id_with_errors` returning `None`");
                 }
-                return ExpandResult { value: None, err };
+                return Ok((ExpandResult { value: None, err }, None));
             }
         };
 
@@ -149,9 +151,10 @@ impl Expander {
                     log::warn!("no error despite `parse_or_expand` failing");
                 }
 
-                return ExpandResult::only_err(err.unwrap_or_else(|| {
+                let err = err.unwrap_or_else(|| {
                     mbe::ExpandError::Other("failed to parse macro invocation".into())
-                }));
+                });
+                return Ok((ExpandResult::only_err(err), Some(call_id)));
             }
         };
 
@@ -159,13 +162,13 @@ impl Expander {
             Some(it) => it,
             None => {
                 // This can happen without being an error, so only forward previous errors.
-                return ExpandResult { value: None, err };
+                return Ok((ExpandResult { value: None, err }, Some(call_id)));
             }
         };
 
         log::debug!("macro expansion {:#?}", node.syntax());
 
-        self.recursion_limit += 1;
+        self.recursion_depth += 1;
         let mark = Mark {
             file_id: self.current_file_id,
             ast_id_map: mem::take(&mut self.ast_id_map),
@@ -175,14 +178,14 @@ impl Expander {
         self.current_file_id = file_id;
         self.ast_id_map = db.ast_id_map(file_id);
 
-        ExpandResult { value: Some((mark, node)), err }
+        Ok((ExpandResult { value: Some((mark, node)), err }, Some(call_id)))
     }
 
     pub(crate) fn exit(&mut self, db: &dyn DefDatabase, mut mark: Mark) {
         self.cfg_expander.hygiene = Hygiene::new(db.upcast(), mark.file_id);
         self.current_file_id = mark.file_id;
         self.ast_id_map = mem::take(&mut mark.ast_id_map);
-        self.recursion_limit -= 1;
+        self.recursion_depth -= 1;
         mark.bomb.defuse();
     }
 
@@ -202,11 +205,13 @@ impl Expander {
         Path::from_src(path, &self.cfg_expander.hygiene)
     }
 
-    fn resolve_path_as_macro(&self, db: &dyn DefDatabase, path: &ModPath) -> Option<MacroDefId> {
-        self.crate_def_map
-            .resolve_path(db, self.module.local_id, path, BuiltinShadowMode::Other)
-            .0
-            .take_macros()
+    fn resolve_path_as_macro(
+        &self,
+        db: &dyn DefDatabase,
+        path: &ModPath,
+        position: FilePosition,
+    ) -> Option<MacroDefId> {
+        self.def_map.resolve_macro_call_path(db, self.module.local_id, path, position)
     }
 
     fn ast_id<N: AstNode>(&self, item: &N) -> AstId<N> {
@@ -236,7 +241,13 @@ pub struct Body {
     pub params: Vec<PatId>,
     /// The `ExprId` of the actual body expression.
     pub body_expr: ExprId,
-    pub item_scope: ItemScope,
+    /// Block expressions in this body that declare items, mapped to the
+    /// `BlockId` of their `CrateDefMap`.
+    block_scopes: FxHashMap<ExprId, BlockId>,
+    /// Syntax contexts of bindings and path expressions which come from macro
+    /// definitions. Everything not listed here belongs to the root context.
+    pat_hygiene: FxHashMap<PatId, SyntaxContext>,
+    expr_hygiene: FxHashMap<ExprId, SyntaxContext>,
 }
 
 pub type ExprPtr = AstPtr<ast::Expr>;
@@ -304,7 +315,7 @@ impl Body {
             }
         };
         let expander = Expander::new(db, file_id, module);
-        let (body, source_map) = Body::new(db, def, expander, params, body);
+        let (body, source_map) = Body::new(db, expander, params, body);
         (Arc::new(body), Arc::new(source_map))
     }
 
@@ -312,14 +323,36 @@ impl Body {
         db.body_with_source_map(def).0
     }
 
+    pub fn pat_hygiene(&self, pat: PatId) -> SyntaxContext {
+        self.pat_hygiene.get(&pat).copied().unwrap_or(SyntaxContext::ROOT)
+    }
+
+    pub fn expr_hygiene(&self, expr: ExprId) -> SyntaxContext {
+        self.expr_hygiene.get(&expr).copied().unwrap_or(SyntaxContext::ROOT)
+    }
+
+    /// Returns the `BlockId` of the block expression `expr`, if it declares any
+    /// items (and thus has a block def map).
+    pub fn block_scope(&self, expr: ExprId) -> Option<BlockId> {
+        self.block_scopes.get(&expr).copied()
+    }
+
+    /// Returns the def maps of all block expressions in this body that declare
+    /// items.
+    pub fn block_def_maps<'a>(
+        &'a self,
+        db: &'a dyn DefDatabase,
+    ) -> impl Iterator<Item = Arc<CrateDefMap>> + 'a {
+        self.block_scopes.values().filter_map(move |&block| db.block_def_map(block))
+    }
+
     fn new(
         db: &dyn DefDatabase,
-        def: DefWithBodyId,
         expander: Expander,
         params: Option<ast::ParamList>,
         body: Option<ast::Expr>,
     ) -> (Body, BodySourceMap) {
-        lower::lower(db, def, expander, params, body)
+        lower::lower(db, expander, params, body)
     }
 }
 
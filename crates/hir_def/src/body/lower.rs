@@ -1,14 +1,14 @@
 //! Transforms `ast::Expr` into an equivalent `hir_def::expr::Expr`
 //! representation.
 
-use std::{any::type_name, sync::Arc};
+use std::{mem, sync::Arc};
 
 use arena::Arena;
 use either::Either;
 use hir_expand::{
-    hygiene::Hygiene,
+    hygiene::{Hygiene, SyntaxContext},
     name::{name, AsName, Name},
-    ExpandError, HirFileId, MacroDefId, MacroDefKind,
+    DiagnosticLevel, ExpandError, HirFileId, InFile, MacroCallError,
 };
 use rustc_hash::FxHashMap;
 use syntax::{
@@ -16,7 +16,7 @@ use syntax::{
         self, ArgListOwner, ArrayExprKind, AstChildren, LiteralKind, LoopBodyOwner, NameOwner,
         SlicePatComponents,
     },
-    AstNode, AstPtr, SyntaxNodePtr,
+    AstNode, AstPtr, SyntaxNodePtr, SyntaxToken,
 };
 use test_utils::mark;
 
@@ -25,18 +25,17 @@ use crate::{
     body::{Body, BodySourceMap, Expander, LabelSource, PatPtr, SyntheticSyntax},
     builtin_type::{BuiltinFloat, BuiltinInt},
     db::DefDatabase,
-    diagnostics::{InactiveCode, MacroError, UnresolvedProcMacro},
+    diagnostics::{InactiveCode, MacroError, UnresolvedMacroCall, UnresolvedProcMacro},
     expr::{
         dummy_expr_id, ArithOp, Array, BinaryOp, BindingAnnotation, CmpOp, Expr, ExprId, Label,
         LabelId, Literal, LogicOp, MatchArm, Ordering, Pat, PatId, RecordFieldPat, RecordLitField,
         Statement,
     },
     item_scope::BuiltinShadowMode,
-    item_tree::{ItemTree, ItemTreeId, ItemTreeNode},
+    item_tree::ItemTree,
     path::{GenericArgs, Path},
     type_ref::{Mutability, Rawness, TypeRef},
-    AdtId, ConstLoc, ContainerId, DefWithBodyId, EnumLoc, FunctionLoc, Intern, ModuleDefId,
-    StaticLoc, StructLoc, TraitLoc, TypeAliasLoc, UnionLoc,
+    AdtId, BlockId, BlockLoc, ModuleDefId, UnresolvedMacro,
 };
 
 use super::{diagnostics::BodyDiagnostic, ExprSource, PatSource};
@@ -60,7 +59,6 @@ impl LowerCtx {
 
 pub(super) fn lower(
     db: &dyn DefDatabase,
-    def: DefWithBodyId,
     expander: Expander,
     params: Option<ast::ParamList>,
     body: Option<ast::Expr>,
@@ -68,7 +66,6 @@ pub(super) fn lower(
     let item_tree = db.item_tree(expander.current_file_id);
     ExprCollector {
         db,
-        def,
         source_map: BodySourceMap::default(),
         body: Body {
             exprs: Arena::default(),
@@ -76,7 +73,9 @@ pub(super) fn lower(
             labels: Arena::default(),
             params: Vec::new(),
             body_expr: dummy_expr_id(),
-            item_scope: Default::default(),
+            block_scopes: FxHashMap::default(),
+            pat_hygiene: FxHashMap::default(),
+            expr_hygiene: FxHashMap::default(),
         },
         item_trees: {
             let mut map = FxHashMap::default();
@@ -90,7 +89,6 @@ pub(super) fn lower(
 
 struct ExprCollector<'a> {
     db: &'a dyn DefDatabase,
-    def: DefWithBodyId,
     expander: Expander,
     body: Body,
     source_map: BodySourceMap,
@@ -177,6 +175,10 @@ impl ExprCollector<'_> {
         id
     }
 
+    fn syntax_context(&self, token: &SyntaxToken) -> SyntaxContext {
+        SyntaxContext::of_token(self.db.upcast(), InFile::new(self.expander.current_file_id, token))
+    }
+
     fn alloc_label(&mut self, label: Label, ptr: AstPtr<ast::Label>) -> LabelId {
         let src = self.expander.to_source(ptr);
         let id = self.make_label(label, src.clone());
@@ -362,7 +364,19 @@ impl ExprCollector<'_> {
                     .and_then(|path| self.expander.parse_path(path))
                     .map(Expr::Path)
                     .unwrap_or(Expr::Missing);
-                self.alloc_expr(path, syntax_ptr)
+                let id = self.alloc_expr(path, syntax_ptr);
+                // Only single-segment paths can refer to local variables.
+                let name_token = e
+                    .path()
+                    .filter(|path| path.qualifier().is_none())
+                    .and_then(|path| path.segment()?.name_ref()?.ident_token());
+                if let Some(token) = name_token {
+                    let ctx = self.syntax_context(&token);
+                    if !ctx.is_root() {
+                        self.body.expr_hygiene.insert(id, ctx);
+                    }
+                }
+                id
             }
             ast::Expr::ContinueExpr(e) => self.alloc_expr(
                 Expr::Continue { label: e.lifetime().map(|l| Name::new_lifetime(&l)) },
@@ -555,7 +569,23 @@ impl ExprCollector<'_> {
         let outer_file = self.expander.current_file_id;
 
         let macro_call = self.expander.to_source(AstPtr::new(&e));
-        let res = self.expander.enter_expand(self.db, Some(&self.body.item_scope), e);
+        let (res, call_id) = match self.expander.enter_expand(self.db, e.clone()) {
+            Ok(res) => res,
+            Err(UnresolvedMacro { path }) => {
+                if let Some(path_node) = e.path() {
+                    self.source_map.diagnostics.push(BodyDiagnostic::UnresolvedMacroCall(
+                        UnresolvedMacroCall {
+                            file: outer_file,
+                            node: AstPtr::new(&e),
+                            path_node: AstPtr::new(&path_node),
+                            path,
+                        },
+                    ));
+                }
+                collector(self, None);
+                return;
+            }
+        };
 
         match &res.err {
             Some(ExpandError::UnresolvedProcMacro) => {
@@ -569,11 +599,28 @@ impl ExprCollector<'_> {
                 ));
             }
             Some(err) => {
-                self.source_map.diagnostics.push(BodyDiagnostic::MacroError(MacroError {
-                    file: outer_file,
-                    node: syntax_ptr.into(),
-                    message: err.to_string(),
-                }));
+                let backtrace =
+                    call_id.map_or(outer_file, |it| it.as_file()).macro_backtrace(self.db.upcast());
+                let errors = match call_id {
+                    Some(call_id) => call_id.errors(self.db.upcast(), err),
+                    None => vec![MacroCallError {
+                        message: err.to_string(),
+                        level: DiagnosticLevel::Error,
+                        location: None,
+                    }],
+                };
+                for error in errors {
+                    let precise_location =
+                        error.location.filter(|it| it.file_id == outer_file).map(|it| it.value);
+                    self.source_map.diagnostics.push(BodyDiagnostic::MacroError(MacroError {
+                        file: outer_file,
+                        node: syntax_ptr.clone().into(),
+                        precise_location,
+                        message: error.message,
+                        level: error.level,
+                        backtrace: backtrace.clone(),
+                    }));
+                }
             }
             None => {}
         }
@@ -600,32 +647,6 @@ impl ExprCollector<'_> {
         }
     }
 
-    fn find_inner_item<N: ItemTreeNode>(&self, ast: &N::Source) -> Option<ItemTreeId<N>> {
-        let id = self.expander.ast_id(ast);
-        let tree = &self.item_trees[&id.file_id];
-
-        // FIXME: This probably breaks with `use` items, since they produce multiple item tree nodes
-
-        // Root file (non-macro).
-        let item_tree_id = tree
-            .all_inner_items()
-            .chain(tree.top_level_items().iter().copied())
-            .filter_map(|mod_item| mod_item.downcast::<N>())
-            .find(|tree_id| tree[*tree_id].ast_id().upcast() == id.value.upcast())
-            .or_else(|| {
-                log::debug!(
-                    "couldn't find inner {} item for {:?} (AST: `{}` - {:?})",
-                    type_name::<N>(),
-                    id,
-                    ast.syntax(),
-                    ast.syntax(),
-                );
-                None
-            })?;
-
-        Some(ItemTreeId::new(id.file_id, item_tree_id))
-    }
-
     fn collect_expr_opt(&mut self, expr: Option<ast::Expr>) -> ExprId {
         if let Some(expr) = expr {
             self.collect_expr(expr)
@@ -657,7 +678,6 @@ impl ExprCollector<'_> {
                             match expansion {
                                 Some(expansion) => {
                                     let statements: ast::MacroStmts = expansion;
-                                    this.collect_stmts_items(statements.statements());
 
                                     statements.statements().for_each(|stmt| {
                                         if let Some(mut r) = this.collect_stmt(stmt) {
@@ -691,114 +711,40 @@ impl ExprCollector<'_> {
     }
 
     fn collect_block(&mut self, block: ast::BlockExpr) -> ExprId {
+        // Blocks that declare items get their own `CrateDefMap`, which is used to resolve names
+        // (and macros) inside of them.
+        let block_id = self.block_id(&block);
+        let def_map = block_id.and_then(|block_id| self.db.block_def_map(block_id));
+        let prev = def_map.map(|def_map| {
+            let module = def_map.module_id(def_map.root);
+            let prev_def_map = mem::replace(&mut self.expander.def_map, def_map);
+            let prev_module = mem::replace(&mut self.expander.module, module);
+            (prev_def_map, prev_module)
+        });
+
         let syntax_node_ptr = AstPtr::new(&block.clone().into());
-        self.collect_stmts_items(block.statements());
         let statements =
             block.statements().filter_map(|s| self.collect_stmt(s)).flatten().collect();
         let tail = block.expr().map(|e| self.collect_expr(e));
-        self.alloc_expr(Expr::Block { statements, tail, label: None }, syntax_node_ptr)
-    }
+        let expr_id =
+            self.alloc_expr(Expr::Block { statements, tail, label: None }, syntax_node_ptr);
 
-    fn collect_stmts_items(&mut self, stmts: ast::AstChildren<ast::Stmt>) {
-        let container = ContainerId::DefWithBodyId(self.def);
-
-        let items = stmts
-            .filter_map(|stmt| match stmt {
-                ast::Stmt::Item(it) => Some(it),
-                ast::Stmt::LetStmt(_) | ast::Stmt::ExprStmt(_) => None,
-            })
-            .filter_map(|item| {
-                let (def, name): (ModuleDefId, Option<ast::Name>) = match item {
-                    ast::Item::Fn(def) => {
-                        let id = self.find_inner_item(&def)?;
-                        (
-                            FunctionLoc { container: container.into(), id }.intern(self.db).into(),
-                            def.name(),
-                        )
-                    }
-                    ast::Item::TypeAlias(def) => {
-                        let id = self.find_inner_item(&def)?;
-                        (
-                            TypeAliasLoc { container: container.into(), id }.intern(self.db).into(),
-                            def.name(),
-                        )
-                    }
-                    ast::Item::Const(def) => {
-                        let id = self.find_inner_item(&def)?;
-                        (
-                            ConstLoc { container: container.into(), id }.intern(self.db).into(),
-                            def.name(),
-                        )
-                    }
-                    ast::Item::Static(def) => {
-                        let id = self.find_inner_item(&def)?;
-                        (StaticLoc { container, id }.intern(self.db).into(), def.name())
-                    }
-                    ast::Item::Struct(def) => {
-                        let id = self.find_inner_item(&def)?;
-                        (StructLoc { container, id }.intern(self.db).into(), def.name())
-                    }
-                    ast::Item::Enum(def) => {
-                        let id = self.find_inner_item(&def)?;
-                        (EnumLoc { container, id }.intern(self.db).into(), def.name())
-                    }
-                    ast::Item::Union(def) => {
-                        let id = self.find_inner_item(&def)?;
-                        (UnionLoc { container, id }.intern(self.db).into(), def.name())
-                    }
-                    ast::Item::Trait(def) => {
-                        let id = self.find_inner_item(&def)?;
-                        (TraitLoc { container, id }.intern(self.db).into(), def.name())
-                    }
-                    ast::Item::ExternBlock(_) => return None, // FIXME: collect from extern blocks
-                    ast::Item::Impl(_)
-                    | ast::Item::Use(_)
-                    | ast::Item::ExternCrate(_)
-                    | ast::Item::Module(_)
-                    | ast::Item::MacroCall(_) => return None,
-                    ast::Item::MacroRules(def) => {
-                        return Some(Either::Right(ast::Macro::from(def)));
-                    }
-                    ast::Item::MacroDef(def) => {
-                        return Some(Either::Right(ast::Macro::from(def)));
-                    }
-                };
+        if let (Some(block_id), Some((def_map, module))) = (block_id, prev) {
+            self.body.block_scopes.insert(expr_id, block_id);
+            self.expander.def_map = def_map;
+            self.expander.module = module;
+        }
+        expr_id
+    }
 
-                Some(Either::Left((def, name)))
-            })
-            .collect::<Vec<_>>();
-
-        for either in items {
-            match either {
-                Either::Left((def, name)) => {
-                    self.body.item_scope.define_def(def);
-                    if let Some(name) = name {
-                        let vis = crate::visibility::Visibility::Public; // FIXME determine correctly
-                        let has_constructor = match def {
-                            ModuleDefId::AdtId(AdtId::StructId(s)) => {
-                                self.db.struct_data(s).variant_data.kind() != StructKind::Record
-                            }
-                            _ => true,
-                        };
-                        self.body.item_scope.push_res(
-                            name.as_name(),
-                            crate::per_ns::PerNs::from_def(def, vis, has_constructor),
-                        );
-                    }
-                }
-                Either::Right(e) => {
-                    let mac = MacroDefId {
-                        krate: self.expander.module.krate,
-                        ast_id: Some(self.expander.ast_id(&e)),
-                        kind: MacroDefKind::Declarative,
-                        local_inner: false,
-                    };
-                    if let Some(name) = e.name() {
-                        self.body.item_scope.define_legacy_macro(name.as_name(), mac);
-                    }
-                }
-            }
+    /// Interns the `BlockId` of `block` if it declares any items.
+    fn block_id(&mut self, block: &ast::BlockExpr) -> Option<BlockId> {
+        let ast_id = self.expander.ast_id(block);
+        let item_tree = &self.item_trees[&ast_id.file_id];
+        if item_tree.inner_items_of_block(ast_id.value).is_empty() {
+            return None;
         }
+        Some(self.db.intern_block(BlockLoc { ast_id, module: self.expander.module }))
     }
 
     fn collect_block_opt(&mut self, expr: Option<ast::BlockExpr>) -> ExprId {
@@ -826,7 +772,7 @@ impl ExprCollector<'_> {
                 if annotation == BindingAnnotation::Unannotated && subpat.is_none() {
                     // This could also be a single-segment path pattern. To
                     // decide that, we need to try resolving the name.
-                    let (resolved, _) = self.expander.crate_def_map.resolve_path(
+                    let (resolved, _) = self.expander.def_map.resolve_path(
                         self.db,
                         self.expander.module.local_id,
                         &name.clone().into(),
@@ -946,7 +892,16 @@ impl ExprCollector<'_> {
             ast::Pat::RangePat(_) | ast::Pat::MacroPat(_) => Pat::Missing,
         };
         let ptr = AstPtr::new(&pat);
-        self.alloc_pat(pattern, Either::Left(ptr))
+        let id = self.alloc_pat(pattern, Either::Left(ptr));
+        if let ast::Pat::IdentPat(bp) = &pat {
+            if let Some(token) = bp.name().and_then(|name| name.ident_token()) {
+                let ctx = self.syntax_context(&token);
+                if !ctx.is_root() {
+                    self.body.pat_hygiene.insert(id, ctx);
+                }
+            }
+        }
+        id
     }
 
     fn collect_pat_opt(&mut self, pat: Option<ast::Pat>) -> PatId {
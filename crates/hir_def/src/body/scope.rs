@@ -2,14 +2,14 @@
 use std::sync::Arc;
 
 use arena::{Arena, Idx};
-use hir_expand::name::Name;
+use hir_expand::{hygiene::SyntaxContext, name::Name};
 use rustc_hash::FxHashMap;
 
 use crate::{
     body::Body,
     db::DefDatabase,
     expr::{Expr, ExprId, Pat, PatId, Statement},
-    DefWithBodyId,
+    BlockId, DefWithBodyId,
 };
 
 pub type ScopeId = Idx<ScopeData>;
@@ -24,6 +24,7 @@ pub struct ExprScopes {
 pub struct ScopeEntry {
     name: Name,
     pat: PatId,
+    hygiene: SyntaxContext,
 }
 
 impl ScopeEntry {
@@ -34,11 +35,17 @@ impl ScopeEntry {
     pub fn pat(&self) -> PatId {
         self.pat
     }
+
+    /// Only names with the same syntax context can refer to this binding.
+    pub fn hygiene(&self) -> SyntaxContext {
+        self.hygiene
+    }
 }
 
 #[derive(Debug, PartialEq, Eq)]
 pub struct ScopeData {
     parent: Option<ScopeId>,
+    block: Option<BlockId>,
     entries: Vec<ScopeEntry>,
 }
 
@@ -61,13 +68,24 @@ impl ExprScopes {
         &self.scopes[scope].entries
     }
 
+    /// If `scope` refers to a block expression scope, returns the corresponding `BlockId`.
+    pub fn block(&self, scope: ScopeId) -> Option<BlockId> {
+        self.scopes[scope].block
+    }
+
     pub fn scope_chain(&self, scope: Option<ScopeId>) -> impl Iterator<Item = ScopeId> + '_ {
         std::iter::successors(scope, move |&scope| self.scopes[scope].parent)
     }
 
-    pub fn resolve_name_in_scope(&self, scope: ScopeId, name: &Name) -> Option<&ScopeEntry> {
-        self.scope_chain(Some(scope))
-            .find_map(|scope| self.entries(scope).iter().find(|it| it.name == *name))
+    pub fn resolve_name_in_scope(
+        &self,
+        scope: ScopeId,
+        name: &Name,
+        hygiene: SyntaxContext,
+    ) -> Option<&ScopeEntry> {
+        self.scope_chain(Some(scope)).find_map(|scope| {
+            self.entries(scope).iter().find(|it| it.name == *name && it.hygiene == hygiene)
+        })
     }
 
     pub fn scope_for(&self, expr: ExprId) -> Option<ScopeId> {
@@ -79,17 +97,21 @@ impl ExprScopes {
     }
 
     fn root_scope(&mut self) -> ScopeId {
-        self.scopes.alloc(ScopeData { parent: None, entries: vec![] })
+        self.scopes.alloc(ScopeData { parent: None, block: None, entries: vec![] })
     }
 
     fn new_scope(&mut self, parent: ScopeId) -> ScopeId {
-        self.scopes.alloc(ScopeData { parent: Some(parent), entries: vec![] })
+        self.scopes.alloc(ScopeData { parent: Some(parent), block: None, entries: vec![] })
+    }
+
+    fn new_block_scope(&mut self, parent: ScopeId, block: BlockId) -> ScopeId {
+        self.scopes.alloc(ScopeData { parent: Some(parent), block: Some(block), entries: vec![] })
     }
 
     fn add_bindings(&mut self, body: &Body, scope: ScopeId, pat: PatId) {
         let pattern = &body[pat];
         if let Pat::Bind { name, .. } = pattern {
-            let entry = ScopeEntry { name: name.clone(), pat };
+            let entry = ScopeEntry { name: name.clone(), pat, hygiene: body.pat_hygiene(pat) };
             self.scopes[scope].entries.push(entry);
         }
 
@@ -137,6 +159,13 @@ fn compute_expr_scopes(expr: ExprId, body: &Body, scopes: &mut ExprScopes, scope
     scopes.set_scope(expr, scope);
     match &body[expr] {
         Expr::Block { statements, tail, .. } => {
+            let scope = match body.block_scope(expr) {
+                Some(block) => scopes.new_block_scope(scope, block),
+                None => scope,
+            };
+            // Overwrite the old scope for the block expr, so that every block scope can be found
+            // via the block itself (important for blocks that only contain items, no expressions).
+            scopes.set_scope(expr, scope);
             compute_block_scopes(&statements, *tail, body, scopes, scope);
         }
         Expr::For { iterable, pat, body: body_expr, .. } => {
@@ -170,7 +199,7 @@ fn compute_expr_scopes(expr: ExprId, body: &Body, scopes: &mut ExprScopes, scope
 #[cfg(test)]
 mod tests {
     use base_db::{fixture::WithFixture, FileId, SourceDatabase};
-    use hir_expand::{name::AsName, InFile};
+    use hir_expand::{hygiene::SyntaxContext, name::AsName, InFile};
     use syntax::{algo::find_node_at_offset, ast, AstNode};
     use test_utils::{assert_eq_text, extract_offset, mark};
 
@@ -372,7 +401,9 @@ fn foo() {
             scopes.scope_for(expr_id).unwrap()
         };
 
-        let resolved = scopes.resolve_name_in_scope(expr_scope, &name_ref.as_name()).unwrap();
+        let resolved = scopes
+            .resolve_name_in_scope(expr_scope, &name_ref.as_name(), SyntaxContext::ROOT)
+            .unwrap();
         let pat_src = source_map.pat_syntax(resolved.pat()).unwrap();
 
         let local_name = pat_src.value.either(
@@ -129,7 +129,58 @@ fn f() {
     m!();
 
     m!(hi);
-  //^^^^^^ leftover tokens
+  //^^^^^^ no rules expected the token `hi`
+}
+      "#,
+    );
+}
+
+#[test]
+fn macro_recursion_limit_diag() {
+    check_diagnostics(
+        r#"
+#![recursion_limit = "2"]
+
+macro_rules! a { () => { b!() } }
+macro_rules! b { () => { c!() } }
+macro_rules! c { () => { 0 } }
+
+fn f() {
+    a!();
+  //^^^^ reached recursion limit during macro expansion
+}
+      "#,
+    );
+}
+
+#[test]
+fn unresolved_macro_diag() {
+    check_diagnostics(
+        r#"
+fn f() {
+    m!();
+  //^ unresolved macro `m!`
+}
+      "#,
+    );
+}
+
+#[test]
+fn derive_macro_called_as_fn_like() {
+    check_diagnostics(
+        r#"
+//- /main.rs crate:main deps:macros
+fn f() {
+    macros::DummyTrait!();
+  //^^^^^^^^^^^^^^^^^^^^^ expected function-like macro, found `macros::DummyTrait`
+}
+
+//- /macros.rs crate:macros kind:proc-macro
+pub struct TokenStream;
+
+#[proc_macro_derive(DummyTrait)]
+pub fn derive_macro(_item: TokenStream) -> TokenStream {
+    TokenStream
 }
       "#,
     );
@@ -157,7 +208,7 @@ macro_rules! outer {
 
 fn f() {
     outer!();
-  //^^^^^^^^ leftover tokens
+  //^^^^^^^^ no rules expected the token `1`
 }
         "#,
     )
@@ -74,8 +74,8 @@ impl ChildBySource for ImplId {
 
 impl ChildBySource for ModuleId {
     fn child_by_source(&self, db: &dyn DefDatabase) -> DynMap {
-        let crate_def_map = db.crate_def_map(self.krate);
-        let module_data = &crate_def_map[self.local_id];
+        let def_map = self.def_map(db);
+        let module_data = &def_map[self.local_id];
         module_data.scope.child_by_source(db)
     }
 }
@@ -83,9 +83,15 @@ impl ChildBySource for ModuleId {
 impl ChildBySource for ItemScope {
     fn child_by_source(&self, db: &dyn DefDatabase) -> DynMap {
         let mut res = DynMap::default();
-        self.declarations().for_each(|item| add_module_def(db, &mut res, item));
-        self.impls().for_each(|imp| add_impl(db, &mut res, imp));
-        return res;
+        self.child_by_source_to(db, &mut res);
+        res
+    }
+}
+
+impl ItemScope {
+    fn child_by_source_to(&self, db: &dyn DefDatabase, res: &mut DynMap) {
+        self.declarations().for_each(|item| add_module_def(db, res, item));
+        self.impls().for_each(|imp| add_impl(db, res, imp));
 
         fn add_module_def(db: &dyn DefDatabase, map: &mut DynMap, item: ModuleDefId) {
             match item {
@@ -171,7 +177,13 @@ impl ChildBySource for EnumId {
 
 impl ChildBySource for DefWithBodyId {
     fn child_by_source(&self, db: &dyn DefDatabase) -> DynMap {
+        let mut res = DynMap::default();
         let body = db.body(*self);
-        body.item_scope.child_by_source(db)
+        for def_map in body.block_def_maps(db) {
+            for (_, module) in def_map.modules.iter() {
+                module.scope.child_by_source_to(db, &mut res);
+            }
+        }
+        res
     }
 }
@@ -2,8 +2,8 @@
 
 use std::sync::Arc;
 
-use hir_expand::{name::Name, InFile};
-use syntax::ast;
+use hir_expand::{name::Name, ExpandResult, InFile};
+use syntax::{ast, SmolStr};
 
 use crate::{
     attr::Attrs,
@@ -29,6 +29,8 @@ pub struct FunctionData {
     pub is_unsafe: bool,
     pub is_varargs: bool,
     pub is_extern: bool,
+    /// The ABI of an `extern "abi" fn` or of a function declared in an `extern` block.
+    pub abi: Option<SmolStr>,
     pub visibility: RawVisibility,
 }
 
@@ -49,6 +51,7 @@ impl FunctionData {
             is_unsafe: func.is_unsafe,
             is_varargs: func.is_varargs,
             is_extern: func.is_extern,
+            abi: func.abi.clone(),
             visibility: item_tree[func.visibility].clone(),
         })
     }
@@ -97,7 +100,7 @@ impl TraitData {
         let tr_def = &item_tree[tr_loc.id.value];
         let name = tr_def.name.clone();
         let auto = tr_def.auto;
-        let module_id = tr_loc.container.module(db);
+        let module_id = tr_loc.container;
         let container = AssocContainerId::TraitId(tr);
         let mut expander = Expander::new(db, tr_loc.id.file_id, module_id);
 
@@ -147,7 +150,7 @@ impl ImplData {
         let target_trait = impl_def.target_trait.clone();
         let target_type = impl_def.target_type.clone();
         let is_negative = impl_def.is_negative;
-        let module_id = impl_loc.container.module(db);
+        let module_id = impl_loc.container;
         let container = AssocContainerId::ImplId(id);
         let mut expander = Expander::new(db, impl_loc.id.file_id, module_id);
 
@@ -262,7 +265,9 @@ fn collect_items(
                 let root = db.parse_or_expand(file_id).unwrap();
                 let call = ast_id_map.get(call.ast_id).to_node(&root);
 
-                if let Some((mark, mac)) = expander.enter_expand(db, None, call).value {
+                let res = expander.enter_expand(db, call);
+
+                if let Ok((ExpandResult { value: Some((mark, mac)), .. }, _)) = res {
                     let src: InFile<ast::MacroItems> = expander.to_source(mac);
                     let item_tree = db.item_tree(src.file_id);
                     let iter =
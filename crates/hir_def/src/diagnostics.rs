@@ -5,10 +5,10 @@ use stdx::format_to;
 
 use cfg::{CfgExpr, CfgOptions, DnfExpr};
 use hir_expand::diagnostics::{Diagnostic, DiagnosticCode, DiagnosticSink};
-use hir_expand::{HirFileId, InFile};
+use hir_expand::{DiagnosticLevel, HirFileId, InFile, MacroBacktraceFrame};
 use syntax::{ast, AstPtr, SyntaxNodePtr, TextRange};
 
-use crate::{db::DefDatabase, DefWithBodyId};
+use crate::{db::DefDatabase, path::ModPath, DefWithBodyId};
 
 pub fn validate_body(db: &dyn DefDatabase, owner: DefWithBodyId, sink: &mut DiagnosticSink<'_>) {
     let source_map = db.body_with_source_map(owner).1;
@@ -164,6 +164,34 @@ impl Diagnostic for UnresolvedProcMacro {
     }
 }
 
+// Diagnostic: unresolved-macro-call
+//
+// This diagnostic is triggered if rust-analyzer is unable to resolve the path to a
+// macro in a macro invocation.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct UnresolvedMacroCall {
+    pub file: HirFileId,
+    pub node: AstPtr<ast::MacroCall>,
+    /// The path of the macro call, which is what the diagnostic points at.
+    pub path_node: AstPtr<ast::Path>,
+    pub path: ModPath,
+}
+
+impl Diagnostic for UnresolvedMacroCall {
+    fn code(&self) -> DiagnosticCode {
+        DiagnosticCode("unresolved-macro-call")
+    }
+    fn message(&self) -> String {
+        format!("unresolved macro `{}!`", self.path)
+    }
+    fn display_source(&self) -> InFile<SyntaxNodePtr> {
+        InFile::new(self.file, self.path_node.clone().into())
+    }
+    fn as_any(&self) -> &(dyn Any + Send + 'static) {
+        self
+    }
+}
+
 // Diagnostic: macro-error
 //
 // This diagnostic is shown for macro expansion errors.
@@ -171,7 +199,14 @@ impl Diagnostic for UnresolvedProcMacro {
 pub struct MacroError {
     pub file: HirFileId,
     pub node: SyntaxNodePtr,
+    /// If the error can be pinpointed to a token of the macro input, this is the `TextRange` of
+    /// the token.
+    pub precise_location: Option<TextRange>,
     pub message: String,
+    /// Proc macros can emit warnings and notes as well, everything else is an error.
+    pub level: DiagnosticLevel,
+    /// The macro calls that led to the error, starting with the innermost one.
+    pub backtrace: Vec<MacroBacktraceFrame>,
 }
 
 impl Diagnostic for MacroError {
@@ -0,0 +1,911 @@
+//! Structured dumps of `CrateDefMap` and `ItemTree`.
+//!
+//! These are debugging aids. A dump is a plain data structure, which can be
+//! printed as indented, IR-like text via `Display`, or converted to other
+//! formats (like JSON) by the caller.
+//!
+//! The text format for a `CrateDefMap` looks like this:
+//!
+//! ```text
+//! crate (crate root)
+//!   items:
+//!     t pub(self) mod foo
+//!     v pub(crate) fn bar
+//!     ? baz
+//!   imports:
+//!     pub(self) use foo::baz; // unresolved
+//!   textual macros:
+//!     macro_rules m // visible from offset 12
+//! ```
+//!
+//! Each item line starts with the namespace (`t`ypes, `v`alues or `m`acros),
+//! `?` marks names which failed to resolve.
+//!
+//! Imports are not stored in the def map, so they are read from the item tree
+//! and resolved again against the final def map when dumping. Imports produced
+//! by macro expansion are not listed.
+
+use std::{fmt, iter, sync::Arc};
+
+use base_db::ProcMacroKind;
+use hir_expand::{ast_id_map::FileAstId, MacroDefId, MacroDefKind};
+use syntax::ast;
+
+use crate::{
+    attr::AttrInput,
+    db::DefDatabase,
+    item_tree::{
+        AssocItem, AttrOwner, Fields, ItemTree, ModItem, ModKind, RawVisibilityId, StructDefKind,
+    },
+    nameres::{CrateDefMap, ModuleOrigin},
+    path::{ImportAlias, ModPath, PathKind},
+    per_ns::PerNs,
+    visibility::{RawVisibility, Visibility},
+    AdtId, LocalModuleId, ModuleDefId, ModuleId,
+};
+
+/// A dump of a `CrateDefMap`, one entry per module.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DefMapDump {
+    pub modules: Vec<ModuleDump>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModuleDump {
+    /// Path of the module, starting at `crate` (or `block` for block def maps).
+    pub path: String,
+    pub origin: String,
+    pub items: Vec<ItemDump>,
+    pub imports: Vec<ImportDump>,
+    pub textual_macros: Vec<TextualMacroDump>,
+    pub impls: usize,
+}
+
+/// A single name in a module scope, in a single namespace.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ItemDump {
+    pub name: String,
+    /// `None` if the name failed to resolve.
+    pub namespace: Option<Namespace>,
+    pub kind: String,
+    /// `None` if the name failed to resolve.
+    pub visibility: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Namespace {
+    Types,
+    Values,
+    Macros,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportDump {
+    pub path: String,
+    pub alias: Option<String>,
+    pub visibility: String,
+    pub is_glob: bool,
+    pub is_extern_crate: bool,
+    pub status: ImportStatus,
+    /// Namespaces the import resolved in.
+    pub namespaces: Vec<Namespace>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportStatus {
+    Resolved,
+    Unresolved,
+}
+
+/// A `macro_rules!` definition in textual scope.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextualMacroDump {
+    pub name: String,
+    pub kind: String,
+    /// Offset in the original file the definition becomes visible at, `None` if it is visible
+    /// in the whole module.
+    pub visible_from: Option<u32>,
+}
+
+/// A dump of an `ItemTree`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ItemTreeDump {
+    pub attrs: Vec<String>,
+    pub items: Vec<ItemTreeItemDump>,
+    /// Items declared inside block expressions, one entry per block, in source order.
+    pub blocks: Vec<Vec<ItemTreeItemDump>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ItemTreeItemDump {
+    pub kind: String,
+    pub name: Option<String>,
+    pub visibility: Option<String>,
+    pub attrs: Vec<String>,
+    /// Additional information, like the path of an import or the qualifiers of a function.
+    pub detail: Option<String>,
+    /// Fields, variants, associated items and items of inline modules.
+    pub children: Vec<ItemTreeItemDump>,
+}
+
+/// Dumps `def_map`, listing modules depth-first and names in each scope alphabetically.
+pub fn def_map(db: &dyn DefDatabase, def_map: &CrateDefMap) -> DefMapDump {
+    let mut modules = Vec::new();
+    let root = if def_map.block_id().is_some() { "block" } else { "crate" };
+    go(db, &mut modules, def_map, root.to_string(), def_map.root);
+    return DefMapDump { modules };
+
+    fn go(
+        db: &dyn DefDatabase,
+        acc: &mut Vec<ModuleDump>,
+        def_map: &CrateDefMap,
+        path: String,
+        module: LocalModuleId,
+    ) {
+        let data = &def_map[module];
+
+        let mut entries: Vec<_> = data.scope.entries().collect();
+        entries.sort_by_key(|(name, _)| name.to_string());
+        let mut items = Vec::new();
+        for (name, def) in entries {
+            items.extend(items_of(def_map, name.to_string(), def));
+        }
+
+        let imports = imports_of(db, def_map, module);
+
+        let mut textual_macros: Vec<_> = data
+            .scope
+            .legacy_macro_definitions()
+            .flat_map(|(name, defs)| {
+                defs.iter().map(move |(def, visible_from)| TextualMacroDump {
+                    name: name.to_string(),
+                    kind: macro_kind(def).to_string(),
+                    visible_from: visible_from.map(|it| it.offset.into()),
+                })
+            })
+            .collect();
+        textual_macros
+            .sort_by(|a, b| a.name.cmp(&b.name).then(a.visible_from.cmp(&b.visible_from)));
+
+        let origin = match data.origin {
+            ModuleOrigin::CrateRoot { .. } => "crate root",
+            ModuleOrigin::File { is_mod_rs: true, .. } => "file (mod.rs)",
+            ModuleOrigin::File { is_mod_rs: false, .. } => "file",
+            ModuleOrigin::Inline { .. } => "inline",
+            ModuleOrigin::BlockExpr { .. } => "block",
+        };
+
+        acc.push(ModuleDump {
+            path: path.clone(),
+            origin: origin.to_string(),
+            items,
+            imports,
+            textual_macros,
+            impls: data.scope.impls().len(),
+        });
+
+        let mut children: Vec<_> = data.children.iter().collect();
+        children.sort_by_key(|(name, _)| name.to_string());
+        for (name, child) in children {
+            go(db, acc, def_map, format!("{}::{}", path, name), *child);
+        }
+    }
+
+    fn items_of(def_map: &CrateDefMap, name: String, def: PerNs) -> Vec<ItemDump> {
+        let mut res = Vec::new();
+        if let Some((def, vis)) = def.types {
+            res.push(ItemDump {
+                name: name.clone(),
+                namespace: Some(Namespace::Types),
+                kind: def_kind(def).to_string(),
+                visibility: Some(visibility(def_map, vis)),
+            });
+        }
+        if let Some((def, vis)) = def.values {
+            res.push(ItemDump {
+                name: name.clone(),
+                namespace: Some(Namespace::Values),
+                kind: def_kind(def).to_string(),
+                visibility: Some(visibility(def_map, vis)),
+            });
+        }
+        if let Some((def, vis)) = def.macros {
+            res.push(ItemDump {
+                name: name.clone(),
+                namespace: Some(Namespace::Macros),
+                kind: macro_kind(&def).to_string(),
+                visibility: Some(visibility(def_map, vis)),
+            });
+        }
+        if res.is_empty() {
+            res.push(ItemDump {
+                name,
+                namespace: None,
+                kind: "unresolved".to_string(),
+                visibility: None,
+            });
+        }
+        res
+    }
+}
+
+/// Lists the `use` and `extern crate` items written in `module`, in source order.
+fn imports_of(
+    db: &dyn DefDatabase,
+    def_map: &CrateDefMap,
+    module: LocalModuleId,
+) -> Vec<ImportDump> {
+    let (tree, items) = match module_items(db, &def_map[module].origin) {
+        Some(it) => it,
+        None => return Vec::new(),
+    };
+    let cfg_options = &db.crate_graph()[def_map.krate].cfg_options;
+    let mut res = Vec::new();
+    for &item in &items {
+        if !tree.attrs(db, def_map.krate, item.into()).is_cfg_enabled(cfg_options) {
+            continue;
+        }
+        let (path, alias, vis, is_glob, is_extern_crate) = match item {
+            ModItem::Import(it) => {
+                let it = &tree[it];
+                (it.path.clone(), &it.alias, it.visibility, it.is_glob, false)
+            }
+            ModItem::ExternCrate(it) => {
+                let it = &tree[it];
+                let path = ModPath::from_segments(PathKind::Plain, Some(it.name.clone()));
+                (path, &it.alias, it.visibility, false, true)
+            }
+            _ => continue,
+        };
+        let ns = def_map.resolve_import_path(db, module, &path, is_extern_crate);
+        res.push(ImportDump {
+            path: path.to_string(),
+            alias: alias.as_ref().map(|alias| match alias {
+                ImportAlias::Underscore => "_".to_string(),
+                ImportAlias::Alias(name) => name.to_string(),
+            }),
+            visibility: raw_visibility(&tree[vis]),
+            is_glob,
+            is_extern_crate,
+            status: if ns.is_none() { ImportStatus::Unresolved } else { ImportStatus::Resolved },
+            namespaces: namespaces(ns),
+        });
+    }
+    res
+}
+
+/// Returns the item tree a module was lowered from, and the items of the module in it.
+fn module_items(
+    db: &dyn DefDatabase,
+    origin: &ModuleOrigin,
+) -> Option<(Arc<ItemTree>, Vec<ModItem>)> {
+    match *origin {
+        ModuleOrigin::CrateRoot { definition } | ModuleOrigin::File { definition, .. } => {
+            let tree = db.item_tree(definition.into());
+            let items = tree.top_level_items().to_vec();
+            Some((tree, items))
+        }
+        ModuleOrigin::Inline { definition } => {
+            let tree = db.item_tree(definition.file_id);
+            let items = iter::once(tree.top_level_items())
+                .chain(tree.blocks_with_items())
+                .find_map(|items| inline_module_items(&tree, items, definition.value))?
+                .to_vec();
+            Some((tree, items))
+        }
+        ModuleOrigin::BlockExpr { block } => {
+            let tree = db.item_tree(block.file_id);
+            let items = tree.inner_items_of_block(block.value).to_vec();
+            Some((tree, items))
+        }
+    }
+}
+
+fn inline_module_items<'a>(
+    tree: &'a ItemTree,
+    items: &'a [ModItem],
+    module: FileAstId<ast::Module>,
+) -> Option<&'a [ModItem]> {
+    items.iter().find_map(|&item| match item {
+        ModItem::Mod(it) => match &tree[it].kind {
+            ModKind::Inline { items } if tree[it].ast_id == module => Some(&items[..]),
+            ModKind::Inline { items } => inline_module_items(tree, items, module),
+            ModKind::Outline {} => None,
+        },
+        _ => None,
+    })
+}
+
+/// Dumps `tree`, listing items in source order.
+pub fn item_tree(tree: &ItemTree) -> ItemTreeDump {
+    let attrs = attrs(tree, AttrOwner::TopLevel);
+    let items = tree.top_level_items().iter().map(|&item| mod_item(tree, item)).collect();
+    let blocks = tree
+        .blocks_with_items()
+        .map(|items| items.iter().map(|&item| mod_item(tree, item)).collect())
+        .collect();
+    ItemTreeDump { attrs, items, blocks }
+}
+
+fn mod_item(tree: &ItemTree, item: ModItem) -> ItemTreeItemDump {
+    let mut dump = ItemTreeItemDump {
+        kind: String::new(),
+        name: None,
+        visibility: None,
+        attrs: attrs(tree, item.into()),
+        detail: None,
+        children: Vec::new(),
+    };
+    let vis = |id: RawVisibilityId| Some(raw_visibility(&tree[id]));
+    match item {
+        ModItem::Import(it) => {
+            let it = &tree[it];
+            dump.kind = "use".to_string();
+            dump.visibility = vis(it.visibility);
+            let mut detail = it.path.to_string();
+            if it.is_glob {
+                detail.push_str(if it.path.segments.is_empty() { "*" } else { "::*" });
+            }
+            if let Some(alias) = &it.alias {
+                detail.push_str(&alias_suffix(alias));
+            }
+            dump.detail = Some(detail);
+        }
+        ModItem::ExternCrate(it) => {
+            let it = &tree[it];
+            dump.kind = "extern crate".to_string();
+            dump.name = Some(it.name.to_string());
+            dump.visibility = vis(it.visibility);
+            dump.detail = it.alias.as_ref().map(|alias| alias_suffix(alias).trim().to_string());
+        }
+        ModItem::Function(it) => {
+            let it = &tree[it];
+            dump.kind = "fn".to_string();
+            dump.name = Some(it.name.to_string());
+            dump.visibility = vis(it.visibility);
+            let mut qualifiers = Vec::new();
+            if it.is_unsafe {
+                qualifiers.push("unsafe".to_string());
+            }
+            if let Some(abi) = &it.abi {
+                qualifiers.push(format!("extern {:?}", abi.as_str()));
+            }
+            if it.has_self_param {
+                qualifiers.push("self".to_string());
+            }
+            if it.is_varargs {
+                qualifiers.push("varargs".to_string());
+            }
+            if !it.has_body {
+                qualifiers.push("no body".to_string());
+            }
+            if !qualifiers.is_empty() {
+                dump.detail = Some(qualifiers.join(", "));
+            }
+        }
+        ModItem::Struct(it) => {
+            let it = &tree[it];
+            dump.kind = "struct".to_string();
+            dump.name = Some(it.name.to_string());
+            dump.visibility = vis(it.visibility);
+            dump.detail = match it.kind {
+                StructDefKind::Record => None,
+                StructDefKind::Tuple => Some("tuple".to_string()),
+                StructDefKind::Unit => Some("unit".to_string()),
+            };
+            dump.children = fields(tree, &it.fields, None);
+        }
+        ModItem::Union(it) => {
+            let it = &tree[it];
+            dump.kind = "union".to_string();
+            dump.name = Some(it.name.to_string());
+            dump.visibility = vis(it.visibility);
+            dump.children = fields(tree, &it.fields, None);
+        }
+        ModItem::Enum(it) => {
+            let it = &tree[it];
+            dump.kind = "enum".to_string();
+            dump.name = Some(it.name.to_string());
+            dump.visibility = vis(it.visibility);
+            // Like in `EnumData`, fields of variants have the visibility of the enum.
+            let enum_visibility = it.visibility;
+            dump.children = it
+                .variants
+                .clone()
+                .map(|variant| {
+                    let data = &tree[variant];
+                    ItemTreeItemDump {
+                        kind: "variant".to_string(),
+                        name: Some(data.name.to_string()),
+                        visibility: None,
+                        attrs: attrs(tree, variant.into()),
+                        detail: None,
+                        children: fields(tree, &data.fields, Some(enum_visibility)),
+                    }
+                })
+                .collect();
+        }
+        ModItem::Const(it) => {
+            let it = &tree[it];
+            dump.kind = "const".to_string();
+            dump.name = Some(it.name.as_ref().map_or("_".to_string(), |name| name.to_string()));
+            dump.visibility = vis(it.visibility);
+        }
+        ModItem::Static(it) => {
+            let it = &tree[it];
+            dump.kind = "static".to_string();
+            dump.name = Some(it.name.to_string());
+            dump.visibility = vis(it.visibility);
+            let mut qualifiers = Vec::new();
+            if it.mutable {
+                qualifiers.push("mut");
+            }
+            if it.is_extern {
+                qualifiers.push("extern");
+            }
+            if !qualifiers.is_empty() {
+                dump.detail = Some(qualifiers.join(", "));
+            }
+        }
+        ModItem::Trait(it) => {
+            let it = &tree[it];
+            dump.kind = "trait".to_string();
+            dump.name = Some(it.name.to_string());
+            dump.visibility = vis(it.visibility);
+            if it.auto {
+                dump.detail = Some("auto".to_string());
+            }
+            dump.children = assoc_items(tree, &it.items);
+        }
+        ModItem::Impl(it) => {
+            let it = &tree[it];
+            dump.kind = "impl".to_string();
+            if it.is_negative {
+                dump.detail = Some("negative".to_string());
+            }
+            dump.children = assoc_items(tree, &it.items);
+        }
+        ModItem::TypeAlias(it) => {
+            let it = &tree[it];
+            dump.kind = "type".to_string();
+            dump.name = Some(it.name.to_string());
+            dump.visibility = vis(it.visibility);
+        }
+        ModItem::Mod(it) => {
+            let it = &tree[it];
+            dump.kind = "mod".to_string();
+            dump.name = Some(it.name.to_string());
+            dump.visibility = vis(it.visibility);
+            match &it.kind {
+                ModKind::Inline { items } => {
+                    dump.children = items.iter().map(|&item| mod_item(tree, item)).collect();
+                }
+                ModKind::Outline {} => dump.detail = Some("outline".to_string()),
+            }
+        }
+        ModItem::MacroCall(it) => {
+            dump.kind = "macro call".to_string();
+            dump.detail = Some(format!("{}!", tree[it].path));
+        }
+        ModItem::MacroRules(it) => {
+            dump.kind = "macro_rules".to_string();
+            dump.name = Some(tree[it].name.to_string());
+        }
+        ModItem::MacroDef(it) => {
+            let it = &tree[it];
+            dump.kind = "macro".to_string();
+            dump.name = Some(it.name.to_string());
+            dump.visibility = vis(it.visibility);
+        }
+    }
+    dump
+}
+
+fn assoc_items(tree: &ItemTree, items: &[AssocItem]) -> Vec<ItemTreeItemDump> {
+    items.iter().map(|&item| mod_item(tree, item.into())).collect()
+}
+
+fn fields(
+    tree: &ItemTree,
+    fields: &Fields,
+    override_visibility: Option<RawVisibilityId>,
+) -> Vec<ItemTreeItemDump> {
+    let fields = match fields {
+        Fields::Record(fields) | Fields::Tuple(fields) => fields.clone(),
+        Fields::Unit => return Vec::new(),
+    };
+    fields
+        .map(|field| {
+            let data = &tree[field];
+            ItemTreeItemDump {
+                kind: "field".to_string(),
+                name: Some(data.name.to_string()),
+                visibility: Some(raw_visibility(
+                    &tree[override_visibility.unwrap_or(data.visibility)],
+                )),
+                attrs: attrs(tree, field.into()),
+                detail: None,
+                children: Vec::new(),
+            }
+        })
+        .collect()
+}
+
+fn attrs(tree: &ItemTree, owner: AttrOwner) -> Vec<String> {
+    // Attributes of the file itself can only be written as inner attributes.
+    let bang = if owner == AttrOwner::TopLevel { "!" } else { "" };
+    tree.raw_attrs(owner)
+        .iter()
+        // Doc comments are lowered to attributes, but are just noise in a dump.
+        .filter(|attr| attr.path.as_ident().map_or(true, |name| name.to_string() != "doc"))
+        .map(|attr| match &attr.input {
+            None => format!("#{}[{}]", bang, attr.path),
+            Some(AttrInput::Literal(lit)) => {
+                format!("#{}[{} = {:?}]", bang, attr.path, lit.as_str())
+            }
+            Some(AttrInput::TokenTree(tt)) => format!("#{}[{}{}]", bang, attr.path, tt),
+        })
+        .collect()
+}
+
+fn alias_suffix(alias: &ImportAlias) -> String {
+    match alias {
+        ImportAlias::Underscore => " as _".to_string(),
+        ImportAlias::Alias(name) => format!(" as {}", name),
+    }
+}
+
+fn namespaces(ns: PerNs) -> Vec<Namespace> {
+    let mut res = Vec::new();
+    if ns.types.is_some() {
+        res.push(Namespace::Types);
+    }
+    if ns.values.is_some() {
+        res.push(Namespace::Values);
+    }
+    if ns.macros.is_some() {
+        res.push(Namespace::Macros);
+    }
+    res
+}
+
+fn def_kind(def: ModuleDefId) -> &'static str {
+    match def {
+        ModuleDefId::ModuleId(_) => "mod",
+        ModuleDefId::FunctionId(_) => "fn",
+        ModuleDefId::AdtId(AdtId::StructId(_)) => "struct",
+        ModuleDefId::AdtId(AdtId::UnionId(_)) => "union",
+        ModuleDefId::AdtId(AdtId::EnumId(_)) => "enum",
+        ModuleDefId::EnumVariantId(_) => "variant",
+        ModuleDefId::ConstId(_) => "const",
+        ModuleDefId::StaticId(_) => "static",
+        ModuleDefId::TraitId(_) => "trait",
+        ModuleDefId::TypeAliasId(_) => "type",
+        ModuleDefId::BuiltinType(_) => "builtin type",
+    }
+}
+
+fn macro_kind(def: &MacroDefId) -> &'static str {
+    match def.kind {
+        MacroDefKind::Declarative => "macro_rules",
+        MacroDefKind::BuiltIn(_) => "builtin macro",
+        MacroDefKind::BuiltInDerive(_) => "builtin derive",
+        MacroDefKind::BuiltInEager(_) => "builtin eager macro",
+        MacroDefKind::ProcMacro(_, ProcMacroKind::FuncLike) => "proc macro",
+        MacroDefKind::ProcMacro(_, ProcMacroKind::CustomDerive) => "proc macro derive",
+        MacroDefKind::ProcMacro(_, ProcMacroKind::Attr) => "proc macro attribute",
+    }
+}
+
+fn raw_visibility(vis: &RawVisibility) -> String {
+    match vis {
+        RawVisibility::Public => "pub".to_string(),
+        RawVisibility::Module(path) if path.segments.is_empty() => match path.kind {
+            PathKind::Super(0) | PathKind::Plain => "pub(self)".to_string(),
+            PathKind::Super(1) => "pub(super)".to_string(),
+            PathKind::Crate => "pub(crate)".to_string(),
+            _ => format!("pub(in {})", path),
+        },
+        RawVisibility::Module(path) => format!("pub(in {})", path),
+    }
+}
+
+fn visibility(def_map: &CrateDefMap, vis: Visibility) -> String {
+    let module = match vis {
+        Visibility::Public => return "pub".to_string(),
+        Visibility::Module(module) => module,
+    };
+    if module.krate != def_map.krate || module.block != def_map.block_id() {
+        return format!("pub(in {:?})", module);
+    }
+    if module.local_id == def_map.root {
+        return if def_map.block_id().is_some() {
+            "pub(self)".to_string()
+        } else {
+            "pub(crate)".to_string()
+        };
+    }
+    format!("pub(in {})", module_path(def_map, module))
+}
+
+fn module_path(def_map: &CrateDefMap, module: ModuleId) -> String {
+    let mut segments = Vec::new();
+    let mut current = module.local_id;
+    while let Some(parent) = def_map[current].parent {
+        if let Some((name, _)) = def_map[parent].children.iter().find(|(_, id)| **id == current) {
+            segments.push(name.to_string());
+        }
+        current = parent;
+    }
+    segments.push("crate".to_string());
+    segments.reverse();
+    segments.join("::")
+}
+
+impl fmt::Display for DefMapDump {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, module) in self.modules.iter().enumerate() {
+            if i != 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", module)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for ModuleDump {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} ({})", self.path, self.origin)?;
+        if !self.items.is_empty() {
+            writeln!(f, "  items:")?;
+            for item in &self.items {
+                match (item.namespace, &item.visibility) {
+                    (Some(ns), Some(vis)) => {
+                        let ns = match ns {
+                            Namespace::Types => 't',
+                            Namespace::Values => 'v',
+                            Namespace::Macros => 'm',
+                        };
+                        writeln!(f, "    {} {} {} {}", ns, vis, item.kind, item.name)?
+                    }
+                    _ => writeln!(f, "    ? {}", item.name)?,
+                }
+            }
+        }
+        if !self.imports.is_empty() {
+            writeln!(f, "  imports:")?;
+            for import in &self.imports {
+                write!(f, "    {} ", import.visibility)?;
+                if import.is_extern_crate {
+                    write!(f, "extern crate {}", import.path)?;
+                } else {
+                    write!(f, "use {}", import.path)?;
+                    if import.is_glob {
+                        f.write_str("::*")?;
+                    }
+                }
+                if let Some(alias) = &import.alias {
+                    write!(f, " as {}", alias)?;
+                }
+                let status = match import.status {
+                    ImportStatus::Resolved => "resolved",
+                    ImportStatus::Unresolved => "unresolved",
+                };
+                write!(f, "; // {}", status)?;
+                if !import.namespaces.is_empty() {
+                    let ns: Vec<_> = import
+                        .namespaces
+                        .iter()
+                        .map(|ns| match ns {
+                            Namespace::Types => "t",
+                            Namespace::Values => "v",
+                            Namespace::Macros => "m",
+                        })
+                        .collect();
+                    write!(f, ": {}", ns.join(" "))?;
+                }
+                writeln!(f)?;
+            }
+        }
+        if !self.textual_macros.is_empty() {
+            writeln!(f, "  textual macros:")?;
+            for mac in &self.textual_macros {
+                write!(f, "    {} {}", mac.kind, mac.name)?;
+                if let Some(offset) = mac.visible_from {
+                    write!(f, " // visible from offset {}", offset)?;
+                }
+                writeln!(f)?;
+            }
+        }
+        if self.impls != 0 {
+            writeln!(f, "  impls: {}", self.impls)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for ItemTreeDump {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for attr in &self.attrs {
+            writeln!(f, "{}", attr)?;
+        }
+        for item in &self.items {
+            write_item(f, item, 0)?;
+        }
+        for (i, block) in self.blocks.iter().enumerate() {
+            writeln!(f, "block #{}:", i)?;
+            for item in block {
+                write_item(f, item, 1)?;
+            }
+        }
+        return Ok(());
+
+        fn write_item(
+            f: &mut fmt::Formatter<'_>,
+            item: &ItemTreeItemDump,
+            indent: usize,
+        ) -> fmt::Result {
+            let indent_str = "    ".repeat(indent);
+            for attr in &item.attrs {
+                writeln!(f, "{}{}", indent_str, attr)?;
+            }
+            f.write_str(&indent_str)?;
+            if let Some(vis) = &item.visibility {
+                write!(f, "{} ", vis)?;
+            }
+            f.write_str(&item.kind)?;
+            if let Some(name) = &item.name {
+                write!(f, " {}", name)?;
+            }
+            if let Some(detail) = &item.detail {
+                write!(f, " {}", detail)?;
+            }
+            writeln!(f)?;
+            for child in &item.children {
+                write_item(f, child, indent + 1)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use base_db::{fixture::WithFixture, FileId};
+    use expect_test::{expect, Expect};
+
+    use crate::{db::DefDatabase, test_db::TestDB};
+
+    fn check_def_map(ra_fixture: &str, expect: Expect) {
+        let db = TestDB::with_files(ra_fixture);
+        let krate = db.module_for_file(FileId(0)).krate;
+        let dump = super::def_map(&db, &db.crate_def_map(krate));
+        expect.assert_eq(&dump.to_string());
+    }
+
+    fn check_item_tree(ra_fixture: &str, expect: Expect) {
+        let (db, file_id) = TestDB::with_single_file(ra_fixture);
+        let dump = super::item_tree(&db.item_tree(file_id.into()));
+        expect.assert_eq(&dump.to_string());
+    }
+
+    #[test]
+    fn def_map_imports() {
+        check_def_map(
+            r#"
+//- /main.rs crate:main deps:dep
+extern crate dep as renamed;
+extern crate missing;
+mod a;
+mod b {
+    pub use super::a::*;
+    use crate::a::S as _;
+    #[cfg(never)]
+    use crate::disabled;
+}
+use b::S;
+pub(crate) use a::nope;
+
+//- /a.rs
+pub struct S;
+pub fn f() {}
+
+//- /dep.rs crate:dep
+"#,
+            expect![[r#"
+                crate (crate root)
+                  items:
+                    t pub(crate) struct S
+                    v pub(crate) struct S
+                    t pub(crate) mod a
+                    t pub(crate) mod b
+                    ? missing
+                    ? nope
+                    t pub(crate) mod renamed
+                  imports:
+                    pub(self) extern crate dep as renamed; // resolved: t
+                    pub(self) extern crate missing; // unresolved
+                    pub(self) use b::S; // resolved: t v
+                    pub(crate) use a::nope; // unresolved
+
+                crate::a (file)
+                  items:
+                    t pub struct S
+                    v pub struct S
+                    v pub fn f
+
+                crate::b (inline)
+                  items:
+                    t pub struct S
+                    v pub struct S
+                    v pub fn f
+                  imports:
+                    pub use super::a::*; // resolved: t
+                    pub(self) use crate::a::S as _; // resolved: t v
+            "#]],
+        );
+    }
+
+    #[test]
+    fn def_map_textual_macros() {
+        check_def_map(
+            r#"
+//- /main.rs crate:main
+macro_rules! m { () => {} }
+#[macro_use]
+mod inner {
+    macro_rules! m { () => {} }
+    macro_rules! exported { () => {} }
+}
+"#,
+            expect![[r#"
+                crate (crate root)
+                  items:
+                    t pub(crate) mod inner
+                  textual macros:
+                    macro_rules exported // visible from offset 28
+                    macro_rules m // visible from offset 0
+                    macro_rules m // visible from offset 28
+
+                crate::inner (inline)
+                  textual macros:
+                    macro_rules exported // visible from offset 89
+                    macro_rules m
+                    macro_rules m // visible from offset 57
+            "#]],
+        );
+    }
+
+    #[test]
+    fn item_tree_items() {
+        check_item_tree(
+            r#"
+extern crate core as _;
+#[cfg(test)]
+const _: () = ();
+pub static mut S: u8 = 0;
+union U { a: u8, pub(crate) b: u16 }
+unsafe auto trait Send {}
+impl !Send for U {}
+macro_rules! m { () => {} }
+pub macro m2() {}
+mod outline;
+"#,
+            expect![[r##"
+                pub(self) extern crate core as _
+                #[cfg(test)]
+                pub(self) const _
+                pub static S mut
+                pub(self) union U
+                    pub(self) field a
+                    pub(crate) field b
+                pub(self) trait Send auto
+                impl negative
+                macro_rules m
+                pub macro m2
+                pub(self) mod outline outline
+            "##]],
+        );
+    }
+}
@@ -13,8 +13,6 @@ use crate::{
     ModuleDefId, ModuleId,
 };
 
-// FIXME: handle local items
-
 /// Find a path that can be used to refer to a certain item. This can depend on
 /// *from where* you're referring to the item, hence the `from` parameter.
 pub fn find_path(db: &dyn DefDatabase, item: ItemInNs, from: ModuleId) -> Option<ModPath> {
@@ -53,12 +51,7 @@ fn check_self_super(def_map: &CrateDefMap, item: ItemInNs, from: ModuleId) -> Op
         Some(ModPath::from_segments(PathKind::Super(0), Vec::new()))
     } else if let Some(parent_id) = def_map.modules[from.local_id].parent {
         // - if the item is the parent module, use `super` (this is not used recursively, since `super::super` is ugly)
-        if item
-            == ItemInNs::Types(ModuleDefId::ModuleId(ModuleId {
-                krate: from.krate,
-                local_id: parent_id,
-            }))
-        {
+        if item == ItemInNs::Types(ModuleDefId::ModuleId(def_map.module_id(parent_id))) {
             Some(ModPath::from_segments(PathKind::Super(1), Vec::new()))
         } else {
             None
@@ -110,22 +103,16 @@ fn find_path_inner(
     // Base cases:
 
     // - if the item is already in scope, return the name under which it is
-    let def_map = db.crate_def_map(from.krate);
-    let from_scope: &crate::item_scope::ItemScope = &def_map.modules[from.local_id].scope;
-    let scope_name =
-        if let Some((name, _)) = from_scope.name_of(item) { Some(name.clone()) } else { None };
+    let def_map = from.def_map(db);
+    let scope_name = find_in_scope(db, &def_map, from, item);
     if prefixed.is_none() && scope_name.is_some() {
         return scope_name
             .map(|scope_name| ModPath::from_segments(PathKind::Plain, vec![scope_name]));
     }
 
     // - if the item is the crate root, return `crate`
-    if item
-        == ItemInNs::Types(ModuleDefId::ModuleId(ModuleId {
-            krate: from.krate,
-            local_id: def_map.root,
-        }))
-    {
+    let crate_root = def_map.crate_root(db);
+    if item == ItemInNs::Types(ModuleDefId::ModuleId(crate_root)) {
         return Some(ModPath::from_segments(PathKind::Crate, Vec::new()));
     }
 
@@ -136,7 +123,9 @@ fn find_path_inner(
     }
 
     // - if the item is the crate root of a dependency crate, return the name from the extern prelude
-    for (name, def_id) in &def_map.extern_prelude {
+    // (block def maps don't have their own extern prelude or prelude)
+    let crate_def_map = db.crate_def_map(from.krate);
+    for (name, def_id) in &crate_def_map.extern_prelude {
         if item == ItemInNs::Types(*def_id) {
             let name = scope_name.unwrap_or_else(|| name.clone());
             return Some(ModPath::from_segments(PathKind::Plain, vec![name]));
@@ -144,7 +133,7 @@ fn find_path_inner(
     }
 
     // - if the item is in the prelude, return the name from there
-    if let Some(prelude_module) = def_map.prelude {
+    if let Some(prelude_module) = crate_def_map.prelude {
         let prelude_def_map = db.crate_def_map(prelude_module.krate);
         let prelude_scope: &crate::item_scope::ItemScope =
             &prelude_def_map.modules[prelude_module.local_id].scope;
@@ -175,7 +164,6 @@ fn find_path_inner(
 
     // - otherwise, look for modules containing (reexporting) it and import it from one of those
 
-    let crate_root = ModuleId { local_id: def_map.root, krate: from.krate };
     let crate_attrs = db.attrs(crate_root.into());
     let prefer_no_std = crate_attrs.by_key("no_std").exists();
     let mut best_path = None;
@@ -247,6 +235,27 @@ fn find_path_inner(
     }
 }
 
+/// Returns the name under which `item` is in scope in `from`, also looking
+/// at the items of the block expressions `from` is nested in.
+fn find_in_scope(
+    db: &dyn DefDatabase,
+    def_map: &CrateDefMap,
+    from: ModuleId,
+    item: ItemInNs,
+) -> Option<Name> {
+    if let Some((name, _)) = def_map[from.local_id].scope.name_of(item) {
+        return Some(name.clone());
+    }
+    // Only the root module of a block def map sees the names of the
+    // surrounding module.
+    match def_map.parent() {
+        Some(parent) if from.local_id == def_map.root => {
+            find_in_scope(db, &parent.def_map(db), parent, item)
+        }
+        _ => None,
+    }
+}
+
 fn select_best_path(old_path: ModPath, new_path: ModPath, prefer_no_std: bool) -> ModPath {
     if old_path.starts_with_std() && new_path.can_start_with_std() {
         if prefer_no_std {
@@ -283,20 +292,18 @@ fn find_local_import_locations(
     // above `from` with any visibility. That means we do not need to descend into private siblings
     // of `from` (and similar).
 
-    let def_map = db.crate_def_map(from.krate);
+    let def_map = from.def_map(db);
 
     // Compute the initial worklist. We start with all direct child modules of `from` as well as all
-    // of its (recursive) parent modules.
+    // of its (recursive) parent modules, including the ones containing the block expressions
+    // `from` is in.
     let data = &def_map.modules[from.local_id];
-    let mut worklist = data
-        .children
-        .values()
-        .map(|child| ModuleId { krate: from.krate, local_id: *child })
-        .collect::<Vec<_>>();
-    let mut parent = data.parent;
+    let mut worklist =
+        data.children.values().map(|child| def_map.module_id(*child)).collect::<Vec<_>>();
+    let mut parent = from.containing_module(db);
     while let Some(p) = parent {
-        worklist.push(ModuleId { krate: from.krate, local_id: p });
-        parent = def_map.modules[p].parent;
+        worklist.push(p);
+        parent = p.containing_module(db);
     }
 
     let mut seen: FxHashSet<_> = FxHashSet::default();
@@ -308,18 +315,19 @@ fn find_local_import_locations(
         }
 
         let ext_def_map;
-        let data = if module.krate == from.krate {
+        let data = if module.krate == from.krate && module.block == from.block {
             &def_map[module.local_id]
         } else {
-            // The crate might reexport a module defined in another crate.
-            ext_def_map = db.crate_def_map(module.krate);
+            // The crate might reexport a module defined in another crate, or the module might be
+            // in a different block.
+            ext_def_map = module.def_map(db);
             &ext_def_map[module.local_id]
         };
 
         if let Some((name, vis)) = data.scope.name_of(item) {
             if vis.is_visible_from(db, from) {
                 let is_private = if let Visibility::Module(private_to) = vis {
-                    private_to.local_id == module.local_id
+                    private_to == module
                 } else {
                     false
                 };
@@ -3,11 +3,12 @@
 
 use std::collections::hash_map::Entry;
 
-use base_db::CrateId;
+use base_db::{CrateId, FilePosition};
 use hir_expand::name::Name;
 use hir_expand::MacroDefKind;
 use once_cell::sync::Lazy;
 use rustc_hash::{FxHashMap, FxHashSet};
+use smallvec::SmallVec;
 use test_utils::mark;
 
 use crate::ModuleId;
@@ -50,9 +51,13 @@ pub struct ItemScope {
     /// Note that this automatically inherit macros defined textually before the definition of module itself.
     ///
     /// Module scoped macros will be inserted into `items` instead of here.
-    // FIXME: Macro shadowing in one module is not properly handled. Non-item place macros will
-    // be all resolved to the last one defined if shadowing happens.
-    legacy_macros: FxHashMap<Name, MacroDefId>,
+    ///
+    /// A name can be defined several times, each definition shadowing the previous ones from its
+    /// position on. Each definition is kept together with the position in the original source file
+    /// it becomes visible at, the start of the `macro_rules!` itself or of a `#[macro_use]` module
+    /// (`None` if visible in the whole module). Definitions are stored in the order they are
+    /// collected in.
+    legacy_macros: FxHashMap<Name, SmallVec<[(MacroDefId, Option<FilePosition>); 1]>>,
 }
 
 pub(crate) static BUILTIN_SCOPE: Lazy<FxHashMap<Name, PerNs>> = Lazy::new(|| {
@@ -114,7 +119,25 @@ impl ItemScope {
 
     /// Iterate over all legacy textual scoped macros visible at the end of the module
     pub(crate) fn legacy_macros<'a>(&'a self) -> impl Iterator<Item = (&'a Name, MacroDefId)> + 'a {
-        self.legacy_macros.iter().map(|(name, def)| (name, *def))
+        self.legacy_macros.iter().filter_map(|(name, defs)| Some((name, defs.last()?.0)))
+    }
+
+    /// Iterate over all definitions of legacy textual scoped macros, with the positions they become
+    /// visible at
+    pub(crate) fn legacy_macro_definitions<'a>(
+        &'a self,
+    ) -> impl Iterator<Item = (&'a Name, &'a [(MacroDefId, Option<FilePosition>)])> + 'a {
+        self.legacy_macros.iter().map(|(name, defs)| (name, &defs[..]))
+    }
+
+    /// Iterate over all legacy textual scoped macros visible at `position`
+    pub(crate) fn legacy_macros_at<'a>(
+        &'a self,
+        position: FilePosition,
+    ) -> impl Iterator<Item = (&'a Name, MacroDefId)> + 'a {
+        self.legacy_macros
+            .keys()
+            .filter_map(move |name| Some((name, self.get_legacy_macro_at(name, position)?)))
     }
 
     /// Get a name from current module scope, legacy macros are not included
@@ -150,15 +173,51 @@ impl ItemScope {
     }
 
     pub(crate) fn get_legacy_macro(&self, name: &Name) -> Option<MacroDefId> {
-        self.legacy_macros.get(name).copied()
+        self.legacy_macros.get(name)?.last().map(|(def, _)| *def)
+    }
+
+    /// Get the legacy macro `name` as seen from `position`, that is, the last definition
+    /// preceding it.
+    pub(crate) fn get_legacy_macro_at(
+        &self,
+        name: &Name,
+        position: FilePosition,
+    ) -> Option<MacroDefId> {
+        let mut res: Option<(MacroDefId, Option<FilePosition>)> = None;
+        for &(def, visible_from) in self.legacy_macros.get(name)?.iter() {
+            if let Some(from) = visible_from.filter(|it| it.file_id == position.file_id) {
+                if from.offset > position.offset {
+                    continue;
+                }
+                // Macro expansions are collected after the items around them, so definitions in
+                // the same file aren't necessarily stored in textual order.
+                if let Some((_, Some(prev))) = res {
+                    if prev.file_id == from.file_id && prev.offset > from.offset {
+                        continue;
+                    }
+                }
+            }
+            // Positions in different files can't be compared, the definition collected last wins.
+            res = Some((def, visible_from));
+        }
+        res.map(|(def, _)| def)
     }
 
     pub(crate) fn define_impl(&mut self, imp: ImplId) {
         self.impls.push(imp)
     }
 
-    pub(crate) fn define_legacy_macro(&mut self, name: Name, mac: MacroDefId) {
-        self.legacy_macros.insert(name, mac);
+    pub(crate) fn define_legacy_macro(
+        &mut self,
+        name: Name,
+        mac: MacroDefId,
+        visible_from: Option<FilePosition>,
+    ) {
+        let defs = self.legacy_macros.entry(name).or_default();
+        // The prelude is imported again whenever items of the module are collected.
+        if !defs.contains(&(mac, visible_from)) {
+            defs.push((mac, visible_from));
+        }
     }
 
     pub(crate) fn unnamed_trait_vis(&self, tr: TraitId) -> Option<Visibility> {
@@ -169,37 +228,6 @@ impl ItemScope {
         self.unnamed_trait_imports.insert(tr, vis);
     }
 
-    pub(crate) fn push_res(&mut self, name: Name, def: PerNs) -> bool {
-        let mut changed = false;
-
-        if let Some(types) = def.types {
-            self.types.entry(name.clone()).or_insert_with(|| {
-                changed = true;
-                types
-            });
-        }
-        if let Some(values) = def.values {
-            self.values.entry(name.clone()).or_insert_with(|| {
-                changed = true;
-                values
-            });
-        }
-        if let Some(macros) = def.macros {
-            self.macros.entry(name.clone()).or_insert_with(|| {
-                changed = true;
-                macros
-            });
-        }
-
-        if def.is_none() {
-            if self.unresolved.insert(name) {
-                changed = true;
-            }
-        }
-
-        changed
-    }
-
     pub(crate) fn push_res_with_import(
         &mut self,
         glob_imports: &mut PerNsGlobImports,
@@ -270,8 +298,9 @@ impl ItemScope {
         )
     }
 
+    /// Returns the legacy macros visible at the end of the module.
     pub(crate) fn collect_legacy_macros(&self) -> FxHashMap<Name, MacroDefId> {
-        self.legacy_macros.clone()
+        self.legacy_macros().map(|(name, def)| (name.clone(), def)).collect()
     }
 
     /// Marks everything that is not a procedural macro as private to `this_module`.
@@ -284,7 +313,7 @@ impl ItemScope {
             .for_each(|vis| *vis = Visibility::Module(this_module));
 
         for (mac, vis) in self.macros.values_mut() {
-            if let MacroDefKind::ProcMacro(_) = mac.kind {
+            if let MacroDefKind::ProcMacro(..) = mac.kind {
                 // FIXME: Technically this is insufficient since reexports of proc macros are also
                 // forbidden. Practically nobody does that.
                 continue;
@@ -356,10 +385,10 @@ impl ItemInNs {
                 ModuleDefId::ModuleId(id) => id.krate,
                 ModuleDefId::FunctionId(id) => id.lookup(db).module(db).krate,
                 ModuleDefId::AdtId(id) => id.module(db).krate,
-                ModuleDefId::EnumVariantId(id) => id.parent.lookup(db).container.module(db).krate,
+                ModuleDefId::EnumVariantId(id) => id.parent.lookup(db).container.krate,
                 ModuleDefId::ConstId(id) => id.lookup(db).container.module(db).krate,
-                ModuleDefId::StaticId(id) => id.lookup(db).container.module(db).krate,
-                ModuleDefId::TraitId(id) => id.lookup(db).container.module(db).krate,
+                ModuleDefId::StaticId(id) => id.lookup(db).container.krate,
+                ModuleDefId::TraitId(id) => id.lookup(db).container.krate,
                 ModuleDefId::TypeAliasId(id) => id.lookup(db).module(db).krate,
                 ModuleDefId::BuiltinType(_) => return None,
             },
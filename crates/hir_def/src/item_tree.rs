@@ -23,7 +23,7 @@ use hir_expand::{
 };
 use rustc_hash::FxHashMap;
 use smallvec::SmallVec;
-use syntax::{ast, match_ast};
+use syntax::{ast, match_ast, SmolStr};
 use test_utils::mark;
 
 use crate::{
@@ -69,7 +69,7 @@ impl GenericParamsId {
 pub struct ItemTree {
     top_level: SmallVec<[ModItem; 1]>,
     attrs: FxHashMap<AttrOwner, RawAttrs>,
-    inner_items: FxHashMap<FileAstId<ast::Item>, SmallVec<[ModItem; 1]>>,
+    inner_items: FxHashMap<FileAstId<ast::BlockExpr>, SmallVec<[ModItem; 1]>>,
 
     data: Option<Box<ItemTreeData>>,
 }
@@ -96,7 +96,7 @@ impl ItemTree {
                     ctx.lower_module_items(&items)
                 },
                 ast::MacroStmts(stmts) => {
-                    ctx.lower_inner_items(stmts.syntax())
+                    ctx.lower_macro_stmts(stmts)
                 },
                 // Macros can expand to expressions. We return an empty item tree in this case, but
                 // still need to collect inner items.
@@ -193,18 +193,27 @@ impl ItemTree {
         self.raw_attrs(of).clone().filter(db, krate)
     }
 
-    /// Returns the lowered inner items that `ast` corresponds to.
+    /// Returns the items declared directly inside `block`, in source order.
     ///
-    /// Most AST items are lowered to a single `ModItem`, but some (eg. `use` items) may be lowered
-    /// to multiple items in the `ItemTree`.
-    pub fn inner_items(&self, ast: FileAstId<ast::Item>) -> &[ModItem] {
-        &self.inner_items[&ast]
+    /// Macro calls in statement position are included, since they may expand to items.
+    pub fn inner_items_of_block(&self, block: FileAstId<ast::BlockExpr>) -> &[ModItem] {
+        match self.inner_items.get(&block) {
+            Some(items) => items,
+            None => &[],
+        }
     }
 
     pub fn all_inner_items(&self) -> impl Iterator<Item = ModItem> + '_ {
         self.inner_items.values().flatten().copied()
     }
 
+    /// Returns the items of every block expression containing items, in source order.
+    pub fn blocks_with_items(&self) -> impl Iterator<Item = &[ModItem]> + '_ {
+        let mut blocks: Vec<_> = self.inner_items.iter().collect();
+        blocks.sort_by_key(|(block, _)| **block);
+        blocks.into_iter().map(|(_, items)| &items[..])
+    }
+
     pub fn source<S: ItemTreeNode>(&self, db: &dyn DefDatabase, of: ItemTreeId<S>) -> S::Source {
         // This unwrap cannot fail, since it has either succeeded above, or resulted in an empty
         // ItemTree (in which case there is no valid `FileItemTreeId` to call this method with).
@@ -529,6 +538,9 @@ pub struct Function {
     /// Whether the function is located in an `extern` block (*not* whether it is an
     /// `extern "abi" fn`).
     pub is_extern: bool,
+    /// The ABI of the function, either from its own `extern "abi"` qualifier or from the
+    /// `extern` block containing it. `None` for plain Rust functions.
+    pub abi: Option<SmolStr>,
     pub params: Box<[TypeRef]>,
     pub is_varargs: bool,
     pub ret_type: TypeRef,
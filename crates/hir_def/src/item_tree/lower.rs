@@ -6,7 +6,7 @@ use hir_expand::{ast_id_map::AstIdMap, hygiene::Hygiene, name::known, HirFileId}
 use smallvec::SmallVec;
 use syntax::{
     ast::{self, ModuleItemOwner},
-    SyntaxNode,
+    AstToken, SyntaxKind, SyntaxNode,
 };
 
 use crate::{
@@ -20,6 +20,20 @@ fn id<N: ItemTreeNode>(index: Idx<N>) -> FileItemTreeId<N> {
     FileItemTreeId { index, _p: PhantomData }
 }
 
+/// Returns the block expression that declares `item` as one of its statements.
+///
+/// Macro calls are only treated as items in statement position. Everywhere else they are
+/// expressions (or patterns, or types) and cannot add items to the block.
+fn containing_block(item: &ast::Item) -> Option<ast::BlockExpr> {
+    let stmt = match item {
+        ast::Item::MacroCall(call) => {
+            call.syntax().parent().filter(|it| it.kind() == SyntaxKind::EXPR_STMT)?
+        }
+        _ => item.syntax().clone(),
+    };
+    stmt.parent().and_then(ast::BlockExpr::cast)
+}
+
 struct ModItems(SmallVec<[ModItem; 1]>);
 
 impl<T> From<T> for ModItems
@@ -63,6 +77,37 @@ impl Ctx {
         self.tree
     }
 
+    pub(super) fn lower_macro_stmts(mut self, stmts: ast::MacroStmts) -> ItemTree {
+        self.tree.top_level = stmts
+            .statements()
+            .filter_map(|stmt| match stmt {
+                ast::Stmt::Item(item) => Some(item),
+                // Macro calls in statement position may expand to items, so treat them as such.
+                ast::Stmt::ExprStmt(stmt) => match stmt.expr()? {
+                    ast::Expr::MacroCall(call) => Some(call.into()),
+                    _ => None,
+                },
+                ast::Stmt::LetStmt(_) => None,
+            })
+            .flat_map(|item| self.lower_mod_item(&item, false))
+            .flat_map(|items| items.0)
+            .collect();
+
+        // Items in nested blocks are only visible inside those blocks.
+        for stmt in stmts.statements() {
+            match stmt {
+                ast::Stmt::ExprStmt(_) | ast::Stmt::LetStmt(_) => {
+                    self.collect_inner_items(stmt.syntax())
+                }
+                ast::Stmt::Item(_) => {}
+            }
+        }
+        if let Some(expr) = stmts.expr() {
+            self.collect_inner_items(expr.syntax());
+        }
+        self.tree
+    }
+
     pub(super) fn lower_inner_items(mut self, within: &SyntaxNode) -> ItemTree {
         self.collect_inner_items(within);
         self.tree
@@ -151,12 +196,15 @@ impl Ctx {
     fn collect_inner_items(&mut self, container: &SyntaxNode) {
         let forced_vis = self.forced_visibility.take();
         let mut inner_items = mem::take(&mut self.tree.inner_items);
-        inner_items.extend(container.descendants().skip(1).filter_map(ast::Item::cast).filter_map(
-            |item| {
-                let ast_id = self.source_ast_id_map.ast_id(&item);
-                Some((ast_id, self.lower_mod_item(&item, true)?.0))
-            },
-        ));
+        for item in container.descendants().skip(1).filter_map(ast::Item::cast) {
+            let block = match containing_block(&item) {
+                Some(block) => self.source_ast_id_map.ast_id(&block),
+                None => continue,
+            };
+            if let Some(items) = self.lower_mod_item(&item, true) {
+                inner_items.entry(block).or_default().extend(items.0);
+            }
+        }
         self.tree.inner_items = inner_items;
         self.forced_visibility = forced_vis;
     }
@@ -350,6 +398,7 @@ impl Ctx {
             has_body,
             is_unsafe: func.unsafe_token().is_some(),
             is_extern: false,
+            abi: func.abi().map(lower_abi),
             params: params.into_boxed_slice(),
             is_varargs,
             ret_type,
@@ -550,6 +599,7 @@ impl Ctx {
     }
 
     fn lower_extern_block(&mut self, block: &ast::ExternBlock) -> Vec<ModItem> {
+        let abi = block.abi().map(lower_abi);
         block.extern_item_list().map_or(Vec::new(), |list| {
             list.extern_items()
                 .filter_map(|item| {
@@ -559,8 +609,12 @@ impl Ctx {
                         ast::ExternItem::Fn(ast) => {
                             let func_id = self.lower_function(&ast)?;
                             let func = &mut self.data().functions[func_id.index];
-                            func.is_unsafe = is_intrinsic_fn_unsafe(&func.name);
+                            // Everything declared in an `extern` block is unsafe to call, except
+                            // for a few compiler intrinsics.
+                            func.is_unsafe = abi.as_deref() != Some("rust-intrinsic")
+                                || is_intrinsic_fn_unsafe(&func.name);
                             func.is_extern = true;
+                            func.abi = abi.clone();
                             func_id.into()
                         }
                         ast::ExternItem::Static(ast) => {
@@ -719,6 +773,19 @@ enum GenericsOwner<'a> {
     Impl,
 }
 
+/// Returns the ABI string of an `extern "abi"` qualifier. A bare `extern` means `"C"`.
+fn lower_abi(abi: ast::Abi) -> SmolStr {
+    let literal = abi
+        .syntax()
+        .children_with_tokens()
+        .filter_map(|it| it.into_token())
+        .find_map(ast::String::cast);
+    match literal.as_ref().and_then(|it| it.value()) {
+        Some(value) => SmolStr::new(value),
+        None => SmolStr::new_inline("C"),
+    }
+}
+
 /// Returns `true` if the given intrinsic is unsafe to call, or false otherwise.
 fn is_intrinsic_fn_unsafe(name: &Name) -> bool {
     // Should be kept in sync with https://github.com/rust-lang/rust/blob/c6e4db620a7d2f569f11dcab627430921ea8aacf/compiler/rustc_typeck/src/check/intrinsic.rs#L68
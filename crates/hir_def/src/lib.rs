@@ -46,11 +46,15 @@ pub mod child_by_source;
 pub mod visibility;
 pub mod find_path;
 pub mod import_map;
+pub mod dump;
 
 #[cfg(test)]
 mod test_db;
 
-use std::hash::{Hash, Hasher};
+use std::{
+    hash::{Hash, Hasher},
+    sync::Arc,
+};
 
 use arena::Idx;
 use base_db::{impl_intern_key, salsa, CrateId};
@@ -58,9 +62,9 @@ use hir_expand::{
     ast_id_map::FileAstId, eager::expand_eager_macro, hygiene::Hygiene, AstId, HirFileId, InFile,
     MacroCallId, MacroCallKind, MacroDefId, MacroDefKind,
 };
-use syntax::ast;
+use syntax::{ast, SmolStr};
 
-use crate::builtin_type::BuiltinType;
+use crate::{builtin_type::BuiltinType, nameres::CrateDefMap};
 use item_tree::{
     Const, Enum, Function, Impl, ItemTreeId, ItemTreeNode, ModItem, Static, Struct, Trait,
     TypeAlias, Union,
@@ -70,15 +74,40 @@ use stdx::impl_from;
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct ModuleId {
     pub krate: CrateId,
+    /// If this `ModuleId` was derived from the `CrateDefMap` of a block expression, this stores
+    /// the `BlockId` of that block expression. If this is `None`, `local_id` refers to a module in
+    /// the crate-level `CrateDefMap`.
+    pub block: Option<BlockId>,
     pub local_id: LocalModuleId,
 }
 
+impl ModuleId {
+    /// Returns the `CrateDefMap` this module is defined in.
+    pub fn def_map(&self, db: &dyn db::DefDatabase) -> Arc<CrateDefMap> {
+        match self.block {
+            Some(block) => db.block_def_map(block).unwrap_or_else(|| {
+                // All `ModuleId`s are created from their `CrateDefMap`, so the block must have
+                // one.
+                panic!("no `block_def_map` for `ModuleId` {:?}", self);
+            }),
+            None => db.crate_def_map(self.krate),
+        }
+    }
+
+    /// Returns the module containing `self`, leaving block expressions if necessary.
+    ///
+    /// Returns `None` for the crate root.
+    pub fn containing_module(&self, db: &dyn db::DefDatabase) -> Option<ModuleId> {
+        self.def_map(db).containing_module(self.local_id)
+    }
+}
+
 /// An ID of a module, **local** to a specific crate
 pub type LocalModuleId = Idx<nameres::ModuleData>;
 
 #[derive(Debug)]
 pub struct ItemLoc<N: ItemTreeNode> {
-    pub container: ContainerId,
+    pub container: ModuleId,
     pub id: ItemTreeId<N>,
 }
 
@@ -216,6 +245,16 @@ pub struct ImplId(salsa::InternId);
 type ImplLoc = ItemLoc<Impl>;
 impl_intern!(ImplId, ImplLoc, intern_impl, lookup_intern_impl);
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BlockId(salsa::InternId);
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct BlockLoc {
+    pub ast_id: AstId<ast::BlockExpr>,
+    /// The module containing the block expression.
+    pub module: ModuleId,
+}
+impl_intern!(BlockId, BlockLoc, intern_block, lookup_intern_block);
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct TypeParamId {
     pub parent: GenericDefId,
@@ -238,19 +277,13 @@ pub struct ConstParamId {
 }
 pub type LocalConstParamId = Idx<generics::ConstParamData>;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-pub enum ContainerId {
-    ModuleId(ModuleId),
-    DefWithBodyId(DefWithBodyId),
-}
-
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum AssocContainerId {
-    ContainerId(ContainerId),
+    ModuleId(ModuleId),
     ImplId(ImplId),
     TraitId(TraitId),
 }
-impl_from!(ContainerId for AssocContainerId);
+impl_from!(ModuleId for AssocContainerId);
 
 /// A Data Type
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
@@ -396,21 +429,12 @@ pub trait HasModule {
     fn module(&self, db: &dyn db::DefDatabase) -> ModuleId;
 }
 
-impl HasModule for ContainerId {
-    fn module(&self, db: &dyn db::DefDatabase) -> ModuleId {
-        match *self {
-            ContainerId::ModuleId(it) => it,
-            ContainerId::DefWithBodyId(it) => it.module(db),
-        }
-    }
-}
-
 impl HasModule for AssocContainerId {
     fn module(&self, db: &dyn db::DefDatabase) -> ModuleId {
         match *self {
-            AssocContainerId::ContainerId(it) => it.module(db),
-            AssocContainerId::ImplId(it) => it.lookup(db).container.module(db),
-            AssocContainerId::TraitId(it) => it.lookup(db).container.module(db),
+            AssocContainerId::ModuleId(it) => it,
+            AssocContainerId::ImplId(it) => it.lookup(db).container,
+            AssocContainerId::TraitId(it) => it.lookup(db).container,
         }
     }
 }
@@ -428,16 +452,15 @@ impl HasModule for AdtId {
             AdtId::UnionId(it) => it.lookup(db).container,
             AdtId::EnumId(it) => it.lookup(db).container,
         }
-        .module(db)
     }
 }
 
 impl HasModule for VariantId {
     fn module(&self, db: &dyn db::DefDatabase) -> ModuleId {
         match self {
-            VariantId::EnumVariantId(it) => it.parent.lookup(db).container.module(db),
-            VariantId::StructId(it) => it.lookup(db).container.module(db),
-            VariantId::UnionId(it) => it.lookup(db).container.module(db),
+            VariantId::EnumVariantId(it) => it.parent.lookup(db).container,
+            VariantId::StructId(it) => it.lookup(db).container,
+            VariantId::UnionId(it) => it.lookup(db).container,
         }
     }
 }
@@ -467,18 +490,18 @@ impl HasModule for GenericDefId {
         match self {
             GenericDefId::FunctionId(it) => it.lookup(db).module(db),
             GenericDefId::AdtId(it) => it.module(db),
-            GenericDefId::TraitId(it) => it.lookup(db).container.module(db),
+            GenericDefId::TraitId(it) => it.lookup(db).container,
             GenericDefId::TypeAliasId(it) => it.lookup(db).module(db),
-            GenericDefId::ImplId(it) => it.lookup(db).container.module(db),
-            GenericDefId::EnumVariantId(it) => it.parent.lookup(db).container.module(db),
+            GenericDefId::ImplId(it) => it.lookup(db).container,
+            GenericDefId::EnumVariantId(it) => it.parent.lookup(db).container,
             GenericDefId::ConstId(it) => it.lookup(db).module(db),
         }
     }
 }
 
 impl HasModule for StaticLoc {
-    fn module(&self, db: &dyn db::DefDatabase) -> ModuleId {
-        self.container.module(db)
+    fn module(&self, _db: &dyn db::DefDatabase) -> ModuleId {
+        self.container
     }
 }
 
@@ -489,12 +512,12 @@ impl AttrDefId {
             AttrDefId::FieldId(it) => it.parent.module(db).krate,
             AttrDefId::AdtId(it) => it.module(db).krate,
             AttrDefId::FunctionId(it) => it.lookup(db).module(db).krate,
-            AttrDefId::EnumVariantId(it) => it.parent.lookup(db).container.module(db).krate,
+            AttrDefId::EnumVariantId(it) => it.parent.lookup(db).container.krate,
             AttrDefId::StaticId(it) => it.lookup(db).module(db).krate,
             AttrDefId::ConstId(it) => it.lookup(db).module(db).krate,
-            AttrDefId::TraitId(it) => it.lookup(db).container.module(db).krate,
+            AttrDefId::TraitId(it) => it.lookup(db).container.krate,
             AttrDefId::TypeAliasId(it) => it.lookup(db).module(db).krate,
-            AttrDefId::ImplId(it) => it.lookup(db).container.module(db).krate,
+            AttrDefId::ImplId(it) => it.lookup(db).container.krate,
             // FIXME: `MacroDefId` should store the defining module, then this can implement
             // `HasModule`
             AttrDefId::MacroDefId(it) => it.krate,
@@ -502,24 +525,33 @@ impl AttrDefId {
     }
 }
 
+/// Returned by [`AsMacroCall::as_call_id_with_errors`] when the path of a macro call does not
+/// resolve to any macro.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnresolvedMacro {
+    pub path: path::ModPath,
+}
+
 /// A helper trait for converting to MacroCallId
 pub trait AsMacroCall {
     fn as_call_id(
         &self,
         db: &dyn db::DefDatabase,
         krate: CrateId,
+        module_path: &dyn Fn() -> SmolStr,
         resolver: impl Fn(path::ModPath) -> Option<MacroDefId>,
     ) -> Option<MacroCallId> {
-        self.as_call_id_with_errors(db, krate, resolver, &mut |_| ())
+        self.as_call_id_with_errors(db, krate, module_path, resolver, &mut |_| ()).ok().flatten()
     }
 
     fn as_call_id_with_errors(
         &self,
         db: &dyn db::DefDatabase,
         krate: CrateId,
+        module_path: &dyn Fn() -> SmolStr,
         resolver: impl Fn(path::ModPath) -> Option<MacroDefId>,
         error_sink: &mut dyn FnMut(mbe::ExpandError),
-    ) -> Option<MacroCallId>;
+    ) -> Result<Option<MacroCallId>, UnresolvedMacro>;
 }
 
 impl AsMacroCall for InFile<&ast::MacroCall> {
@@ -527,31 +559,41 @@ impl AsMacroCall for InFile<&ast::MacroCall> {
         &self,
         db: &dyn db::DefDatabase,
         krate: CrateId,
+        module_path: &dyn Fn() -> SmolStr,
         resolver: impl Fn(path::ModPath) -> Option<MacroDefId>,
         error_sink: &mut dyn FnMut(mbe::ExpandError),
-    ) -> Option<MacroCallId> {
+    ) -> Result<Option<MacroCallId>, UnresolvedMacro> {
         let ast_id = AstId::new(self.file_id, db.ast_id_map(self.file_id).ast_id(self.value));
         let h = Hygiene::new(db.upcast(), self.file_id);
         let path = self.value.path().and_then(|path| path::ModPath::from_src(path, &h));
 
-        if path.is_none() {
-            error_sink(mbe::ExpandError::Other("malformed macro invocation".into()));
-        }
-
-        AstIdWithPath::new(ast_id.file_id, ast_id.value, path?)
-            .as_call_id_with_errors(db, krate, resolver, error_sink)
+        let path = match path {
+            Some(it) => it,
+            None => {
+                error_sink(mbe::ExpandError::Other("malformed macro invocation".into()));
+                return Ok(None);
+            }
+        };
+
+        AstIdWithPath::new(ast_id.file_id, ast_id.value, path).as_call_id_with_errors(
+            db,
+            krate,
+            module_path,
+            resolver,
+            error_sink,
+        )
     }
 }
 
 /// Helper wrapper for `AstId` with `ModPath`
 #[derive(Clone, Debug, Eq, PartialEq)]
-struct AstIdWithPath<T: ast::AstNode> {
+pub struct AstIdWithPath<T: ast::AstNode> {
     ast_id: AstId<T>,
     path: path::ModPath,
 }
 
 impl<T: ast::AstNode> AstIdWithPath<T> {
-    fn new(file_id: HirFileId, ast_id: FileAstId<T>, path: path::ModPath) -> AstIdWithPath<T> {
+    pub fn new(file_id: HirFileId, ast_id: FileAstId<T>, path: path::ModPath) -> AstIdWithPath<T> {
         AstIdWithPath { ast_id: AstId::new(file_id, ast_id), path }
     }
 }
@@ -561,32 +603,46 @@ impl AsMacroCall for AstIdWithPath<ast::MacroCall> {
         &self,
         db: &dyn db::DefDatabase,
         krate: CrateId,
+        module_path: &dyn Fn() -> SmolStr,
         resolver: impl Fn(path::ModPath) -> Option<MacroDefId>,
         error_sink: &mut dyn FnMut(mbe::ExpandError),
-    ) -> Option<MacroCallId> {
-        let def: MacroDefId = resolver(self.path.clone()).or_else(|| {
-            error_sink(mbe::ExpandError::Other(format!("could not resolve macro `{}`", self.path)));
-            None
-        })?;
+    ) -> Result<Option<MacroCallId>, UnresolvedMacro> {
+        let def: MacroDefId = resolver(self.path.clone())
+            .ok_or_else(|| UnresolvedMacro { path: self.path.clone() })?;
+
+        if !def.kind.is_fn_like() {
+            error_sink(mbe::ExpandError::Other(format!(
+                "expected function-like macro, found `{}`",
+                self.path
+            )));
+            return Ok(None);
+        }
 
         if let MacroDefKind::BuiltInEager(_) = def.kind {
             let macro_call = InFile::new(self.ast_id.file_id, self.ast_id.to_node(db.upcast()));
             let hygiene = Hygiene::new(db.upcast(), self.ast_id.file_id);
 
-            Some(
-                expand_eager_macro(
+            Ok(expand_eager_macro(
+                db.upcast(),
+                krate,
+                module_path,
+                macro_call,
+                def,
+                &|path: ast::Path| resolver(path::ModPath::from_src(path, &hygiene)?),
+                error_sink,
+            )
+            .ok()
+            .map(Into::into))
+        } else {
+            Ok(Some(
+                def.as_lazy_macro(
                     db.upcast(),
                     krate,
-                    macro_call,
-                    def,
-                    &|path: ast::Path| resolver(path::ModPath::from_src(path, &hygiene)?),
-                    error_sink,
+                    module_path,
+                    MacroCallKind::FnLike(self.ast_id),
                 )
-                .ok()?
                 .into(),
-            )
-        } else {
-            Some(def.as_lazy_macro(db.upcast(), krate, MacroCallKind::FnLike(self.ast_id)).into())
+            ))
         }
     }
 }
@@ -596,21 +652,33 @@ impl AsMacroCall for AstIdWithPath<ast::Item> {
         &self,
         db: &dyn db::DefDatabase,
         krate: CrateId,
+        module_path: &dyn Fn() -> SmolStr,
         resolver: impl Fn(path::ModPath) -> Option<MacroDefId>,
         error_sink: &mut dyn FnMut(mbe::ExpandError),
-    ) -> Option<MacroCallId> {
-        let def: MacroDefId = resolver(self.path.clone()).or_else(|| {
-            error_sink(mbe::ExpandError::Other(format!("could not resolve macro `{}`", self.path)));
-            None
-        })?;
+    ) -> Result<Option<MacroCallId>, UnresolvedMacro> {
+        let def: MacroDefId = resolver(self.path.clone())
+            .ok_or_else(|| UnresolvedMacro { path: self.path.clone() })?;
+
+        if !def.kind.is_derive() {
+            error_sink(mbe::ExpandError::Other(format!(
+                "expected derive macro, found `{}`",
+                self.path
+            )));
+            return Ok(None);
+        }
 
-        Some(
+        let name = match self.path.segments.last() {
+            Some(it) => it.to_string(),
+            None => return Ok(None),
+        };
+        Ok(Some(
             def.as_lazy_macro(
                 db.upcast(),
                 krate,
-                MacroCallKind::Attr(self.ast_id, self.path.segments.last()?.to_string()),
+                module_path,
+                MacroCallKind::Attr(self.ast_id, name),
             )
             .into(),
-        )
+        ))
     }
 }
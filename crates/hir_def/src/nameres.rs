@@ -57,11 +57,11 @@ mod tests;
 use std::sync::Arc;
 
 use arena::Arena;
-use base_db::{CrateId, Edition, FileId};
-use hir_expand::{diagnostics::DiagnosticSink, name::Name, InFile};
+use base_db::{CrateId, Edition, FileId, FilePosition};
+use hir_expand::{diagnostics::DiagnosticSink, name::Name, InFile, MacroDefId};
 use rustc_hash::FxHashMap;
 use stdx::format_to;
-use syntax::ast;
+use syntax::{ast, SmolStr};
 
 use crate::{
     db::DefDatabase,
@@ -69,25 +69,45 @@ use crate::{
     nameres::{diagnostics::DefDiagnostic, path_resolution::ResolveMode},
     path::ModPath,
     per_ns::PerNs,
-    AstId, LocalModuleId, ModuleDefId, ModuleId,
+    visibility::Visibility,
+    AstId, BlockId, BlockLoc, LocalModuleId, ModuleDefId, ModuleId,
 };
 
-/// Contains all top-level defs from a macro-expanded crate
+/// Contains all top-level defs from a macro-expanded crate, or all items
+/// declared directly inside a block expression.
 #[derive(Debug, PartialEq, Eq)]
 pub struct CrateDefMap {
     pub root: LocalModuleId,
     pub modules: Arena<ModuleData>,
     pub(crate) krate: CrateId,
+    /// When this is a block def map, this will hold the block and the module
+    /// containing it.
+    block: Option<BlockInfo>,
     /// The prelude module for this crate. This either comes from an import
     /// marked with the `prelude_import` attribute, or (in the normal case) from
     /// a dependency (`std` or `core`).
     pub(crate) prelude: Option<ModuleId>,
     pub(crate) extern_prelude: FxHashMap<Name, ModuleDefId>,
+    /// Modules in which `macro` items are defined. Paths written in the body
+    /// of such a macro are resolved relative to this module.
+    macro_def_sites: FxHashMap<AstId<ast::Macro>, LocalModuleId>,
 
     edition: Edition,
+    /// The limit on nested macro expansions, set with `#![recursion_limit = "N"]`.
+    recursion_limit: Option<u32>,
     diagnostics: Vec<DefDiagnostic>,
 }
 
+/// For `CrateDefMap`s computed for a block expression, this stores its location
+/// in the parent map.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+struct BlockInfo {
+    /// The `BlockId` this `CrateDefMap` was created from.
+    block: BlockId,
+    /// The containing module.
+    parent: ModuleId,
+}
+
 impl std::ops::Index<LocalModuleId> for CrateDefMap {
     type Output = ModuleData;
     fn index(&self, id: LocalModuleId) -> &ModuleData {
@@ -109,6 +129,10 @@ pub enum ModuleOrigin {
     Inline {
         definition: AstId<ast::Module>,
     },
+    /// Pseudo-module introduced by a block scope (contains only inner items).
+    BlockExpr {
+        block: AstId<ast::BlockExpr>,
+    },
 }
 
 impl Default for ModuleOrigin {
@@ -122,7 +146,7 @@ impl ModuleOrigin {
         match self {
             ModuleOrigin::File { declaration: module, .. }
             | ModuleOrigin::Inline { definition: module, .. } => Some(*module),
-            ModuleOrigin::CrateRoot { .. } => None,
+            ModuleOrigin::CrateRoot { .. } | ModuleOrigin::BlockExpr { .. } => None,
         }
     }
 
@@ -137,7 +161,7 @@ impl ModuleOrigin {
 
     pub fn is_inline(&self) -> bool {
         match self {
-            ModuleOrigin::Inline { .. } => true,
+            ModuleOrigin::Inline { .. } | ModuleOrigin::BlockExpr { .. } => true,
             ModuleOrigin::CrateRoot { .. } | ModuleOrigin::File { .. } => false,
         }
     }
@@ -155,6 +179,9 @@ impl ModuleOrigin {
                 definition.file_id,
                 ModuleSource::Module(definition.to_node(db.upcast())),
             ),
+            ModuleOrigin::BlockExpr { block } => {
+                InFile::new(block.file_id, ModuleSource::BlockExpr(block.to_node(db.upcast())))
+            }
         }
     }
 }
@@ -174,24 +201,48 @@ impl CrateDefMap {
         let _p = profile::span("crate_def_map_query").detail(|| {
             db.crate_graph()[krate].display_name.as_deref().unwrap_or_default().to_string()
         });
-        let def_map = {
-            let edition = db.crate_graph()[krate].edition;
-            let mut modules: Arena<ModuleData> = Arena::default();
-            let root = modules.alloc(ModuleData::default());
-            CrateDefMap {
-                krate,
-                edition,
-                extern_prelude: FxHashMap::default(),
-                prelude: None,
-                root,
-                modules,
-                diagnostics: Vec::new(),
-            }
-        };
-        let def_map = collector::collect_defs(db, def_map);
+        let edition = db.crate_graph()[krate].edition;
+        let def_map = CrateDefMap::empty(krate, edition);
+        let def_map = collector::collect_defs(db, def_map, None);
         Arc::new(def_map)
     }
 
+    pub(crate) fn block_def_map_query(
+        db: &dyn DefDatabase,
+        block_id: BlockId,
+    ) -> Option<Arc<CrateDefMap>> {
+        let block: BlockLoc = db.lookup_intern_block(block_id);
+        let item_tree = db.item_tree(block.ast_id.file_id);
+        if item_tree.inner_items_of_block(block.ast_id.value).is_empty() {
+            return None;
+        }
+
+        let parent = block.module.def_map(db);
+        let mut def_map = CrateDefMap::empty(block.module.krate, parent.edition);
+        def_map.block = Some(BlockInfo { block: block_id, parent: block.module });
+        def_map.recursion_limit = parent.recursion_limit;
+
+        let def_map = collector::collect_defs(db, def_map, Some(block.ast_id));
+        Some(Arc::new(def_map))
+    }
+
+    fn empty(krate: CrateId, edition: Edition) -> CrateDefMap {
+        let mut modules: Arena<ModuleData> = Arena::default();
+        let root = modules.alloc(ModuleData::default());
+        CrateDefMap {
+            krate,
+            block: None,
+            edition,
+            recursion_limit: None,
+            extern_prelude: FxHashMap::default(),
+            macro_def_sites: FxHashMap::default(),
+            prelude: None,
+            root,
+            modules,
+            diagnostics: Vec::new(),
+        }
+    }
+
     pub fn add_diagnostics(
         &self,
         db: &dyn DefDatabase,
@@ -208,6 +259,70 @@ impl CrateDefMap {
             .map(|(id, _data)| id)
     }
 
+    pub fn module_id(&self, local_id: LocalModuleId) -> ModuleId {
+        let block = self.block.map(|b| b.block);
+        ModuleId { krate: self.krate, block, local_id }
+    }
+
+    /// Returns the `BlockId` this map was computed for, or `None` for the
+    /// crate-level `CrateDefMap`.
+    pub fn block_id(&self) -> Option<BlockId> {
+        self.block.map(|b| b.block)
+    }
+
+    /// The crate's `#![recursion_limit]`, if it sets one.
+    pub fn recursion_limit(&self) -> Option<u32> {
+        self.recursion_limit
+    }
+
+    /// If this is a block def map, returns the module containing the block.
+    pub fn parent(&self) -> Option<ModuleId> {
+        self.block.map(|b| b.parent)
+    }
+
+    /// Returns the root module of the crate, even when called on a block def map.
+    pub(crate) fn crate_root(&self, db: &dyn DefDatabase) -> ModuleId {
+        match self.block {
+            Some(block) => block.parent.def_map(db).crate_root(db),
+            None => self.module_id(self.root),
+        }
+    }
+
+    /// Returns the module containing `local_mod`, leaving this map if
+    /// `local_mod` is the root of a block.
+    pub fn containing_module(&self, local_mod: LocalModuleId) -> Option<ModuleId> {
+        match &self[local_mod].parent {
+            Some(parent) => Some(self.module_id(*parent)),
+            None => self.block.map(|block| block.parent),
+        }
+    }
+
+    /// Returns the path of `local_mod` the way `module_path!()` spells it, like
+    /// `my_crate::net::tcp`. Blocks are not part of the path. The crate is spelled the way
+    /// rustc knows it, which is the name of the target rather than the package.
+    pub fn module_path(&self, db: &dyn DefDatabase, local_mod: LocalModuleId) -> SmolStr {
+        let mut segments = Vec::new();
+        let mut current = local_mod;
+        while let Some(parent) = self[current].parent {
+            if let Some((name, _)) = self[parent].children.iter().find(|(_, id)| **id == current) {
+                segments.push(name.to_string());
+            }
+            current = parent;
+        }
+        let prefix = match self.block {
+            Some(block) => {
+                block.parent.def_map(db).module_path(db, block.parent.local_id).to_string()
+            }
+            None => match &db.crate_graph()[self.krate].display_name {
+                Some(name) => name.to_string(),
+                None => "crate".to_string(),
+            },
+        };
+        segments.push(prefix);
+        segments.reverse();
+        segments.join("::").into()
+    }
+
     pub(crate) fn resolve_path(
         &self,
         db: &dyn DefDatabase,
@@ -220,8 +335,67 @@ impl CrateDefMap {
         (res.resolved_def, res.segment_index)
     }
 
-    // FIXME: this can use some more human-readable format (ideally, an IR
-    // even), as this should be a great debugging aid.
+    /// Like `resolve_path`, but also returns the items the leading segments of `path` resolved
+    /// to, with the visibility they were found with.
+    pub(crate) fn resolve_path_with_segment_visibilities(
+        &self,
+        db: &dyn DefDatabase,
+        original_module: LocalModuleId,
+        path: &ModPath,
+        shadow: BuiltinShadowMode,
+    ) -> (PerNs, Option<usize>, Vec<(ModuleDefId, Visibility)>) {
+        let res =
+            self.resolve_path_fp_with_macro(db, ResolveMode::Other, original_module, path, shadow);
+        (res.resolved_def, res.segment_index, res.segment_visibilities)
+    }
+
+    /// Resolves the path of an import in `original_module` against the final state of the map,
+    /// the same way the collector does.
+    pub(crate) fn resolve_import_path(
+        &self,
+        db: &dyn DefDatabase,
+        original_module: LocalModuleId,
+        path: &ModPath,
+        is_extern_crate: bool,
+    ) -> PerNs {
+        if is_extern_crate {
+            return match path.as_ident() {
+                Some(name) => self.resolve_name_in_extern_prelude(db, name),
+                None => PerNs::none(),
+            };
+        }
+        let res = self.resolve_path_fp_with_macro(
+            db,
+            ResolveMode::Import,
+            original_module,
+            path,
+            BuiltinShadowMode::Module,
+        );
+        res.resolved_def
+    }
+
+    /// Resolves the path of a macro call at `position`.
+    ///
+    /// Unlike `resolve_path`, this respects the textual order of `macro_rules!` definitions
+    /// shadowing each other: the call refers to the last definition preceding it.
+    pub(crate) fn resolve_macro_call_path(
+        &self,
+        db: &dyn DefDatabase,
+        original_module: LocalModuleId,
+        path: &ModPath,
+        position: FilePosition,
+    ) -> Option<MacroDefId> {
+        if let Some(name) = path.as_ident() {
+            if let Some(def) = self[original_module].scope.get_legacy_macro_at(name, position) {
+                return Some(def);
+            }
+        }
+        self.resolve_path(db, original_module, path, BuiltinShadowMode::Other).0.take_macros()
+    }
+
+    /// Returns a compact listing of the names in each module, used by tests.
+    ///
+    /// See `crate::dump::def_map` for a complete, structured dump.
     pub fn dump(&self) -> String {
         let mut buf = String::new();
         go(&mut buf, self, "crate", self.root);
@@ -280,13 +454,15 @@ impl ModuleData {
 pub enum ModuleSource {
     SourceFile(ast::SourceFile),
     Module(ast::Module),
+    BlockExpr(ast::BlockExpr),
 }
 
 mod diagnostics {
     use cfg::{CfgExpr, CfgOptions};
     use hir_expand::diagnostics::DiagnosticSink;
     use hir_expand::hygiene::Hygiene;
-    use hir_expand::{InFile, MacroCallKind};
+    use hir_expand::{InFile, MacroCallId, MacroCallKind};
+    use mbe::ExpandError;
     use syntax::ast::AttrsOwner;
     use syntax::{ast, AstNode, AstPtr, SyntaxKind, SyntaxNodePtr};
 
@@ -305,7 +481,9 @@ mod diagnostics {
 
         UnresolvedProcMacro { ast: MacroCallKind },
 
-        MacroError { ast: MacroCallKind, message: String },
+        MacroError { ast: MacroCallKind, call: MacroCallId, err: ExpandError },
+
+        UnresolvedMacroCall { ast: AstId<ast::MacroCall> },
     }
 
     #[derive(Debug, PartialEq, Eq)]
@@ -360,9 +538,17 @@ mod diagnostics {
         pub(super) fn macro_error(
             container: LocalModuleId,
             ast: MacroCallKind,
-            message: String,
+            call: MacroCallId,
+            err: ExpandError,
+        ) -> Self {
+            Self { in_module: container, kind: DiagnosticKind::MacroError { ast, call, err } }
+        }
+
+        pub(super) fn unresolved_macro_call(
+            container: LocalModuleId,
+            ast: AstId<ast::MacroCall>,
         ) -> Self {
-            Self { in_module: container, kind: DiagnosticKind::MacroError { ast, message } }
+            Self { in_module: container, kind: DiagnosticKind::UnresolvedMacroCall { ast } }
         }
 
         pub(super) fn add_to(
@@ -477,7 +663,7 @@ mod diagnostics {
                     });
                 }
 
-                DiagnosticKind::MacroError { ast, message } => {
+                DiagnosticKind::MacroError { ast, call, err } => {
                     let (file, ast) = match ast {
                         MacroCallKind::FnLike(ast) => {
                             let node = ast.to_node(db.upcast());
@@ -488,7 +674,40 @@ mod diagnostics {
                             (ast.file_id, SyntaxNodePtr::from(AstPtr::new(&node)))
                         }
                     };
-                    sink.push(MacroError { file, node: ast, message: message.clone() });
+                    let backtrace = call.as_file().macro_backtrace(db.upcast());
+                    for error in call.errors(db.upcast(), err) {
+                        let precise_location =
+                            error.location.filter(|it| it.file_id == file).map(|it| it.value);
+                        sink.push(MacroError {
+                            file,
+                            node: ast.clone(),
+                            precise_location,
+                            message: error.message,
+                            level: error.level,
+                            backtrace: backtrace.clone(),
+                        });
+                    }
+                }
+
+                DiagnosticKind::UnresolvedMacroCall { ast } => {
+                    let node = ast.to_node(db.upcast());
+                    // The path used for resolution may have been rewritten, so show the path as
+                    // it was written.
+                    let path_node = match node.path() {
+                        Some(it) => it,
+                        None => return,
+                    };
+                    let hygiene = Hygiene::new(db.upcast(), ast.file_id);
+                    let path = match ModPath::from_src(path_node.clone(), &hygiene) {
+                        Some(it) => it,
+                        None => return,
+                    };
+                    sink.push(UnresolvedMacroCall {
+                        file: ast.file_id,
+                        node: AstPtr::new(&node),
+                        path_node: AstPtr::new(&path_node),
+                        path,
+                    });
                 }
             }
         }
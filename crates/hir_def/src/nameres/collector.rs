@@ -5,7 +5,7 @@
 
 use std::iter;
 
-use base_db::{CrateId, FileId, ProcMacroId};
+use base_db::{CrateId, CrateKind, FileId, FilePosition, ProcMacroId, ProcMacroKind};
 use cfg::{CfgExpr, CfgOptions};
 use hir_expand::{
     ast_id_map::FileAstId,
@@ -13,11 +13,11 @@ use hir_expand::{
     builtin_macro::find_builtin_macro,
     name::{AsName, Name},
     proc_macro::ProcMacroExpander,
-    HirFileId, MacroCallId, MacroDefId, MacroDefKind,
+    EagerCallLoc, ExpandError, HirFileId, MacroCallId, MacroCallKind, MacroDefId, MacroDefKind,
 };
 use hir_expand::{InFile, MacroCallLoc};
 use rustc_hash::{FxHashMap, FxHashSet};
-use syntax::ast;
+use syntax::{ast, AstNode};
 use test_utils::mark;
 use tt::{Leaf, TokenTree};
 
@@ -26,8 +26,8 @@ use crate::{
     db::DefDatabase,
     item_scope::{ImportType, PerNsGlobImports},
     item_tree::{
-        self, FileItemTreeId, ItemTree, ItemTreeId, MacroCall, MacroRules, Mod, ModItem, ModKind,
-        StructDefKind,
+        self, FileItemTreeId, ItemTree, ItemTreeId, MacroCall, MacroDef, MacroRules, Mod, ModItem,
+        ModKind, StructDefKind,
     },
     nameres::{
         diagnostics::DefDiagnostic, mod_resolution::ModDir, path_resolution::ReachedFixedPoint,
@@ -36,37 +36,46 @@ use crate::{
     path::{ImportAlias, ModPath, PathKind},
     per_ns::PerNs,
     visibility::{RawVisibility, Visibility},
-    AdtId, AsMacroCall, AstId, AstIdWithPath, ConstLoc, ContainerId, EnumLoc, EnumVariantId,
-    FunctionLoc, ImplLoc, Intern, LocalModuleId, ModuleDefId, ModuleId, StaticLoc, StructLoc,
-    TraitLoc, TypeAliasLoc, UnionLoc,
+    AdtId, AsMacroCall, AstId, AstIdWithPath, ConstLoc, EnumLoc, EnumVariantId, FunctionLoc,
+    ImplLoc, Intern, LocalModuleId, ModuleDefId, StaticLoc, StructLoc, TraitLoc, TypeAliasLoc,
+    UnionLoc,
 };
 
 const GLOB_RECURSION_LIMIT: usize = 100;
 const EXPANSION_DEPTH_LIMIT: usize = 128;
 const FIXED_POINT_LIMIT: usize = 8192;
 
-pub(super) fn collect_defs(db: &dyn DefDatabase, mut def_map: CrateDefMap) -> CrateDefMap {
+pub(super) fn collect_defs(
+    db: &dyn DefDatabase,
+    mut def_map: CrateDefMap,
+    block: Option<AstId<ast::BlockExpr>>,
+) -> CrateDefMap {
     let crate_graph = db.crate_graph();
 
-    // populate external prelude
-    for dep in &crate_graph[def_map.krate].dependencies {
-        log::debug!("crate dep {:?} -> {:?}", dep.name, dep.crate_id);
-        let dep_def_map = db.crate_def_map(dep.crate_id);
-        def_map.extern_prelude.insert(
-            dep.as_name(),
-            ModuleId { krate: dep.crate_id, local_id: dep_def_map.root }.into(),
-        );
-
-        // look for the prelude
-        // If the dependency defines a prelude, we overwrite an already defined
-        // prelude. This is necessary to import the "std" prelude if a crate
-        // depends on both "core" and "std".
-        if dep_def_map.prelude.is_some() {
-            def_map.prelude = dep_def_map.prelude;
+    // Block def maps don't have their own extern prelude and prelude, paths
+    // that don't resolve in them are looked up in the parent map instead.
+    if block.is_none() {
+        // populate external prelude
+        for dep in &crate_graph[def_map.krate].dependencies {
+            log::debug!("crate dep {:?} -> {:?}", dep.name, dep.crate_id);
+            let dep_def_map = db.crate_def_map(dep.crate_id);
+            def_map
+                .extern_prelude
+                .insert(dep.as_name(), dep_def_map.module_id(dep_def_map.root).into());
+
+            // look for the prelude
+            // If the dependency defines a prelude, we overwrite an already defined
+            // prelude. This is necessary to import the "std" prelude if a crate
+            // depends on both "core" and "std".
+            if dep_def_map.prelude.is_some() {
+                def_map.prelude = dep_def_map.prelude;
+            }
         }
     }
 
     let cfg_options = &crate_graph[def_map.krate].cfg_options;
+    // Block expressions can't define procedural macros, even inside of `proc-macro` crates.
+    let is_proc_macro = block.is_none() && crate_graph[def_map.krate].kind == CrateKind::ProcMacro;
     let proc_macros = &crate_graph[def_map.krate].proc_macro;
     let proc_macros = proc_macros
         .iter()
@@ -90,9 +99,13 @@ pub(super) fn collect_defs(db: &dyn DefDatabase, mut def_map: CrateDefMap) -> Cr
         mod_dirs: FxHashMap::default(),
         cfg_options,
         proc_macros,
-        exports_proc_macros: false,
+        is_proc_macro,
         from_glob_import: Default::default(),
     };
+    match block {
+        Some(block) => collector.seed_with_inner(block),
+        None => collector.seed_with_top_level(),
+    }
     collector.collect();
     collector.finish()
 }
@@ -223,21 +236,26 @@ struct DefCollector<'a> {
     /// empty when proc. macro support is disabled (in which case we still do name resolution for
     /// them).
     proc_macros: Vec<(Name, ProcMacroExpander)>,
-    exports_proc_macros: bool,
+    /// Whether this is the def map of a `proc-macro` crate.
+    is_proc_macro: bool,
     from_glob_import: PerNsGlobImports,
 }
 
 impl DefCollector<'_> {
-    fn collect(&mut self) {
+    fn seed_with_top_level(&mut self) {
         let file_id = self.db.crate_graph()[self.def_map.krate].root_file_id;
         let item_tree = self.db.item_tree(file_id.into());
         let module_id = self.def_map.root;
         self.def_map.modules[module_id].origin = ModuleOrigin::CrateRoot { definition: file_id };
-        if item_tree
-            .top_level_attrs(self.db, self.def_map.krate)
-            .cfg()
-            .map_or(true, |cfg| self.cfg_options.check(&cfg) != Some(false))
-        {
+
+        let attrs = item_tree.top_level_attrs(self.db, self.def_map.krate);
+        if let Some(limit) = attrs.by_key("recursion_limit").string_value() {
+            if let Ok(limit) = limit.parse() {
+                self.def_map.recursion_limit = Some(limit);
+            }
+        }
+
+        if attrs.cfg().map_or(true, |cfg| self.cfg_options.check(&cfg) != Some(false)) {
             ModCollector {
                 def_collector: &mut *self,
                 macro_depth: 0,
@@ -248,7 +266,36 @@ impl DefCollector<'_> {
             }
             .collect(item_tree.top_level_items());
         }
+    }
 
+    fn seed_with_inner(&mut self, block: AstId<ast::BlockExpr>) {
+        let item_tree = self.db.item_tree(block.file_id);
+        let module_id = self.def_map.root;
+        self.def_map.modules[module_id].origin = ModuleOrigin::BlockExpr { block };
+
+        // `macro_rules!` macros of the enclosing scope are textually visible in the block, the
+        // ones defined after it are filtered out when resolving.
+        if let Some(parent) = self.def_map.parent() {
+            let parent_map = parent.def_map(self.db);
+            for (name, defs) in parent_map[parent.local_id].scope.legacy_macro_definitions() {
+                for &(mac, visible_from) in defs {
+                    self.define_legacy_macro(module_id, name.clone(), mac, visible_from);
+                }
+            }
+        }
+
+        ModCollector {
+            def_collector: &mut *self,
+            macro_depth: 0,
+            module_id,
+            file_id: block.file_id,
+            item_tree: &item_tree,
+            mod_dir: ModDir::root(),
+        }
+        .collect(item_tree.inner_items_of_block(block.value));
+    }
+
+    fn collect(&mut self) {
         // main name resolution fixed-point loop.
         let mut i = 0;
         loop {
@@ -288,19 +335,16 @@ impl DefCollector<'_> {
         }
         self.unresolved_imports = unresolved_imports;
 
-        // FIXME: This condition should instead check if this is a `proc-macro` type crate.
-        if self.exports_proc_macros {
+        if self.is_proc_macro {
             // A crate exporting procedural macros is not allowed to export anything else.
             //
             // Additionally, while the proc macro entry points must be `pub`, they are not publicly
             // exported in type/value namespace. This function reduces the visibility of all items
             // in the crate root that aren't proc macros.
             let root = self.def_map.root;
+            let module_id = self.def_map.module_id(root);
             let root = &mut self.def_map.modules[root];
-            root.scope.censor_non_proc_macros(ModuleId {
-                krate: self.def_map.krate,
-                local_id: self.def_map.root,
-            });
+            root.scope.censor_non_proc_macros(module_id);
         }
     }
 
@@ -320,21 +364,16 @@ impl DefCollector<'_> {
     /// use a dummy expander that always errors. This comes with the drawback of macros potentially
     /// going out of sync with what the build system sees (since we resolve using VFS state, but
     /// Cargo builds only on-disk files). We could and probably should add diagnostics for that.
-    fn resolve_proc_macro(&mut self, name: &Name) {
-        self.exports_proc_macros = true;
-        let macro_def = match self.proc_macros.iter().find(|(n, _)| n == name) {
-            Some((_, expander)) => MacroDefId {
-                ast_id: None,
-                krate: self.def_map.krate,
-                kind: MacroDefKind::ProcMacro(*expander),
-                local_inner: false,
-            },
-            None => MacroDefId {
-                ast_id: None,
-                krate: self.def_map.krate,
-                kind: MacroDefKind::ProcMacro(ProcMacroExpander::dummy(self.def_map.krate)),
-                local_inner: false,
-            },
+    fn resolve_proc_macro(&mut self, name: &Name, kind: ProcMacroKind) {
+        let expander = match self.proc_macros.iter().find(|(n, _)| n == name) {
+            Some((_, expander)) => *expander,
+            None => ProcMacroExpander::dummy(self.def_map.krate),
+        };
+        let macro_def = MacroDefId {
+            ast_id: None,
+            krate: self.def_map.krate,
+            kind: MacroDefKind::ProcMacro(expander, kind),
+            local_inner: false,
         };
 
         self.define_proc_macro(name.clone(), macro_def);
@@ -371,9 +410,10 @@ impl DefCollector<'_> {
         name: Name,
         macro_: MacroDefId,
         export: bool,
+        visible_from: FilePosition,
     ) {
         // Textual scoping
-        self.define_legacy_macro(module_id, name.clone(), macro_);
+        self.define_legacy_macro(module_id, name.clone(), macro_, Some(visible_from));
 
         // Module scoping
         // In Rust, `#[macro_export]` macros are unconditionally visible at the
@@ -388,6 +428,20 @@ impl DefCollector<'_> {
         }
     }
 
+    /// Define a `macro` item ("macros 2.0").
+    ///
+    /// Unlike `macro_rules!`, these are not textually scoped: they are items
+    /// like any other, and follow the usual visibility rules.
+    fn define_macro_def(
+        &mut self,
+        module_id: LocalModuleId,
+        name: Name,
+        macro_: MacroDefId,
+        vis: Visibility,
+    ) {
+        self.update(module_id, &[(Some(name), PerNs::macros(macro_, vis))], vis, ImportType::Named);
+    }
+
     /// Define a legacy textual scoped macro in module
     ///
     /// We use a map `legacy_macros` to store all legacy textual scoped macros visible per module.
@@ -395,9 +449,17 @@ impl DefCollector<'_> {
     /// the definition of current module.
     /// And also, `macro_use` on a module will import all legacy macros visible inside to
     /// current legacy scope, with possible shadowing.
-    fn define_legacy_macro(&mut self, module_id: LocalModuleId, name: Name, mac: MacroDefId) {
-        // Always shadowing
-        self.def_map.modules[module_id].scope.define_legacy_macro(name, mac);
+    ///
+    /// `visible_from` is the position the macro becomes visible at, macros without one are
+    /// visible in the whole module (but are still shadowed by the module's own definitions).
+    fn define_legacy_macro(
+        &mut self,
+        module_id: LocalModuleId,
+        name: Name,
+        mac: MacroDefId,
+        visible_from: Option<FilePosition>,
+    ) {
+        self.def_map.modules[module_id].scope.define_legacy_macro(name, mac, visible_from);
     }
 
     /// Define a proc macro
@@ -425,7 +487,7 @@ impl DefCollector<'_> {
             self.def_map.edition,
         );
 
-        let res = self.def_map.resolve_name_in_extern_prelude(&extern_crate.name);
+        let res = self.def_map.resolve_name_in_extern_prelude(self.db, &extern_crate.name);
 
         if let Some(ModuleDefId::ModuleId(m)) = res.take_types() {
             mark::hit!(macro_rules_from_other_crates_are_visible_with_macro_use);
@@ -442,7 +504,7 @@ impl DefCollector<'_> {
         let def_map = self.db.crate_def_map(krate);
         for (name, def) in def_map[def_map.root].scope.macros() {
             // `macro_use` only bring things into legacy scope.
-            self.define_legacy_macro(current_module_id, name.clone(), def);
+            self.define_legacy_macro(current_module_id, name.clone(), def, None);
         }
     }
 
@@ -481,6 +543,7 @@ impl DefCollector<'_> {
         log::debug!("resolving import: {:?} ({:?})", import, self.def_map.edition);
         if import.is_extern_crate {
             let res = self.def_map.resolve_name_in_extern_prelude(
+                self.db,
                 &import
                     .path
                     .as_ident()
@@ -542,7 +605,7 @@ impl DefCollector<'_> {
                     } else if m.krate != self.def_map.krate {
                         mark::hit!(glob_across_crates);
                         // glob import from other crate => we can just import everything once
-                        let item_map = self.db.crate_def_map(m.krate);
+                        let item_map = m.def_map(self.db);
                         let scope = &item_map[m.local_id].scope;
 
                         // Module scoped macros is included
@@ -555,6 +618,28 @@ impl DefCollector<'_> {
                             .filter(|(_, res)| !res.is_none())
                             .collect::<Vec<_>>();
 
+                        self.update(module_id, &items, vis, ImportType::Glob);
+                    } else if m.block != self.def_map.block_id() {
+                        // glob import from an enclosing block or module => that `CrateDefMap`
+                        // is already complete, so we can just import everything once
+                        let item_map = m.def_map(self.db);
+                        let scope = &item_map[m.local_id].scope;
+
+                        // Module scoped macros is included
+                        let items = scope
+                            .resolutions()
+                            // only keep visible names...
+                            .map(|(n, res)| {
+                                (
+                                    n,
+                                    res.filter_visibility(|v| {
+                                        v.is_visible_from_def_map(self.db, &self.def_map, module_id)
+                                    }),
+                                )
+                            })
+                            .filter(|(_, res)| !res.is_none())
+                            .collect::<Vec<_>>();
+
                         self.update(module_id, &items, vis, ImportType::Glob);
                     } else {
                         // glob import from same crate => we do an initial
@@ -570,7 +655,7 @@ impl DefCollector<'_> {
                                 (
                                     n,
                                     res.filter_visibility(|v| {
-                                        v.is_visible_from_def_map(&self.def_map, module_id)
+                                        v.is_visible_from_def_map(self.db, &self.def_map, module_id)
                                     }),
                                 )
                             })
@@ -627,7 +712,10 @@ impl DefCollector<'_> {
                     log::debug!("resolved import {:?} ({:?}) to {:?}", name, import, def);
 
                     // extern crates in the crate root are special-cased to insert entries into the extern prelude: rust-lang/rust#54658
-                    if import.is_extern_crate && module_id == self.def_map.root {
+                    if import.is_extern_crate
+                        && self.def_map.block.is_none()
+                        && module_id == self.def_map.root
+                    {
                         if let (Some(def), Some(name)) = (def.take_types(), name.as_ref()) {
                             self.def_map.extern_prelude.insert(name.clone(), def);
                         }
@@ -691,7 +779,7 @@ impl DefCollector<'_> {
                     let should_update = match old_vis {
                         None => true,
                         Some(old_vis) => {
-                            let max_vis = old_vis.max(vis, &self.def_map).unwrap_or_else(|| {
+                            let max_vis = old_vis.max(vis, self.db, &self.def_map).unwrap_or_else(|| {
                                 panic!("`Tr as _` imports with unrelated visibilities {:?} and {:?} (trait {:?})", old_vis, vis, tr);
                             });
 
@@ -723,7 +811,7 @@ impl DefCollector<'_> {
             .filter(|(glob_importing_module, _)| {
                 // we know all resolutions have the same visibility (`vis`), so we
                 // just need to check that once
-                vis.is_visible_from_def_map(&self.def_map, *glob_importing_module)
+                vis.is_visible_from_def_map(self.db, &self.def_map, *glob_importing_module)
             })
             .cloned()
             .collect::<Vec<_>>();
@@ -752,16 +840,10 @@ impl DefCollector<'_> {
                 return false;
             }
 
+            let module_path = || self.def_map.module_path(self.db, directive.module_id);
             if let Some(call_id) =
-                directive.ast_id.as_call_id(self.db, self.def_map.krate, |path| {
-                    let resolved_res = self.def_map.resolve_path_fp_with_macro(
-                        self.db,
-                        ResolveMode::Other,
-                        directive.module_id,
-                        &path,
-                        BuiltinShadowMode::Module,
-                    );
-                    resolved_res.resolved_def.take_macros()
+                directive.ast_id.as_call_id(self.db, self.def_map.krate, &module_path, |path| {
+                    self.resolve_macro_call_path(directive.module_id, &path)
                 })
             {
                 resolved.push((directive.module_id, call_id, directive.depth));
@@ -772,8 +854,9 @@ impl DefCollector<'_> {
             true
         });
         attribute_macros.retain(|directive| {
+            let module_path = || self.def_map.module_path(self.db, directive.module_id);
             if let Some(call_id) =
-                directive.ast_id.as_call_id(self.db, self.def_map.krate, |path| {
+                directive.ast_id.as_call_id(self.db, self.def_map.krate, &module_path, |path| {
                     self.resolve_attribute_macro(&directive, &path)
                 })
             {
@@ -795,6 +878,24 @@ impl DefCollector<'_> {
         res
     }
 
+    fn resolve_macro_call_path(
+        &self,
+        module_id: LocalModuleId,
+        path: &ModPath,
+    ) -> Option<MacroDefId> {
+        let resolved_res = self.def_map.resolve_path_fp_with_macro(
+            self.db,
+            ResolveMode::Other,
+            module_id,
+            path,
+            BuiltinShadowMode::Module,
+        );
+        resolved_res
+            .resolved_def
+            .filter_visibility(|vis| vis.is_visible_from_def_map(self.db, &self.def_map, module_id))
+            .take_macros()
+    }
+
     fn resolve_attribute_macro(
         &self,
         directive: &DeriveDirective,
@@ -817,9 +918,27 @@ impl DefCollector<'_> {
         macro_call_id: MacroCallId,
         depth: usize,
     ) {
-        if depth > EXPANSION_DEPTH_LIMIT {
+        let kind = match macro_call_id {
+            MacroCallId::LazyMacro(id) => {
+                let loc: MacroCallLoc = self.db.lookup_intern_macro(id);
+                loc.kind
+            }
+            MacroCallId::EagerMacro(id) => {
+                let loc: EagerCallLoc = self.db.lookup_intern_eager_expansion(id);
+                MacroCallKind::FnLike(loc.call)
+            }
+        };
+
+        let limit = self.def_map.recursion_limit.map_or(EXPANSION_DEPTH_LIMIT, |it| it as usize);
+        if depth > limit {
             mark::hit!(macro_expansion_overflow);
             log::warn!("macro expansion is too deep");
+            self.def_map.diagnostics.push(DefDiagnostic::macro_error(
+                module_id,
+                kind,
+                macro_call_id,
+                ExpandError::Other("reached recursion limit during macro expansion".into()),
+            ));
             return;
         }
         let file_id = macro_call_id.as_file();
@@ -829,20 +948,15 @@ impl DefCollector<'_> {
         // incrementality).
         let err = self.db.macro_expand_error(macro_call_id);
         if let Some(err) = err {
-            if let MacroCallId::LazyMacro(id) = macro_call_id {
-                let loc: MacroCallLoc = self.db.lookup_intern_macro(id);
-
-                let diag = match err {
-                    hir_expand::ExpandError::UnresolvedProcMacro => {
-                        // Missing proc macros are non-fatal, so they are handled specially.
-                        DefDiagnostic::unresolved_proc_macro(module_id, loc.kind)
-                    }
-                    _ => DefDiagnostic::macro_error(module_id, loc.kind, err.to_string()),
-                };
+            let diag = match err {
+                ExpandError::UnresolvedProcMacro => {
+                    // Missing proc macros are non-fatal, so they are handled specially.
+                    DefDiagnostic::unresolved_proc_macro(module_id, kind)
+                }
+                _ => DefDiagnostic::macro_error(module_id, kind, macro_call_id, err),
+            };
 
-                self.def_map.diagnostics.push(diag);
-            }
-            // FIXME: Handle eager macros.
+            self.def_map.diagnostics.push(diag);
         }
 
         // Then, fetch and process the item tree. This will reuse the expansion result from above.
@@ -904,6 +1018,17 @@ impl DefCollector<'_> {
             }
         }
 
+        // Macro calls can also stay unexpanded because their expansion failed, so only report
+        // the ones whose path doesn't resolve to a macro.
+        for directive in &self.unexpanded_macros {
+            if self.resolve_macro_call_path(directive.module_id, &directive.ast_id.path).is_none() {
+                self.def_map.diagnostics.push(DefDiagnostic::unresolved_macro_call(
+                    directive.module_id,
+                    directive.ast_id.ast_id,
+                ));
+            }
+        }
+
         self.def_map
     }
 }
@@ -962,9 +1087,7 @@ impl ModCollector<'_, '_> {
                     continue;
                 }
             }
-            let module =
-                ModuleId { krate: self.def_collector.def_map.krate, local_id: self.module_id };
-            let container = ContainerId::ModuleId(module);
+            let module = self.def_collector.def_map.module_id(self.module_id);
 
             let mut def = None;
             match item {
@@ -995,48 +1118,11 @@ impl ModCollector<'_, '_> {
                 }
                 ModItem::MacroCall(mac) => self.collect_macro_call(&self.item_tree[mac]),
                 ModItem::MacroRules(id) => self.collect_macro_rules(id),
-                ModItem::MacroDef(id) => {
-                    let mac = &self.item_tree[id];
-                    let ast_id = InFile::new(self.file_id, mac.ast_id.upcast());
-
-                    // "Macro 2.0" is not currently supported by rust-analyzer, but libcore uses it
-                    // to define builtin macros, so we support at least that part.
-                    let attrs = self.item_tree.attrs(
-                        self.def_collector.db,
-                        krate,
-                        ModItem::from(id).into(),
-                    );
-                    if attrs.by_key("rustc_builtin_macro").exists() {
-                        let krate = self.def_collector.def_map.krate;
-                        let macro_id = find_builtin_macro(&mac.name, krate, ast_id)
-                            .or_else(|| find_builtin_derive(&mac.name, krate, ast_id));
-                        if let Some(macro_id) = macro_id {
-                            let vis = self
-                                .def_collector
-                                .def_map
-                                .resolve_visibility(
-                                    self.def_collector.db,
-                                    self.module_id,
-                                    &self.item_tree[mac.visibility],
-                                )
-                                .unwrap_or(Visibility::Public);
-                            self.def_collector.update(
-                                self.module_id,
-                                &[(Some(mac.name.clone()), PerNs::macros(macro_id, vis))],
-                                vis,
-                                ImportType::Named,
-                            );
-                        }
-                    }
-                }
+                ModItem::MacroDef(id) => self.collect_macro_def(id),
                 ModItem::Impl(imp) => {
-                    let module = ModuleId {
-                        krate: self.def_collector.def_map.krate,
-                        local_id: self.module_id,
-                    };
-                    let container = ContainerId::ModuleId(module);
-                    let impl_id = ImplLoc { container, id: ItemTreeId::new(self.file_id, imp) }
-                        .intern(self.def_collector.db);
+                    let impl_id =
+                        ImplLoc { container: module, id: ItemTreeId::new(self.file_id, imp) }
+                            .intern(self.def_collector.db);
                     self.def_collector.def_map.modules[self.module_id].scope.define_impl(impl_id)
                 }
                 ModItem::Function(id) => {
@@ -1046,7 +1132,7 @@ impl ModCollector<'_, '_> {
 
                     def = Some(DefData {
                         id: FunctionLoc {
-                            container: container.into(),
+                            container: module.into(),
                             id: ItemTreeId::new(self.file_id, id),
                         }
                         .intern(self.def_collector.db)
@@ -1065,7 +1151,7 @@ impl ModCollector<'_, '_> {
                     self.collect_derives(&attrs, it.ast_id.upcast());
 
                     def = Some(DefData {
-                        id: StructLoc { container, id: ItemTreeId::new(self.file_id, id) }
+                        id: StructLoc { container: module, id: ItemTreeId::new(self.file_id, id) }
                             .intern(self.def_collector.db)
                             .into(),
                         name: &it.name,
@@ -1082,7 +1168,7 @@ impl ModCollector<'_, '_> {
                     self.collect_derives(&attrs, it.ast_id.upcast());
 
                     def = Some(DefData {
-                        id: UnionLoc { container, id: ItemTreeId::new(self.file_id, id) }
+                        id: UnionLoc { container: module, id: ItemTreeId::new(self.file_id, id) }
                             .intern(self.def_collector.db)
                             .into(),
                         name: &it.name,
@@ -1099,7 +1185,7 @@ impl ModCollector<'_, '_> {
                     self.collect_derives(&attrs, it.ast_id.upcast());
 
                     def = Some(DefData {
-                        id: EnumLoc { container, id: ItemTreeId::new(self.file_id, id) }
+                        id: EnumLoc { container: module, id: ItemTreeId::new(self.file_id, id) }
                             .intern(self.def_collector.db)
                             .into(),
                         name: &it.name,
@@ -1113,7 +1199,7 @@ impl ModCollector<'_, '_> {
                     if let Some(name) = &it.name {
                         def = Some(DefData {
                             id: ConstLoc {
-                                container: container.into(),
+                                container: module.into(),
                                 id: ItemTreeId::new(self.file_id, id),
                             }
                             .intern(self.def_collector.db)
@@ -1128,7 +1214,7 @@ impl ModCollector<'_, '_> {
                     let it = &self.item_tree[id];
 
                     def = Some(DefData {
-                        id: StaticLoc { container, id: ItemTreeId::new(self.file_id, id) }
+                        id: StaticLoc { container: module, id: ItemTreeId::new(self.file_id, id) }
                             .intern(self.def_collector.db)
                             .into(),
                         name: &it.name,
@@ -1140,7 +1226,7 @@ impl ModCollector<'_, '_> {
                     let it = &self.item_tree[id];
 
                     def = Some(DefData {
-                        id: TraitLoc { container, id: ItemTreeId::new(self.file_id, id) }
+                        id: TraitLoc { container: module, id: ItemTreeId::new(self.file_id, id) }
                             .intern(self.def_collector.db)
                             .into(),
                         name: &it.name,
@@ -1153,7 +1239,7 @@ impl ModCollector<'_, '_> {
 
                     def = Some(DefData {
                         id: TypeAliasLoc {
-                            container: container.into(),
+                            container: module.into(),
                             id: ItemTreeId::new(self.file_id, id),
                         }
                         .intern(self.def_collector.db)
@@ -1207,7 +1293,7 @@ impl ModCollector<'_, '_> {
                     }
                     .collect(&*items);
                     if is_macro_use {
-                        self.import_all_legacy_macros(module_id);
+                        self.import_all_legacy_macros(module_id, module.ast_id);
                     }
                 }
             }
@@ -1238,7 +1324,7 @@ impl ModCollector<'_, '_> {
                         }
                         .collect(item_tree.top_level_items());
                         if is_macro_use {
-                            self.import_all_legacy_macros(module_id);
+                            self.import_all_legacy_macros(module_id, module.ast_id);
                         }
                     }
                     Err(candidate) => {
@@ -1273,10 +1359,10 @@ impl ModCollector<'_, '_> {
             }
         };
         for (name, mac) in modules[self.module_id].scope.collect_legacy_macros() {
-            modules[res].scope.define_legacy_macro(name, mac)
+            modules[res].scope.define_legacy_macro(name, mac, None)
         }
         modules[self.module_id].children.insert(name.clone(), res);
-        let module = ModuleId { krate: self.def_collector.def_map.krate, local_id: res };
+        let module = self.def_collector.def_map.module_id(res);
         let def: ModuleDefId = module.into();
         self.def_collector.def_map.modules[self.module_id].scope.define_def(def);
         self.def_collector.update(
@@ -1308,18 +1394,22 @@ impl ModCollector<'_, '_> {
     }
 
     /// If `attrs` registers a procedural macro, collects its definition.
+    ///
+    /// Only functions in the crate root of `proc-macro` crates can be procedural macros.
     fn collect_proc_macro_def(&mut self, func_name: &Name, attrs: &Attrs) {
-        // FIXME: this should only be done in the root module of `proc-macro` crates, not everywhere
-        // FIXME: distinguish the type of macro
-        let macro_name = if attrs.by_key("proc_macro").exists()
-            || attrs.by_key("proc_macro_attribute").exists()
-        {
-            func_name.clone()
+        if !self.def_collector.is_proc_macro || self.module_id != self.def_collector.def_map.root {
+            return;
+        }
+
+        let (macro_name, kind) = if attrs.by_key("proc_macro").exists() {
+            (func_name.clone(), ProcMacroKind::FuncLike)
+        } else if attrs.by_key("proc_macro_attribute").exists() {
+            (func_name.clone(), ProcMacroKind::Attr)
         } else {
             let derive = attrs.by_key("proc_macro_derive");
             if let Some(arg) = derive.tt_values().next() {
                 if let [TokenTree::Leaf(Leaf::Ident(trait_name)), ..] = &*arg.token_trees {
-                    trait_name.as_name()
+                    (trait_name.as_name(), ProcMacroKind::CustomDerive)
                 } else {
                     log::trace!("malformed `#[proc_macro_derive]`: {}", arg);
                     return;
@@ -1329,7 +1419,7 @@ impl ModCollector<'_, '_> {
             }
         };
 
-        self.def_collector.resolve_proc_macro(&macro_name);
+        self.def_collector.resolve_proc_macro(&macro_name, kind);
     }
 
     fn collect_macro_rules(&mut self, id: FileItemTreeId<MacroRules>) {
@@ -1337,6 +1427,7 @@ impl ModCollector<'_, '_> {
         let mac = &self.item_tree[id];
         let attrs = self.item_tree.attrs(self.def_collector.db, krate, ModItem::from(id).into());
         let ast_id = InFile::new(self.file_id, mac.ast_id.upcast());
+        let position = self.position_of(mac.ast_id);
 
         let export_attr = attrs.by_key("macro_export");
 
@@ -1361,6 +1452,7 @@ impl ModCollector<'_, '_> {
                     mac.name.clone(),
                     macro_id,
                     is_export,
+                    position,
                 );
                 return;
             }
@@ -1373,24 +1465,89 @@ impl ModCollector<'_, '_> {
             kind: MacroDefKind::Declarative,
             local_inner: is_local_inner,
         };
-        self.def_collector.define_macro(self.module_id, mac.name.clone(), macro_id, is_export);
+        self.def_collector.define_macro(
+            self.module_id,
+            mac.name.clone(),
+            macro_id,
+            is_export,
+            position,
+        );
+    }
+
+    fn collect_macro_def(&mut self, id: FileItemTreeId<MacroDef>) {
+        let krate = self.def_collector.def_map.krate;
+        let mac = &self.item_tree[id];
+        let attrs = self.item_tree.attrs(self.def_collector.db, krate, ModItem::from(id).into());
+        let ast_id = InFile::new(self.file_id, mac.ast_id.upcast());
+
+        let vis = self
+            .def_collector
+            .def_map
+            .resolve_visibility(
+                self.def_collector.db,
+                self.module_id,
+                &self.item_tree[mac.visibility],
+            )
+            .unwrap_or(Visibility::Public);
+
+        // Case 1: builtin macros, which libcore defines with `macro`
+        if attrs.by_key("rustc_builtin_macro").exists() {
+            let macro_id = find_builtin_macro(&mac.name, krate, ast_id)
+                .or_else(|| find_builtin_derive(&mac.name, krate, ast_id));
+            if let Some(macro_id) = macro_id {
+                self.def_collector.define_macro_def(
+                    self.module_id,
+                    mac.name.clone(),
+                    macro_id,
+                    vis,
+                );
+            }
+            return;
+        }
+
+        // Case 2: normal `macro` item
+        let macro_id = MacroDefId {
+            ast_id: Some(ast_id),
+            krate,
+            kind: MacroDefKind::Declarative,
+            local_inner: false,
+        };
+        self.def_collector.def_map.macro_def_sites.insert(ast_id, self.module_id);
+        self.def_collector.define_macro_def(self.module_id, mac.name.clone(), macro_id, vis);
     }
 
     fn collect_macro_call(&mut self, mac: &MacroCall) {
         let mut ast_id = AstIdWithPath::new(self.file_id, mac.ast_id, mac.path.clone());
 
         // Case 1: try to resolve in legacy scope and expand macro_rules
-        if let Some(macro_call_id) =
-            ast_id.as_call_id(self.def_collector.db, self.def_collector.def_map.krate, |path| {
-                path.as_ident().and_then(|name| {
-                    self.def_collector.def_map[self.module_id].scope.get_legacy_macro(&name)
-                })
-            })
-        {
+        let legacy_macro = ast_id.path.as_ident().and_then(|name| {
+            self.def_collector.def_map[self.module_id].scope.get_legacy_macro(&name)
+        });
+        if let Some(macro_def) = legacy_macro {
+            // Eager macros expand the macro calls in their arguments right away. Those may refer
+            // to macros that are not resolvable yet, so the expansion is left to name resolution.
+            // The path is kept as is, so that it still resolves to the same legacy macro.
+            let legacy = match macro_def.kind {
+                MacroDefKind::BuiltInEager(_) => None,
+                _ => Some(
+                    macro_def
+                        .as_lazy_macro(
+                            self.def_collector.db.upcast(),
+                            self.def_collector.def_map.krate,
+                            &|| {
+                                self.def_collector
+                                    .def_map
+                                    .module_path(self.def_collector.db, self.module_id)
+                            },
+                            MacroCallKind::FnLike(ast_id.ast_id),
+                        )
+                        .into(),
+                ),
+            };
             self.def_collector.unexpanded_macros.push(MacroDirective {
                 module_id: self.module_id,
                 ast_id,
-                legacy: Some(macro_call_id),
+                legacy,
                 depth: self.macro_depth + 1,
             });
 
@@ -1399,7 +1556,9 @@ impl ModCollector<'_, '_> {
 
         // Case 2: resolve in module scope, expand during name resolution.
         // We rewrite simple path `macro_name` to `self::macro_name` to force resolve in module scope only.
-        if ast_id.path.is_ident() {
+        // Blocks are the exception: names not defined in a block have to be looked up in the
+        // enclosing scopes.
+        if ast_id.path.is_ident() && self.def_collector.def_map.block_id().is_none() {
             ast_id.path.kind = PathKind::Super(0);
         }
 
@@ -1411,13 +1570,28 @@ impl ModCollector<'_, '_> {
         });
     }
 
-    fn import_all_legacy_macros(&mut self, module_id: LocalModuleId) {
+    /// Imports the legacy macros of the `#[macro_use]` module `module_id`, declared by `decl`.
+    fn import_all_legacy_macros(&mut self, module_id: LocalModuleId, decl: FileAstId<ast::Module>) {
+        let visible_from = self.position_of(decl);
         let macros = self.def_collector.def_map[module_id].scope.collect_legacy_macros();
         for (name, macro_) in macros {
-            self.def_collector.define_legacy_macro(self.module_id, name.clone(), macro_);
+            self.def_collector.define_legacy_macro(
+                self.module_id,
+                name.clone(),
+                macro_,
+                Some(visible_from),
+            );
         }
     }
 
+    /// Returns the position of the item `ast_id` in the original source file.
+    fn position_of<N: AstNode>(&self, ast_id: FileAstId<N>) -> FilePosition {
+        let db = self.def_collector.db;
+        let node = InFile::new(self.file_id, ast_id).to_node(db.upcast());
+        let range = InFile::new(self.file_id, node.syntax()).original_file_range(db.upcast());
+        FilePosition { file_id: range.file_id, offset: range.range.start() }
+    }
+
     fn is_cfg_enabled(&self, cfg: &CfgExpr) -> bool {
         self.def_collector.cfg_options.check(cfg) != Some(false)
     }
@@ -1438,7 +1612,6 @@ impl ModCollector<'_, '_> {
 #[cfg(test)]
 mod tests {
     use crate::{db::DefDatabase, test_db::TestDB};
-    use arena::Arena;
     use base_db::{fixture::WithFixture, SourceDatabase};
 
     use super::*;
@@ -1455,9 +1628,10 @@ mod tests {
             mod_dirs: FxHashMap::default(),
             cfg_options: &CfgOptions::default(),
             proc_macros: Default::default(),
-            exports_proc_macros: false,
+            is_proc_macro: false,
             from_glob_import: Default::default(),
         };
+        collector.seed_with_top_level();
         collector.collect();
         collector.def_map
     }
@@ -1466,20 +1640,8 @@ mod tests {
         let (db, _file_id) = TestDB::with_single_file(&code);
         let krate = db.test_crate();
 
-        let def_map = {
-            let edition = db.crate_graph()[krate].edition;
-            let mut modules: Arena<ModuleData> = Arena::default();
-            let root = modules.alloc(ModuleData::default());
-            CrateDefMap {
-                krate,
-                edition,
-                extern_prelude: FxHashMap::default(),
-                prelude: None,
-                root,
-                modules,
-                diagnostics: Vec::new(),
-            }
-        };
+        let edition = db.crate_graph()[krate].edition;
+        let def_map = CrateDefMap::empty(krate, edition);
         do_collect_defs(&db, def_map)
     }
 
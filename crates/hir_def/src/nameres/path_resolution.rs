@@ -10,8 +10,6 @@
 //!
 //! `ReachedFixedPoint` signals about this.
 
-use std::iter::successors;
-
 use base_db::Edition;
 use hir_expand::name::Name;
 use test_utils::mark;
@@ -23,7 +21,7 @@ use crate::{
     path::{ModPath, PathKind},
     per_ns::PerNs,
     visibility::{RawVisibility, Visibility},
-    AdtId, CrateId, EnumVariantId, LocalModuleId, ModuleDefId, ModuleId,
+    AdtId, CrateId, EnumVariantId, LocalModuleId, ModuleDefId,
 };
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -44,6 +42,9 @@ pub(super) struct ResolvePathResult {
     pub(super) segment_index: Option<usize>,
     pub(super) reached_fixedpoint: ReachedFixedPoint,
     pub(super) krate: Option<CrateId>,
+    /// The item each segment but the last resolved to in the types namespace, with the
+    /// visibility it was found with.
+    pub(super) segment_visibilities: Vec<(ModuleDefId, Visibility)>,
 }
 
 impl ResolvePathResult {
@@ -57,12 +58,26 @@ impl ResolvePathResult {
         segment_index: Option<usize>,
         krate: Option<CrateId>,
     ) -> ResolvePathResult {
-        ResolvePathResult { resolved_def, reached_fixedpoint, segment_index, krate }
+        ResolvePathResult {
+            resolved_def,
+            reached_fixedpoint,
+            segment_index,
+            krate,
+            segment_visibilities: Vec::new(),
+        }
     }
 }
 
 impl CrateDefMap {
-    pub(super) fn resolve_name_in_extern_prelude(&self, name: &Name) -> PerNs {
+    pub(super) fn resolve_name_in_extern_prelude(
+        &self,
+        db: &dyn DefDatabase,
+        name: &Name,
+    ) -> PerNs {
+        if self.block.is_some() {
+            // Block def maps share the extern prelude of their crate.
+            return self.crate_root(db).def_map(db).resolve_name_in_extern_prelude(db, name);
+        }
         self.extern_prelude
             .get(name)
             .map_or(PerNs::none(), |&it| PerNs::types(it, Visibility::Public))
@@ -97,6 +112,58 @@ impl CrateDefMap {
     // Returns Yes if we are sure that additions to `ItemMap` wouldn't change
     // the result.
     pub(super) fn resolve_path_fp_with_macro(
+        &self,
+        db: &dyn DefDatabase,
+        mode: ResolveMode,
+        mut original_module: LocalModuleId,
+        path: &ModPath,
+        shadow: BuiltinShadowMode,
+    ) -> ResolvePathResult {
+        let mut result = ResolvePathResult::empty(ReachedFixedPoint::No);
+        let mut arc;
+        let mut current_map = self;
+        loop {
+            let new = current_map.resolve_path_fp_with_macro_single(
+                db,
+                mode,
+                original_module,
+                path,
+                shadow,
+            );
+
+            // Merge `new` into `result`.
+            if result.resolved_def.is_none() {
+                result.segment_visibilities = new.segment_visibilities;
+            }
+            result.resolved_def = result.resolved_def.or(new.resolved_def);
+            if result.reached_fixedpoint == ReachedFixedPoint::No {
+                result.reached_fixedpoint = new.reached_fixedpoint;
+            }
+            result.krate = result.krate.or(new.krate);
+            result.segment_index = match (result.segment_index, new.segment_index) {
+                (Some(old), Some(new)) => Some(old.max(new)),
+                (old, new) => old.or(new),
+            };
+
+            // Names that aren't found in a block are looked up in the enclosing scope. All other
+            // kinds of paths are either resolved from the crate root, or have already walked
+            // out of the block in `resolve_path_fp_with_macro_single`.
+            let is_scope_relative = match path.kind {
+                PathKind::Plain | PathKind::DefSite(..) => true,
+                _ => false,
+            };
+            match current_map.block {
+                Some(block) if is_scope_relative => {
+                    original_module = block.parent.local_id;
+                    arc = block.parent.def_map(db);
+                    current_map = &*arc;
+                }
+                _ => return result,
+            }
+        }
+    }
+
+    fn resolve_path_fp_with_macro_single(
         &self,
         db: &dyn DefDatabase,
         mode: ResolveMode,
@@ -109,21 +176,45 @@ impl CrateDefMap {
             PathKind::DollarCrate(krate) => {
                 if krate == self.krate {
                     mark::hit!(macro_dollar_crate_self);
-                    PerNs::types(
-                        ModuleId { krate: self.krate, local_id: self.root }.into(),
-                        Visibility::Public,
-                    )
+                    PerNs::types(self.crate_root(db).into(), Visibility::Public)
                 } else {
                     let def_map = db.crate_def_map(krate);
-                    let module = ModuleId { krate, local_id: def_map.root };
+                    let module = def_map.module_id(def_map.root);
                     mark::hit!(macro_dollar_crate_other);
                     PerNs::types(module.into(), Visibility::Public)
                 }
             }
-            PathKind::Crate => PerNs::types(
-                ModuleId { krate: self.krate, local_id: self.root }.into(),
-                Visibility::Public,
-            ),
+            PathKind::DefSite(krate, def) => {
+                let path = ModPath { kind: PathKind::Plain, segments: path.segments.clone() };
+                if krate == self.krate {
+                    let def_module = match self.macro_def_sites.get(&def) {
+                        Some(&it) => it,
+                        None => return ResolvePathResult::empty(ReachedFixedPoint::No),
+                    };
+                    return self
+                        .resolve_path_fp_with_macro_single(db, mode, def_module, &path, shadow);
+                }
+
+                let def_map = db.crate_def_map(krate);
+                let def_module = match def_map.macro_def_sites.get(&def) {
+                    Some(&it) => it,
+                    None => return ResolvePathResult::empty(ReachedFixedPoint::Yes),
+                };
+                log::debug!("resolving {:?} at definition site in other crate", path);
+                let res = def_map.resolve_path_fp_with_macro(
+                    db,
+                    ResolveMode::Other,
+                    def_module,
+                    &path,
+                    shadow,
+                );
+                return ResolvePathResult {
+                    reached_fixedpoint: ReachedFixedPoint::Yes,
+                    krate: Some(krate),
+                    ..res
+                };
+            }
+            PathKind::Crate => PerNs::types(self.crate_root(db).into(), Visibility::Public),
             // plain import or absolute path in 2015: crate-relative with
             // fallback to extern prelude (with the simplification in
             // rust-lang/rust#57745)
@@ -137,7 +228,7 @@ impl CrateDefMap {
                     None => return ResolvePathResult::empty(ReachedFixedPoint::Yes),
                 };
                 log::debug!("resolving {:?} in crate root (+ extern prelude)", segment);
-                self.resolve_name_in_crate_root_or_extern_prelude(&segment)
+                self.resolve_name_in_crate_root_or_extern_prelude(db, &segment)
             }
             PathKind::Plain => {
                 let (_, segment) = match segments.next() {
@@ -157,17 +248,35 @@ impl CrateDefMap {
                 self.resolve_name_in_module(db, original_module, &segment, prefer_module)
             }
             PathKind::Super(lvl) => {
-                let m = successors(Some(original_module), |m| self.modules[*m].parent)
-                    .nth(lvl as usize);
-                if let Some(local_id) = m {
-                    PerNs::types(
-                        ModuleId { krate: self.krate, local_id }.into(),
-                        Visibility::Public,
-                    )
-                } else {
-                    log::debug!("super path in root module");
-                    return ResolvePathResult::empty(ReachedFixedPoint::Yes);
+                let mut module = original_module;
+                let mut lvl = lvl;
+                loop {
+                    if let (Some(block), true) = (self.block, module == self.root) {
+                        // Blocks are not named modules, so `self` and `super` refer to (the
+                        // ancestors of) the module containing the block.
+                        let path =
+                            ModPath { kind: PathKind::Super(lvl), segments: path.segments.clone() };
+                        return block.parent.def_map(db).resolve_path_fp_with_macro(
+                            db,
+                            mode,
+                            block.parent.local_id,
+                            &path,
+                            shadow,
+                        );
+                    }
+                    if lvl == 0 {
+                        break;
+                    }
+                    match self.modules[module].parent {
+                        Some(parent) => module = parent,
+                        None => {
+                            log::debug!("super path in root module");
+                            return ResolvePathResult::empty(ReachedFixedPoint::Yes);
+                        }
+                    }
+                    lvl -= 1;
                 }
+                PerNs::types(self.module_id(module).into(), Visibility::Public)
             }
             PathKind::Abs => {
                 // 2018-style absolute path -- only extern prelude
@@ -175,15 +284,17 @@ impl CrateDefMap {
                     Some((_, segment)) => segment,
                     None => return ResolvePathResult::empty(ReachedFixedPoint::Yes),
                 };
-                if let Some(def) = self.extern_prelude.get(&segment) {
+                let def = self.resolve_name_in_extern_prelude(db, &segment);
+                if !def.is_none() {
                     log::debug!("absolute path {:?} resolved to crate {:?}", path, def);
-                    PerNs::types(*def, Visibility::Public)
+                    def
                 } else {
                     return ResolvePathResult::empty(ReachedFixedPoint::No); // extern crate declarations can add to the extern prelude
                 }
             }
         };
 
+        let mut segment_visibilities = Vec::new();
         for (i, segment) in segments {
             let (curr, vis) = match curr_per_ns.take_types_vis() {
                 Some(r) => r,
@@ -196,6 +307,7 @@ impl CrateDefMap {
                     return ResolvePathResult::empty(ReachedFixedPoint::No);
                 }
             };
+            segment_visibilities.push((curr, vis));
             // resolve segment in curr
 
             curr_per_ns = match curr {
@@ -206,14 +318,52 @@ impl CrateDefMap {
                             kind: PathKind::Super(0),
                         };
                         log::debug!("resolving {:?} in other crate", path);
-                        let defp_map = db.crate_def_map(module.krate);
-                        let (def, s) = defp_map.resolve_path(db, module.local_id, &path, shadow);
-                        return ResolvePathResult::with(
-                            def,
-                            ReachedFixedPoint::Yes,
-                            s.map(|s| s + i),
-                            Some(module.krate),
+                        let defp_map = module.def_map(db);
+                        let res = defp_map.resolve_path_fp_with_macro(
+                            db,
+                            ResolveMode::Other,
+                            module.local_id,
+                            &path,
+                            shadow,
                         );
+                        segment_visibilities.extend(res.segment_visibilities);
+                        return ResolvePathResult {
+                            segment_visibilities,
+                            ..ResolvePathResult::with(
+                                res.resolved_def,
+                                ReachedFixedPoint::Yes,
+                                res.segment_index.map(|s| s + i),
+                                Some(module.krate),
+                            )
+                        };
+                    }
+
+                    if module.block != self.block_id() {
+                        // The module belongs to an enclosing block or to the crate-level
+                        // `CrateDefMap`, both of which are already complete.
+                        let path = ModPath {
+                            segments: path.segments[i..].to_vec(),
+                            kind: PathKind::Super(0),
+                        };
+                        log::debug!("resolving {:?} in enclosing scope", path);
+                        let def_map = module.def_map(db);
+                        let res = def_map.resolve_path_fp_with_macro(
+                            db,
+                            ResolveMode::Other,
+                            module.local_id,
+                            &path,
+                            shadow,
+                        );
+                        segment_visibilities.extend(res.segment_visibilities);
+                        return ResolvePathResult {
+                            segment_visibilities,
+                            ..ResolvePathResult::with(
+                                res.resolved_def,
+                                ReachedFixedPoint::Yes,
+                                res.segment_index.map(|s| s + i),
+                                Some(self.krate),
+                            )
+                        };
                     }
 
                     // Since it is a qualified path here, it should not contains legacy macros
@@ -237,12 +387,15 @@ impl CrateDefMap {
                             }
                         }
                         None => {
-                            return ResolvePathResult::with(
-                                PerNs::types(e.into(), vis),
-                                ReachedFixedPoint::Yes,
-                                Some(i),
-                                Some(self.krate),
-                            );
+                            return ResolvePathResult {
+                                segment_visibilities,
+                                ..ResolvePathResult::with(
+                                    PerNs::types(e.into(), vis),
+                                    ReachedFixedPoint::Yes,
+                                    Some(i),
+                                    Some(self.krate),
+                                )
+                            };
                         }
                     }
                 }
@@ -255,17 +408,23 @@ impl CrateDefMap {
                         curr,
                     );
 
-                    return ResolvePathResult::with(
-                        PerNs::types(s, vis),
-                        ReachedFixedPoint::Yes,
-                        Some(i),
-                        Some(self.krate),
-                    );
+                    return ResolvePathResult {
+                        segment_visibilities,
+                        ..ResolvePathResult::with(
+                            PerNs::types(s, vis),
+                            ReachedFixedPoint::Yes,
+                            Some(i),
+                            Some(self.krate),
+                        )
+                    };
                 }
             };
         }
 
-        ResolvePathResult::with(curr_per_ns, ReachedFixedPoint::Yes, None, Some(self.krate))
+        ResolvePathResult {
+            segment_visibilities,
+            ..ResolvePathResult::with(curr_per_ns, ReachedFixedPoint::Yes, None, Some(self.krate))
+        }
     }
 
     fn resolve_name_in_module(
@@ -285,6 +444,11 @@ impl CrateDefMap {
             .get_legacy_macro(name)
             .map_or_else(PerNs::none, |m| PerNs::macros(m, Visibility::Public));
         let from_scope = self[module].scope.get(name);
+        if self.block.is_some() {
+            // Builtins and preludes are resolved by the crate-level `CrateDefMap`, after all
+            // enclosing scopes had a chance to shadow them.
+            return from_legacy_macro.or(from_scope);
+        }
         let from_builtin = BUILTIN_SCOPE.get(name).copied().unwrap_or_else(PerNs::none);
         let from_scope_or_builtin = match shadow {
             BuiltinShadowMode::Module => from_scope.or(from_builtin),
@@ -305,9 +469,21 @@ impl CrateDefMap {
         from_legacy_macro.or(from_scope_or_builtin).or(from_extern_prelude).or(from_prelude)
     }
 
-    fn resolve_name_in_crate_root_or_extern_prelude(&self, name: &Name) -> PerNs {
-        let from_crate_root = self[self.root].scope.get(name);
-        let from_extern_prelude = self.resolve_name_in_extern_prelude(name);
+    fn resolve_name_in_crate_root_or_extern_prelude(
+        &self,
+        db: &dyn DefDatabase,
+        name: &Name,
+    ) -> PerNs {
+        let arc;
+        let crate_def_map = match self.block {
+            Some(_) => {
+                arc = self.crate_root(db).def_map(db);
+                &*arc
+            }
+            None => self,
+        };
+        let from_crate_root = crate_def_map[crate_def_map.root].scope.get(name);
+        let from_extern_prelude = self.resolve_name_in_extern_prelude(db, name);
 
         from_crate_root.or(from_extern_prelude)
     }
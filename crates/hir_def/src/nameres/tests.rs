@@ -7,7 +7,12 @@ mod primitives;
 
 use std::sync::Arc;
 
-use base_db::{fixture::WithFixture, SourceDatabase};
+use base_db::{
+    fixture::{ChangeFixture, WithFixture},
+    CrateGraph, CrateId, CrateKind, CrateName, Edition, Env, FileId, ProcMacro, ProcMacroExpander,
+    ProcMacroKind, SourceDatabase,
+};
+use cfg::CfgOptions;
 use expect_test::{expect, Expect};
 use test_utils::mark;
 
@@ -25,6 +30,41 @@ fn check(ra_fixture: &str, expect: Expect) {
     expect.assert_eq(&actual);
 }
 
+/// Sets up a crate rooted at the first file of `ra_fixture`, which depends on the proc macro
+/// crate `macros` rooted at the second file. `macros` exports `expander` as the derive `name`.
+fn with_proc_macro_derive(
+    ra_fixture: &str,
+    name: &str,
+    expander: Arc<dyn ProcMacroExpander>,
+) -> (TestDB, CrateId, Vec<FileId>) {
+    let fixture = ChangeFixture::parse(ra_fixture);
+    let mut db = TestDB::default();
+    fixture.change.apply(&mut db);
+
+    let mut crate_graph = CrateGraph::default();
+    let krate = crate_graph.add_crate_root(
+        fixture.files[0],
+        Edition::Edition2018,
+        CrateKind::Lib,
+        None,
+        CfgOptions::default(),
+        Env::default(),
+        Vec::new(),
+    );
+    let macros = crate_graph.add_crate_root(
+        fixture.files[1],
+        Edition::Edition2018,
+        CrateKind::ProcMacro,
+        None,
+        CfgOptions::default(),
+        Env::default(),
+        vec![ProcMacro { name: name.into(), kind: ProcMacroKind::CustomDerive, expander }],
+    );
+    crate_graph.add_dep(krate, CrateName::new("macros").unwrap(), macros).unwrap();
+    db.set_crate_graph(Arc::new(crate_graph));
+    (db, krate, fixture.files)
+}
+
 #[test]
 fn crate_def_map_smoke_test() {
     check(
@@ -1,7 +1,11 @@
-use base_db::fixture::WithFixture;
+use std::sync::Arc;
+
+use base_db::{fixture::WithFixture, Env, ProcMacroExpander, ProcMacroExpansion};
+use hir_expand::DiagnosticLevel;
 use test_utils::mark;
 
-use crate::test_db::TestDB;
+use super::with_proc_macro_derive;
+use crate::{db::DefDatabase, diagnostics::MacroError, test_db::TestDB};
 
 fn check_diagnostics(ra_fixture: &str) {
     let db: TestDB = TestDB::with_files(ra_fixture);
@@ -137,3 +141,104 @@ fn inactive_via_cfg_attr() {
         "#,
     );
 }
+
+#[test]
+fn eager_macro_error_in_item_position() {
+    check_diagnostics(
+        r#"
+        #[rustc_builtin_macro]
+        macro_rules! compile_error { () => {} }
+
+        compile_error! { "compile_error works" }
+      //^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^ compile_error works
+        "#,
+    );
+}
+
+#[test]
+fn macro_recursion_limit() {
+    check_diagnostics(
+        r#"
+        #![recursion_limit = "2"]
+
+        macro_rules! a { () => { b!(); } }
+        macro_rules! b { () => { c!(); } }
+        macro_rules! c { () => {} }
+
+        a!();
+      //^^^^^ reached recursion limit during macro expansion
+        "#,
+    );
+}
+
+#[test]
+fn unresolved_macro_call() {
+    check_diagnostics(
+        r#"
+        macro_rules! m { () => {} }
+        m!();
+        missing!();
+      //^^^^^^^ unresolved macro `missing!`
+        foo::bar!();
+      //^^^^^^^^ unresolved macro `foo::bar!`
+        "#,
+    );
+}
+
+#[test]
+fn proc_macro_diagnostics_keep_expansion() {
+    #[derive(Debug)]
+    struct WarningExpander;
+
+    impl ProcMacroExpander for WarningExpander {
+        fn expand(
+            &self,
+            subtree: &tt::Subtree,
+            _attrs: Option<&tt::Subtree>,
+            _env: &Env,
+        ) -> Result<ProcMacroExpansion, tt::ExpansionError> {
+            let span = match subtree.token_trees.first() {
+                Some(tt::TokenTree::Leaf(tt::Leaf::Ident(ident))) => ident.id,
+                _ => tt::TokenId::unspecified(),
+            };
+            Ok(ProcMacroExpansion {
+                subtree: mbe::parse_to_token_tree("struct Generated;").unwrap().0,
+                diagnostics: vec![tt::Diagnostic {
+                    level: tt::DiagnosticLevel::Warning,
+                    message: "deprecated".to_string(),
+                    spans: vec![span],
+                    children: Vec::new(),
+                }],
+                ..ProcMacroExpansion::default()
+            })
+        }
+    }
+
+    let (db, krate, _) = with_proc_macro_derive(
+        r#"
+//- /main.rs
+use macros::Warn;
+
+#[derive(Warn)]
+struct S;
+//- /macros.rs
+#[proc_macro_derive(Warn)]
+pub fn warn() {}
+"#,
+        "Warn",
+        Arc::new(WarningExpander),
+    );
+
+    let def_map = db.crate_def_map(krate);
+    // The warning doesn't stop the expansion from being collected.
+    let scope = &def_map[def_map.root].scope;
+    assert!(scope.entries().any(|(name, _)| name.to_string() == "Generated"));
+
+    let mut errors = Vec::new();
+    db.diagnostics(|d| {
+        if let Some(d) = d.as_any().downcast_ref::<MacroError>() {
+            errors.push((d.message.clone(), d.level, d.precise_location.is_some()));
+        }
+    });
+    assert_eq!(errors, vec![("deprecated".to_string(), DiagnosticLevel::Warning, true)]);
+}
@@ -1,6 +1,7 @@
 use std::sync::Arc;
 
-use base_db::SourceDatabaseExt;
+use base_db::{Env, ProcMacroExpander, ProcMacroExpansion, SourceDatabaseExt};
+use hir_expand::proc_macro::ProcMacroDatabase;
 
 use super::*;
 
@@ -105,3 +106,118 @@ fn typing_inside_a_macro_should_not_invalidate_def_map() {
         assert!(!format!("{:?}", events).contains("crate_def_map"), "{:#?}", events)
     }
 }
+
+#[test]
+fn editing_a_tracked_file_reexpands_proc_macro() {
+    #[derive(Debug)]
+    struct QueryExpander;
+
+    impl ProcMacroExpander for QueryExpander {
+        fn expand(
+            &self,
+            _subtree: &tt::Subtree,
+            _attrs: Option<&tt::Subtree>,
+            _env: &Env,
+        ) -> Result<ProcMacroExpansion, tt::ExpansionError> {
+            Ok(ProcMacroExpansion {
+                subtree: mbe::parse_to_token_tree("struct Query;").unwrap().0,
+                tracked_paths: vec!["queries/user.sql".into()],
+                ..ProcMacroExpansion::default()
+            })
+        }
+    }
+
+    let (mut db, krate, files) = with_proc_macro_derive(
+        r#"
+//- /main.rs
+use macros::Query;
+
+#[derive(Query)]
+struct User;
+//- /macros.rs
+#[proc_macro_derive(Query)]
+pub fn query() {}
+//- /queries/user.sql
+SELECT * FROM users
+//- /queries/post.sql
+SELECT * FROM posts
+"#,
+        "Query",
+        Arc::new(QueryExpander),
+    );
+    {
+        let events = db.log_executed(|| {
+            db.crate_def_map(krate);
+        });
+        assert!(format!("{:?}", events).contains("expand_proc_macro"), "{:#?}", events)
+    }
+
+    db.set_file_text(files[3], Arc::new("SELECT id FROM posts".to_string()));
+    {
+        let events = db.log_executed(|| {
+            db.crate_def_map(krate);
+        });
+        assert!(!format!("{:?}", events).contains("expand_proc_macro"), "{:#?}", events)
+    }
+
+    db.set_file_text(files[2], Arc::new("SELECT id FROM users".to_string()));
+    {
+        let events = db.log_executed(|| {
+            db.crate_def_map(krate);
+        });
+        assert!(format!("{:?}", events).contains("expand_proc_macro"), "{:#?}", events)
+    }
+}
+
+#[test]
+fn changing_the_server_env_reexpands_proc_macro() {
+    #[derive(Debug)]
+    struct EnvExpander;
+
+    impl ProcMacroExpander for EnvExpander {
+        fn expand(
+            &self,
+            _subtree: &tt::Subtree,
+            _attrs: Option<&tt::Subtree>,
+            _env: &Env,
+        ) -> Result<ProcMacroExpansion, tt::ExpansionError> {
+            Ok(ProcMacroExpansion {
+                subtree: mbe::parse_to_token_tree("struct Query;").unwrap().0,
+                tracked_env: vec![("DATABASE_URL".into(), None)],
+                ..ProcMacroExpansion::default()
+            })
+        }
+    }
+
+    let (mut db, krate, _) = with_proc_macro_derive(
+        r#"
+//- /main.rs
+use macros::Query;
+
+#[derive(Query)]
+struct User;
+//- /macros.rs
+#[proc_macro_derive(Query)]
+pub fn query() {}
+"#,
+        "Query",
+        Arc::new(EnvExpander),
+    );
+    db.set_proc_macro_env(Arc::new(Env::default()));
+    {
+        let events = db.log_executed(|| {
+            db.crate_def_map(krate);
+        });
+        assert!(format!("{:?}", events).contains("expand_proc_macro"), "{:#?}", events)
+    }
+
+    let mut env = Env::default();
+    env.set("DATABASE_URL", "sqlite::memory:".to_string());
+    db.set_proc_macro_env(Arc::new(env));
+    {
+        let events = db.log_executed(|| {
+            db.crate_def_map(krate);
+        });
+        assert!(format!("{:?}", events).contains("expand_proc_macro"), "{:#?}", events)
+    }
+}
@@ -534,6 +534,79 @@ mod m {
     );
 }
 
+#[test]
+fn macro_def_is_module_scoped() {
+    check(
+        r#"
+m::make!(ViaPath);
+make!(NotVisible);
+
+mod m {
+    pub macro make($x:ident) {
+        struct $x;
+    }
+    macro private($x:ident) {
+        struct $x;
+    }
+}
+
+mod n {
+    use crate::m::make;
+    make!(ViaImport);
+    crate::m::private!(NotAccessible);
+}
+"#,
+        expect![[r#"
+            crate
+            ViaPath: t v
+            m: t
+            n: t
+
+            crate::m
+            make: m
+            private: m
+
+            crate::n
+            ViaImport: t v
+            make: m
+        "#]],
+    );
+}
+
+#[test]
+fn macro_def_resolves_paths_at_def_site() {
+    check(
+        r#"
+//- /main.rs crate:main deps:lib
+m::alias!(Local);
+lib::alias!(Extern);
+
+mod m {
+    struct Hidden;
+    pub macro alias($name:ident) {
+        use Hidden as $name;
+    }
+}
+
+//- /lib.rs crate:lib
+struct Private;
+pub macro alias($name:ident) {
+    use Private as $name;
+}
+"#,
+        expect![[r#"
+            crate
+            Extern: t v
+            Local: t v
+            m: t
+
+            crate::m
+            Hidden: t v
+            alias: m
+        "#]],
+    );
+}
+
 #[test]
 fn macro_dollar_crate_is_correct_in_item() {
     mark::check!(macro_dollar_crate_self);
@@ -670,6 +743,38 @@ pub trait Clone {}
     );
 }
 
+#[test]
+fn eager_macros_in_macro_output() {
+    check(
+        r#"
+//- /main.rs crate:main env:OUT_DIR=/out
+#[rustc_builtin_macro]
+macro_rules! include { () => {} }
+
+#[rustc_builtin_macro]
+macro_rules! concat { () => {} }
+
+#[rustc_builtin_macro]
+macro_rules! env { () => {} }
+
+macro_rules! include_proto {
+    ($name:literal) => {
+        include!(concat!(env!("OUT_DIR"), "/", $name));
+    };
+}
+
+include_proto!("generated.rs");
+
+//- /out/generated.rs
+pub struct Message;
+"#,
+        expect![[r#"
+            crate
+            Message: t v
+        "#]],
+    );
+}
+
 #[test]
 fn macro_expansion_overflow() {
     mark::check!(macro_expansion_overflow);
@@ -701,6 +806,7 @@ b! { static = #[] ();}
 fn resolves_proc_macros() {
     check(
         r"
+        //- /lib.rs kind:proc-macro
         struct TokenStream;
 
         #[proc_macro]
@@ -745,7 +851,7 @@ fn proc_macro_censoring() {
         //- /main.rs crate:main deps:macros
         pub use macros::*;
 
-        //- /macros.rs crate:macros
+        //- /macros.rs crate:macros kind:proc-macro
         pub struct TokenStream;
 
         #[proc_macro]
@@ -776,3 +882,45 @@ fn proc_macro_censoring() {
         "#]],
     );
 }
+
+#[test]
+fn proc_macros_only_in_proc_macro_crate_root() {
+    check(
+        r"
+        //- /main.rs crate:main deps:macros,lib
+        pub use macros::*;
+        pub use lib::*;
+
+        //- /macros.rs crate:macros kind:proc-macro
+        pub struct TokenStream;
+
+        #[proc_macro]
+        pub fn function_like_macro(args: TokenStream) -> TokenStream {
+            args
+        }
+
+        pub mod inner {
+            use super::TokenStream;
+
+            #[proc_macro]
+            pub fn nested_macro(args: TokenStream) -> TokenStream {
+                args
+            }
+        }
+
+        //- /lib.rs crate:lib
+        pub struct TokenStream;
+
+        #[proc_macro_derive(NotAMacro)]
+        pub fn derive_macro(_item: TokenStream) -> TokenStream {
+            TokenStream
+        }
+        ",
+        expect![[r#"
+            crate
+            TokenStream: t v
+            derive_macro: v
+            function_like_macro: m
+        "#]],
+    );
+}
@@ -14,7 +14,7 @@ use syntax::ast;
 
 use crate::{
     type_ref::{TypeBound, TypeRef},
-    InFile,
+    AstId, InFile,
 };
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -33,6 +33,8 @@ pub enum PathKind {
     Abs,
     /// `$crate` from macro expansion
     DollarCrate(CrateId),
+    /// Path written in the body of a `macro` item, resolved at its definition site
+    DefSite(CrateId, AstId<ast::Macro>),
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -74,6 +76,7 @@ impl ModPath {
                 PathKind::Crate => 1,
                 PathKind::Abs => 0,
                 PathKind::DollarCrate(_) => 1,
+                PathKind::DefSite(..) => 0,
             }
     }
 
@@ -277,7 +280,7 @@ impl Display for ModPath {
             Ok(())
         };
         match self.kind {
-            PathKind::Plain => {}
+            PathKind::Plain | PathKind::DefSite(..) => {}
             PathKind::Super(0) => add_segment("self")?,
             PathKind::Super(n) => {
                 for _ in 0..n {
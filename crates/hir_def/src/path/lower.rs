@@ -117,6 +117,15 @@ pub(super) fn lower_path(mut path: ast::Path, hygiene: &Hygiene) -> Option<Path>
     segments.reverse();
     generic_args.reverse();
 
+    // Names written in the body of a `macro` item resolve at its definition site.
+    if kind == PathKind::Plain && type_anchor.is_none() {
+        if let Some(ast::PathSegmentKind::Name(name_ref)) = path.segment().and_then(|s| s.kind()) {
+            if let Some((krate, def)) = hygiene.def_site(&name_ref) {
+                kind = PathKind::DefSite(krate, def);
+            }
+        }
+    }
+
     // handle local_inner_macros :
     // Basically, even in rustc it is quite hacky:
     // https://github.com/rust-lang/rust/blob/614f273e9388ddd7804d5cbc80b8865068a3744e/src/librustc_resolve/macros.rs#L456
@@ -72,12 +72,17 @@ fn convert_path(prefix: Option<ModPath>, path: ast::Path, hygiene: &Hygiene) ->
     let segment = path.segment()?;
     let res = match segment.kind()? {
         ast::PathSegmentKind::Name(name_ref) => {
+            let def_site = hygiene.def_site(&name_ref);
             match hygiene.name_ref_to_name(name_ref) {
                 Either::Left(name) => {
                     // no type args in use
-                    let mut res = prefix.unwrap_or_else(|| ModPath {
-                        kind: segment.coloncolon_token().map_or(PathKind::Plain, |_| PathKind::Abs),
-                        segments: Vec::with_capacity(1),
+                    let mut res = prefix.unwrap_or_else(|| {
+                        let kind = match (segment.coloncolon_token(), def_site) {
+                            (Some(_), _) => PathKind::Abs,
+                            (None, Some((krate, def))) => PathKind::DefSite(krate, def),
+                            (None, None) => PathKind::Plain,
+                        };
+                        ModPath { kind, segments: Vec::with_capacity(1) }
                     });
                     res.segments.push(name);
                     res
@@ -1,8 +1,9 @@
 //! Name resolution façade.
 use std::sync::Arc;
 
-use base_db::CrateId;
+use base_db::{CrateId, FilePosition};
 use hir_expand::{
+    hygiene::SyntaxContext,
     name::{name, Name},
     MacroDefId,
 };
@@ -10,7 +11,6 @@ use rustc_hash::FxHashSet;
 
 use crate::{
     body::scope::{ExprScopes, ScopeId},
-    body::Body,
     builtin_type::BuiltinType,
     db::DefDatabase,
     expr::{ExprId, PatId},
@@ -20,9 +20,9 @@ use crate::{
     path::{ModPath, PathKind},
     per_ns::PerNs,
     visibility::{RawVisibility, Visibility},
-    AdtId, AssocContainerId, ConstId, ConstParamId, ContainerId, DefWithBodyId, EnumId,
-    EnumVariantId, FunctionId, GenericDefId, HasModule, ImplId, LocalModuleId, Lookup, ModuleDefId,
-    ModuleId, StaticId, StructId, TraitId, TypeAliasId, TypeParamId, VariantId,
+    AdtId, AssocContainerId, ConstId, ConstParamId, DefWithBodyId, EnumId, EnumVariantId,
+    FunctionId, GenericDefId, HasModule, ImplId, LocalModuleId, Lookup, ModuleDefId, ModuleId,
+    StaticId, StructId, TraitId, TypeAliasId, TypeParamId, VariantId,
 };
 
 #[derive(Debug, Clone, Default)]
@@ -47,7 +47,8 @@ struct ExprScope {
 
 #[derive(Debug, Clone)]
 enum Scope {
-    /// All the items and imported names of a module
+    /// All the items and imported names of a module (or of a block expression, in which case
+    /// names that are not found fall back to the enclosing module)
     ModuleScope(ModuleItemMap),
     /// Brings the generic parameters of an item into scope
     GenericParams { def: GenericDefId, params: Arc<GenericParams> },
@@ -57,8 +58,6 @@ enum Scope {
     AdtScope(AdtId),
     /// Local bindings
     ExprScope(ExprScope),
-    /// Temporary hack to support local items.
-    LocalItemsScope(Arc<Body>),
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -151,17 +150,11 @@ impl Resolver {
         path: &ModPath,
     ) -> Option<(TypeNs, Option<usize>)> {
         let first_name = path.segments.first()?;
-        let skip_to_mod = path.kind != PathKind::Plain;
+        let skip_to_mod = !matches!(path.kind, PathKind::Plain | PathKind::DefSite(..));
         for scope in self.scopes.iter().rev() {
             match scope {
                 Scope::ExprScope(_) => continue,
-                Scope::GenericParams { .. }
-                | Scope::ImplDefScope(_)
-                | Scope::LocalItemsScope(_)
-                    if skip_to_mod =>
-                {
-                    continue
-                }
+                Scope::GenericParams { .. } | Scope::ImplDefScope(_) if skip_to_mod => continue,
 
                 Scope::GenericParams { params, def } => {
                     if let Some(local_id) = params.find_type_by_name(first_name) {
@@ -191,13 +184,13 @@ impl Resolver {
                         &path,
                         BuiltinShadowMode::Other,
                     );
-                    let res = to_type_ns(module_def)?;
-                    return Some((res, idx));
-                }
-                Scope::LocalItemsScope(body) => {
-                    let def = body.item_scope.get(first_name);
-                    if let Some(res) = to_type_ns(def) {
-                        return Some((res, None));
+                    // Names that are not declared in a block may still refer to generic
+                    // parameters of the enclosing item, so keep looking.
+                    if let Some(res) = to_type_ns(module_def) {
+                        return Some((res, idx));
+                    }
+                    if m.crate_def_map.block_id().is_none() {
+                        return None;
                     }
                 }
             }
@@ -251,22 +244,36 @@ impl Resolver {
         }
     }
 
+    /// `hygiene` is the syntax context of the path's first segment; local
+    /// variables are only found if they were introduced in the same context.
     pub fn resolve_path_in_value_ns(
         &self,
         db: &dyn DefDatabase,
         path: &ModPath,
+        hygiene: SyntaxContext,
     ) -> Option<ResolveValueResult> {
+        self.resolve_path_in_value_ns_with_private_segment(db, path, hygiene).map(|(it, _)| it)
+    }
+
+    /// Like `resolve_path_in_value_ns`, but also returns the first item named by a leading
+    /// segment of `path` which isn't visible from the resolver's module.
+    pub fn resolve_path_in_value_ns_with_private_segment(
+        &self,
+        db: &dyn DefDatabase,
+        path: &ModPath,
+        hygiene: SyntaxContext,
+    ) -> Option<(ResolveValueResult, Option<ModuleDefId>)> {
         let n_segments = path.segments.len();
         let tmp = name![self];
         let first_name = if path.is_self() { &tmp } else { &path.segments.first()? };
-        let skip_to_mod = path.kind != PathKind::Plain && !path.is_self();
+        let skip_to_mod =
+            !matches!(path.kind, PathKind::Plain | PathKind::DefSite(..)) && !path.is_self();
         for scope in self.scopes.iter().rev() {
             match scope {
                 Scope::AdtScope(_)
                 | Scope::ExprScope(_)
                 | Scope::GenericParams { .. }
                 | Scope::ImplDefScope(_)
-                | Scope::LocalItemsScope(_)
                     if skip_to_mod =>
                 {
                     continue
@@ -277,10 +284,11 @@ impl Resolver {
                         .expr_scopes
                         .entries(scope.scope_id)
                         .iter()
-                        .find(|entry| entry.name() == first_name);
+                        .find(|entry| entry.name() == first_name && entry.hygiene() == hygiene);
 
                     if let Some(e) = entry {
-                        return Some(ResolveValueResult::ValueNs(ValueNs::LocalBinding(e.pat())));
+                        let value = ValueNs::LocalBinding(e.pat());
+                        return Some((ResolveValueResult::ValueNs(value), None));
                     }
                 }
                 Scope::ExprScope(_) => continue,
@@ -288,13 +296,13 @@ impl Resolver {
                 Scope::GenericParams { params, def } if n_segments > 1 => {
                     if let Some(local_id) = params.find_type_by_name(first_name) {
                         let ty = TypeNs::GenericParam(TypeParamId { local_id, parent: *def });
-                        return Some(ResolveValueResult::Partial(ty, 1));
+                        return Some((ResolveValueResult::Partial(ty, 1), None));
                     }
                 }
                 Scope::GenericParams { params, def } if n_segments == 1 => {
                     if let Some(local_id) = params.find_const_by_name(first_name) {
                         let val = ValueNs::GenericParam(ConstParamId { local_id, parent: *def });
-                        return Some(ResolveValueResult::ValueNs(val));
+                        return Some((ResolveValueResult::ValueNs(val), None));
                     }
                 }
                 Scope::GenericParams { .. } => continue,
@@ -303,9 +311,12 @@ impl Resolver {
                     if first_name == &name![Self] {
                         if n_segments > 1 {
                             let ty = TypeNs::SelfType(*impl_);
-                            return Some(ResolveValueResult::Partial(ty, 1));
+                            return Some((ResolveValueResult::Partial(ty, 1), None));
                         } else {
-                            return Some(ResolveValueResult::ValueNs(ValueNs::ImplSelf(*impl_)));
+                            return Some((
+                                ResolveValueResult::ValueNs(ValueNs::ImplSelf(*impl_)),
+                                None,
+                            ));
                         }
                     }
                 }
@@ -316,24 +327,26 @@ impl Resolver {
                     }
                     if first_name == &name![Self] {
                         let ty = TypeNs::AdtSelfType(*adt);
-                        return Some(ResolveValueResult::Partial(ty, 1));
+                        return Some((ResolveValueResult::Partial(ty, 1), None));
                     }
                 }
 
                 Scope::ModuleScope(m) => {
-                    let (module_def, idx) = m.crate_def_map.resolve_path(
-                        db,
-                        m.module_id,
-                        &path,
-                        BuiltinShadowMode::Other,
-                    );
-                    return match idx {
-                        None => {
-                            let value = to_value_ns(module_def)?;
-                            Some(ResolveValueResult::ValueNs(value))
-                        }
+                    let (module_def, idx, segment_visibilities) =
+                        m.crate_def_map.resolve_path_with_segment_visibilities(
+                            db,
+                            m.module_id,
+                            &path,
+                            BuiltinShadowMode::Other,
+                        );
+                    let res = match idx {
+                        None => to_value_ns(module_def).map(ResolveValueResult::ValueNs),
                         Some(idx) => {
-                            let ty = match module_def.take_types()? {
+                            let ty = match module_def.take_types() {
+                                Some(it) => it,
+                                None => continue,
+                            };
+                            let ty = match ty {
                                 ModuleDefId::AdtId(it) => TypeNs::AdtId(it),
                                 ModuleDefId::TraitId(it) => TypeNs::TraitId(it),
                                 ModuleDefId::TypeAliasId(it) => TypeNs::TypeAliasId(it),
@@ -348,13 +361,15 @@ impl Resolver {
                             Some(ResolveValueResult::Partial(ty, idx))
                         }
                     };
-                }
-                Scope::LocalItemsScope(body) => {
-                    // we don't bother looking in the builtin scope here because there are no builtin values
-                    let def = to_value_ns(body.item_scope.get(first_name));
-
-                    if let Some(res) = def {
-                        return Some(ResolveValueResult::ValueNs(res));
+                    // A block's def map only knows about the items of the block; local bindings
+                    // declared outside of the block are still in scope.
+                    if res.is_some() || m.crate_def_map.block_id().is_none() {
+                        let from_module = m.crate_def_map.module_id(m.module_id);
+                        let private_segment = segment_visibilities
+                            .into_iter()
+                            .find(|(_, vis)| !vis.is_visible_from(db, from_module))
+                            .map(|(def, _)| def);
+                        return res.map(|it| (it, private_segment));
                     }
                 }
             }
@@ -384,8 +399,9 @@ impl Resolver {
         &self,
         db: &dyn DefDatabase,
         path: &ModPath,
+        hygiene: SyntaxContext,
     ) -> Option<ValueNs> {
-        match self.resolve_path_in_value_ns(db, path)? {
+        match self.resolve_path_in_value_ns(db, path, hygiene)? {
             ResolveValueResult::ValueNs(it) => Some(it),
             ResolveValueResult::Partial(..) => None,
         }
@@ -396,18 +412,37 @@ impl Resolver {
         db: &dyn DefDatabase,
         path: &ModPath,
     ) -> Option<MacroDefId> {
-        // Search item scope legacy macro first
-        if let Some(def) = self.resolve_local_macro_def(path) {
-            return Some(def);
-        }
-
         let (item_map, module) = self.module_scope()?;
         item_map.resolve_path(db, module, &path, BuiltinShadowMode::Other).0.take_macros()
     }
 
+    /// Resolves the path of a macro call at `position`, taking the textual shadowing of
+    /// `macro_rules!` macros into account.
+    pub fn resolve_path_as_macro_at(
+        &self,
+        db: &dyn DefDatabase,
+        path: &ModPath,
+        position: FilePosition,
+    ) -> Option<MacroDefId> {
+        let (item_map, module) = self.module_scope()?;
+        item_map.resolve_macro_call_path(db, module, path, position)
+    }
+
     pub fn process_all_names(&self, db: &dyn DefDatabase, f: &mut dyn FnMut(Name, ScopeDef)) {
         for scope in self.scopes.iter().rev() {
-            scope.process_names(db, f);
+            scope.process_names(db, None, f);
+        }
+    }
+
+    /// Like `process_all_names`, but only lists the `macro_rules!` macros visible at `position`.
+    pub fn process_all_names_at(
+        &self,
+        db: &dyn DefDatabase,
+        position: FilePosition,
+        f: &mut dyn FnMut(Name, ScopeDef),
+    ) {
+        for scope in self.scopes.iter().rev() {
+            scope.process_names(db, Some(position), f);
         }
     }
 
@@ -415,11 +450,23 @@ impl Resolver {
         let mut traits = FxHashSet::default();
         for scope in &self.scopes {
             if let Scope::ModuleScope(m) = scope {
-                if let Some(prelude) = m.crate_def_map.prelude {
-                    let prelude_def_map = db.crate_def_map(prelude.krate);
-                    traits.extend(prelude_def_map[prelude.local_id].scope.traits());
+                let mut def_map = m.crate_def_map.clone();
+                let mut module_id = m.module_id;
+                loop {
+                    if let Some(prelude) = def_map.prelude {
+                        let prelude_def_map = db.crate_def_map(prelude.krate);
+                        traits.extend(prelude_def_map[prelude.local_id].scope.traits());
+                    }
+                    traits.extend(def_map[module_id].scope.traits());
+                    // Traits declared in enclosing blocks and modules are in scope as well.
+                    match def_map.parent() {
+                        Some(parent) => {
+                            module_id = parent.local_id;
+                            def_map = parent.def_map(db);
+                        }
+                        None => break,
+                    }
                 }
-                traits.extend(m.crate_def_map[m.module_id].scope.traits());
             }
         }
         traits
@@ -433,19 +480,9 @@ impl Resolver {
         })
     }
 
-    fn resolve_local_macro_def(&self, path: &ModPath) -> Option<MacroDefId> {
-        let name = path.as_ident()?;
-        self.scopes.iter().rev().find_map(|scope| {
-            if let Scope::LocalItemsScope(body) = scope {
-                return body.item_scope.get_legacy_macro(name);
-            }
-            None
-        })
-    }
-
     pub fn module(&self) -> Option<ModuleId> {
         let (def_map, local_id) = self.module_scope()?;
-        Some(ModuleId { krate: def_map.krate, local_id })
+        Some(def_map.module_id(local_id))
     }
 
     pub fn krate(&self) -> Option<CrateId> {
@@ -489,7 +526,12 @@ pub enum ScopeDef {
 }
 
 impl Scope {
-    fn process_names(&self, db: &dyn DefDatabase, f: &mut dyn FnMut(Name, ScopeDef)) {
+    fn process_names(
+        &self,
+        db: &dyn DefDatabase,
+        position: Option<FilePosition>,
+        f: &mut dyn FnMut(Name, ScopeDef),
+    ) {
         match self {
             Scope::ModuleScope(m) => {
                 // FIXME: should we provide `self` here?
@@ -502,9 +544,32 @@ impl Scope {
                 m.crate_def_map[m.module_id].scope.entries().for_each(|(name, def)| {
                     f(name.clone(), ScopeDef::PerNs(def));
                 });
-                m.crate_def_map[m.module_id].scope.legacy_macros().for_each(|(name, macro_)| {
+                let scope = &m.crate_def_map[m.module_id].scope;
+                // A block inherits the `macro_rules!` macros of the enclosing module, which are
+                // already listed by the module's scope.
+                let parent = m.crate_def_map.parent().map(|it| (it.def_map(db), it.local_id));
+                let add_legacy_macro = |(name, macro_): (&Name, MacroDefId)| {
+                    if let Some((def_map, local_id)) = &parent {
+                        let parent_scope = &def_map[*local_id].scope;
+                        let inherited = match position {
+                            Some(position) => parent_scope.get_legacy_macro_at(name, position),
+                            None => parent_scope.get_legacy_macro(name),
+                        };
+                        if inherited == Some(macro_) {
+                            return;
+                        }
+                    }
                     f(name.clone(), ScopeDef::PerNs(PerNs::macros(macro_, Visibility::Public)));
-                });
+                };
+                match position {
+                    Some(position) => scope.legacy_macros_at(position).for_each(add_legacy_macro),
+                    None => scope.legacy_macros().for_each(add_legacy_macro),
+                }
+                if m.crate_def_map.block_id().is_some() {
+                    // The enclosing module (and the builtins and preludes) are reached via the
+                    // parent scopes.
+                    return;
+                }
                 m.crate_def_map.extern_prelude.iter().for_each(|(name, &def)| {
                     f(name.clone(), ScopeDef::PerNs(PerNs::types(def, Visibility::Public)));
                 });
@@ -518,9 +583,6 @@ impl Scope {
                     });
                 }
             }
-            Scope::LocalItemsScope(body) => body.item_scope.entries().for_each(|(name, def)| {
-                f(name.clone(), ScopeDef::PerNs(def));
-            }),
             Scope::GenericParams { params, def } => {
                 for (local_id, param) in params.types.iter() {
                     if let Some(name) = &param.name {
@@ -538,9 +600,15 @@ impl Scope {
                 f(name![Self], ScopeDef::AdtSelfType(*i));
             }
             Scope::ExprScope(scope) => {
-                scope.expr_scopes.entries(scope.scope_id).iter().for_each(|e| {
-                    f(e.name().clone(), ScopeDef::Local(e.pat()));
-                });
+                // Bindings introduced by macro expansions are not nameable by the user.
+                scope
+                    .expr_scopes
+                    .entries(scope.scope_id)
+                    .iter()
+                    .filter(|e| e.hygiene().is_root())
+                    .for_each(|e| {
+                        f(e.name().clone(), ScopeDef::Local(e.pat()));
+                    });
             }
         }
     }
@@ -558,10 +626,16 @@ pub fn resolver_for_scope(
     scope_id: Option<ScopeId>,
 ) -> Resolver {
     let mut r = owner.resolver(db);
-    r = r.push_local_items_scope(db.body(owner));
     let scopes = db.expr_scopes(owner);
     let scope_chain = scopes.scope_chain(scope_id).collect::<Vec<_>>();
     for scope in scope_chain.into_iter().rev() {
+        if let Some(block) = scopes.block(scope) {
+            if let Some(def_map) = db.block_def_map(block) {
+                let root = def_map.root;
+                r = r.push_module_scope(def_map, root);
+            }
+        }
+
         r = r.push_expr_scope(owner, Arc::clone(&scopes), scope);
     }
     r
@@ -590,10 +664,6 @@ impl Resolver {
         self.push_scope(Scope::ModuleScope(ModuleItemMap { crate_def_map, module_id }))
     }
 
-    fn push_local_items_scope(self, body: Arc<Body>) -> Resolver {
-        self.push_scope(Scope::LocalItemsScope(body))
-    }
-
     fn push_expr_scope(
         self,
         owner: DefWithBodyId,
@@ -611,8 +681,20 @@ pub trait HasResolver: Copy {
 
 impl HasResolver for ModuleId {
     fn resolver(self, db: &dyn DefDatabase) -> Resolver {
-        let def_map = db.crate_def_map(self.krate);
-        Resolver::default().push_module_scope(def_map, self.local_id)
+        let mut def_map = self.def_map(db);
+        let mut module_id = self.local_id;
+        let mut modules = vec![(def_map.clone(), module_id)];
+        while let Some(parent) = def_map.parent() {
+            def_map = parent.def_map(db);
+            module_id = parent.local_id;
+            modules.push((def_map.clone(), module_id));
+        }
+
+        let mut resolver = Resolver::default();
+        for (def_map, module_id) in modules.into_iter().rev() {
+            resolver = resolver.push_module_scope(def_map, module_id);
+        }
+        resolver
     }
 }
 
@@ -676,19 +758,10 @@ impl HasResolver for DefWithBodyId {
     }
 }
 
-impl HasResolver for ContainerId {
-    fn resolver(self, db: &dyn DefDatabase) -> Resolver {
-        match self {
-            ContainerId::ModuleId(it) => it.resolver(db),
-            ContainerId::DefWithBodyId(it) => it.module(db).resolver(db),
-        }
-    }
-}
-
 impl HasResolver for AssocContainerId {
     fn resolver(self, db: &dyn DefDatabase) -> Resolver {
         match self {
-            AssocContainerId::ContainerId(it) => it.resolver(db),
+            AssocContainerId::ModuleId(it) => it.resolver(db),
             AssocContainerId::TraitId(it) => it.resolver(db),
             AssocContainerId::ImplId(it) => it.resolver(db),
         }
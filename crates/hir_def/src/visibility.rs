@@ -1,5 +1,8 @@
 //! Defines hir-level representation of visibility (e.g. `pub` and `pub(crate)`).
 
+use std::sync::Arc;
+
+use arena::map::ArenaMap;
 use hir_expand::{hygiene::Hygiene, InFile};
 use syntax::ast;
 
@@ -7,7 +10,8 @@ use crate::{
     db::DefDatabase,
     nameres::CrateDefMap,
     path::{ModPath, PathKind},
-    ModuleId,
+    resolver::HasResolver,
+    FunctionId, LocalFieldId, ModuleId, VariantId,
 };
 
 /// Visibility of an item, not yet resolved.
@@ -103,8 +107,8 @@ impl Visibility {
         if from_module.krate != to_module.krate {
             return false;
         }
-        let def_map = db.crate_def_map(from_module.krate);
-        self.is_visible_from_def_map(&def_map, from_module.local_id)
+        let def_map = from_module.def_map(db);
+        self.is_visible_from_def_map(db, &def_map, from_module.local_id)
     }
 
     pub(crate) fn is_visible_from_other_crate(self) -> bool {
@@ -116,26 +120,50 @@ impl Visibility {
 
     pub(crate) fn is_visible_from_def_map(
         self,
+        db: &dyn DefDatabase,
         def_map: &CrateDefMap,
-        from_module: crate::LocalModuleId,
+        mut from_module: crate::LocalModuleId,
     ) -> bool {
         let to_module = match self {
             Visibility::Module(m) => m,
             Visibility::Public => return true,
         };
-        // from_module needs to be a descendant of to_module
-        let mut ancestors = std::iter::successors(Some(from_module), |m| {
-            let parent_id = def_map[*m].parent?;
-            Some(parent_id)
-        });
-        ancestors.any(|m| m == to_module.local_id)
+        if to_module.krate != def_map.krate {
+            return false;
+        }
+
+        // from_module needs to be a descendant of to_module. Block expressions are
+        // descendants of the module they are in.
+        let mut arc;
+        let mut def_map = def_map;
+        loop {
+            if def_map.block_id() == to_module.block && from_module == to_module.local_id {
+                return true;
+            }
+            match def_map[from_module].parent {
+                Some(parent) => from_module = parent,
+                None => match def_map.parent() {
+                    Some(parent) => {
+                        arc = parent.def_map(db);
+                        def_map = &*arc;
+                        from_module = parent.local_id;
+                    }
+                    None => return false,
+                },
+            }
+        }
     }
 
     /// Returns the most permissive visibility of `self` and `other`.
     ///
     /// If there is no subset relation between `self` and `other`, returns `None` (ie. they're only
     /// visible in unrelated modules).
-    pub(crate) fn max(self, other: Visibility, def_map: &CrateDefMap) -> Option<Visibility> {
+    pub(crate) fn max(
+        self,
+        other: Visibility,
+        db: &dyn DefDatabase,
+        def_map: &CrateDefMap,
+    ) -> Option<Visibility> {
         match (self, other) {
             (Visibility::Module(_), Visibility::Public)
             | (Visibility::Public, Visibility::Module(_))
@@ -145,21 +173,22 @@ impl Visibility {
                     return None;
                 }
 
-                let mut a_ancestors = std::iter::successors(Some(mod_a.local_id), |m| {
-                    let parent_id = def_map[*m].parent?;
-                    Some(parent_id)
-                });
-                let mut b_ancestors = std::iter::successors(Some(mod_b.local_id), |m| {
-                    let parent_id = def_map[*m].parent?;
-                    Some(parent_id)
-                });
+                // `def_map` may still be under construction, so only go through the database for
+                // modules of other (enclosing) maps.
+                let is_visible_from = |vis: Visibility, from: ModuleId| {
+                    if from.block == def_map.block_id() {
+                        vis.is_visible_from_def_map(db, def_map, from.local_id)
+                    } else {
+                        vis.is_visible_from(db, from)
+                    }
+                };
 
-                if a_ancestors.any(|m| m == mod_b.local_id) {
+                if is_visible_from(other, mod_a) {
                     // B is above A
                     return Some(Visibility::Module(mod_b));
                 }
 
-                if b_ancestors.any(|m| m == mod_a.local_id) {
+                if is_visible_from(self, mod_b) {
                     // A is above B
                     return Some(Visibility::Module(mod_a));
                 }
@@ -169,3 +198,29 @@ impl Visibility {
         }
     }
 }
+
+/// Resolves the visibility of all fields of a variant.
+pub(crate) fn field_visibilities_query(
+    db: &dyn DefDatabase,
+    variant_id: VariantId,
+) -> Arc<ArenaMap<LocalFieldId, Visibility>> {
+    let var_data = match variant_id {
+        VariantId::StructId(it) => db.struct_data(it).variant_data.clone(),
+        VariantId::UnionId(it) => db.union_data(it).variant_data.clone(),
+        VariantId::EnumVariantId(it) => {
+            db.enum_data(it.parent).variants[it.local_id].variant_data.clone()
+        }
+    };
+    let resolver = variant_id.resolver(db);
+    let mut res = ArenaMap::default();
+    for (field_id, field_data) in var_data.fields().iter() {
+        res.insert(field_id, field_data.visibility.resolve(db, &resolver))
+    }
+    Arc::new(res)
+}
+
+/// Resolves the visibility of a function.
+pub(crate) fn function_visibility_query(db: &dyn DefDatabase, def: FunctionId) -> Visibility {
+    let resolver = def.resolver(db);
+    db.function_data(def).visibility.resolve(db, &resolver)
+}
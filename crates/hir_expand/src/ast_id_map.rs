@@ -1,5 +1,5 @@
-//! `AstIdMap` allows to create stable IDs for "large" syntax nodes like items
-//! and macro calls.
+//! `AstIdMap` allows to create stable IDs for "large" syntax nodes like items,
+//! block expressions and macro calls.
 //!
 //! Specifically, it enumerates all items in a file and uses position of a an
 //! item as an ID. That way, id's don't change unless the set of items itself
@@ -7,6 +7,7 @@
 
 use std::{
     any::type_name,
+    cmp::Ordering,
     fmt,
     hash::{Hash, Hasher},
     marker::PhantomData,
@@ -34,6 +35,16 @@ impl<N: AstNode> PartialEq for FileAstId<N> {
     }
 }
 impl<N: AstNode> Eq for FileAstId<N> {}
+impl<N: AstNode> PartialOrd for FileAstId<N> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl<N: AstNode> Ord for FileAstId<N> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.raw.into_raw().cmp(&other.raw.into_raw())
+    }
+}
 impl<N: AstNode> Hash for FileAstId<N> {
     fn hash<H: Hasher>(&self, hasher: &mut H) {
         self.raw.hash(hasher);
@@ -73,8 +84,11 @@ impl AstIdMap {
         // change parent's id. This means that, say, adding a new function to a
         // trait does not change ids of top-level items, which helps caching.
         bfs(node, |it| {
-            if let Some(module_item) = ast::Item::cast(it) {
+            if let Some(module_item) = ast::Item::cast(it.clone()) {
                 res.alloc(module_item.syntax());
+            } else if let Some(block) = ast::BlockExpr::cast(it) {
+                // Blocks can contain items, which makes them anonymous modules.
+                res.alloc(block.syntax());
             }
         });
         res
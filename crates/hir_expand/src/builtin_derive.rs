@@ -4,8 +4,12 @@ use log::debug;
 
 use parser::FragmentKind;
 use syntax::{
-    ast::{self, AstNode, GenericParamsOwner, ModuleItemOwner, NameOwner},
-    match_ast,
+    ast::{
+        self, AstNode, AttrsOwner, GenericParamsOwner, ModuleItemOwner, NameOwner, TypeBoundsOwner,
+    },
+    match_ast, SmolStr, SyntaxKind,
+    SyntaxKind::IDENT,
+    SyntaxNode, T,
 };
 
 use crate::{db::AstDatabase, name, quote, AstId, CrateId, LazyMacroId, MacroDefId, MacroDefKind};
@@ -69,7 +73,105 @@ pub fn find_builtin_derive(
 
 struct BasicAdtInfo {
     name: tt::Ident,
-    type_params: usize,
+    shape: AdtShape,
+    params: Vec<GenericParam>,
+    where_predicates: Vec<tt::Subtree>,
+    /// Field types which are associated types of type parameters, like `T::Item`. Like rustc, we
+    /// require them to implement the derived trait as well.
+    assoc_field_types: Vec<tt::Subtree>,
+}
+
+enum AdtShape {
+    Struct(VariantShape),
+    Enum { variants: Vec<(tt::Ident, VariantShape)>, default_variant: Option<usize> },
+    Union,
+}
+
+enum VariantShape {
+    Struct(Vec<tt::Ident>),
+    Tuple(usize),
+    Unit,
+}
+
+/// A generic parameter of the ADT, without its default.
+enum GenericParam {
+    Lifetime { param: tt::Subtree, lifetime: tt::Subtree },
+    Type { name: tt::Ident, bounds: Option<tt::Subtree> },
+    Const { name: tt::Ident, ty: tt::Subtree },
+}
+
+impl BasicAdtInfo {
+    /// The variants of the ADT, as the path used to match or construct them, the name used by
+    /// `Debug` and their fields.
+    fn variants(&self) -> Vec<(tt::Subtree, String, &VariantShape)> {
+        let name = &self.name;
+        match &self.shape {
+            AdtShape::Struct(shape) => vec![(quote! { #name }, display_name(name), shape)],
+            AdtShape::Enum { variants, .. } => variants
+                .iter()
+                .map(|(variant, shape)| {
+                    (quote! { #name :: #variant }, display_name(variant), shape)
+                })
+                .collect(),
+            AdtShape::Union => Vec::new(),
+        }
+    }
+
+    fn is_enum(&self) -> bool {
+        matches!(self.shape, AdtShape::Enum { .. })
+    }
+}
+
+impl VariantShape {
+    fn field_count(&self) -> usize {
+        match self {
+            VariantShape::Struct(fields) => fields.len(),
+            VariantShape::Tuple(n) => *n,
+            VariantShape::Unit => 0,
+        }
+    }
+
+    /// A pattern for this variant which binds the fields to `__{prefix}_{index}`.
+    fn pattern(&self, path: &tt::Subtree, prefix: &str) -> tt::Subtree {
+        self.construct(path, |i| {
+            let field = binding(prefix, i);
+            quote! { #field }
+        })
+    }
+
+    /// A pattern for this variant which ignores the fields.
+    fn wildcard_pattern(&self, path: &tt::Subtree) -> tt::Subtree {
+        let path = path.clone();
+        match self {
+            VariantShape::Struct(_) => quote! { #path { .. } },
+            VariantShape::Tuple(_) => quote! { #path (..) },
+            VariantShape::Unit => path,
+        }
+    }
+
+    /// Constructs this variant with the field at each index set to `field(index)`. Also used to
+    /// build patterns.
+    fn construct(
+        &self,
+        path: &tt::Subtree,
+        mut field: impl FnMut(usize) -> tt::Subtree,
+    ) -> tt::Subtree {
+        let path = path.clone();
+        match self {
+            VariantShape::Struct(fields) => {
+                let fields = comma_separated(fields.iter().enumerate().map(|(i, name)| {
+                    let value = field(i);
+                    quote! { #name : #value }
+                }));
+                quote! { #path { ##fields } }
+            }
+            VariantShape::Tuple(n) => {
+                let fields = comma_separated((0..*n).map(field));
+                quote! { #path ( ##fields ) }
+            }
+            VariantShape::Unit => path,
+        }
+    }
 }
 
 fn parse_adt(tt: &tt::Subtree) -> Result<BasicAdtInfo, mbe::ExpandError> {
@@ -83,11 +185,34 @@ fn parse_adt(tt: &tt::Subtree) -> Result<BasicAdtInfo, mbe::ExpandError> {
         mbe::ExpandError::NoMatchingRule
     })?;
     let node = item.syntax();
-    let (name, params) = match_ast! {
+    let (name, params, where_clause, shape) = match_ast! {
         match node {
-            ast::Struct(it) => (it.name(), it.generic_param_list()),
-            ast::Enum(it) => (it.name(), it.generic_param_list()),
-            ast::Union(it) => (it.name(), it.generic_param_list()),
+            ast::Struct(it) => {
+                let shape = variant_shape(it.field_list(), &token_map);
+                (it.name(), it.generic_param_list(), it.where_clause(), AdtShape::Struct(shape))
+            },
+            ast::Enum(it) => {
+                let mut default_variant = None;
+                let variants = it
+                    .variant_list()
+                    .into_iter()
+                    .flat_map(|it| it.variants())
+                    .filter_map(|variant| {
+                        let name = name_to_tt(&variant.name()?, &token_map);
+                        Some((variant, name))
+                    })
+                    .enumerate()
+                    .map(|(i, (variant, name))| {
+                        if variant.has_atom_attr("default") {
+                            default_variant = Some(i);
+                        }
+                        (name, variant_shape(variant.field_list(), &token_map))
+                    })
+                    .collect();
+                let shape = AdtShape::Enum { variants, default_variant };
+                (it.name(), it.generic_param_list(), it.where_clause(), shape)
+            },
+            ast::Union(it) => (it.name(), it.generic_param_list(), it.where_clause(), AdtShape::Union),
             _ => {
                 debug!("unexpected node is {:?}", node);
                 return Err(mbe::ExpandError::ConversionError)
@@ -103,66 +228,279 @@ fn parse_adt(tt: &tt::Subtree) -> Result<BasicAdtInfo, mbe::ExpandError> {
         mbe::ExpandError::ConversionError
     })?;
     let name_token = tt::Ident { id: name_token_id, text: name.text().clone() };
-    let type_params = params.map_or(0, |type_param_list| type_param_list.type_params().count());
-    Ok(BasicAdtInfo { name: name_token, type_params })
-}
 
-fn make_type_args(n: usize, bound: Vec<tt::TokenTree>) -> Vec<tt::TokenTree> {
-    let mut result = Vec::<tt::TokenTree>::new();
-    result.push(
-        tt::Leaf::Punct(tt::Punct {
-            char: '<',
-            spacing: tt::Spacing::Alone,
-            id: tt::TokenId::unspecified(),
+    let params: Vec<_> = params
+        .into_iter()
+        .flat_map(|it| it.generic_params())
+        .filter_map(|param| {
+            let param = match param {
+                ast::GenericParam::LifetimeParam(it) => GenericParam::Lifetime {
+                    param: node_to_tt(it.syntax(), &token_map),
+                    lifetime: node_to_tt(it.lifetime()?.syntax(), &token_map),
+                },
+                ast::GenericParam::TypeParam(it) => GenericParam::Type {
+                    name: name_to_tt(&it.name()?, &token_map),
+                    bounds: it.type_bound_list().map(|it| node_to_tt(it.syntax(), &token_map)),
+                },
+                ast::GenericParam::ConstParam(it) => GenericParam::Const {
+                    name: name_to_tt(&it.name()?, &token_map),
+                    ty: node_to_tt(it.ty()?.syntax(), &token_map),
+                },
+            };
+            Some(param)
         })
-        .into(),
-    );
-    for i in 0..n {
+        .collect();
+    let where_predicates = where_clause
+        .into_iter()
+        .flat_map(|it| it.predicates())
+        .map(|it| node_to_tt(it.syntax(), &token_map))
+        .collect();
+
+    let type_param_names: Vec<_> = params
+        .iter()
+        .filter_map(|it| match it {
+            GenericParam::Type { name, .. } => Some(name.text.clone()),
+            _ => None,
+        })
+        .collect();
+    let assoc_field_types = item
+        .syntax()
+        .descendants()
+        .filter(|it| ast::RecordField::can_cast(it.kind()) || ast::TupleField::can_cast(it.kind()))
+        .flat_map(|field| field.descendants().filter_map(ast::PathType::cast))
+        .filter(|ty| is_type_param_assoc_type(ty, &type_param_names))
+        .map(|ty| node_to_tt(ty.syntax(), &token_map))
+        .collect();
+
+    Ok(BasicAdtInfo { name: name_token, shape, params, where_predicates, assoc_field_types })
+}
+
+fn variant_shape(field_list: Option<ast::FieldList>, token_map: &mbe::TokenMap) -> VariantShape {
+    match field_list {
+        Some(ast::FieldList::RecordFieldList(it)) => VariantShape::Struct(
+            it.fields().filter_map(|it| Some(name_to_tt(&it.name()?, token_map))).collect(),
+        ),
+        Some(ast::FieldList::TupleFieldList(it)) => VariantShape::Tuple(it.fields().count()),
+        None => VariantShape::Unit,
+    }
+}
+
+/// Whether `ty` is a path starting with a type parameter, like `T::Item`, but not just `T`.
+fn is_type_param_assoc_type(ty: &ast::PathType, type_param_names: &[SmolStr]) -> bool {
+    let mut path = match ty.path() {
+        Some(it) => it,
+        None => return false,
+    };
+    if path.qualifier().is_none() {
+        return false;
+    }
+    while let Some(qualifier) = path.qualifier() {
+        path = qualifier;
+    }
+    match path.segment().and_then(|it| it.name_ref()) {
+        Some(name_ref) => type_param_names.contains(name_ref.text()),
+        None => false,
+    }
+}
+
+fn name_to_tt(name: &ast::Name, token_map: &mbe::TokenMap) -> tt::Ident {
+    let id = token_map
+        .token_by_range(name.syntax().text_range())
+        .unwrap_or_else(tt::TokenId::unspecified);
+    tt::Ident { id, text: name.text().clone() }
+}
+
+/// Converts a node of the parsed ADT back into tokens, keeping the ids of the original tokens so
+/// that they can be mapped back to the item.
+fn node_to_tt(node: &SyntaxNode, token_map: &mbe::TokenMap) -> tt::Subtree {
+    let (mut subtree, node_map) = mbe::syntax_node_to_token_tree(node).unwrap_or_default();
+    let offset = node.text_range().start();
+    let original_id = |id: tt::TokenId, kind: SyntaxKind| {
+        node_map
+            .range_by_token(id)
+            .and_then(|range| range.by_kind(kind))
+            .and_then(|range| token_map.token_by_range(range + offset))
+            .unwrap_or_else(tt::TokenId::unspecified)
+    };
+    remap_ids(&mut subtree, &original_id);
+    subtree
+}
+
+fn remap_ids(subtree: &mut tt::Subtree, f: &dyn Fn(tt::TokenId, SyntaxKind) -> tt::TokenId) {
+    if let Some(delimiter) = &mut subtree.delimiter {
+        delimiter.id = f(delimiter.id, T!['(']);
+    }
+    for tt in subtree.token_trees.iter_mut() {
+        match tt {
+            tt::TokenTree::Leaf(tt::Leaf::Ident(it)) => it.id = f(it.id, IDENT),
+            tt::TokenTree::Leaf(tt::Leaf::Literal(it)) => it.id = f(it.id, IDENT),
+            tt::TokenTree::Leaf(tt::Leaf::Punct(it)) => it.id = f(it.id, IDENT),
+            tt::TokenTree::Subtree(it) => remap_ids(it, f),
+        }
+    }
+}
+
+fn display_name(name: &tt::Ident) -> String {
+    name.text.trim_start_matches("r#").to_string()
+}
+
+fn binding(prefix: &str, index: usize) -> tt::Ident {
+    tt::Ident { text: format!("__{}_{}", prefix, index).into(), id: tt::TokenId::unspecified() }
+}
+
+fn string_literal(text: &str) -> tt::Literal {
+    tt::Literal { text: format!("{:?}", text).into(), id: tt::TokenId::unspecified() }
+}
+
+fn punct(char: char) -> tt::TokenTree {
+    tt::Leaf::Punct(tt::Punct { char, spacing: tt::Spacing::Alone, id: tt::TokenId::unspecified() })
+        .into()
+}
+
+fn comma_separated(items: impl IntoIterator<Item = tt::Subtree>) -> Vec<tt::TokenTree> {
+    let mut result = Vec::new();
+    for (i, item) in items.into_iter().enumerate() {
         if i > 0 {
-            result.push(
-                tt::Leaf::Punct(tt::Punct {
-                    char: ',',
-                    spacing: tt::Spacing::Alone,
-                    id: tt::TokenId::unspecified(),
-                })
-                .into(),
-            );
+            result.push(punct(','));
         }
-        result.push(
-            tt::Leaf::Ident(tt::Ident {
-                id: tt::TokenId::unspecified(),
-                text: format!("T{}", i).into(),
-            })
-            .into(),
-        );
-        result.extend(bound.iter().cloned());
+        result.push(item.into());
     }
-    result.push(
-        tt::Leaf::Punct(tt::Punct {
-            char: '>',
-            spacing: tt::Spacing::Alone,
-            id: tt::TokenId::unspecified(),
-        })
-        .into(),
-    );
     result
 }
 
 fn expand_simple_derive(
     tt: &tt::Subtree,
     trait_path: tt::Subtree,
+    make_body: impl FnOnce(&BasicAdtInfo) -> Result<tt::Subtree, mbe::ExpandError>,
 ) -> Result<tt::Subtree, mbe::ExpandError> {
     let info = parse_adt(tt)?;
+    let body = make_body(&info)?;
+    let trait_path = &trait_path;
+
+    let mut impl_params = Vec::new();
+    let mut type_args = Vec::new();
+    for param in info.params.iter() {
+        match param {
+            GenericParam::Lifetime { param, lifetime } => {
+                impl_params.push(param.clone());
+                type_args.push(lifetime.clone());
+            }
+            GenericParam::Type { name, bounds } => {
+                let param = match bounds {
+                    Some(bounds) => quote! { #name : #bounds + #trait_path },
+                    None => quote! { #name : #trait_path },
+                };
+                impl_params.push(param);
+                type_args.push(quote! { #name });
+            }
+            GenericParam::Const { name, ty } => {
+                impl_params.push(quote! { const #name : #ty });
+                type_args.push(quote! { #name });
+            }
+        }
+    }
+    let (impl_params, type_args) = if info.params.is_empty() {
+        (Vec::new(), Vec::new())
+    } else {
+        let impl_params = comma_separated(impl_params);
+        let type_args = comma_separated(type_args);
+        ((quote! { < ##impl_params > }).token_trees, (quote! { < ##type_args > }).token_trees)
+    };
+
+    let predicates = info
+        .where_predicates
+        .iter()
+        .cloned()
+        .chain(info.assoc_field_types.iter().map(|ty| quote! { #ty : #trait_path }));
+    let predicates = comma_separated(predicates);
+    let where_clause = if predicates.is_empty() {
+        Vec::new()
+    } else {
+        (quote! { where ##predicates }).token_trees
+    };
+
     let name = info.name;
-    let trait_path_clone = trait_path.token_trees.clone();
-    let bound = (quote! { : ##trait_path_clone }).token_trees;
-    let type_params = make_type_args(info.type_params, bound);
-    let type_args = make_type_args(info.type_params, Vec::new());
-    let trait_path = trait_path.token_trees;
     let expanded = quote! {
-        impl ##type_params ##trait_path for #name ##type_args {}
+        impl ##impl_params #trait_path for #name ##type_args ##where_clause { #body }
     };
-    Ok(expanded)
+    Ok(flatten(expanded))
+}
+
+/// Inlines the invisible groups `quote!` creates for interpolated subtrees, the parser only
+/// accepts them in expressions.
+fn flatten(subtree: tt::Subtree) -> tt::Subtree {
+    let mut token_trees = Vec::new();
+    for tt in subtree.token_trees {
+        match tt {
+            tt::TokenTree::Subtree(it) if it.delimiter.is_none() => {
+                token_trees.extend(flatten(it).token_trees)
+            }
+            tt::TokenTree::Subtree(it) => token_trees.push(flatten(it).into()),
+            leaf => token_trees.push(leaf),
+        }
+    }
+    tt::Subtree { delimiter: subtree.delimiter, token_trees }
+}
+
+/// `match self { .. }` with an arm per variant, binding the fields to `__self_{index}`.
+/// `make_value` gets the path, the name and the shape of the variant.
+fn match_self(
+    info: &BasicAdtInfo,
+    mut make_value: impl FnMut(&tt::Subtree, &str, &VariantShape) -> tt::Subtree,
+) -> tt::Subtree {
+    let variants = info.variants();
+    if variants.is_empty() {
+        return quote! { match *self {} };
+    }
+    let arms = variants.iter().map(|(path, name, shape)| {
+        let pattern = shape.pattern(path, "self");
+        let value = make_value(path, name, shape);
+        quote! { #pattern => #value }
+    });
+    let arms = comma_separated(arms);
+    quote! { match self { ##arms } }
+}
+
+/// `match (self, other) { .. }` with an arm per variant, binding the fields to `__self_{index}`
+/// and `__other_{index}`. `make_value` gets the shape of the variant, `fallback` is used when the
+/// variants differ.
+fn match_self_other(
+    info: &BasicAdtInfo,
+    mut make_value: impl FnMut(&VariantShape) -> tt::Subtree,
+    fallback: tt::Subtree,
+) -> tt::Subtree {
+    let variants = info.variants();
+    if variants.is_empty() {
+        return quote! { match *self {} };
+    }
+    let mut arms: Vec<_> = variants
+        .iter()
+        .map(|(path, _, shape)| {
+            let self_pattern = shape.pattern(path, "self");
+            let other_pattern = shape.pattern(path, "other");
+            let value = make_value(shape);
+            quote! { (#self_pattern, #other_pattern) => #value }
+        })
+        .collect();
+    if variants.len() > 1 {
+        arms.push(quote! { _ => #fallback });
+    }
+    let arms = comma_separated(arms);
+    quote! { match (self, other) { ##arms } }
+}
+
+/// The index of the variant `value` is, used to order variants in `PartialOrd` and `Ord`.
+fn variant_index(info: &BasicAdtInfo, value: tt::Ident) -> tt::Subtree {
+    let arms = info.variants().into_iter().enumerate().map(|(i, (path, _, shape))| {
+        let pattern = shape.wildcard_pattern(&path);
+        quote! { #pattern => #i }
+    });
+    let arms = comma_separated(arms);
+    quote! { match #value { ##arms } }
+}
+
+fn ident(text: &str) -> tt::Ident {
+    tt::Ident { text: text.into(), id: tt::TokenId::unspecified() }
 }
 
 fn find_builtin_crate(db: &dyn AstDatabase, id: LazyMacroId) -> tt::TokenTree {
@@ -183,13 +521,17 @@ fn find_builtin_crate(db: &dyn AstDatabase, id: LazyMacroId) -> tt::TokenTree {
     tt.token_trees[0].clone()
 }
 
+fn not_for_unions(trait_name: &str) -> mbe::ExpandError {
+    mbe::ExpandError::Other(format!("`{}` cannot be derived for unions", trait_name))
+}
+
 fn copy_expand(
     db: &dyn AstDatabase,
     id: LazyMacroId,
     tt: &tt::Subtree,
 ) -> Result<tt::Subtree, mbe::ExpandError> {
-    let krate = find_builtin_crate(db, id);
-    expand_simple_derive(tt, quote! { #krate::marker::Copy })
+    let krate = &find_builtin_crate(db, id);
+    expand_simple_derive(tt, quote! { #krate::marker::Copy }, |_| Ok(quote! {}))
 }
 
 fn clone_expand(
@@ -197,8 +539,24 @@ fn clone_expand(
     id: LazyMacroId,
     tt: &tt::Subtree,
 ) -> Result<tt::Subtree, mbe::ExpandError> {
-    let krate = find_builtin_crate(db, id);
-    expand_simple_derive(tt, quote! { #krate::clone::Clone })
+    let krate = &find_builtin_crate(db, id);
+    expand_simple_derive(tt, quote! { #krate::clone::Clone }, |info| {
+        let body = match info.shape {
+            // Unions can only derive `Clone` together with `Copy`.
+            AdtShape::Union => quote! { *self },
+            _ => match_self(info, |path, _, shape| {
+                shape.construct(path, |i| {
+                    let field = binding("self", i);
+                    quote! { #krate::clone::Clone::clone(#field) }
+                })
+            }),
+        };
+        Ok(quote! {
+            fn clone(&self) -> Self {
+                #body
+            }
+        })
+    })
 }
 
 fn default_expand(
@@ -206,8 +564,26 @@ fn default_expand(
     id: LazyMacroId,
     tt: &tt::Subtree,
 ) -> Result<tt::Subtree, mbe::ExpandError> {
-    let krate = find_builtin_crate(db, id);
-    expand_simple_derive(tt, quote! { #krate::default::Default })
+    let krate = &find_builtin_crate(db, id);
+    expand_simple_derive(tt, quote! { #krate::default::Default }, |info| {
+        let variants = info.variants();
+        let (path, _, shape) = match &info.shape {
+            AdtShape::Struct(_) => &variants[0],
+            AdtShape::Enum { default_variant: Some(idx), .. } => &variants[*idx],
+            AdtShape::Enum { default_variant: None, .. } => {
+                return Err(mbe::ExpandError::Other(
+                    "`Default` can only be derived for enums with a `#[default]` variant".into(),
+                ))
+            }
+            AdtShape::Union => return Err(not_for_unions("Default")),
+        };
+        let value = shape.construct(path, |_| quote! { #krate::default::Default::default() });
+        Ok(quote! {
+            fn default() -> Self {
+                #value
+            }
+        })
+    })
 }
 
 fn debug_expand(
@@ -215,8 +591,42 @@ fn debug_expand(
     id: LazyMacroId,
     tt: &tt::Subtree,
 ) -> Result<tt::Subtree, mbe::ExpandError> {
-    let krate = find_builtin_crate(db, id);
-    expand_simple_derive(tt, quote! { #krate::fmt::Debug })
+    let krate = &find_builtin_crate(db, id);
+    expand_simple_derive(tt, quote! { #krate::fmt::Debug }, |info| {
+        if let AdtShape::Union = info.shape {
+            return Err(not_for_unions("Debug"));
+        }
+        let body = match_self(info, |_, name, shape| {
+            let name = string_literal(name);
+            let mut value = Vec::new();
+            match shape {
+                VariantShape::Struct(fields) => {
+                    value.extend(quote! { f.debug_struct(#name) }.token_trees);
+                    for (i, field) in fields.iter().enumerate() {
+                        let field_name = string_literal(&display_name(field));
+                        let field = binding("self", i);
+                        value.extend(quote! { .field(#field_name, #field) }.token_trees);
+                    }
+                    value.extend(quote! { .finish() }.token_trees);
+                }
+                VariantShape::Tuple(n) => {
+                    value.extend(quote! { f.debug_tuple(#name) }.token_trees);
+                    for i in 0..*n {
+                        let field = binding("self", i);
+                        value.extend(quote! { .field(#field) }.token_trees);
+                    }
+                    value.extend(quote! { .finish() }.token_trees);
+                }
+                VariantShape::Unit => value.extend(quote! { f.write_str(#name) }.token_trees),
+            }
+            tt::Subtree { delimiter: None, token_trees: value }
+        });
+        Ok(quote! {
+            fn fmt(&self, f: &mut #krate::fmt::Formatter) -> #krate::fmt::Result {
+                #body
+            }
+        })
+    })
 }
 
 fn hash_expand(
@@ -224,8 +634,31 @@ fn hash_expand(
     id: LazyMacroId,
     tt: &tt::Subtree,
 ) -> Result<tt::Subtree, mbe::ExpandError> {
-    let krate = find_builtin_crate(db, id);
-    expand_simple_derive(tt, quote! { #krate::hash::Hash })
+    let krate = &find_builtin_crate(db, id);
+    expand_simple_derive(tt, quote! { #krate::hash::Hash }, |info| {
+        if let AdtShape::Union = info.shape {
+            return Err(not_for_unions("Hash"));
+        }
+        let discriminant = if info.is_enum() {
+            quote! { #krate::hash::Hash::hash(&#krate::mem::discriminant(self), state); }
+        } else {
+            quote! {}
+        };
+        let body = match_self(info, |_, _, shape| {
+            let fields = (0..shape.field_count()).map(|i| {
+                let field = binding("self", i);
+                quote! { #krate::hash::Hash::hash(#field, state); }
+            });
+            let fields: Vec<_> = fields.map(tt::TokenTree::from).collect();
+            quote! { { ##fields } }
+        });
+        Ok(quote! {
+            fn hash<__H: #krate::hash::Hasher>(&self, state: &mut __H) {
+                #discriminant
+                #body
+            }
+        })
+    })
 }
 
 fn eq_expand(
@@ -233,8 +666,11 @@ fn eq_expand(
     id: LazyMacroId,
     tt: &tt::Subtree,
 ) -> Result<tt::Subtree, mbe::ExpandError> {
-    let krate = find_builtin_crate(db, id);
-    expand_simple_derive(tt, quote! { #krate::cmp::Eq })
+    let krate = &find_builtin_crate(db, id);
+    expand_simple_derive(tt, quote! { #krate::cmp::Eq }, |info| match info.shape {
+        AdtShape::Union => Err(not_for_unions("Eq")),
+        _ => Ok(quote! {}),
+    })
 }
 
 fn partial_eq_expand(
@@ -242,8 +678,74 @@ fn partial_eq_expand(
     id: LazyMacroId,
     tt: &tt::Subtree,
 ) -> Result<tt::Subtree, mbe::ExpandError> {
-    let krate = find_builtin_crate(db, id);
-    expand_simple_derive(tt, quote! { #krate::cmp::PartialEq })
+    let krate = &find_builtin_crate(db, id);
+    expand_simple_derive(tt, quote! { #krate::cmp::PartialEq }, |info| {
+        if let AdtShape::Union = info.shape {
+            return Err(not_for_unions("PartialEq"));
+        }
+        let body = match_self_other(
+            info,
+            |shape| {
+                let mut value = Vec::new();
+                for i in 0..shape.field_count() {
+                    if i > 0 {
+                        value.extend(quote! { && }.token_trees);
+                    }
+                    let (lhs, rhs) = (binding("self", i), binding("other", i));
+                    value.extend(quote! { #krate::cmp::PartialEq::eq(#lhs, #rhs) }.token_trees);
+                }
+                if value.is_empty() {
+                    value.extend(quote! { true }.token_trees);
+                }
+                tt::Subtree { delimiter: None, token_trees: value }
+            },
+            quote! { false },
+        );
+        Ok(quote! {
+            fn eq(&self, other: &Self) -> bool {
+                #body
+            }
+        })
+    })
+}
+
+/// Shared by `PartialOrd` and `Ord`: compares the fields of equal variants in order, returning
+/// the first result which isn't `equal`, and orders different variants by their index.
+fn compare_body(
+    info: &BasicAdtInfo,
+    compare: tt::Subtree,
+    equal: tt::Subtree,
+) -> Result<tt::Subtree, mbe::ExpandError> {
+    if let AdtShape::Union = info.shape {
+        return Err(mbe::ExpandError::Other(
+            "`PartialOrd` and `Ord` cannot be derived for unions".into(),
+        ));
+    }
+    let (compare, equal) = (&compare, &equal);
+    let self_index = variant_index(info, ident("self"));
+    let other_index = variant_index(info, ident("other"));
+    let body = match_self_other(
+        info,
+        |shape| {
+            let mut value = Vec::new();
+            for i in 0..shape.field_count() {
+                let (lhs, rhs) = (binding("self", i), binding("other", i));
+                value.extend(
+                    quote! {
+                        match #compare(#lhs, #rhs) {
+                            #equal => {}
+                            cmp => return cmp,
+                        }
+                    }
+                    .token_trees,
+                );
+            }
+            value.extend(equal.token_trees.iter().cloned());
+            quote! { { ##value } }
+        },
+        quote! { #compare(&#self_index, &#other_index) },
+    );
+    Ok(body)
 }
 
 fn ord_expand(
@@ -251,8 +753,19 @@ fn ord_expand(
     id: LazyMacroId,
     tt: &tt::Subtree,
 ) -> Result<tt::Subtree, mbe::ExpandError> {
-    let krate = find_builtin_crate(db, id);
-    expand_simple_derive(tt, quote! { #krate::cmp::Ord })
+    let krate = &find_builtin_crate(db, id);
+    expand_simple_derive(tt, quote! { #krate::cmp::Ord }, |info| {
+        let body = compare_body(
+            info,
+            quote! { #krate::cmp::Ord::cmp },
+            quote! { #krate::cmp::Ordering::Equal },
+        )?;
+        Ok(quote! {
+            fn cmp(&self, other: &Self) -> #krate::cmp::Ordering {
+                #body
+            }
+        })
+    })
 }
 
 fn partial_ord_expand(
@@ -260,8 +773,19 @@ fn partial_ord_expand(
     id: LazyMacroId,
     tt: &tt::Subtree,
 ) -> Result<tt::Subtree, mbe::ExpandError> {
-    let krate = find_builtin_crate(db, id);
-    expand_simple_derive(tt, quote! { #krate::cmp::PartialOrd })
+    let krate = &find_builtin_crate(db, id);
+    expand_simple_derive(tt, quote! { #krate::cmp::PartialOrd }, |info| {
+        let body = compare_body(
+            info,
+            quote! { #krate::cmp::PartialOrd::partial_cmp },
+            quote! { #krate::option::Option::Some(#krate::cmp::Ordering::Equal) },
+        )?;
+        Ok(quote! {
+            fn partial_cmp(&self, other: &Self) -> #krate::option::Option<#krate::cmp::Ordering> {
+                #body
+            }
+        })
+    })
 }
 
 #[cfg(test)]
@@ -303,6 +827,7 @@ mod tests {
                 local_inner: false,
             },
             krate: CrateId(0),
+            module_path: None,
             kind: MacroCallKind::Attr(attr_id, name.to_string()),
         };
 
@@ -324,7 +849,7 @@ mod tests {
             known::Copy,
         );
 
-        assert_eq!(expanded, "impl< >core::marker::CopyforFoo< >{}");
+        assert_eq!(expanded, "implcore::marker::CopyforFoo{}");
     }
 
     #[test]
@@ -339,7 +864,7 @@ mod tests {
 
         assert_eq!(
             expanded,
-            "impl<T0:core::marker::Copy,T1:core::marker::Copy>core::marker::CopyforFoo<T0,T1>{}"
+            "impl<A:core::marker::Copy,B:core::marker::Copy>core::marker::CopyforFoo<A,B>{}"
         );
     }
 
@@ -353,11 +878,9 @@ mod tests {
             known::Copy,
         );
 
-        // We currently just ignore lifetimes
-
         assert_eq!(
             expanded,
-            "impl<T0:core::marker::Copy,T1:core::marker::Copy>core::marker::CopyforFoo<T0,T1>{}"
+            "impl<A:core::marker::Copy,B:core::marker::Copy, 'a, 'b>core::marker::CopyforFoo<A,B, 'a, 'b>{}"
         );
     }
 
@@ -373,7 +896,68 @@ mod tests {
 
         assert_eq!(
             expanded,
-            "impl<T0:core::clone::Clone,T1:core::clone::Clone>core::clone::CloneforFoo<T0,T1>{}"
+            "impl<A:core::clone::Clone,B:core::clone::Clone>core::clone::CloneforFoo<A,B>{fnclone(&self)->Self{matchself{Foo=>Foo}}}"
+        );
+    }
+
+    #[test]
+    fn test_clone_expand_with_assoc_type_field() {
+        let expanded = expand_builtin_derive(
+            r#"
+        #[derive(Clone)]
+        struct Foo<T: Iterator> where T: Copy { item: T::Item }
+"#,
+            known::Clone,
+        );
+
+        assert_eq!(
+            expanded,
+            "impl<T:Iterator+core::clone::Clone>core::clone::CloneforFoo<T>whereT:Copy,T::Item:core::clone::Clone{fnclone(&self)->Self{matchself{Foo{item:__self_0}=>Foo{item:core::clone::Clone::clone(__self_0)}}}}"
+        );
+    }
+
+    #[test]
+    fn test_debug_expand() {
+        let expanded = expand_builtin_derive(
+            r#"
+        #[derive(Debug)]
+        struct Foo { r#type: u8, b: u8 }
+"#,
+            known::Debug,
+        );
+
+        assert_eq!(
+            expanded,
+            r#"implcore::fmt::DebugforFoo{fnfmt(&self,f: &mutcore::fmt::Formatter)->core::fmt::Result{matchself{Foo{r#type:__self_0,b:__self_1}=>f.debug_struct("Foo").field("type",__self_0).field("b",__self_1).finish()}}}"#
+        );
+    }
+
+    #[test]
+    fn test_partial_eq_expand_enum() {
+        let expanded = expand_builtin_derive(
+            r#"
+        #[derive(PartialEq)]
+        enum Command { Move { x: i32 }, Jump(u8), Stop }
+"#,
+            known::PartialEq,
         );
+
+        assert_eq!(
+            expanded,
+            "implcore::cmp::PartialEqforCommand{fneq(&self,other: &Self)->bool{match(self,other){(Command::Move{x:__self_0},Command::Move{x:__other_0})=>core::cmp::PartialEq::eq(__self_0,__other_0),(Command::Jump(__self_0),Command::Jump(__other_0))=>core::cmp::PartialEq::eq(__self_0,__other_0),(Command::Stop,Command::Stop)=>true, _ =>false}}}"
+        );
+    }
+
+    #[test]
+    fn test_default_expand_enum() {
+        let expanded = expand_builtin_derive(
+            r#"
+        #[derive(Default)]
+        enum Foo { A(u8), #[default] B }
+"#,
+            known::Default,
+        );
+
+        assert_eq!(expanded, "implcore::default::DefaultforFoo{fndefault()->Self{Foo::B}}");
     }
 }
@@ -109,12 +109,13 @@ register_builtin! {
 }
 
 fn module_path_expand(
-    _db: &dyn AstDatabase,
-    _id: LazyMacroId,
+    db: &dyn AstDatabase,
+    id: LazyMacroId,
     _tt: &tt::Subtree,
 ) -> ExpandResult<tt::Subtree> {
-    // Just return a dummy result.
-    ExpandResult::ok(quote! { "module::path" })
+    let loc = db.lookup_intern_macro(id);
+    let module_path = loc.module_path.as_deref().unwrap_or("");
+    ExpandResult::ok(quote! { #module_path })
 }
 
 fn line_expand(
@@ -357,7 +358,14 @@ fn relative_file(
 ) -> Option<FileId> {
     let call_site = call_id.as_file().original_file(db);
     let path = AnchoredPath { anchor: call_site, path };
-    let res = db.resolve_path(path)?;
+    let res = match db.resolve_path(path) {
+        Some(it) => it,
+        None => {
+            // Expansion is re-run once the file is loaded, as that changes the source root.
+            db.request_file(path);
+            return None;
+        }
+    };
     // Prevent include itself
     if res == call_site && !allow_recursion {
         None
@@ -402,23 +410,44 @@ fn include_expand(
 }
 
 fn include_bytes_expand(
-    _db: &dyn AstDatabase,
-    _arg_id: EagerMacroId,
+    db: &dyn AstDatabase,
+    arg_id: EagerMacroId,
     tt: &tt::Subtree,
 ) -> ExpandResult<Option<(tt::Subtree, FragmentKind)>> {
-    if let Err(e) = parse_string(tt) {
-        return ExpandResult::only_err(e);
+    let path = match parse_string(tt) {
+        Ok(it) => it,
+        Err(e) => return ExpandResult::only_err(e),
+    };
+
+    // Files which aren't Rust source code are loaded when first requested, return an empty byte
+    // string until then to at least get the type right.
+    let mut err = None;
+    let bytes = match relative_file(db, arg_id.into(), &path, true) {
+        Some(file_id) => match db.file_bytes(file_id) {
+            Some(bytes) => bytes.to_vec(),
+            None => db.file_text(file_id).as_bytes().to_vec(),
+        },
+        None => {
+            err = Some(unloaded_file_err(&path));
+            Vec::new()
+        }
+    };
+
+    let mut text = String::with_capacity(bytes.len() + 3);
+    text.push_str("b\"");
+    for byte in bytes {
+        text.extend(std::ascii::escape_default(byte).map(char::from));
     }
+    text.push('"');
 
-    // FIXME: actually read the file here if the user asked for macro expansion
     let res = tt::Subtree {
         delimiter: None,
         token_trees: vec![tt::TokenTree::Leaf(tt::Leaf::Literal(tt::Literal {
-            text: r#"b"""#.into(),
+            text: text.into(),
             id: tt::TokenId::unspecified(),
         }))],
     };
-    ExpandResult::ok(Some((res, FragmentKind::Expr)))
+    ExpandResult { value: Some((res, FragmentKind::Expr)), err }
 }
 
 fn include_str_expand(
@@ -431,21 +460,35 @@ fn include_str_expand(
         Err(e) => return ExpandResult::only_err(e),
     };
 
-    // FIXME: we're not able to read excluded files (which is most of them because
-    // it's unusual to `include_str!` a Rust file), but we can return an empty string.
-    // Ideally, we'd be able to offer a precise expansion if the user asks for macro
-    // expansion.
-    let file_id = match relative_file(db, arg_id.into(), &path, true) {
-        Some(file_id) => file_id,
+    // Like in `include_bytes!`, fall back to an empty string for files which aren't loaded.
+    let mut err = None;
+    let text = match relative_file(db, arg_id.into(), &path, true) {
+        Some(file_id) => match db.file_bytes(file_id) {
+            Some(bytes) => String::from_utf8(bytes.to_vec()).unwrap_or_else(|_| {
+                err = Some(mbe::ExpandError::Other(format!("`{}` wasn't valid UTF-8", path)));
+                String::new()
+            }),
+            None => db.file_text(file_id).to_string(),
+        },
         None => {
-            return ExpandResult::ok(Some((quote!(""), FragmentKind::Expr)));
+            err = Some(unloaded_file_err(&path));
+            String::new()
         }
     };
 
-    let text = db.file_text(file_id);
-    let text = &*text;
+    // `Debug` escapes the string the same way a Rust string literal does.
+    let res = tt::Subtree {
+        delimiter: None,
+        token_trees: vec![tt::TokenTree::Leaf(tt::Leaf::Literal(tt::Literal {
+            text: format!("{:?}", text).into(),
+            id: tt::TokenId::unspecified(),
+        }))],
+    };
+    ExpandResult { value: Some((res, FragmentKind::Expr)), err }
+}
 
-    ExpandResult::ok(Some((quote!(#text), FragmentKind::Expr)))
+fn unloaded_file_err(path: &str) -> mbe::ExpandError {
+    mbe::ExpandError::Other(format!("`{}` isn't loaded", path))
 }
 
 fn get_env_inner(db: &dyn AstDatabase, arg_id: EagerMacroId, key: &str) -> Option<String> {
@@ -465,8 +508,8 @@ fn env_expand(
 
     let mut err = None;
     let s = get_env_inner(db, arg_id, &key).unwrap_or_else(|| {
-        // The only variable rust-analyzer ever sets is `OUT_DIR`, so only diagnose that to avoid
-        // unnecessary diagnostics for eg. `CARGO_PKG_NAME`.
+        // Build script variables are only known with "load out dirs from check". Only diagnose
+        // `OUT_DIR`, other variables might come from the environment the crate is built in.
         if key == "OUT_DIR" {
             err = Some(mbe::ExpandError::Other(
                 r#"`OUT_DIR` not set, enable "load out dirs from check" to fix"#.into(),
@@ -509,12 +552,16 @@ mod tests {
         name::AsName, test_db::TestDB, AstNode, EagerCallLoc, MacroCallId, MacroCallKind,
         MacroCallLoc,
     };
-    use base_db::{fixture::WithFixture, SourceDatabase};
+    use base_db::{fixture::ChangeFixture, SourceDatabase};
     use std::sync::Arc;
     use syntax::ast::NameOwner;
 
     fn expand_builtin_macro(ra_fixture: &str) -> String {
-        let (db, file_id) = TestDB::with_single_file(&ra_fixture);
+        let fixture = ChangeFixture::parse(ra_fixture);
+        let mut db = TestDB::default();
+        fixture.change.apply(&mut db);
+        // The macro call is always in the first file, the others can be included by it.
+        let file_id = fixture.files[0];
         let parsed = db.parse(file_id);
         let mut macro_rules: Vec<_> =
             parsed.syntax_node().descendants().filter_map(ast::MacroRules::cast).collect();
@@ -544,6 +591,7 @@ mod tests {
                 let loc = MacroCallLoc {
                     def,
                     krate,
+                    module_path: Some("test::foo".into()),
                     kind: MacroCallKind::FnLike(AstId::new(
                         file_id.into(),
                         ast_id_map.ast_id(&macro_call),
@@ -573,6 +621,7 @@ mod tests {
                         subtree: Arc::new(parsed_args.clone()),
                         krate,
                         call: call_id,
+                        error: None,
                     }
                 });
 
@@ -583,6 +632,7 @@ mod tests {
                     subtree: Arc::new(subtree),
                     krate,
                     call: call_id,
+                    error: None,
                 };
 
                 let id: MacroCallId = db.intern_eager_expansion(eager).into();
@@ -658,6 +708,19 @@ mod tests {
         assert_eq!(expanded, "std::option::Option::None:: < &str>");
     }
 
+    #[test]
+    fn test_module_path_expand() {
+        let expanded = expand_builtin_macro(
+            r#"
+            #[rustc_builtin_macro]
+            macro_rules! module_path {() => {}}
+            module_path!()
+            "#,
+        );
+
+        assert_eq!(expanded, "\"test::foo\"");
+    }
+
     #[test]
     fn test_file_expand() {
         let expanded = expand_builtin_macro(
@@ -727,16 +790,35 @@ mod tests {
     fn test_include_bytes_expand() {
         let expanded = expand_builtin_macro(
             r#"
+            //- /main.rs
             #[rustc_builtin_macro]
             macro_rules! include_bytes {
                 ($file:expr) => {{ /* compiler built-in */ }};
                 ($file:expr,) => {{ /* compiler built-in */ }};
             }
-            include_bytes("foo");
+            include_bytes!("foo.bin");
+            //- /foo.bin
+            a"\
+            "#,
+        );
+
+        assert_eq!(expanded, r#"b"a\"\\\n""#);
+    }
+
+    #[test]
+    fn test_include_str_expand() {
+        let expanded = expand_builtin_macro(
+            r#"
+            //- /main.rs
+            #[rustc_builtin_macro]
+            macro_rules! include_str {() => {}}
+            include_str!("schema.sql");
+            //- /schema.sql
+            CREATE TABLE "t" ();
             "#,
         );
 
-        assert_eq!(expanded, r#"b"""#);
+        assert_eq!(expanded, r#""CREATE TABLE \"t\" ();\n""#);
     }
 
     #[test]
@@ -29,7 +29,7 @@ use base_db::CrateId;
 use mbe::ExpandResult;
 use parser::FragmentKind;
 use std::sync::Arc;
-use syntax::{algo::SyntaxRewriter, SyntaxNode};
+use syntax::{algo::SyntaxRewriter, SmolStr, SyntaxNode};
 
 pub struct ErrorEmitted {
     _private: (),
@@ -60,16 +60,6 @@ trait ErrorSink {
         self.option(opt(), error)
     }
 
-    fn result<T>(&mut self, res: Result<T, mbe::ExpandError>) -> Result<T, ErrorEmitted> {
-        match res {
-            Ok(it) => Ok(it),
-            Err(e) => {
-                self.emit(e);
-                Err(ErrorEmitted { _private: () })
-            }
-        }
-    }
-
     fn expand_result_option<T>(&mut self, res: ExpandResult<Option<T>>) -> Result<T, ErrorEmitted> {
         match (res.value, res.err) {
             (None, Some(err)) => {
@@ -100,6 +90,7 @@ fn err(msg: impl Into<String>) -> mbe::ExpandError {
 pub fn expand_eager_macro(
     db: &dyn AstDatabase,
     krate: CrateId,
+    module_path: &dyn Fn() -> SmolStr,
     macro_call: InFile<ast::MacroCall>,
     def: MacroDefId,
     resolver: &dyn Fn(ast::Path) -> Option<MacroDefId>,
@@ -121,31 +112,46 @@ pub fn expand_eager_macro(
         EagerCallLoc {
             def,
             fragment: FragmentKind::Expr,
-            subtree: Arc::new(parsed_args.clone()),
+            subtree: Arc::new(parsed_args),
             krate,
             call: call_id,
+            error: None,
         }
     });
     let arg_file_id: MacroCallId = arg_id.into();
 
-    let parsed_args =
-        diagnostic_sink.result(mbe::token_tree_to_syntax_node(&parsed_args, FragmentKind::Expr))?.0;
-    let result = eager_macro_recur(
-        db,
-        InFile::new(arg_file_id.as_file(), parsed_args.syntax_node()),
-        krate,
-        resolver,
-        diagnostic_sink,
-    )?;
-    let subtree =
-        diagnostic_sink.option(to_subtree(&result), || err("failed to parse macro result"))?;
+    // Nested calls are looked up in the argument file itself, so that the `AstId`s they are
+    // interned with point into the same tree `parse_or_expand` produces for it.
+    let parsed_args = diagnostic_sink.option(db.parse_or_expand(arg_file_id.as_file()), || {
+        err("failed to parse macro arguments")
+    })?;
+
+    // Errors in the arguments don't necessarily prevent the expansion, but they have to be
+    // reported for it, so remember the first one.
+    let mut first_error = None;
+    let subtree = {
+        let mut sink: &mut dyn FnMut(mbe::ExpandError) = &mut |e: mbe::ExpandError| {
+            first_error.get_or_insert_with(|| e.clone());
+            diagnostic_sink(e);
+        };
+        let result = eager_macro_recur(
+            db,
+            InFile::new(arg_file_id.as_file(), parsed_args),
+            krate,
+            module_path,
+            resolver,
+            sink,
+        )?;
+        sink.option(to_subtree(&result), || err("failed to parse macro result"))?
+    };
 
     if let MacroDefKind::BuiltInEager(eager) = def.kind {
         let res = eager.expand(db, arg_id, &subtree);
+        let error = first_error.or_else(|| res.err.clone());
 
         let (subtree, fragment) = diagnostic_sink.expand_result_option(res)?;
         let eager =
-            EagerCallLoc { def, fragment, subtree: Arc::new(subtree), krate, call: call_id };
+            EagerCallLoc { def, fragment, subtree: Arc::new(subtree), krate, call: call_id, error };
 
         Ok(db.intern_eager_expansion(eager))
     } else {
@@ -164,11 +170,13 @@ fn lazy_expand(
     def: &MacroDefId,
     macro_call: InFile<ast::MacroCall>,
     krate: CrateId,
+    module_path: &dyn Fn() -> SmolStr,
 ) -> ExpandResult<Option<InFile<SyntaxNode>>> {
     let ast_id = db.ast_id_map(macro_call.file_id).ast_id(&macro_call.value);
 
-    let id: MacroCallId =
-        def.as_lazy_macro(db, krate, MacroCallKind::FnLike(macro_call.with_value(ast_id))).into();
+    let id: MacroCallId = def
+        .as_lazy_macro(db, krate, module_path, MacroCallKind::FnLike(macro_call.with_value(ast_id)))
+        .into();
 
     let err = db.macro_expand_error(id);
     let value = db.parse_or_expand(id.as_file()).map(|node| InFile::new(id.as_file(), node));
@@ -180,6 +188,7 @@ fn eager_macro_recur(
     db: &dyn AstDatabase,
     curr: InFile<SyntaxNode>,
     krate: CrateId,
+    module_path: &dyn Fn() -> SmolStr,
     macro_resolver: &dyn Fn(ast::Path) -> Option<MacroDefId>,
     mut diagnostic_sink: &mut dyn FnMut(mbe::ExpandError),
 ) -> Result<SyntaxNode, ErrorEmitted> {
@@ -197,6 +206,7 @@ fn eager_macro_recur(
                 let id: MacroCallId = expand_eager_macro(
                     db,
                     krate,
+                    module_path,
                     curr.with_value(child.clone()),
                     def,
                     macro_resolver,
@@ -209,15 +219,20 @@ fn eager_macro_recur(
             MacroDefKind::Declarative
             | MacroDefKind::BuiltIn(_)
             | MacroDefKind::BuiltInDerive(_)
-            | MacroDefKind::ProcMacro(_) => {
-                let res = lazy_expand(db, &def, curr.with_value(child.clone()), krate);
+            | MacroDefKind::ProcMacro(..) => {
+                let res = lazy_expand(db, &def, curr.with_value(child.clone()), krate, module_path);
                 let val = diagnostic_sink.expand_result_option(res)?;
 
                 // replace macro inside
-                eager_macro_recur(db, val, krate, macro_resolver, diagnostic_sink)?
+                eager_macro_recur(db, val, krate, module_path, macro_resolver, diagnostic_sink)?
             }
         };
 
+        // `SyntaxRewriter` never replaces the node it is applied to, so a call making up the
+        // whole tree (as in a `macro_rules!` expanding to `env!(...)`) is substituted here.
+        if child.syntax() == &original {
+            return Ok(insert);
+        }
         rewriter.replace(child.syntax(), &insert);
     }
 
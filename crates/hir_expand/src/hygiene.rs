@@ -1,15 +1,21 @@
 //! This modules handles hygiene information.
 //!
 //! Specifically, `ast` + `Hygiene` allows you to create a `Name`. Note that, at
-//! this moment, this is horribly incomplete and handles only `$crate`.
+//! this moment, this is horribly incomplete and handles only `$crate` and
+//! definition-site resolution of names produced by `macro` items.
+//!
+//! Local variables are handled separately, via `SyntaxContext`.
+use std::sync::Arc;
+
 use base_db::CrateId;
 use either::Either;
-use syntax::ast;
+use mbe::Origin;
+use syntax::{ast, AstNode, SyntaxToken};
 
 use crate::{
-    db::AstDatabase,
+    db::{AstDatabase, TokenExpander},
     name::{AsName, Name},
-    HirFileId, HirFileIdRepr, MacroCallId, MacroDefKind,
+    AstId, HirFileId, HirFileIdRepr, InFile, MacroCallId, MacroDefId, MacroDefKind, MacroFile,
 };
 
 #[derive(Clone, Debug)]
@@ -19,31 +25,46 @@ pub struct Hygiene {
 
     // Indicate this is a local inner macro
     local_inner: bool,
+
+    // Set for expansions of `macro` items, whose names resolve at definition site
+    def_site: Option<DefSiteInfo>,
+}
+
+#[derive(Clone, Debug)]
+struct DefSiteInfo {
+    krate: CrateId,
+    def: AstId<ast::Macro>,
+    macro_def: Arc<(TokenExpander, mbe::TokenMap)>,
+    exp_map: Arc<mbe::TokenMap>,
 }
 
 impl Hygiene {
     pub fn new(db: &dyn AstDatabase, file_id: HirFileId) -> Hygiene {
-        let (def_crate, local_inner) = match file_id.0 {
-            HirFileIdRepr::FileId(_) => (None, false),
+        let (def_crate, local_inner, def_site) = match file_id.0 {
+            HirFileIdRepr::FileId(_) => (None, false, None),
             HirFileIdRepr::MacroFile(macro_file) => match macro_file.macro_call_id {
                 MacroCallId::LazyMacro(id) => {
                     let loc = db.lookup_intern_macro(id);
                     match loc.def.kind {
-                        MacroDefKind::Declarative => (Some(loc.def.krate), loc.def.local_inner),
-                        MacroDefKind::BuiltIn(_) => (Some(loc.def.krate), false),
-                        MacroDefKind::BuiltInDerive(_) => (None, false),
-                        MacroDefKind::BuiltInEager(_) => (None, false),
-                        MacroDefKind::ProcMacro(_) => (None, false),
+                        MacroDefKind::Declarative => (
+                            Some(loc.def.krate),
+                            loc.def.local_inner,
+                            def_site_info(db, macro_file, loc.def),
+                        ),
+                        MacroDefKind::BuiltIn(_) => (Some(loc.def.krate), false, None),
+                        MacroDefKind::BuiltInDerive(_) => (None, false, None),
+                        MacroDefKind::BuiltInEager(_) => (None, false, None),
+                        MacroDefKind::ProcMacro(..) => (None, false, None),
                     }
                 }
-                MacroCallId::EagerMacro(_id) => (None, false),
+                MacroCallId::EagerMacro(_id) => (None, false, None),
             },
         };
-        Hygiene { def_crate, local_inner }
+        Hygiene { def_crate, local_inner, def_site }
     }
 
     pub fn new_unhygienic() -> Hygiene {
-        Hygiene { def_crate: None, local_inner: false }
+        Hygiene { def_crate: None, local_inner: false, def_site: None }
     }
 
     // FIXME: this should just return name
@@ -63,4 +84,67 @@ impl Hygiene {
             None
         }
     }
+
+    /// If `name_ref` was written in the body of a `macro` item (as opposed to
+    /// being passed in by the caller), returns the crate and the definition of
+    /// that macro: such names are resolved at the definition site.
+    pub fn def_site(&self, name_ref: &ast::NameRef) -> Option<(CrateId, AstId<ast::Macro>)> {
+        let info = self.def_site.as_ref()?;
+        let token_id = info.exp_map.token_by_range(name_ref.syntax().text_range())?;
+        match info.macro_def.0.map_id_up(token_id) {
+            (_, Origin::Def) => Some((info.krate, info.def)),
+            (_, Origin::Call) => None,
+        }
+    }
+}
+
+/// The syntax context of a name: the macro call whose definition the name was
+/// written in, or the root context for names written by the user.
+///
+/// Local variables are only visible to names with the same syntax context, so
+/// that a `let x` in the body of a `macro_rules!` neither captures nor shadows
+/// the caller's `x`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SyntaxContext(Option<MacroCallId>);
+
+impl SyntaxContext {
+    pub const ROOT: SyntaxContext = SyntaxContext(None);
+
+    pub fn is_root(self) -> bool {
+        self.0.is_none()
+    }
+
+    /// Computes the syntax context of `token` by mapping it up through the
+    /// macro calls that produced it, until it reaches either the definition of
+    /// a macro or a real file.
+    pub fn of_token(db: &dyn AstDatabase, token: InFile<&SyntaxToken>) -> SyntaxContext {
+        let mut token = token.cloned();
+        loop {
+            let call_id = match token.file_id.0 {
+                HirFileIdRepr::FileId(_) => return SyntaxContext::ROOT,
+                HirFileIdRepr::MacroFile(macro_file) => macro_file.macro_call_id,
+            };
+            let mapped =
+                token.file_id.expansion_info(db).and_then(|info| info.map_token_up(token.as_ref()));
+            match mapped {
+                Some((up, Origin::Call)) => token = up,
+                // Either written in the macro definition, or made up by a builtin macro.
+                Some((_, Origin::Def)) | None => return SyntaxContext(Some(call_id)),
+            }
+        }
+    }
+}
+
+fn def_site_info(
+    db: &dyn AstDatabase,
+    macro_file: MacroFile,
+    def: MacroDefId,
+) -> Option<DefSiteInfo> {
+    let ast_id = def.ast_id?;
+    if !matches!(ast_id.to_node(db), ast::Macro::MacroDef(_)) {
+        return None;
+    }
+    let macro_def = db.macro_def(def)?;
+    let (_, exp_map) = db.parse_macro_expansion(macro_file).value?;
+    Some(DefSiteInfo { krate: def.krate, def: ast_id, macro_def, exp_map })
 }
@@ -16,15 +16,16 @@ pub mod quote;
 pub mod eager;
 
 pub use mbe::{ExpandError, ExpandResult};
+pub use tt::DiagnosticLevel;
 
 use std::hash::Hash;
 use std::sync::Arc;
 
-use base_db::{impl_intern_key, salsa, CrateId, FileId, FileRange};
+use base_db::{impl_intern_key, salsa, CrateId, FileId, FileRange, ProcMacroKind};
 use syntax::{
     algo::{self, skip_trivia_token},
     ast::{self, AstNode},
-    Direction, SyntaxNode, SyntaxToken, TextRange, TextSize,
+    Direction, SmolStr, SyntaxKind, SyntaxNode, SyntaxToken, TextRange, TextSize, T,
 };
 
 use crate::ast_id_map::FileAstId;
@@ -48,10 +49,10 @@ mod test_db;
 /// finite (because everything bottoms out at the real `FileId`) and small
 /// (`MacroCallId` uses the location interning. You can check details here:
 /// https://en.wikipedia.org/wiki/String_interning).
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct HirFileId(HirFileIdRepr);
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 enum HirFileIdRepr {
     FileId(FileId),
     MacroFile(MacroFile),
@@ -146,7 +147,7 @@ impl HirFileId {
                 let def = loc.def.ast_id.and_then(|id| {
                     let def_tt = match id.to_node(db) {
                         ast::Macro::MacroRules(mac) => mac.token_tree()?,
-                        ast::Macro::MacroDef(_) => return None,
+                        ast::Macro::MacroDef(mac) => mac.body()?,
                     };
                     Some(InFile::new(id.file_id, def_tt))
                 });
@@ -167,6 +168,55 @@ impl HirFileId {
         }
     }
 
+    /// For macro-expansion files, returns the chain of macro calls that produced this file,
+    /// starting with the innermost call.
+    pub fn macro_backtrace(self, db: &dyn db::AstDatabase) -> Vec<MacroBacktraceFrame> {
+        let mut frames = Vec::new();
+        let mut file_id = self;
+        while let HirFileIdRepr::MacroFile(macro_file) = file_id.0 {
+            let call = match file_id.call_node(db) {
+                Some(it) => it,
+                None => break,
+            };
+            frames.push(MacroBacktraceFrame::new(db, macro_file.macro_call_id, &call));
+            file_id = call.file_id;
+        }
+        frames
+    }
+
+    /// For macro-expansion files of `macro_rules!` and `macro` calls, returns which rules were
+    /// tried to expand the call and what their meta variables were bound to.
+    pub fn trace_expansion(self, db: &dyn db::AstDatabase) -> Option<MacroExpansionTrace> {
+        let macro_file = match self.0 {
+            HirFileIdRepr::FileId(_) => return None,
+            HirFileIdRepr::MacroFile(it) => it,
+        };
+        let loc = match macro_file.macro_call_id {
+            MacroCallId::LazyMacro(id) => db.lookup_intern_macro(id),
+            MacroCallId::EagerMacro(_) => return None,
+        };
+        let macro_def = db.macro_def(loc.def)?;
+        let macro_arg = db.macro_arg(macro_file.macro_call_id)?;
+        let trace = macro_def.0.trace_expand(&macro_arg.0)?;
+        let rules = trace
+            .rules
+            .into_iter()
+            .map(|rule| MacroRuleTrace {
+                index: rule.index,
+                err: rule.err,
+                failed_at: rule
+                    .failed_at
+                    .and_then(|id| arg_token_range(db, &loc, &macro_arg.1, id, T!['('])),
+                bindings: rule
+                    .bindings
+                    .into_iter()
+                    .map(|(name, value)| (name.to_string(), MacroBinding::new(value)))
+                    .collect(),
+            })
+            .collect();
+        Some(MacroExpansionTrace { rules, chosen: trace.chosen })
+    }
+
     /// Indicate it is macro file generated for builtin derive
     pub fn is_builtin_derive(&self, db: &dyn db::AstDatabase) -> Option<InFile<ast::Item>> {
         match self.0 {
@@ -189,24 +239,24 @@ impl HirFileId {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct MacroFile {
     macro_call_id: MacroCallId,
 }
 
 /// `MacroCallId` identifies a particular macro invocation, like
 /// `println!("Hello, {}", world)`.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum MacroCallId {
     LazyMacro(LazyMacroId),
     EagerMacro(EagerMacroId),
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct LazyMacroId(salsa::InternId);
 impl_intern_key!(LazyMacroId);
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct EagerMacroId(salsa::InternId);
 impl_intern_key!(EagerMacroId);
 
@@ -235,9 +285,16 @@ impl MacroDefId {
         self,
         db: &dyn db::AstDatabase,
         krate: CrateId,
+        module_path: &dyn Fn() -> SmolStr,
         kind: MacroCallKind,
     ) -> LazyMacroId {
-        db.intern_macro(MacroCallLoc { def: self, krate, kind })
+        // Only `module_path!()` needs the path of the calling module. Leaving it out for other
+        // macros avoids computing it, and keeps calls that differ only in their module identical.
+        let module_path = match self.kind {
+            MacroDefKind::BuiltIn(BuiltinFnLikeExpander::ModulePath) => Some(module_path()),
+            _ => None,
+        };
+        db.intern_macro(MacroCallLoc { def: self, krate, module_path, kind })
     }
 }
 
@@ -248,13 +305,38 @@ pub enum MacroDefKind {
     // FIXME: maybe just Builtin and rename BuiltinFnLikeExpander to BuiltinExpander
     BuiltInDerive(BuiltinDeriveExpander),
     BuiltInEager(EagerExpander),
-    ProcMacro(ProcMacroExpander),
+    ProcMacro(ProcMacroExpander, ProcMacroKind),
+}
+
+impl MacroDefKind {
+    /// Whether macros of this kind are invoked like functions, as in `foo!()`.
+    pub fn is_fn_like(&self) -> bool {
+        match self {
+            MacroDefKind::Declarative
+            | MacroDefKind::BuiltIn(_)
+            | MacroDefKind::BuiltInEager(_)
+            | MacroDefKind::ProcMacro(_, ProcMacroKind::FuncLike) => true,
+            MacroDefKind::BuiltInDerive(_) | MacroDefKind::ProcMacro(..) => false,
+        }
+    }
+
+    /// Whether macros of this kind are invoked from `#[derive(...)]`.
+    pub fn is_derive(&self) -> bool {
+        matches!(
+            self,
+            MacroDefKind::BuiltInDerive(_)
+                | MacroDefKind::ProcMacro(_, ProcMacroKind::CustomDerive)
+        )
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct MacroCallLoc {
     pub(crate) def: MacroDefId,
     pub(crate) krate: CrateId,
+    /// Path of the module containing the call, as expanded by `module_path!()`. `None` for all
+    /// other macros.
+    pub(crate) module_path: Option<SmolStr>,
     pub kind: MacroCallKind,
 }
 
@@ -295,6 +377,188 @@ impl MacroCallId {
     pub fn as_file(self) -> HirFileId {
         MacroFile { macro_call_id: self }.into()
     }
+
+    /// Splits an error from expanding this call into the errors to report. Proc macros can emit
+    /// several diagnostics, each with its own level and pointing at a token of the input.
+    pub fn errors(self, db: &dyn db::AstDatabase, err: &ExpandError) -> Vec<MacroCallError> {
+        match err {
+            ExpandError::ProcMacroError(tt::ExpansionError::Diagnostics(diagnostics)) => {
+                diagnostics
+                    .iter()
+                    .map(|diagnostic| MacroCallError {
+                        message: diagnostic.to_string(),
+                        level: diagnostic.level,
+                        location: diagnostic
+                            .spans
+                            .first()
+                            .and_then(|&id| self.arg_token_range(db, id, T!['('])),
+                    })
+                    .collect()
+            }
+            _ => vec![MacroCallError {
+                message: err.to_string(),
+                level: DiagnosticLevel::Error,
+                location: self.error_location(db, err),
+            }],
+        }
+    }
+
+    /// For an error from expanding this call, returns the range of the token in the input of
+    /// the call at which the expansion failed, if the error points at one.
+    fn error_location(
+        self,
+        db: &dyn db::AstDatabase,
+        err: &ExpandError,
+    ) -> Option<InFile<TextRange>> {
+        let (token_id, kind) = match err {
+            ExpandError::NoRuleExpected {
+                found: mbe::UnmatchedInput::Token { id, .. }, ..
+            } => (*id, T!['(']),
+            // Point at the closing delimiter of the group that ended early.
+            ExpandError::NoRuleExpected {
+                found: mbe::UnmatchedInput::End { delimiter }, ..
+            } => ((*delimiter)?, T![')']),
+            _ => return None,
+        };
+        self.arg_token_range(db, token_id, kind)
+    }
+
+    fn arg_token_range(
+        self,
+        db: &dyn db::AstDatabase,
+        token_id: tt::TokenId,
+        kind: SyntaxKind,
+    ) -> Option<InFile<TextRange>> {
+        let loc = match self {
+            MacroCallId::LazyMacro(id) => db.lookup_intern_macro(id),
+            MacroCallId::EagerMacro(_) => return None,
+        };
+        let macro_arg = db.macro_arg(self)?;
+        arg_token_range(db, &loc, &macro_arg.1, token_id, kind)
+    }
+}
+
+/// An error from expanding a macro call, see [`MacroCallId::errors`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MacroCallError {
+    pub message: String,
+    /// Proc macros can emit warnings and notes as well, everything else is an error.
+    pub level: DiagnosticLevel,
+    /// The range of the token in the input of the call the error points at.
+    pub location: Option<InFile<TextRange>>,
+}
+
+/// A macro call in the chain of calls that produced some code, see
+/// [`HirFileId::macro_backtrace`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MacroBacktraceFrame {
+    pub id: MacroCallId,
+    /// The range of the macro call.
+    pub call: InFile<TextRange>,
+    /// How the macro is called, like `vec!` or `Clone`.
+    pub name: String,
+    /// The rule of a `macro_rules!` or `macro` that was used for the expansion.
+    pub arm: Option<usize>,
+    /// The token of the call's input at which matching `arm` failed.
+    pub failed_at: Option<InFile<TextRange>>,
+}
+
+impl MacroBacktraceFrame {
+    fn new(db: &dyn db::AstDatabase, id: MacroCallId, call: &InFile<SyntaxNode>) -> Self {
+        let loc = match id {
+            MacroCallId::LazyMacro(id) => Some(db.lookup_intern_macro(id)),
+            MacroCallId::EagerMacro(_) => None,
+        };
+        let name = match (ast::MacroCall::cast(call.value.clone()), &loc) {
+            (Some(mac), _) => {
+                format!("{}!", mac.path().map_or_else(String::new, |it| it.to_string()))
+            }
+            (None, Some(MacroCallLoc { kind: MacroCallKind::Attr(_, name), .. })) => name.clone(),
+            (None, _) => String::new(),
+        };
+
+        let mut frame = MacroBacktraceFrame {
+            id,
+            call: call.with_value(call.value.text_range()),
+            name,
+            arm: None,
+            failed_at: None,
+        };
+        let (loc, macro_def, macro_arg) = match (loc, db.macro_arg(id)) {
+            (Some(loc), Some(macro_arg)) => match db.macro_def(loc.def) {
+                Some(macro_def) => (loc, macro_def, macro_arg),
+                None => return frame,
+            },
+            _ => return frame,
+        };
+        let arm = match macro_def.0.matched_arm(&macro_arg.0) {
+            Some(it) => it,
+            None => return frame,
+        };
+        frame.arm = Some(arm.index);
+        frame.failed_at =
+            arm.failed_at.and_then(|id| arg_token_range(db, &loc, &macro_arg.1, id, T!['(']));
+        frame
+    }
+}
+
+/// A record of how a call of a `macro_rules!` or `macro` was expanded, see
+/// [`HirFileId::trace_expansion`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MacroExpansionTrace {
+    /// The rules that were tried, in order.
+    pub rules: Vec<MacroRuleTrace>,
+    /// The rule used for the expansion. If no rule applied, this is the one that came closest.
+    pub chosen: Option<usize>,
+}
+
+/// An attempt to match the input of a macro call against a single rule.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MacroRuleTrace {
+    /// Index of the rule in the macro definition.
+    pub index: usize,
+    /// Why the rule doesn't apply, or `None` if it does.
+    pub err: Option<ExpandError>,
+    /// The token of the call's input at which matching failed.
+    pub failed_at: Option<InFile<TextRange>>,
+    /// The meta variables bound while matching, sorted by name.
+    pub bindings: Vec<(String, MacroBinding)>,
+}
+
+/// What a meta variable is bound to. A variable inside of `$(...)*` is bound to a list with
+/// one value per repetition.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MacroBinding {
+    Fragment(String),
+    Nested(Vec<MacroBinding>),
+    /// The variable is inside of a repetition that matched nothing.
+    Empty,
+}
+
+impl MacroBinding {
+    fn new(value: mbe::BoundValue) -> MacroBinding {
+        match value {
+            mbe::BoundValue::Fragment(tt) => MacroBinding::Fragment(tt.to_string()),
+            mbe::BoundValue::Nested(it) => {
+                MacroBinding::Nested(it.into_iter().map(MacroBinding::new).collect())
+            }
+            mbe::BoundValue::Empty => MacroBinding::Empty,
+        }
+    }
+}
+
+/// Maps a token of the input of the call `loc` back to its range in the source. For delimiters,
+/// `kind` selects the opening or the closing one.
+fn arg_token_range(
+    db: &dyn db::AstDatabase,
+    loc: &MacroCallLoc,
+    arg_map: &mbe::TokenMap,
+    token_id: tt::TokenId,
+    kind: SyntaxKind,
+) -> Option<InFile<TextRange>> {
+    let arg = loc.kind.arg(db)?;
+    let range = arg_map.range_by_token(token_id)?.by_kind(kind)?;
+    Some(InFile::new(loc.kind.file_id(), range + arg.text_range().start()))
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -303,7 +567,9 @@ pub struct EagerCallLoc {
     pub(crate) fragment: FragmentKind,
     pub(crate) subtree: Arc<tt::Subtree>,
     pub(crate) krate: CrateId,
-    pub(crate) call: AstId<ast::MacroCall>,
+    pub call: AstId<ast::MacroCall>,
+    /// The first error encountered while expanding the arguments or the macro itself.
+    pub(crate) error: Option<ExpandError>,
 }
 
 /// ExpansionInfo mainly describes how to map text range between src and expanded macro
@@ -311,7 +577,7 @@ pub struct EagerCallLoc {
 pub struct ExpansionInfo {
     expanded: InFile<SyntaxNode>,
     arg: InFile<SyntaxNode>,
-    /// The `macro_rules!` arguments.
+    /// The `macro_rules!` arguments or the `macro` body.
     def: Option<InFile<ast::TokenTree>>,
 
     macro_def: Arc<(db::TokenExpander, mbe::TokenMap)>,
@@ -353,7 +619,7 @@ impl ExpansionInfo {
                 &self.macro_def.1,
                 self.def
                     .as_ref()
-                    .expect("`Origin::Def` used with non-`macro_rules!` macro")
+                    .expect("`Origin::Def` used with non-declarative macro")
                     .as_ref()
                     .map(|tt| tt.syntax().clone()),
             ),
@@ -386,7 +652,7 @@ impl<N: AstNode> AstId<N> {
 /// * `InFile<SyntaxNode>` -- syntax node in a file
 /// * `InFile<ast::FnDef>` -- ast node in a file
 /// * `InFile<TextSize>` -- offset in a file
-#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Hash)]
 pub struct InFile<T> {
     pub file_id: HirFileId,
     pub value: T,
@@ -1,9 +1,20 @@
 //! Proc Macro Expander stub
 
-use crate::db::AstDatabase;
-use base_db::{CrateId, ProcMacroId};
+use std::sync::Arc;
+
+use crate::{db::AstDatabase, ExpandResult};
+use base_db::{salsa, AnchoredPath, CrateId, Env, ProcMacroId, SourceDatabase};
 use tt::buffer::{Cursor, TokenBuffer};
 
+/// Inputs of proc macro expansion which aren't part of the crate graph.
+#[salsa::query_group(ProcMacroDatabaseStorage)]
+pub trait ProcMacroDatabase: SourceDatabase {
+    /// The environment the proc macro server runs in. Macros read the variables the calling
+    /// crate doesn't set from it.
+    #[salsa::input]
+    fn proc_macro_env(&self) -> Arc<Env>;
+}
+
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
 pub struct ProcMacroExpander {
     krate: CrateId,
@@ -29,31 +40,69 @@ impl ProcMacroExpander {
         Self { krate, proc_macro_id: None }
     }
 
+    /// Expands `tt`. Diagnostics the macro emitted are returned as the error, alongside its
+    /// output.
     pub fn expand(
         self,
         db: &dyn AstDatabase,
         calling_crate: CrateId,
         tt: &tt::Subtree,
-    ) -> Result<tt::Subtree, mbe::ExpandError> {
-        match self.proc_macro_id {
-            Some(id) => {
-                let krate_graph = db.crate_graph();
-                let proc_macro = krate_graph[self.krate]
-                    .proc_macro
-                    .get(id.0 as usize)
-                    .clone()
-                    .ok_or_else(|| err!("No derive macro found."))?;
-
-                let tt = remove_derive_attrs(tt)
-                    .ok_or_else(|| err!("Fail to remove derive for custom derive"))?;
-
-                // Proc macros have access to the environment variables of the invoking crate.
-                let env = &krate_graph[calling_crate].env;
-
-                proc_macro.expander.expand(&tt, None, &env).map_err(mbe::ExpandError::from)
+    ) -> ExpandResult<tt::Subtree> {
+        let id = match self.proc_macro_id {
+            Some(it) => it,
+            None => return ExpandResult::only_err(mbe::ExpandError::UnresolvedProcMacro),
+        };
+        let krate_graph = db.crate_graph();
+        let proc_macro = match krate_graph[self.krate].proc_macro.get(id.0 as usize) {
+            Some(it) => it,
+            None => return ExpandResult::only_err(err!("No derive macro found.")),
+        };
+
+        let tt = match remove_derive_attrs(tt) {
+            Some(it) => it,
+            None => return ExpandResult::only_err(err!("Fail to remove derive for custom derive")),
+        };
+
+        // Proc macros have access to the environment variables of the invoking crate.
+        let env = &krate_graph[calling_crate].env;
+
+        let expansion = match proc_macro.expander.expand(&tt, None, env) {
+            Ok(it) => it,
+            Err(err) => return ExpandResult::only_err(err.into()),
+        };
+
+        // Reading the files the macro read makes salsa re-run the expansion when they
+        // change. Files which aren't in the VFS yet are requested, the expansion is re-run
+        // once they are loaded.
+        let anchor = krate_graph[calling_crate].root_file_id;
+        for path in &expansion.tracked_paths {
+            let path = match path.to_str() {
+                Some(it) => it,
+                None => continue,
+            };
+            let path = AnchoredPath { anchor, path };
+            match db.resolve_path(path) {
+                Some(file_id) => {
+                    if db.file_bytes(file_id).is_none() {
+                        db.file_text(file_id);
+                    }
+                }
+                None => db.request_file(path),
             }
-            None => Err(mbe::ExpandError::UnresolvedProcMacro),
         }
+
+        // Variables set by the calling crate come from the crate graph, which is an input
+        // already. The others come from the environment of the server.
+        if expansion.tracked_env.iter().any(|(var, _)| env.get(var).is_none()) {
+            db.proc_macro_env();
+        }
+
+        let err = if expansion.diagnostics.is_empty() {
+            None
+        } else {
+            Some(tt::ExpansionError::Diagnostics(expansion.diagnostics).into())
+        };
+        ExpandResult { value: expansion.subtree, err }
     }
 }
 
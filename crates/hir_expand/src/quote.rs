@@ -96,7 +96,14 @@ macro_rules! __quote {
     // Puncts
     // FIXME: Not all puncts are handled
     ( -> ) => {$crate::__quote!(@PUNCT '-', '>')};
+    ( => ) => {$crate::__quote!(@PUNCT '=', '>')};
     ( & ) => {$crate::__quote!(@PUNCT '&')};
+    ( && ) => {$crate::__quote!(@PUNCT '&', '&')};
+    ( * ) => {$crate::__quote!(@PUNCT '*')};
+    ( + ) => {$crate::__quote!(@PUNCT '+')};
+    ( = ) => {$crate::__quote!(@PUNCT '=')};
+    ( _ ) => {$crate::__quote!(@PUNCT '_')};
+    ( .. ) => {$crate::__quote!(@PUNCT '.', '.')};
     ( , ) => {$crate::__quote!(@PUNCT ',')};
     ( : ) => {$crate::__quote!(@PUNCT ':')};
     ( ; ) => {$crate::__quote!(@PUNCT ';')};
@@ -166,6 +173,18 @@ impl ToTokenTree for tt::Subtree {
     }
 }
 
+impl ToTokenTree for &tt::TokenTree {
+    fn to_token(self) -> tt::TokenTree {
+        self.clone()
+    }
+}
+
+impl ToTokenTree for &tt::Subtree {
+    fn to_token(self) -> tt::TokenTree {
+        self.clone().into()
+    }
+}
+
 macro_rules! impl_to_to_tokentrees {
     ($($ty:ty => $this:ident $im:block);*) => {
         $(
@@ -190,6 +209,7 @@ impl_to_to_tokentrees! {
     u32 => self { tt::Literal{text: self.to_string().into(), id: tt::TokenId::unspecified()} };
     usize => self { tt::Literal{text: self.to_string().into(), id: tt::TokenId::unspecified()}};
     i32 => self { tt::Literal{text: self.to_string().into(), id: tt::TokenId::unspecified()}};
+    bool => self { tt::Ident{text: self.to_string().into(), id: tt::TokenId::unspecified()}};
     tt::Leaf => self { self };
     tt::Literal => self { self };
     tt::Ident => self { self };
@@ -11,7 +11,8 @@ use rustc_hash::FxHashSet;
 #[salsa::database(
     base_db::SourceDatabaseExtStorage,
     base_db::SourceDatabaseStorage,
-    crate::db::AstDatabaseStorage
+    crate::db::AstDatabaseStorage,
+    crate::proc_macro::ProcMacroDatabaseStorage
 )]
 #[derive(Default)]
 pub(crate) struct TestDB {
@@ -40,6 +41,9 @@ impl FileLoader for TestDB {
     fn file_text(&self, file_id: FileId) -> Arc<String> {
         FileLoaderDelegate(self).file_text(file_id)
     }
+    fn file_bytes(&self, file_id: FileId) -> Option<Arc<Vec<u8>>> {
+        FileLoaderDelegate(self).file_bytes(file_id)
+    }
     fn resolve_path(&self, path: AnchoredPath) -> Option<FileId> {
         FileLoaderDelegate(self).resolve_path(path)
     }
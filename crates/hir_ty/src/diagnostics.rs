@@ -65,6 +65,68 @@ impl Diagnostic for NoSuchField {
     }
 }
 
+// Diagnostic: private-item
+//
+// This diagnostic is triggered if an item is used outside of the modules it is visible in.
+#[derive(Debug)]
+pub struct PrivateItem {
+    pub file: HirFileId,
+    pub expr: AstPtr<ast::Expr>,
+    pub item_kind: String,
+    pub item_name: String,
+}
+
+impl Diagnostic for PrivateItem {
+    fn code(&self) -> DiagnosticCode {
+        DiagnosticCode("private-item")
+    }
+    fn message(&self) -> String {
+        format!("{} `{}` is private", self.item_kind, self.item_name)
+    }
+    fn display_source(&self) -> InFile<SyntaxNodePtr> {
+        InFile::new(self.file, self.expr.clone().into())
+    }
+    fn as_any(&self) -> &(dyn Any + Send + 'static) {
+        self
+    }
+    fn is_experimental(&self) -> bool {
+        // Newly added, and visibility is not checked for everything yet (e.g. imports).
+        true
+    }
+}
+
+// Diagnostic: private-field
+//
+// This diagnostic is triggered if a field is accessed or initialized outside of the modules it
+// is visible in.
+#[derive(Debug)]
+pub struct PrivateField {
+    pub file: HirFileId,
+    /// Either the `ast::FieldExpr` or the `ast::RecordExprField` using the field.
+    pub node: SyntaxNodePtr,
+    pub field_name: String,
+    pub adt_kind: String,
+    pub adt_name: String,
+}
+
+impl Diagnostic for PrivateField {
+    fn code(&self) -> DiagnosticCode {
+        DiagnosticCode("private-field")
+    }
+    fn message(&self) -> String {
+        format!("field `{}` of {} `{}` is private", self.field_name, self.adt_kind, self.adt_name)
+    }
+    fn display_source(&self) -> InFile<SyntaxNodePtr> {
+        InFile::new(self.file, self.node.clone())
+    }
+    fn as_any(&self) -> &(dyn Any + Send + 'static) {
+        self
+    }
+    fn is_experimental(&self) -> bool {
+        true
+    }
+}
+
 // Diagnostic: missing-structure-fields
 //
 // This diagnostic is triggered if record lacks some fields that exist in the corresponding structure.
@@ -515,6 +577,160 @@ impl MyStruct {
         );
     }
 
+    #[test]
+    fn private_item_diagnostics() {
+        check_diagnostics(
+            r#"
+mod m {
+    fn private() {}
+    pub(super) fn visible() {}
+    const C: u8 = 0;
+    struct Unit;
+    pub struct S;
+    impl S {
+        fn assoc() {}
+    }
+    fn in_module() { private(); }
+}
+fn f() {
+    m::private();
+  //^^^^^^^^^^ function `private` is private
+    m::visible();
+    m::C;
+  //^^^^ constant `C` is private
+    m::Unit;
+  //^^^^^^^ struct `Unit` is private
+    m::S::assoc();
+  //^^^^^^^^^^^ function `assoc` is private
+}
+"#,
+        );
+    }
+
+    #[test]
+    fn private_path_segment_diagnostics() {
+        check_diagnostics(
+            r#"
+mod a {
+    mod private_mod {
+        pub fn f() {}
+    }
+    pub mod public_mod {
+        pub fn f() {}
+    }
+    struct Private;
+    impl Private {
+        pub fn new() {}
+    }
+}
+fn f() {
+    a::private_mod::f();
+  //^^^^^^^^^^^^^^^^^ module `private_mod` is private
+    a::public_mod::f();
+    a::Private::new();
+  //^^^^^^^^^^^^^^^ struct `Private` is private
+}
+"#,
+        );
+    }
+
+    #[test]
+    fn private_path_segment_in_other_crate_diagnostics() {
+        check_diagnostics(
+            r#"
+//- /main.rs crate:main deps:lib
+fn f() {
+    lib::a::private_mod::f();
+  //^^^^^^^^^^^^^^^^^^^^^^ module `private_mod` is private
+    lib::a::public_mod::f();
+}
+//- /lib.rs crate:lib
+pub mod a {
+    mod private_mod {
+        pub fn f() {}
+    }
+    pub mod public_mod {
+        pub fn f() {}
+    }
+}
+"#,
+        );
+    }
+
+    #[test]
+    fn private_method_diagnostics() {
+        check_diagnostics(
+            r#"
+mod m {
+    pub struct S;
+    impl S {
+        fn private(&self) {}
+        pub fn public(&self) {}
+        fn assoc() {}
+    }
+    pub trait Tr {
+        fn tr(&self);
+    }
+    impl Tr for S {
+        fn tr(&self) {}
+    }
+    fn in_module(s: S) {
+        s.private();
+        S::assoc();
+    }
+}
+use m::{S, Tr};
+fn f(s: S) {
+    s.private();
+  //^^^^^^^^^^^ function `private` is private
+    s.public();
+    s.tr();
+    S::assoc();
+  //^^^^^^^^ function `assoc` is private
+    <S>::assoc();
+  //^^^^^^^^^^ function `assoc` is private
+    S::tr(&s);
+}
+"#,
+        );
+    }
+
+    #[test]
+    fn private_field_diagnostics() {
+        check_diagnostics(
+            r#"
+mod m {
+    pub struct S { pub a: u8, b: u8 }
+    pub struct T(pub u8, u8);
+    fn in_module(s: S) -> u8 { s.b }
+}
+fn f(s: m::S, t: m::T) {
+    s.a;
+    s.b;
+  //^^^ field `b` of struct `S` is private
+    t.0;
+    t.1;
+  //^^^ field `1` of struct `T` is private
+    m::S { a: 0, b: 1 };
+               //^^^^ field `b` of struct `S` is private
+}
+"#,
+        );
+    }
+
+    #[test]
+    fn block_local_fields_are_visible_in_block() {
+        check_diagnostics(
+            r#"
+fn f() {
+    struct S { x: u8 }
+    let s = S { x: 0 };
+    s.x;
+}
+"#,
+        );
+    }
+
     #[test]
     fn no_such_field_enum_with_feature_flag_diagnostics() {
         check_diagnostics(
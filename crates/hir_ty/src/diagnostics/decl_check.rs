@@ -99,9 +99,11 @@ impl<'a, 'b> DeclValidator<'a, 'b> {
         let body = self.db.body(func.into());
 
         // Recursively validate inner scope items, such as static variables and constants.
-        for (item_id, _) in body.item_scope.values() {
-            let mut validator = DeclValidator::new(self.db, self.krate, self.sink);
-            validator.validate_item(item_id);
+        for def_map in body.block_def_maps(self.db.upcast()) {
+            for (item_id, _) in def_map[def_map.root].scope.values() {
+                let mut validator = DeclValidator::new(self.db, self.krate, self.sink);
+                validator.validate_item(item_id);
+            }
         }
 
         // Check whether non-snake case identifiers are allowed for this function.
@@ -1495,6 +1495,25 @@ fn main(f: Foo) {
         );
     }
 
+    #[test]
+    fn internal_or() {
+        check_diagnostics(
+            r#"
+fn main() {
+    enum Either { A(bool), B }
+    match Either::B {
+        //^^^^^^^^^ Missing match arm
+        Either::A(true | false) => (),
+    }
+    match Either::B {
+        Either::A(true | false) => (),
+        Either::B => (),
+    }
+}
+"#,
+        );
+    }
+
     mod false_negatives {
         //! The implementation of match checking here is a work in progress. As we roll this out, we
         //! prefer false negatives to false positives (ideally there would be no false positives). This
@@ -1518,21 +1537,6 @@ fn main() {
         11..20 => (),
     }
 }
-"#,
-            );
-        }
-
-        #[test]
-        fn internal_or() {
-            // We do not currently handle patterns with internal `or`s.
-            check_diagnostics(
-                r#"
-fn main() {
-    enum Either { A(bool), B }
-    match Either::B {
-        Either::A(true | false) => (),
-    }
-}
 "#,
             );
         }
@@ -101,9 +101,15 @@ fn walk_unsafe(
         }
         Expr::Path(path) => {
             let resolver = resolver_for_expr(db.upcast(), def, current);
-            let value_or_partial = resolver.resolve_path_in_value_ns(db.upcast(), path.mod_path());
+            let value_or_partial = resolver.resolve_path_in_value_ns(
+                db.upcast(),
+                path.mod_path(),
+                body.expr_hygiene(current),
+            );
             if let Some(ResolveValueResult::ValueNs(ValueNs::StaticId(id))) = value_or_partial {
-                if db.static_data(id).mutable {
+                // Accessing a `static mut` or any `static` from an `extern` block is unsafe.
+                let data = db.static_data(id);
+                if data.mutable || data.is_extern {
                     unsafe_exprs.push(UnsafeExpr { expr: current, inside_unsafe_block });
                 }
             }
@@ -217,6 +223,46 @@ fn main() {
     let _ = floorf32(12.0);
           //^^^^^^^^^^^^^^ This operation is unsafe and requires an unsafe function or block
 }
+"#,
+        );
+    }
+
+    #[test]
+    fn missing_unsafe_diagnostic_with_extern_items() {
+        check_diagnostics(
+            r#"
+extern "C" {
+    fn abort();
+    static EXTERN: i32;
+}
+
+fn main() {
+    abort();
+  //^^^^^^^ This operation is unsafe and requires an unsafe function or block
+    let x = EXTERN;
+          //^^^^^^ This operation is unsafe and requires an unsafe function or block
+    unsafe {
+        abort();
+        let x = EXTERN;
+    }
+}
+"#,
+        );
+    }
+
+    #[test]
+    fn missing_unsafe_diagnostic_with_extern_block_in_body() {
+        check_diagnostics(
+            r#"
+fn main() {
+    extern {
+        fn local_ffi(x: i32) -> i32;
+    }
+
+    local_ffi(0);
+  //^^^^^^^^^^^^ This operation is unsafe and requires an unsafe function or block
+    unsafe { local_ffi(1) };
+}
 "#,
         );
     }
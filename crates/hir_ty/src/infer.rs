@@ -784,18 +784,22 @@ impl std::ops::BitOrAssign for Diverges {
 }
 
 mod diagnostics {
-    use hir_def::{expr::ExprId, DefWithBodyId};
-    use hir_expand::diagnostics::DiagnosticSink;
+    use hir_def::{expr::ExprId, AdtId, DefWithBodyId, FieldId, ModuleDefId, VariantId};
+    use hir_expand::{diagnostics::DiagnosticSink, name::Name, HirFileId};
+    use syntax::SyntaxNodePtr;
 
     use crate::{
         db::HirDatabase,
-        diagnostics::{BreakOutsideOfLoop, NoSuchField},
+        diagnostics::{BreakOutsideOfLoop, NoSuchField, PrivateField, PrivateItem},
     };
 
     #[derive(Debug, PartialEq, Eq, Clone)]
     pub(super) enum InferenceDiagnostic {
         NoSuchField { expr: ExprId, field: usize },
         BreakOutsideOfLoop { expr: ExprId },
+        PrivateItem { expr: ExprId, item: ModuleDefId },
+        PrivateField { expr: ExprId, field: FieldId },
+        PrivateRecordField { expr: ExprId, field_idx: usize, field: FieldId },
     }
 
     impl InferenceDiagnostic {
@@ -818,7 +822,106 @@ mod diagnostics {
                         .expect("break outside of loop in synthetic syntax");
                     sink.push(BreakOutsideOfLoop { file: ptr.file_id, expr: ptr.value })
                 }
+                InferenceDiagnostic::PrivateItem { expr, item } => {
+                    let (_, source_map) = db.body_with_source_map(owner);
+                    let ptr = match source_map.expr_syntax(*expr) {
+                        Ok(it) => it,
+                        Err(_) => return,
+                    };
+                    let (item_kind, item_name) = match *item {
+                        ModuleDefId::FunctionId(it) => {
+                            ("function", db.function_data(it).name.clone())
+                        }
+                        ModuleDefId::ConstId(it) => (
+                            "constant",
+                            db.const_data(it).name.clone().unwrap_or_else(Name::missing),
+                        ),
+                        ModuleDefId::StaticId(it) => (
+                            "static",
+                            db.static_data(it).name.clone().unwrap_or_else(Name::missing),
+                        ),
+                        ModuleDefId::AdtId(AdtId::StructId(it)) => {
+                            ("struct", db.struct_data(it).name.clone())
+                        }
+                        ModuleDefId::AdtId(AdtId::UnionId(it)) => {
+                            ("union", db.union_data(it).name.clone())
+                        }
+                        ModuleDefId::AdtId(AdtId::EnumId(it)) => {
+                            ("enum", db.enum_data(it).name.clone())
+                        }
+                        ModuleDefId::TraitId(it) => ("trait", db.trait_data(it).name.clone()),
+                        ModuleDefId::TypeAliasId(it) => {
+                            ("type alias", db.type_alias_data(it).name.clone())
+                        }
+                        ModuleDefId::ModuleId(it) => {
+                            let def_map = it.def_map(db.upcast());
+                            let parent = match def_map[it.local_id].parent {
+                                Some(it) => it,
+                                None => return,
+                            };
+                            let name = def_map[parent]
+                                .children
+                                .iter()
+                                .find(|(_, child)| **child == it.local_id)
+                                .map(|(name, _)| name.clone());
+                            match name {
+                                Some(name) => ("module", name),
+                                None => return,
+                            }
+                        }
+                        _ => return,
+                    };
+                    sink.push(PrivateItem {
+                        file: ptr.file_id,
+                        expr: ptr.value,
+                        item_kind: item_kind.to_string(),
+                        item_name: item_name.to_string(),
+                    })
+                }
+                InferenceDiagnostic::PrivateField { expr, field } => {
+                    let (_, source_map) = db.body_with_source_map(owner);
+                    let ptr = match source_map.expr_syntax(*expr) {
+                        Ok(it) => it,
+                        Err(_) => return,
+                    };
+                    if let Some(diag) =
+                        private_field(db, *field, ptr.file_id, ptr.value.syntax_node_ptr())
+                    {
+                        sink.push(diag)
+                    }
+                }
+                InferenceDiagnostic::PrivateRecordField { expr, field_idx, field } => {
+                    let (_, source_map) = db.body_with_source_map(owner);
+                    let ptr = source_map.field_syntax(*expr, *field_idx);
+                    if let Some(diag) =
+                        private_field(db, *field, ptr.file_id, ptr.value.syntax_node_ptr())
+                    {
+                        sink.push(diag)
+                    }
+                }
             }
         }
     }
+
+    fn private_field(
+        db: &dyn HirDatabase,
+        field: FieldId,
+        file: HirFileId,
+        node: SyntaxNodePtr,
+    ) -> Option<PrivateField> {
+        let (adt_kind, data) = match field.parent {
+            VariantId::StructId(it) => ("struct", db.struct_data(it)),
+            VariantId::UnionId(it) => ("union", db.union_data(it)),
+            // Fields of enum variants are always public.
+            VariantId::EnumVariantId(_) => return None,
+        };
+        let field_name = data.variant_data.fields()[field.local_id].name.to_string();
+        Some(PrivateField {
+            file,
+            node,
+            field_name,
+            adt_kind: adt_kind.to_string(),
+            adt_name: data.name.to_string(),
+        })
+    }
 }
@@ -7,7 +7,7 @@ use hir_def::{
     builtin_type::Signedness,
     expr::{Array, BinaryOp, Expr, ExprId, Literal, Statement, UnaryOp},
     path::{GenericArg, GenericArgs},
-    resolver::resolver_for_expr,
+    resolver::{resolver_for_expr, ValueNs},
     AdtId, AssocContainerId, FieldId, Lookup,
 };
 use hir_expand::name::{name, Name};
@@ -145,7 +145,12 @@ impl<'a> InferenceContext<'a> {
                         break_ty: break_ty.clone(),
                         label: label.map(|label| self.body[label].name.clone()),
                     });
-                    let ty = self.infer_block(statements, *tail, &Expectation::has_type(break_ty));
+                    let ty = self.infer_block(
+                        tgt_expr,
+                        statements,
+                        *tail,
+                        &Expectation::has_type(break_ty),
+                    );
                     let ctxt = self.breakables.pop().expect("breakable stack broken");
                     if ctxt.may_break {
                         ctxt.break_ty
@@ -153,7 +158,7 @@ impl<'a> InferenceContext<'a> {
                         ty
                     }
                 }
-                None => self.infer_block(statements, *tail, expected),
+                None => self.infer_block(tgt_expr, statements, *tail, expected),
             },
             Expr::Unsafe { body } | Expr::Const { body } => self.infer_expr(*body, expected),
             Expr::TryBlock { body } => {
@@ -392,6 +397,13 @@ impl<'a> InferenceContext<'a> {
                         });
                     if let Some(field_def) = field_def {
                         self.result.record_field_resolutions.insert(field.expr, field_def);
+                        if !self.is_field_visible(tgt_expr, field_def) {
+                            self.push_diagnostic(InferenceDiagnostic::PrivateRecordField {
+                                expr: tgt_expr,
+                                field_idx,
+                                field: field_def,
+                            });
+                        }
                     }
                     let field_ty = field_def
                         .map_or(Ty::Unknown, |it| field_types[it.local_id].clone().subst(&substs));
@@ -441,6 +453,14 @@ impl<'a> InferenceContext<'a> {
                     _ => None,
                 })
                 .unwrap_or(Ty::Unknown);
+                if let Some(field) = self.result.field_resolution(tgt_expr) {
+                    if !self.is_field_visible(tgt_expr, field) {
+                        self.push_diagnostic(InferenceDiagnostic::PrivateField {
+                            expr: tgt_expr,
+                            field,
+                        });
+                    }
+                }
                 let ty = self.insert_type_vars(ty);
                 self.normalize_associated_types_in(ty)
             }
@@ -682,6 +702,8 @@ impl<'a> InferenceContext<'a> {
                     Ty::apply_one(TypeCtor::Ref(Mutability::Shared), Ty::simple(TypeCtor::Str))
                 }
                 Literal::ByteString(..) => {
+                    // FIXME: array types don't carry their length yet, so this is `&[u8; _]`
+                    // rather than the precise type, also for the output of `include_bytes!`.
                     let byte_type = Ty::simple(TypeCtor::Int(IntTy::u8()));
                     let array_type = Ty::apply_one(TypeCtor::Array, byte_type);
                     Ty::apply_one(TypeCtor::Ref(Mutability::Shared), array_type)
@@ -705,6 +727,32 @@ impl<'a> InferenceContext<'a> {
     }
 
     fn infer_block(
+        &mut self,
+        expr: ExprId,
+        statements: &[Statement],
+        tail: Option<ExprId>,
+        expected: &Expectation,
+    ) -> Ty {
+        // Blocks that declare items need their own resolver (for the items) and trait environment
+        // (for the impls).
+        let prev = self.body.block_scope(expr).map(|block| {
+            let resolver = resolver_for_expr(self.db.upcast(), self.owner, expr);
+            let prev_resolver = mem::replace(&mut self.resolver, resolver);
+            let prev_env = self.trait_env.clone();
+            Arc::make_mut(&mut self.trait_env).block = Some(block);
+            (prev_resolver, prev_env)
+        });
+
+        let ty = self.infer_block_inner(statements, tail, expected);
+
+        if let Some((resolver, env)) = prev {
+            self.resolver = resolver;
+            self.trait_env = env;
+        }
+        ty
+    }
+
+    fn infer_block_inner(
         &mut self,
         statements: &[Statement],
         tail: Option<ExprId>,
@@ -757,6 +805,21 @@ impl<'a> InferenceContext<'a> {
         ty
     }
 
+    fn is_field_visible(&self, expr: ExprId, field: FieldId) -> bool {
+        let visibility = self.db.field_visibilities(field.parent)[field.local_id];
+        match self.resolver.module() {
+            Some(module) if !visibility.is_visible_from(self.db.upcast(), module) => {}
+            _ => return true,
+        }
+        // A field visible from the module of the body is visible from the blocks inside it as
+        // well. Only fields of structs declared in a block can be visible from the block alone,
+        // which needs the (expensive) resolver of the expression to find out.
+        match resolver_for_expr(self.db.upcast(), self.owner, expr).module() {
+            Some(module) => visibility.is_visible_from(self.db.upcast(), module),
+            None => true,
+        }
+    }
+
     fn infer_method_call(
         &mut self,
         tgt_expr: ExprId,
@@ -784,6 +847,11 @@ impl<'a> InferenceContext<'a> {
             Some((ty, func)) => {
                 let ty = canonicalized_receiver.decanonicalize_ty(ty);
                 self.write_method_resolution(tgt_expr, func);
+                self.check_value_visibility(
+                    self.resolver.module(),
+                    &ValueNs::FunctionId(func),
+                    tgt_expr,
+                );
                 (ty, self.db.value_ty(func.into()), Some(generics(self.db.upcast(), func.into())))
             }
             None => (receiver_ty, Binders::new(0, Ty::Unknown), None),
@@ -3,15 +3,16 @@
 use std::iter;
 
 use hir_def::{
+    expr::ExprId,
     path::{Path, PathSegment},
-    resolver::{ResolveValueResult, Resolver, TypeNs, ValueNs},
-    AdtId, AssocContainerId, AssocItemId, EnumVariantId, Lookup,
+    resolver::{HasResolver, ResolveValueResult, Resolver, TypeNs, ValueNs},
+    AdtId, AssocContainerId, AssocItemId, EnumVariantId, HasModule, Lookup, ModuleDefId, ModuleId,
 };
-use hir_expand::name::Name;
+use hir_expand::{hygiene::SyntaxContext, name::Name};
 
 use crate::{method_resolution, Substs, Ty, ValueTyDefId};
 
-use super::{ExprOrPatId, InferenceContext, TraitRef};
+use super::{ExprOrPatId, InferenceContext, InferenceDiagnostic, TraitRef};
 
 impl<'a> InferenceContext<'a> {
     pub(super) fn infer_path(
@@ -32,6 +33,7 @@ impl<'a> InferenceContext<'a> {
         path: &Path,
         id: ExprOrPatId,
     ) -> Option<Ty> {
+        let mut private_segment = None;
         let (value, self_subst) = if let Some(type_ref) = path.type_anchor() {
             if path.segments().is_empty() {
                 // This can't actually happen syntax-wise
@@ -47,8 +49,17 @@ impl<'a> InferenceContext<'a> {
                 id,
             )?
         } else {
-            let value_or_partial =
-                resolver.resolve_path_in_value_ns(self.db.upcast(), path.mod_path())?;
+            let hygiene = match id {
+                ExprOrPatId::ExprId(expr) => self.body.expr_hygiene(expr),
+                ExprOrPatId::PatId(_) => SyntaxContext::ROOT,
+            };
+            let (value_or_partial, private) = resolver
+                .resolve_path_in_value_ns_with_private_segment(
+                    self.db.upcast(),
+                    path.mod_path(),
+                    hygiene,
+                )?;
+            private_segment = private;
 
             match value_or_partial {
                 ResolveValueResult::ValueNs(it) => (it, None),
@@ -58,6 +69,13 @@ impl<'a> InferenceContext<'a> {
             }
         };
 
+        if let ExprOrPatId::ExprId(expr) = id {
+            match private_segment {
+                Some(item) => self.push_diagnostic(InferenceDiagnostic::PrivateItem { expr, item }),
+                None => self.check_value_visibility(resolver.module(), &value, expr),
+            }
+        }
+
         let typable: ValueTyDefId = match value {
             ValueNs::LocalBinding(pat) => {
                 let ty = self.result.type_of_pat.get(pat)?.clone();
@@ -105,6 +123,59 @@ impl<'a> InferenceContext<'a> {
         Some(ty)
     }
 
+    pub(super) fn check_value_visibility(
+        &mut self,
+        from_module: Option<ModuleId>,
+        value: &ValueNs,
+        expr: ExprId,
+    ) {
+        let db = self.db.upcast();
+        let (item, visibility): (ModuleDefId, _) = match *value {
+            ValueNs::FunctionId(it) => {
+                if self.is_trait_item(it.lookup(db).container) {
+                    return;
+                }
+                (it.into(), self.db.function_visibility(it))
+            }
+            ValueNs::ConstId(it) => {
+                if self.is_trait_item(it.lookup(db).container) {
+                    return;
+                }
+                (it.into(), self.db.const_data(it).visibility.resolve(db, &it.resolver(db)))
+            }
+            ValueNs::StaticId(it) => {
+                (it.into(), self.db.static_data(it).visibility.resolve(db, &it.resolver(db)))
+            }
+            ValueNs::StructId(it) => {
+                let module = AdtId::from(it).module(db);
+                let visibility =
+                    match module.def_map(db)[module.local_id].scope.visibility_of(it.into()) {
+                        Some(it) => it,
+                        None => return,
+                    };
+                (it.into(), visibility)
+            }
+            _ => return,
+        };
+        let from_module = match from_module {
+            Some(it) => it,
+            None => return,
+        };
+        if !visibility.is_visible_from(db, from_module) {
+            self.push_diagnostic(InferenceDiagnostic::PrivateItem { expr, item });
+        }
+    }
+
+    /// Items of traits and trait impls are as visible as the trait itself, which is checked
+    /// when the trait is named.
+    fn is_trait_item(&self, container: AssocContainerId) -> bool {
+        match container {
+            AssocContainerId::TraitId(_) => true,
+            AssocContainerId::ImplId(it) => self.db.impl_data(it).target_trait.is_some(),
+            AssocContainerId::ModuleId(_) => false,
+        }
+    }
+
     fn resolve_assoc_item(
         &mut self,
         def: TypeNs,
@@ -260,7 +331,7 @@ impl<'a> InferenceContext<'a> {
                         }));
                         Some(trait_substs)
                     }
-                    AssocContainerId::ContainerId(_) => None,
+                    AssocContainerId::ModuleId(_) => None,
                 };
 
                 self.write_assoc_resolution(id, item);
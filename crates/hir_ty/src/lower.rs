@@ -946,7 +946,8 @@ impl TraitEnvironment {
             }
         }
 
-        Arc::new(TraitEnvironment { predicates })
+        let block = resolver.module().and_then(|module| module.block);
+        Arc::new(TraitEnvironment { block, predicates })
     }
 }
 
@@ -1144,8 +1145,8 @@ impl CallableDefId {
         let db = db.upcast();
         match self {
             CallableDefId::FunctionId(f) => f.lookup(db).module(db),
-            CallableDefId::StructId(s) => s.lookup(db).container.module(db),
-            CallableDefId::EnumVariantId(e) => e.parent.lookup(db).container.module(db),
+            CallableDefId::StructId(s) => s.lookup(db).container,
+            CallableDefId::EnumVariantId(e) => e.parent.lookup(db).container,
         }
         .krate
     }
@@ -9,8 +9,9 @@ use base_db::CrateId;
 use hir_def::{
     builtin_type::{IntBitness, Signedness},
     lang_item::LangItemTarget,
+    nameres::CrateDefMap,
     type_ref::Mutability,
-    AssocContainerId, AssocItemId, FunctionId, HasModule, ImplId, Lookup, TraitId,
+    AssocContainerId, AssocItemId, BlockId, FunctionId, HasModule, ImplId, Lookup, TraitId,
 };
 use hir_expand::name::Name;
 use rustc_hash::{FxHashMap, FxHashSet};
@@ -112,7 +113,26 @@ impl TraitImpls {
         let mut impls = Self { map: FxHashMap::default() };
 
         let crate_def_map = db.crate_def_map(krate);
-        for (_module_id, module_data) in crate_def_map.modules.iter() {
+        impls.collect_def_map(db, &crate_def_map);
+
+        Arc::new(impls)
+    }
+
+    pub(crate) fn trait_impls_in_block_query(
+        db: &dyn HirDatabase,
+        block: BlockId,
+    ) -> Option<Arc<Self>> {
+        let _p = profile::span("trait_impls_in_block_query");
+        let mut impls = Self { map: FxHashMap::default() };
+
+        let block_def_map = db.block_def_map(block)?;
+        impls.collect_def_map(db, &block_def_map);
+
+        Some(Arc::new(impls))
+    }
+
+    fn collect_def_map(&mut self, db: &dyn HirDatabase, def_map: &CrateDefMap) {
+        for (_module_id, module_data) in def_map.modules.iter() {
             for impl_id in module_data.scope.impls() {
                 let target_trait = match db.impl_trait(impl_id) {
                     Some(tr) => tr.value.trait_,
@@ -120,8 +140,7 @@ impl TraitImpls {
                 };
                 let self_ty = db.impl_self_ty(impl_id);
                 let self_ty_fp = TyFingerprint::for_impl(&self_ty.value);
-                impls
-                    .map
+                self.map
                     .entry(target_trait)
                     .or_default()
                     .entry(self_ty_fp)
@@ -129,8 +148,6 @@ impl TraitImpls {
                     .push(impl_id);
             }
         }
-
-        Arc::new(impls)
     }
 
     pub(crate) fn trait_impls_in_deps_query(db: &dyn HirDatabase, krate: CrateId) -> Arc<Self> {
@@ -195,10 +212,28 @@ pub struct InherentImpls {
 
 impl InherentImpls {
     pub(crate) fn inherent_impls_in_crate_query(db: &dyn HirDatabase, krate: CrateId) -> Arc<Self> {
-        let mut map: FxHashMap<_, Vec<_>> = FxHashMap::default();
+        let mut impls = Self { map: FxHashMap::default() };
 
         let crate_def_map = db.crate_def_map(krate);
-        for (_module_id, module_data) in crate_def_map.modules.iter() {
+        impls.collect_def_map(db, &crate_def_map);
+
+        Arc::new(impls)
+    }
+
+    pub(crate) fn inherent_impls_in_block_query(
+        db: &dyn HirDatabase,
+        block: BlockId,
+    ) -> Option<Arc<Self>> {
+        let mut impls = Self { map: FxHashMap::default() };
+
+        let block_def_map = db.block_def_map(block)?;
+        impls.collect_def_map(db, &block_def_map);
+
+        Some(Arc::new(impls))
+    }
+
+    fn collect_def_map(&mut self, db: &dyn HirDatabase, def_map: &CrateDefMap) {
+        for (_module_id, module_data) in def_map.modules.iter() {
             for impl_id in module_data.scope.impls() {
                 let data = db.impl_data(impl_id);
                 if data.target_trait.is_some() {
@@ -207,12 +242,10 @@ impl InherentImpls {
 
                 let self_ty = db.impl_self_ty(impl_id);
                 if let Some(fp) = TyFingerprint::for_impl(&self_ty.value) {
-                    map.entry(fp).or_default().push(impl_id);
+                    self.map.entry(fp).or_default().push(impl_id);
                 }
             }
         }
-
-        Arc::new(Self { map })
     }
 
     pub fn for_self_ty(&self, self_ty: &Ty) -> &[ImplId] {
@@ -227,6 +260,15 @@ impl InherentImpls {
     }
 }
 
+/// Returns `block` and all block expressions (with item declarations) it is
+/// nested in, innermost first.
+pub(crate) fn block_chain(
+    db: &dyn HirDatabase,
+    block: Option<BlockId>,
+) -> impl Iterator<Item = BlockId> + '_ {
+    std::iter::successors(block, move |&block| db.block_def_map(block)?.parent()?.block)
+}
+
 impl Ty {
     pub fn def_crates(
         &self,
@@ -280,7 +322,7 @@ impl Ty {
                 LangItemTarget::ImplDefId(it) => Some(it),
                 _ => None,
             })
-            .map(|it| it.lookup(db.upcast()).container.module(db.upcast()).krate)
+            .map(|it| it.lookup(db.upcast()).container.krate)
             .collect();
         Some(res)
     }
@@ -489,7 +531,15 @@ fn iterate_method_candidates_by_receiver(
     // be found in any of the derefs of receiver_ty, so we have to go through
     // that.
     for self_ty in std::iter::once(receiver_ty).chain(rest_of_deref_chain) {
-        if iterate_inherent_methods(self_ty, db, name, Some(receiver_ty), krate, &mut callback) {
+        if iterate_inherent_methods(
+            self_ty,
+            db,
+            env.clone(),
+            name,
+            Some(receiver_ty),
+            krate,
+            &mut callback,
+        ) {
             return true;
         }
     }
@@ -519,7 +569,7 @@ fn iterate_method_candidates_for_self_ty(
     name: Option<&Name>,
     mut callback: &mut dyn FnMut(&Ty, AssocItemId) -> bool,
 ) -> bool {
-    if iterate_inherent_methods(self_ty, db, name, None, krate, &mut callback) {
+    if iterate_inherent_methods(self_ty, db, env.clone(), name, None, krate, &mut callback) {
         return true;
     }
     iterate_trait_method_candidates(self_ty, db, env, krate, traits_in_scope, name, None, callback)
@@ -578,6 +628,7 @@ fn iterate_trait_method_candidates(
 fn iterate_inherent_methods(
     self_ty: &Canonical<Ty>,
     db: &dyn HirDatabase,
+    env: Arc<TraitEnvironment>,
     name: Option<&Name>,
     receiver_ty: Option<&Canonical<Ty>>,
     krate: CrateId,
@@ -587,9 +638,13 @@ fn iterate_inherent_methods(
         Some(k) => k,
         None => return false,
     };
-    for krate in def_crates {
-        let impls = db.inherent_impls_in_crate(krate);
-
+    // Impls in blocks can only be seen from inside of the block, but they may be for types
+    // defined anywhere.
+    let impl_maps = block_chain(db, env.block)
+        .filter_map(|block| db.inherent_impls_in_block(block))
+        .chain(def_crates.into_iter().map(|krate| db.inherent_impls_in_crate(krate)))
+        .collect::<Vec<_>>();
+    for impls in impl_maps {
         for &impl_def in impls.for_self_ty(&self_ty.value) {
             for &item in db.impl_data(impl_def).items.iter() {
                 if !is_valid_candidate(db, name, receiver_ty, item, self_ty) {
@@ -727,7 +782,7 @@ fn transform_receiver_ty(
                 .fill_with_unknown()
                 .build()
         }
-        AssocContainerId::ContainerId(_) => unreachable!(),
+        AssocContainerId::ModuleId(_) => unreachable!(),
     };
     let sig = db.callable_item_signature(function_id.into());
     Some(sig.value.params()[0].clone().subst_bound_vars(&substs))
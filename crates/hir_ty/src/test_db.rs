@@ -18,6 +18,7 @@ use test_utils::extract_annotations;
     base_db::SourceDatabaseExtStorage,
     base_db::SourceDatabaseStorage,
     hir_expand::db::AstDatabaseStorage,
+    hir_expand::proc_macro::ProcMacroDatabaseStorage,
     hir_def::db::InternDatabaseStorage,
     hir_def::db::DefDatabaseStorage,
     crate::db::HirDatabaseStorage
@@ -69,6 +70,9 @@ impl FileLoader for TestDB {
     fn file_text(&self, file_id: FileId) -> Arc<String> {
         FileLoaderDelegate(self).file_text(file_id)
     }
+    fn file_bytes(&self, file_id: FileId) -> Option<Arc<Vec<u8>>> {
+        FileLoaderDelegate(self).file_bytes(file_id)
+    }
     fn resolve_path(&self, path: AnchoredPath) -> Option<FileId> {
         FileLoaderDelegate(self).resolve_path(path)
     }
@@ -83,7 +87,7 @@ impl TestDB {
             let crate_def_map = self.crate_def_map(krate);
             for (local_id, data) in crate_def_map.modules.iter() {
                 if data.origin.file_id() == Some(file_id) {
-                    return ModuleId { krate, local_id };
+                    return crate_def_map.module_id(local_id);
                 }
             }
         }
@@ -225,7 +225,7 @@ fn visit_module(
     module_id: LocalModuleId,
     cb: &mut dyn FnMut(DefWithBodyId),
 ) {
-    visit_scope(db, crate_def_map, &crate_def_map[module_id].scope, cb);
+    visit_scope(db, &crate_def_map[module_id].scope, cb);
     for impl_id in crate_def_map[module_id].scope.impls() {
         let impl_data = db.impl_data(impl_id);
         for &item in impl_data.items.iter() {
@@ -233,45 +233,35 @@ fn visit_module(
                 AssocItemId::FunctionId(it) => {
                     let def = it.into();
                     cb(def);
-                    let body = db.body(def);
-                    visit_scope(db, crate_def_map, &body.item_scope, cb);
+                    visit_body(db, def, cb);
                 }
                 AssocItemId::ConstId(it) => {
                     let def = it.into();
                     cb(def);
-                    let body = db.body(def);
-                    visit_scope(db, crate_def_map, &body.item_scope, cb);
+                    visit_body(db, def, cb);
                 }
                 AssocItemId::TypeAliasId(_) => (),
             }
         }
     }
 
-    fn visit_scope(
-        db: &TestDB,
-        crate_def_map: &CrateDefMap,
-        scope: &ItemScope,
-        cb: &mut dyn FnMut(DefWithBodyId),
-    ) {
+    fn visit_scope(db: &TestDB, scope: &ItemScope, cb: &mut dyn FnMut(DefWithBodyId)) {
         for decl in scope.declarations() {
             match decl {
                 ModuleDefId::FunctionId(it) => {
                     let def = it.into();
                     cb(def);
-                    let body = db.body(def);
-                    visit_scope(db, crate_def_map, &body.item_scope, cb);
+                    visit_body(db, def, cb);
                 }
                 ModuleDefId::ConstId(it) => {
                     let def = it.into();
                     cb(def);
-                    let body = db.body(def);
-                    visit_scope(db, crate_def_map, &body.item_scope, cb);
+                    visit_body(db, def, cb);
                 }
                 ModuleDefId::StaticId(it) => {
                     let def = it.into();
                     cb(def);
-                    let body = db.body(def);
-                    visit_scope(db, crate_def_map, &body.item_scope, cb);
+                    visit_body(db, def, cb);
                 }
                 ModuleDefId::TraitId(it) => {
                     let trait_data = db.trait_data(it);
@@ -283,11 +273,18 @@ fn visit_module(
                         }
                     }
                 }
-                ModuleDefId::ModuleId(it) => visit_module(db, crate_def_map, it.local_id, cb),
+                ModuleDefId::ModuleId(it) => visit_module(db, &it.def_map(db), it.local_id, cb),
                 _ => (),
             }
         }
     }
+
+    fn visit_body(db: &TestDB, def: DefWithBodyId, cb: &mut dyn FnMut(DefWithBodyId)) {
+        let body = db.body(def);
+        for def_map in body.block_def_maps(db) {
+            visit_scope(db, &def_map[def_map.root].scope, cb);
+        }
+    }
 }
 
 fn ellipsize(mut text: String, max_len: usize) -> String {
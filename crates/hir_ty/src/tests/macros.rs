@@ -108,6 +108,81 @@ fn infer_legacy_textual_scoped_macros_expanded() {
     );
 }
 
+#[test]
+fn infer_shadowed_legacy_macros_expanded() {
+    check_types(
+        r#"
+macro_rules! m { () => { 0u8 } }
+
+fn f() {
+    let a = m!();
+    a;
+  //^ u8
+}
+
+macro_rules! m { () => { 0u16 } }
+
+fn g() {
+    let b = m!();
+    b;
+  //^ u16
+    {
+        macro_rules! m { () => { 0u32 } }
+        let c = m!();
+        c;
+      //^ u32
+    }
+    let d = m!();
+    d;
+  //^ u16
+}
+"#,
+    );
+}
+
+#[test]
+fn infer_legacy_macros_shadowed_by_macro_use_module() {
+    check_types(
+        r#"
+//- /main.rs
+macro_rules! m { () => { 0u8 } }
+
+fn f() {
+    let a = m!();
+    a;
+  //^ u8
+}
+
+#[macro_use]
+mod a;
+
+fn g() {
+    let b = m!();
+    b;
+  //^ u16
+}
+
+mod b;
+//- /a.rs
+macro_rules! m { () => { 0u16 } }
+//- /b.rs
+fn h() {
+    let c = m!();
+    c;
+  //^ u16
+}
+
+macro_rules! m { () => { 0u32 } }
+
+fn i() {
+    let d = m!();
+    d;
+  //^ u32
+}
+"#,
+    );
+}
+
 #[test]
 fn infer_path_qualified_macros_expanded() {
     check_infer(
@@ -443,6 +518,56 @@ macro_rules! bar {
     );
 }
 
+#[test]
+fn macro_local_does_not_shadow_caller_local() {
+    check_types(
+        r#"
+macro_rules! m {
+    ($e:expr) => {{ let x = 1u8; $e }};
+}
+
+fn test() {
+    let x = "";
+    let y = m!(x);
+    y;
+} //^ &str
+"#,
+    );
+}
+
+#[test]
+fn macro_body_does_not_see_caller_local() {
+    check_types(
+        r#"
+macro_rules! m {
+    () => { x };
+}
+
+fn test() {
+    let x = 1u32;
+    let y = m!();
+    y;
+} //^ {unknown}
+"#,
+    );
+}
+
+#[test]
+fn macro_body_sees_its_own_local() {
+    check_types(
+        r#"
+macro_rules! m {
+    ($e:expr) => {{ let x = $e; x }};
+}
+
+fn test() {
+    let y = m!(1u16);
+    y;
+} //^ u16
+"#,
+    );
+}
+
 #[test]
 fn infer_builtin_macros_line() {
     check_infer(
@@ -539,6 +664,65 @@ fn bar() -> u32 {0}
     );
 }
 
+#[test]
+fn infer_builtin_macros_include_str() {
+    check_types(
+        r#"
+//- /main.rs
+#[rustc_builtin_macro]
+macro_rules! include_str {() => {}}
+
+fn main() {
+    let query = include_str!("query.sql");
+    query;
+} //^ &str
+
+//- /query.sql
+SELECT 1;
+"#,
+    );
+}
+
+#[test]
+fn infer_builtin_macros_include_bytes() {
+    check_types(
+        r#"
+//- /main.rs
+#[rustc_builtin_macro]
+macro_rules! include_bytes {() => {}}
+
+fn main() {
+    let data = include_bytes!("data.bin");
+    data;
+} //^ &[u8; _]
+
+//- /data.bin
+\x00\x01
+"#,
+    );
+}
+
+#[test]
+fn infer_builtin_macros_include_unloaded_file() {
+    check_types(
+        r#"
+//- /main.rs
+#[rustc_builtin_macro]
+macro_rules! include_str {() => {}}
+#[rustc_builtin_macro]
+macro_rules! include_bytes {() => {}}
+
+fn main() {
+    let text = include_str!("missing.txt");
+    text;
+  //^ &str
+    let data = include_bytes!("missing.bin");
+    data;
+} //^ &[u8; _]
+"#,
+    );
+}
+
 #[test]
 #[ignore]
 fn include_accidentally_quadratic() {
@@ -611,6 +795,34 @@ fn bar() -> u32 {0}
     );
 }
 
+#[test]
+fn infer_builtin_macros_include_concat_with_env_from_macro_rules() {
+    check_types(
+        r#"
+//- /main.rs crate:main env:OUT_DIR=/out
+#[rustc_builtin_macro]
+macro_rules! include {() => {}}
+
+#[rustc_builtin_macro]
+macro_rules! concat {() => {}}
+
+#[rustc_builtin_macro]
+macro_rules! env {() => {}}
+
+macro_rules! out_dir {() => { env!("OUT_DIR") }}
+
+include!(concat!(out_dir!(), "/foo.rs"));
+
+fn main() {
+    bar();
+}     //^ u32
+
+//- /out/foo.rs
+fn bar() -> u32 {0}
+"#,
+    );
+}
+
 #[test]
 fn infer_builtin_macros_include_itself_should_failed() {
     check_types(
@@ -1106,3 +1106,66 @@ fn main() {
 "#,
     );
 }
+
+#[test]
+fn method_in_block_impl() {
+    check_types(
+        r#"
+struct S;
+
+fn main() {
+    impl S {
+        fn foo(&self) -> u8 { 0 }
+    }
+
+    {
+        S.foo();
+      //^^^^^^^ u8
+    }
+}
+"#,
+    );
+}
+
+#[test]
+fn trait_impl_in_block() {
+    check_types(
+        r#"
+struct S;
+
+fn main() {
+    trait Tr {
+        fn tr(&self) -> u16;
+    }
+    impl Tr for S {
+        fn tr(&self) -> u16 { 0 }
+    }
+
+    S.tr();
+  //^^^^^^ u16
+}
+"#,
+    );
+}
+
+#[test]
+fn local_struct_shadows_module_struct() {
+    check_types(
+        r#"
+struct S;
+impl S {
+    fn foo(&self) -> u8 { 0 }
+}
+
+fn main() {
+    struct S;
+    impl S {
+        fn foo(&self) -> i64 { 0 }
+    }
+
+    S.foo();
+  //^^^^^^^ i64
+}
+"#,
+    );
+}
@@ -2391,3 +2391,39 @@ fn infer_const_params() {
         "#]],
     );
 }
+
+#[test]
+fn infer_use_in_block() {
+    check_types(
+        r#"
+mod m {
+    pub struct Inner;
+}
+
+fn f() {
+    use m::Inner;
+
+    let x = Inner;
+    x;
+  //^ Inner
+}
+"#,
+    );
+}
+
+#[test]
+fn infer_item_in_nested_block() {
+    check_types(
+        r#"
+fn f() {
+    struct Outer(u8);
+    {
+        struct Inner;
+        let x = (Outer(0), Inner);
+        x;
+      //^ (Outer, Inner)
+    }
+}
+"#,
+    );
+}
@@ -5,7 +5,7 @@ use std::sync::Arc;
 use base_db::CrateId;
 use chalk_ir::cast::Cast;
 use chalk_solve::{logging_db::LoggingRustIrDatabase, Solver};
-use hir_def::{lang_item::LangItemTarget, TraitId};
+use hir_def::{lang_item::LangItemTarget, BlockId, TraitId};
 use stdx::panic_context;
 
 use crate::{db::HirDatabase, DebruijnIndex, Substs};
@@ -23,6 +23,7 @@ const CHALK_SOLVER_FUEL: i32 = 100;
 struct ChalkContext<'a> {
     db: &'a dyn HirDatabase,
     krate: CrateId,
+    block: Option<BlockId>,
 }
 
 fn create_chalk_solver() -> chalk_recursive::RecursiveSolver<Interner> {
@@ -40,6 +41,10 @@ fn create_chalk_solver() -> chalk_recursive::RecursiveSolver<Interner> {
 /// we assume that `T: Default`.
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct TraitEnvironment {
+    /// The innermost block expression with item declarations this environment is used in. Impls
+    /// declared in it (and in the blocks containing it) are taken into account in addition to
+    /// the crate-level impls.
+    pub block: Option<BlockId>,
     pub predicates: Vec<GenericPredicate>,
 }
 
@@ -135,21 +140,23 @@ pub(crate) fn trait_solve_query(
         }
     }
 
+    let block = goal.value.environment.block;
     let canonical = goal.to_chalk(db).cast(&Interner);
 
     // We currently don't deal with universes (I think / hope they're not yet
     // relevant for our use cases?)
     let u_canonical = chalk_ir::UCanonical { canonical, universes: 1 };
-    let solution = solve(db, krate, &u_canonical);
+    let solution = solve(db, krate, block, &u_canonical);
     solution.map(|solution| solution_from_chalk(db, solution))
 }
 
 fn solve(
     db: &dyn HirDatabase,
     krate: CrateId,
+    block: Option<BlockId>,
     goal: &chalk_ir::UCanonical<chalk_ir::InEnvironment<chalk_ir::Goal<Interner>>>,
 ) -> Option<chalk_solve::Solution<Interner>> {
-    let context = ChalkContext { db, krate };
+    let context = ChalkContext { db, krate, block };
     log::debug!("solve goal: {:?}", goal);
     let mut solver = create_chalk_solver();
 
@@ -9,7 +9,7 @@ use chalk_solve::rust_ir::{self, OpaqueTyDatumBound, WellKnownTrait};
 use base_db::{salsa::InternKey, CrateId};
 use hir_def::{
     lang_item::{lang_attr, LangItemTarget},
-    AssocContainerId, AssocItemId, HasModule, Lookup, TypeAliasId,
+    AssocContainerId, AssocItemId, Lookup, TypeAliasId,
 };
 use hir_expand::name::name;
 
@@ -17,7 +17,7 @@ use super::ChalkContext;
 use crate::{
     db::HirDatabase,
     display::HirDisplay,
-    method_resolution::{TyFingerprint, ALL_FLOAT_FPS, ALL_INT_FPS},
+    method_resolution::{block_chain, TyFingerprint, ALL_FLOAT_FPS, ALL_INT_FPS},
     utils::generics,
     BoundVar, CallableDefId, DebruijnIndex, FnSig, GenericPredicate, ProjectionPredicate,
     ProjectionTy, Substs, TraitRef, Ty, TypeCtor,
@@ -113,7 +113,11 @@ impl<'a> chalk_solve::RustIrDatabase<Interner> for ChalkContext<'a> {
         // and will panic if the trait can't be resolved.
         let in_deps = self.db.trait_impls_in_deps(self.krate);
         let in_self = self.db.trait_impls_in_crate(self.krate);
-        let impl_maps = [in_deps, in_self];
+        let mut impl_maps = vec![in_deps, in_self];
+        impl_maps.extend(
+            block_chain(self.db, self.block)
+                .filter_map(|block| self.db.trait_impls_in_block(block)),
+        );
 
         let id_to_chalk = |id: hir_def::ImplId| id.to_chalk(self.db);
 
@@ -372,7 +376,7 @@ pub(crate) fn program_clauses_for_chalk_env_query(
     krate: CrateId,
     environment: chalk_ir::Environment<Interner>,
 ) -> chalk_ir::ProgramClauses<Interner> {
-    chalk_solve::program_clauses_for_env(&ChalkContext { db, krate }, &environment)
+    chalk_solve::program_clauses_for_env(&ChalkContext { db, krate, block: None }, &environment)
 }
 
 pub(crate) fn associated_ty_data_query(
@@ -426,7 +430,7 @@ pub(crate) fn trait_datum_query(
     let bound_vars = Substs::bound_vars(&generic_params, DebruijnIndex::INNERMOST);
     let flags = rust_ir::TraitFlags {
         auto: trait_data.auto,
-        upstream: trait_.lookup(db.upcast()).container.module(db.upcast()).krate != krate,
+        upstream: trait_.lookup(db.upcast()).container.krate != krate,
         non_enumerable: true,
         coinductive: false, // only relevant for Chalk testing
         // FIXME: set these flags correctly
@@ -549,7 +553,7 @@ fn impl_def_datum(
     let generic_params = generics(db.upcast(), impl_id.into());
     let bound_vars = Substs::bound_vars(&generic_params, DebruijnIndex::INNERMOST);
     let trait_ = trait_ref.trait_;
-    let impl_type = if impl_id.lookup(db.upcast()).container.module(db.upcast()).krate == krate {
+    let impl_type = if impl_id.lookup(db.upcast()).container.krate == krate {
         rust_ir::ImplType::Local
     } else {
         rust_ir::ImplType::External
@@ -10,8 +10,8 @@ mod field_shorthand;
 use std::cell::RefCell;
 
 use hir::{
-    diagnostics::{Diagnostic as _, DiagnosticCode, DiagnosticSinkBuilder},
-    Semantics,
+    diagnostics::{Diagnostic as _, DiagnosticCode, DiagnosticLevel, DiagnosticSinkBuilder},
+    InFile, MacroBacktraceFrame, Semantics,
 };
 use ide_db::base_db::SourceDatabase;
 use ide_db::RootDatabase;
@@ -23,7 +23,7 @@ use syntax::{
 };
 use text_edit::TextEdit;
 
-use crate::{FileId, Label, SourceChange, SourceFileEdit};
+use crate::{FileId, FileRange, Label, SourceChange, SourceFileEdit};
 
 use self::fixes::DiagnosticWithFix;
 
@@ -36,11 +36,21 @@ pub struct Diagnostic {
     pub fix: Option<Fix>,
     pub unused: bool,
     pub code: Option<DiagnosticCode>,
+    /// Other locations that help to understand the diagnostic.
+    pub related: Vec<RelatedInformation>,
 }
 
 impl Diagnostic {
     fn error(range: TextRange, message: String) -> Self {
-        Self { message, range, severity: Severity::Error, fix: None, unused: false, code: None }
+        Self {
+            message,
+            range,
+            severity: Severity::Error,
+            fix: None,
+            unused: false,
+            code: None,
+            related: Vec::new(),
+        }
     }
 
     fn hint(range: TextRange, message: String) -> Self {
@@ -51,6 +61,7 @@ impl Diagnostic {
             fix: None,
             unused: false,
             code: None,
+            related: Vec::new(),
         }
     }
 
@@ -65,6 +76,17 @@ impl Diagnostic {
     fn with_code(self, code: Option<DiagnosticCode>) -> Self {
         Self { code, ..self }
     }
+
+    fn with_related(self, related: Vec<RelatedInformation>) -> Self {
+        Self { related, ..self }
+    }
+}
+
+/// A location related to a diagnostic, like a macro call that led to an error.
+#[derive(Debug)]
+pub struct RelatedInformation {
+    pub range: FileRange,
+    pub message: String,
 }
 
 #[derive(Debug)]
@@ -134,6 +156,15 @@ pub(crate) fn diagnostics(
         .on::<hir::diagnostics::RemoveThisSemicolon, _>(|d| {
             res.borrow_mut().push(diagnostic_with_fix(d, &sema));
         })
+        .on::<hir::diagnostics::UnresolvedMacroCall, _>(|d| {
+            res.borrow_mut().push(diagnostic_with_fix(d, &sema));
+        })
+        .on::<hir::diagnostics::PrivateItem, _>(|d| {
+            res.borrow_mut().push(diagnostic_with_fix(d, &sema));
+        })
+        .on::<hir::diagnostics::PrivateField, _>(|d| {
+            res.borrow_mut().push(diagnostic_with_fix(d, &sema));
+        })
         .on::<hir::diagnostics::IncorrectCase, _>(|d| {
             res.borrow_mut().push(warning_with_fix(d, &sema));
         })
@@ -150,6 +181,24 @@ pub(crate) fn diagnostics(
                     .with_code(Some(d.code())),
             );
         })
+        .on::<hir::diagnostics::MacroError, _>(|d| {
+            // Point at the offending input token if we know it.
+            let display_range = match d.precise_location {
+                Some(range) => sema.original_file_range(InFile::new(d.file, range)).range,
+                None => sema.diagnostics_display_range(d).range,
+            };
+            let diagnostic = match d.level {
+                DiagnosticLevel::Error => Diagnostic::error(display_range, d.message()),
+                DiagnosticLevel::Warning | DiagnosticLevel::Note | DiagnosticLevel::Help => {
+                    Diagnostic::hint(display_range, d.message())
+                }
+            };
+            res.borrow_mut().push(
+                diagnostic
+                    .with_code(Some(d.code()))
+                    .with_related(macro_backtrace(&sema, &d.backtrace)),
+            );
+        })
         .on::<hir::diagnostics::UnresolvedProcMacro, _>(|d| {
             // Use more accurate position if available.
             let display_range =
@@ -192,6 +241,28 @@ fn warning_with_fix<D: DiagnosticWithFix>(d: &D, sema: &Semantics<RootDatabase>)
         .with_code(Some(d.code()))
 }
 
+/// Shows the macro calls that led to an error inside a macro expansion, innermost first.
+fn macro_backtrace(
+    sema: &Semantics<RootDatabase>,
+    backtrace: &[MacroBacktraceFrame],
+) -> Vec<RelatedInformation> {
+    let mut res = Vec::new();
+    for frame in backtrace {
+        if let (Some(arm), Some(failed_at)) = (frame.arm, frame.failed_at) {
+            res.push(RelatedInformation {
+                range: sema.original_file_range(failed_at),
+                message: format!("rule {} of `{}` failed to match here", arm + 1, frame.name),
+            });
+        }
+        let message = match frame.arm {
+            Some(arm) => format!("in this expansion of `{}` (rule {})", frame.name, arm + 1),
+            None => format!("in this expansion of `{}`", frame.name),
+        };
+        res.push(RelatedInformation { range: sema.original_file_range(frame.call), message });
+    }
+    res
+}
+
 fn check_unnecessary_braces_in_use_statement(
     acc: &mut Vec<Diagnostic>,
     file_id: FileId,
@@ -590,6 +661,109 @@ fn test_fn() {
                                 "unresolved-module",
                             ),
                         ),
+                        related: [],
+                    },
+                ]
+            "#]],
+        );
+    }
+
+    #[test]
+    fn macro_error_backtrace() {
+        check_expect(
+            r#"
+macro_rules! inner { (a) => {}; }
+macro_rules! outer { () => { inner!(b) }; }
+fn f() { outer!(); }
+"#,
+            expect![[r#"
+                [
+                    Diagnostic {
+                        message: "no rules expected the token `b`; expected `a`",
+                        range: 87..95,
+                        severity: Error,
+                        fix: None,
+                        unused: false,
+                        code: Some(
+                            DiagnosticCode(
+                                "macro-error",
+                            ),
+                        ),
+                        related: [
+                            RelatedInformation {
+                                range: FileRange {
+                                    file_id: FileId(
+                                        0,
+                                    ),
+                                    range: 87..95,
+                                },
+                                message: "rule 1 of `inner!` failed to match here",
+                            },
+                            RelatedInformation {
+                                range: FileRange {
+                                    file_id: FileId(
+                                        0,
+                                    ),
+                                    range: 87..95,
+                                },
+                                message: "in this expansion of `inner!` (rule 1)",
+                            },
+                            RelatedInformation {
+                                range: FileRange {
+                                    file_id: FileId(
+                                        0,
+                                    ),
+                                    range: 87..95,
+                                },
+                                message: "in this expansion of `outer!` (rule 1)",
+                            },
+                        ],
+                    },
+                ]
+            "#]],
+        );
+    }
+
+    #[test]
+    fn macro_error_points_at_unexpected_token() {
+        check_expect(
+            r#"
+macro_rules! m { (a) => {}; }
+fn f() { m!(b); }
+"#,
+            expect![[r#"
+                [
+                    Diagnostic {
+                        message: "no rules expected the token `b`; expected `a`",
+                        range: 42..43,
+                        severity: Error,
+                        fix: None,
+                        unused: false,
+                        code: Some(
+                            DiagnosticCode(
+                                "macro-error",
+                            ),
+                        ),
+                        related: [
+                            RelatedInformation {
+                                range: FileRange {
+                                    file_id: FileId(
+                                        0,
+                                    ),
+                                    range: 42..43,
+                                },
+                                message: "rule 1 of `m!` failed to match here",
+                            },
+                            RelatedInformation {
+                                range: FileRange {
+                                    file_id: FileId(
+                                        0,
+                                    ),
+                                    range: 39..44,
+                                },
+                                message: "in this expansion of `m!` (rule 1)",
+                            },
+                        ],
                     },
                 ]
             "#]],
@@ -881,4 +1055,82 @@ impl TestStruct {
 
         check_fix(input, expected);
     }
+
+    #[test]
+    fn private_item_fix() {
+        check_fix(
+            r#"
+mod m {
+    fn frobnicate() {}
+}
+fn main() {
+    m::frob<|>nicate();
+}
+"#,
+            r#"
+mod m {
+    pub(crate) fn frobnicate() {}
+}
+fn main() {
+    m::frobnicate();
+}
+"#,
+        );
+    }
+
+    #[test]
+    fn private_field_fix() {
+        check_fix(
+            r#"
+mod m {
+    pub struct S { field: u8 }
+}
+fn f(s: m::S) {
+    s.fi<|>eld;
+}
+"#,
+            r#"
+mod m {
+    pub struct S { pub(crate) field: u8 }
+}
+fn f(s: m::S) {
+    s.field;
+}
+"#,
+        );
+    }
+
+    #[test]
+    fn unresolved_macro_call_import_fix() {
+        check_fix(
+            r#"
+//- /main.rs crate:main deps:dep
+fn main() {
+    m<|>!();
+}
+//- /dep.rs crate:dep
+#[macro_export]
+macro_rules! m { () => {} }
+"#,
+            r#"
+use dep::m;
+
+fn main() {
+    m!();
+}
+"#,
+        );
+    }
+
+    #[test]
+    fn no_unresolved_macro_call_for_textual_scope() {
+        check_no_diagnostics(
+            r#"
+macro_rules! m { () => {} }
+fn main() {
+    m!();
+}
+"#,
+        );
+    }
 }
@@ -1,22 +1,29 @@
 //! Provides a way to attach fixes to the diagnostics.
 //! The same module also has all curret custom fixes for the diagnostics implemented.
+use assists::{Assist, AssistConfig};
+use either::Either;
 use hir::{
     db::AstDatabase,
     diagnostics::{
-        Diagnostic, IncorrectCase, MissingFields, MissingOkInTailExpr, NoSuchField,
-        RemoveThisSemicolon, UnresolvedModule,
+        Diagnostic, IncorrectCase, MissingFields, MissingOkInTailExpr, NoSuchField, PrivateField,
+        PrivateItem, RemoveThisSemicolon, UnresolvedMacroCall, UnresolvedModule,
     },
-    HasSource, HirDisplay, InFile, Semantics, VariantDef,
+    HasSource, HirDisplay, HirFileId, InFile, Semantics, VariantDef,
 };
-use ide_db::base_db::{AnchoredPathBuf, FileId};
+use ide_db::base_db::{AnchoredPathBuf, FileId, FileRange};
 use ide_db::{
+    helpers::{
+        insert_use::{insert_use, ImportScope, MergeBehavior},
+        mod_path_to_ast,
+    },
+    imports_locator,
     source_change::{FileSystemEdit, SourceFileEdit},
     RootDatabase,
 };
 use syntax::{
     algo,
     ast::{self, edit::IndentLevel, make},
-    AstNode,
+    AstNode, SyntaxNode, TextRange,
 };
 use text_edit::TextEdit;
 
@@ -144,6 +151,98 @@ impl DiagnosticWithFix for IncorrectCase {
     }
 }
 
+impl DiagnosticWithFix for UnresolvedMacroCall {
+    fn fix(&self, sema: &Semantics<RootDatabase>) -> Option<Fix> {
+        // Only a plain macro name can be brought into scope with a `use`.
+        let name = self.path.as_ident()?;
+
+        // Calls inside of macro expansions can't be fixed from here.
+        let file_id = self.file.original_file(sema.db);
+        if self.file != file_id.into() {
+            return None;
+        }
+        let source_file = sema.parse(file_id);
+        let macro_call = self.node.to_node(source_file.syntax());
+
+        let module = sema.scope(macro_call.syntax()).module()?;
+        let krate = module.krate();
+        let mut candidates: Vec<_> =
+            imports_locator::find_exact_imports(sema, krate, name.to_string())
+                .filter_map(|it| match it {
+                    Either::Right(mac) => Some(mac),
+                    Either::Left(_) => None,
+                })
+                // Macros of the current crate aren't found by their path, but they can
+                // be in textual scope, so only suggest `#[macro_export]`ed ones of dependencies.
+                .filter(|mac| mac.module(sema.db).map_or(false, |it| it.krate() != krate))
+                .filter_map(|mac| module.find_use_path(sema.db, mac))
+                .collect();
+        candidates.sort_by_cached_key(|path| path.to_string());
+        let import = candidates.into_iter().next()?;
+
+        let scope = ImportScope::find_insert_use_container(macro_call.syntax(), sema)?;
+        // FIXME: respect the configured merge behavior once diagnostics have access to it.
+        let rewriter = insert_use(&scope, mod_path_to_ast(&import), Some(MergeBehavior::Full));
+        let edit = {
+            let mut builder = TextEdit::builder();
+            algo::diff(scope.as_syntax_node(), &rewriter.rewrite(scope.as_syntax_node()))
+                .into_text_edit(&mut builder);
+            builder.finish()
+        };
+        Some(Fix::new(
+            &format!("Import `{}`", import),
+            SourceFileEdit { file_id, edit }.into(),
+            macro_call.syntax().text_range(),
+        ))
+    }
+}
+
+impl DiagnosticWithFix for PrivateItem {
+    fn fix(&self, sema: &Semantics<RootDatabase>) -> Option<Fix> {
+        let root = sema.db.parse_or_expand(self.file)?;
+        let expr = self.expr.to_node(&root);
+        let path = match &expr {
+            ast::Expr::PathExpr(it) => it.path()?,
+            _ => return None,
+        };
+        // `fix_visibility` looks for the innermost path, so point it at the last segment.
+        let name_ref = path.segment()?.name_ref()?;
+        fix_visibility(sema, self.file, name_ref.syntax(), expr.syntax())
+    }
+}
+
+impl DiagnosticWithFix for PrivateField {
+    fn fix(&self, sema: &Semantics<RootDatabase>) -> Option<Fix> {
+        let root = sema.db.parse_or_expand(self.file)?;
+        let node = self.node.to_node(&root);
+        let usage = match ast::FieldExpr::cast(node.clone()) {
+            Some(field_expr) => field_expr.name_ref()?.syntax().clone(),
+            None => node.clone(),
+        };
+        fix_visibility(sema, self.file, &usage, &node)
+    }
+}
+
+/// Runs the `fix_visibility` assist on `usage`, offering it as a fix triggered anywhere in
+/// `trigger`.
+fn fix_visibility(
+    sema: &Semantics<RootDatabase>,
+    file: HirFileId,
+    usage: &SyntaxNode,
+    trigger: &SyntaxNode,
+) -> Option<Fix> {
+    // The assist works on files, usages inside of macro expansions can't be fixed this way.
+    let file_id = file.original_file(sema.db);
+    if file != file_id.into() {
+        return None;
+    }
+
+    let config = AssistConfig { snippet_cap: None, ..AssistConfig::default() };
+    let range = TextRange::empty(usage.text_range().start());
+    let assist = Assist::fix_visibility(sema.db, &config, FileRange { file_id, range })?;
+    Some(Fix::new(&assist.label.to_string(), assist.source_change?, trigger.text_range()))
+}
+
 fn missing_record_expr_field_fix(
     sema: &Semantics<RootDatabase>,
     usage_file_id: FileId,
@@ -1,13 +1,14 @@
-use hir::Semantics;
-use ide_db::RootDatabase;
+use hir::{AsName, MacroBinding, ModPath, PathKind, Semantics};
+use ide_db::{helpers::insert_whitespaces, RootDatabase};
 use syntax::{
     algo::{find_node_at_offset, SyntaxRewriter},
-    ast, AstNode, NodeOrToken, SyntaxKind,
+    ast::{self, make},
+    AstNode,
     SyntaxKind::*,
-    SyntaxNode, WalkEvent, T,
+    SyntaxNode, SyntaxToken, TextRange, TextSize, T,
 };
 
-use crate::FilePosition;
+use crate::{FilePosition, FileRange};
 
 pub struct ExpandedMacro {
     pub name: String,
@@ -16,7 +17,8 @@ pub struct ExpandedMacro {
 
 // Feature: Expand Macro Recursively
 //
-// Shows the full macro expansion of the macro at current cursor.
+// Shows the full macro expansion of the macro at current cursor. This includes derives, like
+// `Clone` in `#[derive(Clone)]`.
 //
 // |===
 // | Editor  | Action Name
@@ -26,6 +28,9 @@ pub struct ExpandedMacro {
 pub(crate) fn expand_macro(db: &RootDatabase, position: FilePosition) -> Option<ExpandedMacro> {
     let sema = Semantics::new(db);
     let file = sema.parse(position.file_id);
+    if let Some(expanded) = expand_derive(&sema, &file, position.offset) {
+        return Some(expanded);
+    }
     let name_ref = find_node_at_offset::<ast::NameRef>(file.syntax(), position.offset)?;
     let mac = name_ref.syntax().ancestors().find_map(ast::MacroCall::cast)?;
 
@@ -38,6 +43,124 @@ pub(crate) fn expand_macro(db: &RootDatabase, position: FilePosition) -> Option<
     Some(ExpandedMacro { name: name_ref.text().to_string(), expansion })
 }
 
+/// One level of expansion of a macro call, see [`expand_macro_once`].
+pub struct MacroExpansionStep {
+    pub name: String,
+    /// The range of the expanded macro call.
+    pub call: FileRange,
+    /// The syntax tree of the expansion. The macro calls in it are not expanded.
+    pub expansion: SyntaxNode,
+    /// Maps the range of a token of `expansion` to the range of the token it was copied from,
+    /// either in the macro call or in the macro definition. Tokens produced by the macro itself,
+    /// like the ones of a builtin derive, are missing.
+    pub source_map: Vec<(TextRange, FileRange)>,
+}
+
+/// Expands the macro call at the cursor by one level, leaving the macro calls in the expansion as
+/// they are.
+pub(crate) fn expand_macro_once(
+    db: &RootDatabase,
+    position: FilePosition,
+) -> Option<MacroExpansionStep> {
+    let sema = Semantics::new(db);
+    let file = sema.parse(position.file_id);
+    let name_ref = find_node_at_offset::<ast::NameRef>(file.syntax(), position.offset)?;
+    let mac = name_ref.syntax().ancestors().find_map(ast::MacroCall::cast)?;
+
+    let expansion = sema.expand(&mac)?;
+    let source_map = expansion
+        .descendants_with_tokens()
+        .filter_map(|it| it.into_token())
+        .filter_map(|token| Some((token.text_range(), sema.original_token_range(&token)?)))
+        .collect();
+    Some(MacroExpansionStep {
+        name: name_ref.text().to_string(),
+        call: sema.original_range(mac.syntax()),
+        expansion,
+        source_map,
+    })
+}
+
+/// How a `macro_rules!` or `macro` call was expanded, see [`debug_macro`].
+pub struct DebuggedMacro {
+    pub name: String,
+    /// The rules that were tried, in order.
+    pub rules: Vec<MacroRuleAttempt>,
+    /// The rule used for the expansion. If no rule applied, this is the one that came closest.
+    pub chosen: Option<usize>,
+    /// The expansion of the call, without expanding the macro calls in it.
+    pub expansion: Option<String>,
+}
+
+pub struct MacroRuleAttempt {
+    /// Index of the rule in the macro definition.
+    pub index: usize,
+    /// Why the rule doesn't apply, or `None` if it does.
+    pub error: Option<String>,
+    /// The token of the call at which matching failed.
+    pub failed_at: Option<FileRange>,
+    /// The meta variables bound while matching, sorted by name.
+    pub bindings: Vec<(String, MacroBinding)>,
+}
+
+/// Traces the expansion of the `macro_rules!` or `macro` call at the cursor: which rules were
+/// tried, why they don't match, and what their meta variables were bound to.
+pub(crate) fn debug_macro(db: &RootDatabase, position: FilePosition) -> Option<DebuggedMacro> {
+    let sema = Semantics::new(db);
+    let file = sema.parse(position.file_id);
+    let name_ref = find_node_at_offset::<ast::NameRef>(file.syntax(), position.offset)?;
+    let mac = name_ref.syntax().ancestors().find_map(ast::MacroCall::cast)?;
+
+    let trace = sema.trace_macro_expansion(&mac)?;
+    let rules = trace
+        .rules
+        .into_iter()
+        .map(|rule| MacroRuleAttempt {
+            index: rule.index,
+            error: rule.err.map(|it| it.to_string()),
+            failed_at: rule.failed_at.map(|it| sema.original_file_range(it)),
+            bindings: rule.bindings,
+        })
+        .collect();
+    let expansion = sema.expand(&mac).map(insert_whitespaces);
+    Some(DebuggedMacro {
+        name: name_ref.text().to_string(),
+        rules,
+        chosen: trace.chosen,
+        expansion,
+    })
+}
+
+fn expand_derive(
+    sema: &Semantics<RootDatabase>,
+    file: &ast::SourceFile,
+    offset: TextSize,
+) -> Option<ExpandedMacro> {
+    let ident = file.syntax().token_at_offset(offset).find(|it| it.kind() == IDENT)?;
+    let tt = ident.parent().ancestors().find_map(ast::TokenTree::cast)?;
+    let attr = tt.syntax().parent().and_then(ast::Attr::cast)?;
+    if attr.simple_name()? != "derive" {
+        return None;
+    }
+    let item = attr.syntax().parent().and_then(ast::Item::cast)?;
+
+    // The path of the derive are the tokens between the commas around the cursor.
+    let tokens: Vec<SyntaxToken> =
+        tt.syntax().children_with_tokens().filter_map(|it| it.into_token()).collect();
+    let idx = tokens.iter().position(|it| *it == ident)?;
+    let is_separator = |it: &SyntaxToken| matches!(it.kind(), T![,] | T!['('] | T![')']);
+    let start = tokens[..idx].iter().rposition(is_separator).map_or(0, |it| it + 1);
+    let end = tokens[idx..].iter().position(is_separator).map_or(tokens.len(), |it| idx + it);
+    let segments = tokens[start..end]
+        .iter()
+        .filter(|it| it.kind() == IDENT)
+        .map(|it| make::name_ref(it.text()).as_name());
+    let path = ModPath::from_segments(PathKind::Plain, segments);
+
+    let expanded = sema.expand_derive(&item, &path)?;
+    Some(ExpandedMacro { name: ident.text().to_string(), expansion: insert_whitespaces(expanded) })
+}
+
 fn expand_macro_recur(
     sema: &Semantics<RootDatabase>,
     macro_call: &ast::MacroCall,
@@ -64,63 +187,10 @@ fn expand_macro_recur(
     Some(res)
 }
 
-// FIXME: It would also be cool to share logic here and in the mbe tests,
-// which are pretty unreadable at the moment.
-fn insert_whitespaces(syn: SyntaxNode) -> String {
-    let mut res = String::new();
-    let mut token_iter = syn
-        .preorder_with_tokens()
-        .filter_map(|event| {
-            if let WalkEvent::Enter(NodeOrToken::Token(token)) = event {
-                Some(token)
-            } else {
-                None
-            }
-        })
-        .peekable();
-
-    let mut indent = 0;
-    let mut last: Option<SyntaxKind> = None;
-
-    while let Some(token) = token_iter.next() {
-        let mut is_next = |f: fn(SyntaxKind) -> bool, default| -> bool {
-            token_iter.peek().map(|it| f(it.kind())).unwrap_or(default)
-        };
-        let is_last =
-            |f: fn(SyntaxKind) -> bool, default| -> bool { last.map(f).unwrap_or(default) };
-
-        res += &match token.kind() {
-            k if is_text(k) && is_next(|it| !it.is_punct(), true) => token.text().to_string() + " ",
-            L_CURLY if is_next(|it| it != R_CURLY, true) => {
-                indent += 1;
-                let leading_space = if is_last(is_text, false) { " " } else { "" };
-                format!("{}{{\n{}", leading_space, "  ".repeat(indent))
-            }
-            R_CURLY if is_last(|it| it != L_CURLY, true) => {
-                indent = indent.saturating_sub(1);
-                format!("\n{}}}", "  ".repeat(indent))
-            }
-            R_CURLY => format!("}}\n{}", "  ".repeat(indent)),
-            T![;] => format!(";\n{}", "  ".repeat(indent)),
-            T![->] => " -> ".to_string(),
-            T![=] => " = ".to_string(),
-            T![=>] => " => ".to_string(),
-            _ => token.text().to_string(),
-        };
-
-        last = Some(token.kind());
-    }
-
-    return res;
-
-    fn is_text(k: SyntaxKind) -> bool {
-        k.is_keyword() || k.is_literal() || k == IDENT
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use expect_test::{expect, Expect};
+    use ide_db::helpers::insert_whitespaces;
 
     use crate::fixture;
 
@@ -131,6 +201,41 @@ mod tests {
         expect.assert_eq(&actual);
     }
 
+    fn check_once(ra_fixture: &str, expect: Expect) {
+        let (analysis, pos) = fixture::position(ra_fixture);
+        let step = analysis.expand_macro_once(pos).unwrap().unwrap();
+        let text = step.expansion.to_string();
+        let mut actual = format!(
+            "{} at {:?}\n{}",
+            step.name,
+            step.call.range,
+            insert_whitespaces(step.expansion)
+        );
+        for (range, original) in step.source_map {
+            actual += &format!("`{}` <- {:?}\n", &text[range], original.range);
+        }
+        expect.assert_eq(&actual);
+    }
+
+    fn check_debug(ra_fixture: &str, expect: Expect) {
+        let (analysis, pos) = fixture::position(ra_fixture);
+        let debugged = analysis.debug_macro(pos).unwrap().unwrap();
+        let mut actual = format!("{} (chosen rule: {:?})\n", debugged.name, debugged.chosen);
+        for rule in debugged.rules {
+            let status = match (&rule.error, rule.failed_at) {
+                (None, _) => "matched".to_string(),
+                (Some(err), Some(at)) => format!("{} at {:?}", err, at.range),
+                (Some(err), None) => err.clone(),
+            };
+            actual += &format!("rule {}: {}\n", rule.index, status);
+            for (name, value) in rule.bindings {
+                actual += &format!("    ${} = {:?}\n", name, value);
+            }
+        }
+        actual += &debugged.expansion.unwrap_or_default();
+        expect.assert_eq(&actual);
+    }
+
     #[test]
     fn macro_expand_recursive_expansion() {
         check(
@@ -280,4 +385,100 @@ fn main() {
                 0 "#]],
         );
     }
+
+    #[test]
+    fn macro_expand_module_path() {
+        check(
+            r#"
+//- /main.rs crate:my_crate
+#[rustc_builtin_macro]
+macro_rules! module_path {() => {}}
+mod net;
+//- /net.rs
+mod tcp {
+    fn f() {
+        let path = module_p<|>ath!();
+    }
+}
+"#,
+            expect![[r#"
+                module_path
+                "my_crate::net::tcp" "#]],
+        );
+    }
+
+    #[test]
+    fn macro_expand_derive() {
+        check(
+            r#"
+#[rustc_builtin_macro]
+macro Clone {}
+
+#[derive(Cl<|>one)]
+struct Foo {
+    a: u32,
+}
+"#,
+            expect![[r#"
+                Clone
+                impl crate::clone::Clone for Foo {
+                  fn clone(&self) -> Self {
+                    match self {
+                      Foo {
+                        a:__self_0
+                      } => Foo {
+                        a:crate::clone::Clone::clone(__self_0)
+                      }
+                    }
+                  }
+                }"#]],
+        );
+    }
+
+    #[test]
+    fn macro_expand_once() {
+        check_once(
+            r#"
+macro_rules! bar {
+    () => { fn b() {} }
+}
+macro_rules! foo {
+    ($i:ident) => { struct $i; bar!(); }
+}
+f<|>oo!(S);
+"#,
+            expect![[r#"
+                foo at 107..115
+                struct S;
+                bar!();
+                `struct` <- 84..90
+                `S` <- 112..113
+                `;` <- 93..94
+                `bar` <- 95..98
+                `!` <- 98..99
+                `(` <- 99..100
+                `)` <- 100..101
+                `;` <- 101..102
+            "#]],
+        );
+    }
+
+    #[test]
+    fn debug_macro_rules() {
+        check_debug(
+            r#"
+macro_rules! foo {
+    (a $i:ident) => { fn $i() {} };
+    ($($e:literal),*) => { [$($e),*] };
+}
+fn f() { f<|>oo!(1, 2); }
+"#,
+            expect![[r#"
+                foo (chosen rule: Some(1))
+                rule 0: unexpected token in input at 111..112
+                rule 1: matched
+                    $e = Nested([Fragment("1"), Fragment("2")])
+                [1,2]"#]],
+        );
+    }
 }
@@ -266,6 +266,33 @@ fn bar() {
         );
     }
 
+    #[test]
+    fn goto_def_for_shadowed_macros() {
+        check(
+            r#"
+macro_rules! foo { () => { () } }
+           //^^^
+fn bar() {
+    <|>foo!();
+}
+macro_rules! foo { () => { () } }
+"#,
+        );
+        check(
+            r#"
+macro_rules! foo { () => { () } }
+fn bar() {
+    foo!();
+}
+macro_rules! foo { () => { () } }
+           //^^^
+fn baz() {
+    <|>foo!();
+}
+"#,
+        );
+    }
+
     #[test]
     fn goto_def_for_macros_from_other_crates() {
         check(
@@ -318,6 +345,23 @@ fn bar() {
         );
     }
 
+    #[test]
+    fn goto_def_for_local_passed_through_hygienic_macro() {
+        check(
+            r#"
+macro_rules! m {
+    ($e:expr) => {{ let x = 92; $e }};
+}
+
+fn bar() {
+    let x = 1;
+      //^
+    m!(<|>x);
+}
+"#,
+        );
+    }
+
     #[test]
     fn goto_def_for_macro_defined_fn_no_arg() {
         check(
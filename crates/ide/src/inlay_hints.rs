@@ -634,6 +634,7 @@ fn main() {
     let test = "test";
       //^^^^ &str
     let test = InnerStruct {};
+      //^^^^ InnerStruct
 
     let test = unresolved();
 
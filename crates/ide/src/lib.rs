@@ -42,6 +42,7 @@ mod runnables;
 mod status;
 mod syntax_highlighting;
 mod syntax_tree;
+mod view_def_map;
 mod typing;
 mod markdown_remove;
 mod doc_links;
@@ -63,9 +64,9 @@ use crate::display::ToNav;
 
 pub use crate::{
     call_hierarchy::CallItem,
-    diagnostics::{Diagnostic, DiagnosticsConfig, Fix, Severity},
+    diagnostics::{Diagnostic, DiagnosticsConfig, Fix, RelatedInformation, Severity},
     display::navigation_target::{NavigationTarget, SymbolKind},
-    expand_macro::ExpandedMacro,
+    expand_macro::{DebuggedMacro, ExpandedMacro, MacroExpansionStep, MacroRuleAttempt},
     file_structure::StructureNode,
     folding_ranges::{Fold, FoldKind},
     hover::{HoverAction, HoverConfig, HoverGotoTypeData, HoverResult},
@@ -84,10 +85,13 @@ pub use completion::{
     CompletionConfig, CompletionItem, CompletionItemKind, CompletionResolveCapability,
     CompletionScore, ImportEdit, InsertTextFormat,
 };
-pub use hir::{Documentation, Semantics};
+pub use hir::{
+    dump::{self, DefMapDump, ItemTreeDump},
+    Documentation, MacroBinding, Semantics,
+};
 pub use ide_db::base_db::{
-    Canceled, Change, CrateGraph, CrateId, Edition, FileId, FilePosition, FileRange, SourceRoot,
-    SourceRootId,
+    Canceled, Change, CrateGraph, CrateId, CrateKind, Edition, FileId, FilePosition, FileRange,
+    SourceRoot, SourceRootId,
 };
 pub use ide_db::{
     call_info::CallInfo,
@@ -207,6 +211,7 @@ impl Analysis {
         crate_graph.add_crate_root(
             file_id,
             Edition::Edition2018,
+            CrateKind::Bin,
             None,
             cfg_options,
             Env::default(),
@@ -271,10 +276,35 @@ impl Analysis {
         self.with_db(|db| syntax_tree::syntax_tree(&db, file_id, text_range))
     }
 
+    /// Returns a structured dump of the `CrateDefMap` of the crate containing the file, for
+    /// debug purposes.
+    pub fn view_def_map(&self, file_id: FileId) -> Cancelable<Option<DefMapDump>> {
+        self.with_db(|db| view_def_map::view_def_map(db, file_id))
+    }
+
+    /// Returns a structured dump of the `ItemTree` of the file, for debug purposes.
+    pub fn view_item_tree(&self, file_id: FileId) -> Cancelable<ItemTreeDump> {
+        self.with_db(|db| view_def_map::view_item_tree(db, file_id))
+    }
+
     pub fn expand_macro(&self, position: FilePosition) -> Cancelable<Option<ExpandedMacro>> {
         self.with_db(|db| expand_macro::expand_macro(db, position))
     }
 
+    /// Expands the macro call at the position by one level.
+    pub fn expand_macro_once(
+        &self,
+        position: FilePosition,
+    ) -> Cancelable<Option<MacroExpansionStep>> {
+        self.with_db(|db| expand_macro::expand_macro_once(db, position))
+    }
+
+    /// Returns which rules were tried to expand the `macro_rules!` call at the position, and
+    /// what their meta variables were bound to.
+    pub fn debug_macro(&self, position: FilePosition) -> Cancelable<Option<DebuggedMacro>> {
+        self.with_db(|db| expand_macro::debug_macro(db, position))
+    }
+
     /// Returns an edit to remove all newlines in the range, cleaning up minor
     /// stuff like trailing commas.
     pub fn join_lines(&self, frange: FileRange) -> Cancelable<TextEdit> {
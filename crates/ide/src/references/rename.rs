@@ -241,7 +241,7 @@ fn rename_mod(
             let move_file = FileSystemEdit::MoveFile { src: file_id, dst };
             file_system_edits.push(move_file);
         }
-        ModuleSource::Module(..) => {}
+        ModuleSource::Module(..) | ModuleSource::BlockExpr(..) => {}
     }
 
     if let Some(src) = module.declaration_source(sema.db) {
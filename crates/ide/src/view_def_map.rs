@@ -0,0 +1,135 @@
+use hir::{
+    db::DefDatabase,
+    dump::{self, DefMapDump, ItemTreeDump},
+    Semantics,
+};
+use ide_db::{base_db::FileId, RootDatabase};
+
+// Feature: View Def Map
+//
+// Shows the result of name resolution for the crate containing the current
+// file: the modules, the names in their scopes, imports and macros. The item
+// tree of the current file, which is the input of name resolution, can be
+// shown as well. This exists mostly for debugging rust-analyzer itself.
+//
+// |===
+// | Editor  | Action Name
+//
+// | VS Code | **Rust Analyzer: View Def Map**, **Rust Analyzer: View Item Tree**
+// |===
+pub(crate) fn view_def_map(db: &RootDatabase, file_id: FileId) -> Option<DefMapDump> {
+    let sema = Semantics::new(db);
+    let krate = sema.to_module_def(file_id)?.krate();
+    Some(krate.dump_def_map(db))
+}
+
+pub(crate) fn view_item_tree(db: &RootDatabase, file_id: FileId) -> ItemTreeDump {
+    dump::item_tree(&db.item_tree(file_id.into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use expect_test::{expect, Expect};
+
+    use crate::fixture;
+
+    fn check_def_map(ra_fixture: &str, expect: Expect) {
+        let (analysis, file_id) = fixture::file(ra_fixture);
+        let dump = analysis.view_def_map(file_id).unwrap().unwrap();
+        expect.assert_eq(&dump.to_string());
+    }
+
+    fn check_item_tree(ra_fixture: &str, expect: Expect) {
+        let (analysis, file_id) = fixture::file(ra_fixture);
+        let dump = analysis.view_item_tree(file_id).unwrap();
+        expect.assert_eq(&dump.to_string());
+    }
+
+    #[test]
+    fn def_map_with_imports_and_macros() {
+        check_def_map(
+            r#"
+mod foo {
+    pub struct Bar;
+    pub(super) fn private() {}
+}
+use foo::Bar as Baz;
+use missing::Thing;
+pub(crate) fn f() {}
+impl Baz {}
+macro_rules! m { () => {} }
+"#,
+            expect![[r#"
+                crate (crate root)
+                  items:
+                    t pub(crate) struct Baz
+                    v pub(crate) struct Baz
+                    ? Thing
+                    v pub(crate) fn f
+                    t pub(crate) mod foo
+                  imports:
+                    pub(self) use foo::Bar as Baz; // resolved: t v
+                    pub(self) use missing::Thing; // unresolved
+                  textual macros:
+                    macro_rules m // visible from offset 137
+                  impls: 1
+
+                crate::foo (inline)
+                  items:
+                    t pub struct Bar
+                    v pub struct Bar
+                    v pub(crate) fn private
+            "#]],
+        );
+    }
+
+    #[test]
+    fn item_tree() {
+        check_item_tree(
+            r#"
+#![allow(unused)]
+pub mod m {
+    #[derive(Debug)]
+    pub(crate) struct S(u8, pub i32);
+    enum E { A, B { x: u8 } }
+}
+use std::{fmt, io::*};
+extern "C" {
+    fn ext(x: i32, ...);
+}
+impl m::S {
+    /// Docs are skipped.
+    fn new() -> Self { loop {} }
+}
+trait T { type Assoc; }
+fn main() {
+    struct Inner;
+    println!("hi");
+}
+"#,
+            expect![[r#"
+                #![allow(unused)]
+                pub mod m
+                    #[derive(Debug)]
+                    pub(crate) struct S tuple
+                        pub(self) field 0
+                        pub field 1
+                    pub(self) enum E
+                        variant A
+                        variant B
+                            pub(self) field x
+                pub(self) use std::fmt
+                pub(self) use std::io::*
+                pub(self) fn ext unsafe, extern "C", varargs, no body
+                impl
+                    pub(self) fn new
+                pub(self) trait T
+                    pub(self) type Assoc
+                pub(self) fn main
+                block #0:
+                    pub(self) struct Inner unit
+                    macro call println!
+            "#]],
+        );
+    }
+}
@@ -9,6 +9,8 @@ use base_db::{
 use profile::{memory_usage, Bytes};
 use rustc_hash::FxHashSet;
 
+use hir::db::ProcMacroDatabase;
+
 use crate::{symbol_index::SymbolsDatabase, RootDatabase};
 
 #[derive(Debug)]
@@ -45,7 +47,7 @@ impl RootDatabase {
         self.salsa_runtime_mut().synthetic_write(Durability::LOW);
     }
 
-    pub fn apply_change(&mut self, change: Change) {
+    pub fn apply_change(&mut self, mut change: Change) {
         let _p = profile::span("RootDatabase::apply_change");
         self.request_cancellation();
         log::info!("apply_change {:?}", change);
@@ -63,6 +65,9 @@ impl RootDatabase {
             self.set_local_roots_with_durability(Arc::new(local_roots), Durability::HIGH);
             self.set_library_roots_with_durability(Arc::new(library_roots), Durability::HIGH);
         }
+        if let Some(env) = change.proc_macro_env.take() {
+            self.set_proc_macro_env_with_durability(Arc::new(env), Durability::HIGH);
+        }
         change.apply(self);
     }
 
@@ -135,6 +140,7 @@ impl RootDatabase {
 
             // SourceDatabaseExt
             base_db::FileTextQuery
+            base_db::FileBytesQuery
             base_db::FileSourceRootQuery
             base_db::SourceRootQuery
             base_db::SourceRootCratesQuery
@@ -149,6 +155,7 @@ impl RootDatabase {
             // DefDatabase
             hir::db::ItemTreeQuery
             hir::db::CrateDefMapQueryQuery
+            hir::db::BlockDefMapQuery
             hir::db::StructDataQuery
             hir::db::UnionDataQuery
             hir::db::EnumDataQuery
@@ -166,6 +173,8 @@ impl RootDatabase {
             hir::db::CrateLangItemsQuery
             hir::db::LangItemQuery
             hir::db::ImportMapQuery
+            hir::db::FieldVisibilitiesQuery
+            hir::db::FunctionVisibilityQuery
 
             // HirDatabase
             hir::db::InferQueryQuery
@@ -179,7 +188,9 @@ impl RootDatabase {
             hir::db::GenericPredicatesQuery
             hir::db::GenericDefaultsQuery
             hir::db::InherentImplsInCrateQuery
+            hir::db::InherentImplsInBlockQuery
             hir::db::TraitImplsInCrateQuery
+            hir::db::TraitImplsInBlockQuery
             hir::db::TraitImplsInDepsQuery
             hir::db::AssociatedTyDataQuery
             hir::db::AssociatedTyDataQuery
@@ -226,6 +237,7 @@ impl RootDatabase {
             hir::db::InternTraitQuery
             hir::db::InternTypeAliasQuery
             hir::db::InternImplQuery
+            hir::db::InternBlockQuery
 
             // HirDatabase
             hir::db::InternTypeParamIdQuery
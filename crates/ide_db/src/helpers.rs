@@ -1,7 +1,12 @@
 //! A module with ide helpers for high-level ide features.
 use crate::RootDatabase;
 use hir::{Crate, Enum, Module, ScopeDef, Semantics, Trait};
-use syntax::ast::{self, make};
+use syntax::{
+    ast::{self, make},
+    NodeOrToken, SyntaxKind,
+    SyntaxKind::*,
+    SyntaxNode, WalkEvent, T,
+};
 
 pub mod insert_use;
 
@@ -12,7 +17,7 @@ pub fn mod_path_to_ast(path: &hir::ModPath) -> ast::Path {
     let mut segments = Vec::new();
     let mut is_abs = false;
     match path.kind {
-        hir::PathKind::Plain => {}
+        hir::PathKind::Plain | hir::PathKind::DefSite(..) => {}
         hir::PathKind::Super(0) => segments.push(make::path_segment_self()),
         hir::PathKind::Super(n) => segments.extend((0..n).map(|_| make::path_segment_super())),
         hir::PathKind::DollarCrate(_) | hir::PathKind::Crate => {
@@ -29,6 +34,62 @@ pub fn mod_path_to_ast(path: &hir::ModPath) -> ast::Path {
     make::path_from_segments(segments, is_abs)
 }
 
+/// Renders a macro expansion, which has no whitespace of its own, with some heuristic
+/// whitespace and indentation.
+// FIXME: It would also be cool to share logic here and in the mbe tests,
+// which are pretty unreadable at the moment.
+pub fn insert_whitespaces(syn: SyntaxNode) -> String {
+    let mut res = String::new();
+    let mut token_iter = syn
+        .preorder_with_tokens()
+        .filter_map(|event| {
+            if let WalkEvent::Enter(NodeOrToken::Token(token)) = event {
+                Some(token)
+            } else {
+                None
+            }
+        })
+        .peekable();
+
+    let mut indent = 0;
+    let mut last: Option<SyntaxKind> = None;
+
+    while let Some(token) = token_iter.next() {
+        let mut is_next = |f: fn(SyntaxKind) -> bool, default| -> bool {
+            token_iter.peek().map(|it| f(it.kind())).unwrap_or(default)
+        };
+        let is_last =
+            |f: fn(SyntaxKind) -> bool, default| -> bool { last.map(f).unwrap_or(default) };
+
+        res += &match token.kind() {
+            k if is_text(k) && is_next(|it| !it.is_punct(), true) => token.text().to_string() + " ",
+            L_CURLY if is_next(|it| it != R_CURLY, true) => {
+                indent += 1;
+                let leading_space = if is_last(is_text, false) { " " } else { "" };
+                format!("{}{{\n{}", leading_space, "  ".repeat(indent))
+            }
+            R_CURLY if is_last(|it| it != L_CURLY, true) => {
+                indent = indent.saturating_sub(1);
+                format!("\n{}}}", "  ".repeat(indent))
+            }
+            R_CURLY => format!("}}\n{}", "  ".repeat(indent)),
+            T![;] => format!(";\n{}", "  ".repeat(indent)),
+            T![->] => " -> ".to_string(),
+            T![=] => " = ".to_string(),
+            T![=>] => " => ".to_string(),
+            _ => token.text().to_string(),
+        };
+
+        last = Some(token.kind());
+    }
+
+    return res;
+
+    fn is_text(k: SyntaxKind) -> bool {
+        k.is_keyword() || k.is_literal() || k == IDENT
+    }
+}
+
 /// Helps with finding well-know things inside the standard library. This is
 /// somewhat similar to the known paths infra inside hir, but it different; We
 /// want to make sure that IDE specific paths don't become interesting inside
@@ -15,14 +15,17 @@ pub mod traits;
 pub mod call_info;
 pub mod helpers;
 
-use std::{fmt, sync::Arc};
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+};
 
 use base_db::{
     salsa::{self, Durability},
-    AnchoredPath, Canceled, CheckCanceled, CrateId, FileId, FileLoader, FileLoaderDelegate,
-    SourceDatabase, Upcast,
+    AnchoredPath, AnchoredPathBuf, Canceled, CheckCanceled, CrateId, FileId, FileLoader,
+    FileLoaderDelegate, SourceDatabase, Upcast,
 };
-use hir::db::{AstDatabase, DefDatabase, HirDatabase};
+use hir::db::{AstDatabase, DefDatabase, HirDatabase, ProcMacroDatabase};
 use rustc_hash::FxHashSet;
 
 use crate::{line_index::LineIndex, symbol_index::SymbolsDatabase};
@@ -37,11 +40,14 @@ pub use base_db;
     symbol_index::SymbolsDatabaseStorage,
     hir::db::InternDatabaseStorage,
     hir::db::AstDatabaseStorage,
+    hir::db::ProcMacroDatabaseStorage,
     hir::db::DefDatabaseStorage,
     hir::db::HirDatabaseStorage
 )]
 pub struct RootDatabase {
     storage: salsa::Storage<RootDatabase>,
+    /// Files which were looked up, but aren't loaded yet. Shared with all snapshots.
+    requested_files: Arc<Mutex<FxHashSet<AnchoredPathBuf>>>,
 }
 
 impl fmt::Debug for RootDatabase {
@@ -72,9 +78,16 @@ impl FileLoader for RootDatabase {
     fn file_text(&self, file_id: FileId) -> Arc<String> {
         FileLoaderDelegate(self).file_text(file_id)
     }
+    fn file_bytes(&self, file_id: FileId) -> Option<Arc<Vec<u8>>> {
+        FileLoaderDelegate(self).file_bytes(file_id)
+    }
     fn resolve_path(&self, path: AnchoredPath) -> Option<FileId> {
         FileLoaderDelegate(self).resolve_path(path)
     }
+    fn request_file(&self, path: AnchoredPath) {
+        let path = AnchoredPathBuf { anchor: path.anchor, path: path.path.to_string() };
+        self.requested_files.lock().unwrap().insert(path);
+    }
     fn relevant_crates(&self, file_id: FileId) -> Arc<FxHashSet<CrateId>> {
         FileLoaderDelegate(self).relevant_crates(file_id)
     }
@@ -103,14 +116,23 @@ impl Default for RootDatabase {
 
 impl RootDatabase {
     pub fn new(lru_capacity: Option<usize>) -> RootDatabase {
-        let mut db = RootDatabase { storage: salsa::Storage::default() };
+        let mut db = RootDatabase {
+            storage: salsa::Storage::default(),
+            requested_files: Default::default(),
+        };
         db.set_crate_graph_with_durability(Default::default(), Durability::HIGH);
         db.set_local_roots_with_durability(Default::default(), Durability::HIGH);
         db.set_library_roots_with_durability(Default::default(), Durability::HIGH);
+        db.set_proc_macro_env_with_durability(Default::default(), Durability::HIGH);
         db.update_lru_capacity(lru_capacity);
         db
     }
 
+    /// Returns the files requested with `FileLoader::request_file` since the last call.
+    pub fn take_requested_files(&self) -> FxHashSet<AnchoredPathBuf> {
+        std::mem::take(&mut *self.requested_files.lock().unwrap())
+    }
+
     pub fn update_lru_capacity(&mut self, lru_capacity: Option<usize>) {
         let lru_capacity = lru_capacity.unwrap_or(base_db::DEFAULT_LRU_CAP);
         base_db::ParseQuery.in_db_mut(self).set_lru_capacity(lru_capacity);
@@ -121,7 +143,10 @@ impl RootDatabase {
 
 impl salsa::ParallelDatabase for RootDatabase {
     fn snapshot(&self) -> salsa::Snapshot<RootDatabase> {
-        salsa::Snapshot::new(RootDatabase { storage: self.storage.snapshot() })
+        salsa::Snapshot::new(RootDatabase {
+            storage: self.storage.snapshot(),
+            requested_files: self.requested_files.clone(),
+        })
     }
 }
 
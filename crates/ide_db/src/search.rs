@@ -173,6 +173,12 @@ impl Definition {
                     ModuleSource::SourceFile(_) => {
                         res.insert(file_id, None);
                     }
+                    ModuleSource::BlockExpr(b) => {
+                        if is_first {
+                            let range = Some(b.syntax().text_range());
+                            res.insert(file_id, range);
+                        }
+                    }
                 };
                 is_first = false;
                 to_visit.extend(module.children(db));
@@ -199,6 +205,7 @@ impl Definition {
         let mut res = FxHashMap::default();
         let range = match module_src.value {
             ModuleSource::Module(m) => Some(m.syntax().text_range()),
+            ModuleSource::BlockExpr(b) => Some(b.syntax().text_range()),
             ModuleSource::SourceFile(_) => None,
         };
         res.insert(file_id, range);
@@ -14,6 +14,8 @@ mod tests;
 
 use std::fmt;
 
+use syntax::SmolStr;
+
 pub use tt::{Delimiter, DelimiterKind, Punct};
 
 use crate::{
@@ -27,9 +29,15 @@ pub enum ParseError {
     RepetitionEmtpyTokenTree,
 }
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
 pub enum ExpandError {
     NoMatchingRule,
+    /// No rule of a `macro_rules!` or `macro` matched the input past `found`.
+    NoRuleExpected {
+        found: UnmatchedInput,
+        /// What the rules that got furthest expected instead, like "`=>`" or "`$e:expr`".
+        expected: Vec<String>,
+    },
     UnexpectedToken,
     BindingError(String),
     ConversionError,
@@ -39,6 +47,20 @@ pub enum ExpandError {
     Other(String),
 }
 
+/// Where matching the input of a macro call failed, see [`ExpandError::NoRuleExpected`].
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+pub enum UnmatchedInput {
+    Token {
+        id: tt::TokenId,
+        text: SmolStr,
+    },
+    /// The input, or a delimited group in it, ended early. `delimiter` is the id of the
+    /// delimiters of the group.
+    End {
+        delimiter: Option<tt::TokenId>,
+    },
+}
+
 impl From<tt::ExpansionError> for ExpandError {
     fn from(it: tt::ExpansionError) -> Self {
         ExpandError::ProcMacroError(it)
@@ -49,6 +71,24 @@ impl fmt::Display for ExpandError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             ExpandError::NoMatchingRule => f.write_str("no rule matches input tokens"),
+            ExpandError::NoRuleExpected { found, expected } => {
+                match found {
+                    UnmatchedInput::Token { text, .. } => {
+                        write!(f, "no rules expected the token `{}`", text)?
+                    }
+                    UnmatchedInput::End { .. } => {
+                        f.write_str("unexpected end of macro invocation")?
+                    }
+                }
+                if let Some((last, rest)) = expected.split_last() {
+                    f.write_str("; expected ")?;
+                    if !rest.is_empty() {
+                        write!(f, "{} or ", rest.join(", "))?;
+                    }
+                    f.write_str(last)?;
+                }
+                Ok(())
+            }
             ExpandError::UnexpectedToken => f.write_str("unexpected token in input"),
             ExpandError::BindingError(e) => f.write_str(e),
             ExpandError::ConversionError => f.write_str("could not convert tokens"),
@@ -76,10 +116,22 @@ pub struct MacroRules {
     shift: Shift,
 }
 
+/// This struct contains AST for a single `macro` definition (aka "macros 2.0").
+/// It is either a single rule, `macro m($args) { $body }`, or a list of
+/// `macro_rules`-like rules, which can be delimited by `,` as well as `;`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MacroDef {
+    rules: Vec<Rule>,
+    /// Highest id of the token we have in TokenMap
+    shift: Shift,
+}
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 struct Rule {
     lhs: MetaTemplate,
     rhs: MetaTemplate,
+    /// What the input has to start with to match `lhs`.
+    first: mbe_expander::FirstToken,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -123,12 +175,14 @@ impl Shift {
                             _ => tree_id,
                         }
                     }
-                    tt::TokenTree::Leaf(tt::Leaf::Ident(ident))
-                        if ident.id != tt::TokenId::unspecified() =>
-                    {
-                        Some(ident.id.0)
+                    tt::TokenTree::Leaf(leaf) => {
+                        let id = match leaf {
+                            tt::Leaf::Ident(ident) => ident.id,
+                            tt::Leaf::Punct(punct) => punct.id,
+                            tt::Leaf::Literal(lit) => lit.id,
+                        };
+                        Some(id.0).filter(|_| id != tt::TokenId::unspecified())
                     }
-                    _ => None,
                 })
                 .max()
         }
@@ -136,6 +190,9 @@ impl Shift {
 
     /// Shift given TokenTree token id
     fn shift_all(self, tt: &mut tt::Subtree) {
+        if let Some(it) = tt.delimiter.as_mut() {
+            it.id = self.shift(it.id);
+        };
         for t in tt.token_trees.iter_mut() {
             match t {
                 tt::TokenTree::Leaf(leaf) => match leaf {
@@ -143,12 +200,7 @@ impl Shift {
                     tt::Leaf::Punct(punct) => punct.id = self.shift(punct.id),
                     tt::Leaf::Literal(lit) => lit.id = self.shift(lit.id),
                 },
-                tt::TokenTree::Subtree(tt) => {
-                    if let Some(it) = tt.delimiter.as_mut() {
-                        it.id = self.shift(it.id);
-                    };
-                    self.shift_all(tt)
-                }
+                tt::TokenTree::Subtree(tt) => self.shift_all(tt),
             }
         }
     }
@@ -163,6 +215,83 @@ impl Shift {
     fn unshift(self, id: tt::TokenId) -> Option<tt::TokenId> {
         id.0.checked_sub(self.0).map(tt::TokenId)
     }
+
+    fn unshift_arm(self, arm: MatchedArm) -> MatchedArm {
+        MatchedArm { failed_at: self.unshift_failed_at(arm.failed_at), ..arm }
+    }
+
+    fn unshift_trace(self, mut trace: ExpansionTrace) -> ExpansionTrace {
+        for rule in trace.rules.iter_mut() {
+            rule.failed_at = self.unshift_failed_at(rule.failed_at);
+        }
+        trace.expansion.err = trace.expansion.err.map(|err| self.unshift_err(err));
+        trace
+    }
+
+    fn unshift_err(self, err: ExpandError) -> ExpandError {
+        match err {
+            ExpandError::NoRuleExpected { found, expected } => {
+                let found = match found {
+                    UnmatchedInput::Token { id, text } => {
+                        let id = self.unshift_failed_at(Some(id)).unwrap_or(id);
+                        UnmatchedInput::Token { id, text }
+                    }
+                    UnmatchedInput::End { delimiter } => {
+                        UnmatchedInput::End { delimiter: self.unshift_failed_at(delimiter) }
+                    }
+                };
+                ExpandError::NoRuleExpected { found, expected }
+            }
+            err => err,
+        }
+    }
+
+    fn unshift_failed_at(self, id: Option<tt::TokenId>) -> Option<tt::TokenId> {
+        id.filter(|&id| id != tt::TokenId::unspecified()).and_then(|id| self.unshift(id))
+    }
+}
+
+/// The rule of a `macro_rules!` or `macro` that was used to expand a call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MatchedArm {
+    /// Index of the rule in the macro definition.
+    pub index: usize,
+    /// If the input didn't match the rule, the input token at which matching failed.
+    pub failed_at: Option<tt::TokenId>,
+}
+
+/// A record of how a call was expanded, rule by rule. Meant for debugging macros.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExpansionTrace {
+    /// The rules that were tried, in order. Rules after the first one that applies are not
+    /// tried.
+    pub rules: Vec<RuleTrace>,
+    /// The rule used for the expansion. If no rule applied, this is the one that came closest.
+    pub chosen: Option<usize>,
+    pub expansion: ExpandResult<tt::Subtree>,
+}
+
+/// An attempt to match the input of a call against a single rule.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuleTrace {
+    /// Index of the rule in the macro definition.
+    pub index: usize,
+    /// Why the rule doesn't apply, or `None` if it does.
+    pub err: Option<ExpandError>,
+    /// The input token at which matching failed, if it is known.
+    pub failed_at: Option<tt::TokenId>,
+    /// The meta variables bound while matching, sorted by name.
+    pub bindings: Vec<(SmolStr, BoundValue)>,
+}
+
+/// What a meta variable is bound to. A variable inside of `$(...)*` is bound to a list with
+/// one value per repetition.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BoundValue {
+    Fragment(tt::TokenTree),
+    Nested(Vec<BoundValue>),
+    /// The variable is inside of a repetition that matched nothing.
+    Empty,
 }
 
 #[derive(Debug, Eq, PartialEq)]
@@ -179,7 +308,7 @@ impl MacroRules {
         let mut src = TtIter::new(tt);
         let mut rules = Vec::new();
         while src.len() > 0 {
-            let rule = Rule::parse(&mut src)?;
+            let rule = Rule::parse(&mut src, true)?;
             rules.push(rule);
             if let Err(()) = src.expect_char(';') {
                 if src.len() > 0 {
@@ -200,7 +329,95 @@ impl MacroRules {
         // apply shift
         let mut tt = tt.clone();
         self.shift.shift_all(&mut tt);
-        mbe_expander::expand(self, &tt)
+        let mut res = mbe_expander::expand_rules(&self.rules, &tt).0;
+        res.err = res.err.map(|err| self.shift.unshift_err(err));
+        res
+    }
+
+    /// Returns the rule used to expand `tt`, and where matching it failed.
+    pub fn matched_arm(&self, tt: &tt::Subtree) -> Option<MatchedArm> {
+        let mut tt = tt.clone();
+        self.shift.shift_all(&mut tt);
+        let arm = mbe_expander::expand_rules(&self.rules, &tt).1?;
+        Some(self.shift.unshift_arm(arm))
+    }
+
+    /// Expands `tt` like [`Self::expand`] does, recording each rule that was tried.
+    pub fn trace_expand(&self, tt: &tt::Subtree) -> ExpansionTrace {
+        let mut tt = tt.clone();
+        self.shift.shift_all(&mut tt);
+        self.shift.unshift_trace(mbe_expander::trace_rules(&self.rules, &tt))
+    }
+
+    pub fn map_id_down(&self, id: tt::TokenId) -> tt::TokenId {
+        self.shift.shift(id)
+    }
+
+    pub fn map_id_up(&self, id: tt::TokenId) -> (tt::TokenId, Origin) {
+        match self.shift.unshift(id) {
+            Some(id) => (id, Origin::Call),
+            None => (id, Origin::Def),
+        }
+    }
+}
+
+impl MacroDef {
+    pub fn parse(tt: &tt::Subtree) -> Result<MacroDef, ParseError> {
+        let mut src = TtIter::new(tt);
+        let mut rules = Vec::new();
+
+        if tt.delimiter_kind() == Some(DelimiterKind::Brace) {
+            // `macro m { ($a) => { ... }, ($b) => { ... } }`
+            while src.len() > 0 {
+                let rule = Rule::parse(&mut src, true)?;
+                rules.push(rule);
+                if let Err(()) = src.expect_any_char(&[';', ',']) {
+                    if src.len() > 0 {
+                        return Err(ParseError::Expected(
+                            "expected `;` or `,` to delimit rules".to_string(),
+                        ));
+                    }
+                    break;
+                }
+            }
+        } else {
+            // `macro m($a) { ... }`
+            let rule = Rule::parse(&mut src, false)?;
+            if src.len() != 0 {
+                return Err(ParseError::Expected("remaining tokens in macro def".to_string()));
+            }
+            rules.push(rule);
+        }
+
+        for rule in rules.iter() {
+            validate(&rule.lhs)?;
+        }
+
+        Ok(MacroDef { rules, shift: Shift::new(tt) })
+    }
+
+    pub fn expand(&self, tt: &tt::Subtree) -> ExpandResult<tt::Subtree> {
+        // apply shift
+        let mut tt = tt.clone();
+        self.shift.shift_all(&mut tt);
+        let mut res = mbe_expander::expand_rules(&self.rules, &tt).0;
+        res.err = res.err.map(|err| self.shift.unshift_err(err));
+        res
+    }
+
+    /// Returns the rule used to expand `tt`, and where matching it failed.
+    pub fn matched_arm(&self, tt: &tt::Subtree) -> Option<MatchedArm> {
+        let mut tt = tt.clone();
+        self.shift.shift_all(&mut tt);
+        let arm = mbe_expander::expand_rules(&self.rules, &tt).1?;
+        Some(self.shift.unshift_arm(arm))
+    }
+
+    /// Expands `tt` like [`Self::expand`] does, recording each rule that was tried.
+    pub fn trace_expand(&self, tt: &tt::Subtree) -> ExpansionTrace {
+        let mut tt = tt.clone();
+        self.shift.shift_all(&mut tt);
+        self.shift.unshift_trace(mbe_expander::trace_rules(&self.rules, &tt))
     }
 
     pub fn map_id_down(&self, id: tt::TokenId) -> tt::TokenId {
@@ -216,12 +433,14 @@ impl MacroRules {
 }
 
 impl Rule {
-    fn parse(src: &mut TtIter) -> Result<Rule, ParseError> {
+    fn parse(src: &mut TtIter, expect_arrow: bool) -> Result<Rule, ParseError> {
         let lhs = src
             .expect_subtree()
             .map_err(|()| ParseError::Expected("expected subtree".to_string()))?;
-        src.expect_char('=').map_err(|()| ParseError::Expected("expected `=`".to_string()))?;
-        src.expect_char('>').map_err(|()| ParseError::Expected("expected `>`".to_string()))?;
+        if expect_arrow {
+            src.expect_char('=').map_err(|()| ParseError::Expected("expected `=`".to_string()))?;
+            src.expect_char('>').map_err(|()| ParseError::Expected("expected `>`".to_string()))?;
+        }
         let rhs = src
             .expect_subtree()
             .map_err(|()| ParseError::Expected("expected subtree".to_string()))?;
@@ -229,7 +448,8 @@ impl Rule {
         let lhs = MetaTemplate { tokens: parse_pattern(&lhs), delimiter: None };
         let rhs = MetaTemplate { tokens: parse_template(&rhs), delimiter: None };
 
-        Ok(crate::Rule { lhs, rhs })
+        let first = mbe_expander::FirstToken::of(&lhs);
+        Ok(crate::Rule { lhs, rhs, first })
     }
 }
 
@@ -8,51 +8,105 @@ mod transcriber;
 use rustc_hash::FxHashMap;
 use syntax::SmolStr;
 
-use crate::{ExpandError, ExpandResult};
+use crate::{BoundValue, ExpandError, ExpandResult, ExpansionTrace, MatchedArm, RuleTrace};
 
-pub(crate) fn expand(rules: &crate::MacroRules, input: &tt::Subtree) -> ExpandResult<tt::Subtree> {
-    expand_rules(&rules.rules, input)
-}
+pub(crate) use matcher::FirstToken;
 
-fn expand_rules(rules: &[crate::Rule], input: &tt::Subtree) -> ExpandResult<tt::Subtree> {
-    let mut match_: Option<(matcher::Match, &crate::Rule)> = None;
-    for rule in rules {
-        let new_match = match matcher::match_(&rule.lhs, input) {
-            Ok(m) => m,
-            Err(_e) => {
-                // error in pattern parsing
-                continue;
-            }
-        };
-        if new_match.err.is_none() {
-            // If we find a rule that applies without errors, we're done.
-            // Unconditionally returning the transcription here makes the
-            // `test_repeat_bad_var` test fail.
-            let ExpandResult { value, err: transcribe_err } =
-                transcriber::transcribe(&rule.rhs, &new_match.bindings);
-            if transcribe_err.is_none() {
-                return ExpandResult::ok(value);
+pub(crate) fn expand_rules(
+    rules: &[crate::Rule],
+    input: &tt::Subtree,
+) -> (ExpandResult<tt::Subtree>, Option<MatchedArm>) {
+    let mut cache = matcher::FragmentCache::default();
+    let mut matches = Vec::with_capacity(rules.len());
+    for (index, rule) in rules.iter().enumerate() {
+        // Rules that can't match the first token would only be needed to pick the closest
+        // match below, so don't run the matcher for them yet.
+        if !rule.first.admits(input) {
+            matches.push(None);
+            continue;
+        }
+        let new_match = matcher::match_(&rule.lhs, input, &mut cache);
+        if let Ok(new_match) = &new_match {
+            if new_match.err.is_none() {
+                // If we find a rule that applies without errors, we're done.
+                // Unconditionally returning the transcription here makes the
+                // `test_repeat_bad_var` test fail.
+                let ExpandResult { value, err: transcribe_err } =
+                    transcriber::transcribe(&rule.rhs, &new_match.bindings);
+                if transcribe_err.is_none() {
+                    return (ExpandResult::ok(value), Some(MatchedArm { index, failed_at: None }));
+                }
             }
         }
+        matches.push(Some(new_match));
+    }
+
+    let mut match_: Option<(matcher::Match, usize)> = None;
+    // Where the rules got furthest into the input, to report what was expected there.
+    let mut failure = None;
+    for (index, (rule, new_match)) in rules.iter().zip(matches).enumerate() {
+        let new_match =
+            match new_match.unwrap_or_else(|| matcher::match_(&rule.lhs, input, &mut cache)) {
+                Ok(m) => m,
+                Err(_e) => {
+                    // error in pattern parsing
+                    continue;
+                }
+            };
+        if let Some(it) = &new_match.failure {
+            matcher::Failure::merge(&mut failure, it.clone());
+        }
         // Use the rule if we matched more tokens, or had fewer errors
         if let Some((prev_match, _)) = &match_ {
             if (new_match.unmatched_tts, new_match.err_count)
                 < (prev_match.unmatched_tts, prev_match.err_count)
             {
-                match_ = Some((new_match, rule));
+                match_ = Some((new_match, index));
             }
         } else {
-            match_ = Some((new_match, rule));
+            match_ = Some((new_match, index));
         }
     }
-    if let Some((match_, rule)) = match_ {
+    if let Some((match_, index)) = match_ {
         // if we got here, there was no match without errors
         let ExpandResult { value, err: transcribe_err } =
-            transcriber::transcribe(&rule.rhs, &match_.bindings);
-        ExpandResult { value, err: match_.err.or(transcribe_err) }
+            transcriber::transcribe(&rules[index].rhs, &match_.bindings);
+        let arm = MatchedArm { index, failed_at: match_.failed_at() };
+        let err = match (match_.err, failure) {
+            (Some(_), Some(failure)) => Some(ExpandError::NoRuleExpected {
+                found: failure.found,
+                expected: failure.expected,
+            }),
+            (err, _) => err,
+        };
+        (ExpandResult { value, err: err.or(transcribe_err) }, Some(arm))
     } else {
-        ExpandResult::only_err(ExpandError::NoMatchingRule)
+        (ExpandResult::only_err(ExpandError::NoMatchingRule), None)
+    }
+}
+
+pub(crate) fn trace_rules(rules: &[crate::Rule], input: &tt::Subtree) -> ExpansionTrace {
+    let mut cache = matcher::FragmentCache::default();
+    let mut traces = Vec::new();
+    for (index, rule) in rules.iter().enumerate() {
+        let trace = match matcher::match_(&rule.lhs, input, &mut cache) {
+            Ok(m) => {
+                let failed_at = m.failed_at();
+                let matcher::Match { bindings, err, .. } = m;
+                // Like `expand_rules`, a rule only applies if transcribing it works as well.
+                let err = err.or_else(|| transcriber::transcribe(&rule.rhs, &bindings).err);
+                RuleTrace { index, err, failed_at, bindings: bindings.traced() }
+            }
+            Err(e) => RuleTrace { index, err: Some(e), failed_at: None, bindings: Vec::new() },
+        };
+        let applies = trace.err.is_none();
+        traces.push(trace);
+        if applies {
+            break;
+        }
     }
+    let (expansion, arm) = expand_rules(rules, input);
+    ExpansionTrace { rules: traces, chosen: arm.map(|it| it.index), expansion }
 }
 
 /// The actual algorithm for expansion is not too hard, but is pretty tricky.
@@ -105,6 +159,15 @@ struct Bindings {
     inner: FxHashMap<SmolStr, Binding>,
 }
 
+impl Bindings {
+    fn traced(&self) -> Vec<(SmolStr, BoundValue)> {
+        let mut res: Vec<_> =
+            self.inner.iter().map(|(name, binding)| (name.clone(), binding.traced())).collect();
+        res.sort_by(|(a, _), (b, _)| a.cmp(b));
+        res
+    }
+}
+
 #[derive(Debug)]
 enum Binding {
     Fragment(Fragment),
@@ -112,6 +175,18 @@ enum Binding {
     Empty,
 }
 
+impl Binding {
+    fn traced(&self) -> BoundValue {
+        match self {
+            Binding::Fragment(Fragment::Tokens(tt)) | Binding::Fragment(Fragment::Ast(tt)) => {
+                BoundValue::Fragment(tt.clone())
+            }
+            Binding::Nested(it) => BoundValue::Nested(it.iter().map(Binding::traced).collect()),
+            Binding::Empty => BoundValue::Empty,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 enum Fragment {
     /// token fragments are just copy-pasted into the output
@@ -178,6 +253,6 @@ mod tests {
         let (invocation_tt, _) =
             ast_to_token_tree(&macro_invocation.token_tree().unwrap()).unwrap();
 
-        expand_rules(&rules.rules, &invocation_tt)
+        expand_rules(&rules.rules, &invocation_tt).0
     }
 }
@@ -5,11 +5,12 @@ use crate::{
     parser::{Op, RepeatKind, Separator},
     subtree_source::SubtreeTokenSource,
     tt_iter::TtIter,
-    ExpandError, MetaTemplate,
+    ExpandError, MetaTemplate, UnmatchedInput,
 };
 
 use super::ExpandResult;
-use parser::{FragmentKind::*, TreeSink};
+use parser::{FragmentKind, FragmentKind::*, TreeSink};
+use rustc_hash::FxHashMap;
 use syntax::{SmolStr, SyntaxKind};
 use tt::buffer::{Cursor, TokenBuffer};
 
@@ -67,6 +68,8 @@ pub(super) struct Match {
     pub(super) err_count: usize,
     /// How many top-level token trees were left to match.
     pub(super) unmatched_tts: usize,
+    /// The furthest position in the input at which matching failed.
+    pub(super) failure: Option<Failure>,
 }
 
 impl Match {
@@ -75,6 +78,212 @@ impl Match {
         self.err = prev_err.or(Some(err));
         self.err_count += 1;
     }
+
+    /// Records where matching failed. Only failures up to the first error count, as the
+    /// matcher is just recovering after that.
+    fn fail(&mut self, failure: Failure) {
+        if self.err_count == 0 {
+            Failure::merge(&mut self.failure, failure);
+        }
+    }
+
+    /// The input token at which matching failed, if it is known.
+    pub(super) fn failed_at(&self) -> Option<tt::TokenId> {
+        match self.failure.as_ref()?.found {
+            UnmatchedInput::Token { id, .. } => Some(id),
+            UnmatchedInput::End { .. } => None,
+        }
+    }
+}
+
+/// A position in the input, ordered by how far into the input it is. This relies on the ids
+/// of the input tokens being allocated in source order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct Position {
+    id: u32,
+    /// Whether this is the end of a subtree, right after the token `id`.
+    at_end: bool,
+}
+
+impl Position {
+    fn new(id: tt::TokenId, at_end: bool) -> Option<Position> {
+        if id == tt::TokenId::unspecified() {
+            return None;
+        }
+        Some(Position { id: id.0, at_end })
+    }
+}
+
+/// Where matching failed, and what the rules expected there.
+#[derive(Debug, Clone)]
+pub(super) struct Failure {
+    pos: Option<Position>,
+    pub(super) found: UnmatchedInput,
+    /// Like "`=>`" or "`$e:expr`".
+    pub(super) expected: Vec<String>,
+}
+
+impl Failure {
+    /// A failure at the next token of `src`, which iterates over `enclosing`.
+    fn at(src: &TtIter, enclosing: &tt::Subtree, expected: Option<String>) -> Failure {
+        let expected = expected.into_iter().collect();
+        match src.peek_n(0) {
+            Some(tt) => {
+                let id = token_id(tt);
+                let text = match tt {
+                    tt::TokenTree::Subtree(it) if it.delimiter.is_some() => {
+                        delimiter_text(it.delimiter_kind()).0.into()
+                    }
+                    _ => tt.to_string().into(),
+                };
+                Failure {
+                    pos: Position::new(id, false),
+                    found: UnmatchedInput::Token { id, text },
+                    expected,
+                }
+            }
+            None => Failure {
+                pos: last_token_id(enclosing).and_then(|id| Position::new(id, true)),
+                found: UnmatchedInput::End { delimiter: enclosing.delimiter.map(|it| it.id) },
+                expected,
+            },
+        }
+    }
+
+    /// Keeps the failure that is further into the input. Failures at the same position are
+    /// combined.
+    pub(super) fn merge(into: &mut Option<Failure>, failure: Failure) {
+        match into {
+            Some(it) if it.pos > failure.pos => {}
+            Some(it) if it.pos == failure.pos => {
+                for expected in failure.expected {
+                    if !it.expected.contains(&expected) {
+                        it.expected.push(expected);
+                    }
+                }
+            }
+            _ => *into = Some(failure),
+        }
+    }
+}
+
+fn last_token_id(subtree: &tt::Subtree) -> Option<tt::TokenId> {
+    let own = subtree.delimiter.map(|it| it.id);
+    match subtree.token_trees.last() {
+        Some(tt::TokenTree::Subtree(it)) => last_token_id(it).or(own),
+        Some(tt) => Some(token_id(tt)),
+        None => own,
+    }
+}
+
+fn delimiter_text(kind: Option<tt::DelimiterKind>) -> (&'static str, &'static str) {
+    match kind {
+        Some(tt::DelimiterKind::Parenthesis) => ("(", ")"),
+        Some(tt::DelimiterKind::Brace) => ("{", "}"),
+        Some(tt::DelimiterKind::Bracket) => ("[", "]"),
+        None => ("", ""),
+    }
+}
+
+/// What the first token of an input has to be for a rule to match it. This is computed once
+/// when parsing the rule, so that rules that can't match are skipped without running the
+/// matcher.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum FirstToken {
+    /// The pattern starts with a fragment or a repetition, which can start with many tokens.
+    Any,
+    /// The pattern is empty, so the input has to be empty as well.
+    End,
+    Leaf(tt::Leaf),
+    Subtree(Option<tt::DelimiterKind>),
+}
+
+impl FirstToken {
+    pub(crate) fn of(pattern: &MetaTemplate) -> FirstToken {
+        match pattern.tokens.first() {
+            None => FirstToken::End,
+            Some(Ok(Op::Leaf(leaf))) => FirstToken::Leaf(leaf.clone()),
+            Some(Ok(Op::Subtree(subtree))) => FirstToken::Subtree(subtree.delimiter_kind()),
+            // Patterns with errors are rejected by `match_` anyway.
+            Some(Ok(Op::Var { .. })) | Some(Ok(Op::Repeat { .. })) | Some(Err(_)) => {
+                FirstToken::Any
+            }
+        }
+    }
+
+    /// Returns `false` if a rule starting with `self` can't match `input` without errors.
+    pub(crate) fn admits(&self, input: &tt::Subtree) -> bool {
+        match (self, input.token_trees.first()) {
+            (FirstToken::Any, _) => true,
+            (FirstToken::End, first) => first.is_none(),
+            (FirstToken::Leaf(lhs), Some(tt::TokenTree::Leaf(rhs))) => leaf_matches(lhs, rhs),
+            (FirstToken::Subtree(kind), Some(tt::TokenTree::Subtree(rhs))) => {
+                *kind == rhs.delimiter_kind()
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Fragments parsed while matching the rules of a macro against the same input. Rules often
+/// start with the same fragments, like `$e:expr`, and parsing them is the expensive part of
+/// matching, so they are parsed only once.
+#[derive(Default)]
+pub(crate) struct FragmentCache {
+    /// Maps the remaining input, identified by its address and length, and the kind of the
+    /// fragment to the parse result and the number of token trees it consumed.
+    parsed: FxHashMap<
+        (*const tt::TokenTree, usize, FragmentKind),
+        (ExpandResult<Option<tt::TokenTree>>, usize),
+    >,
+}
+
+impl FragmentCache {
+    fn expect_fragment(
+        &mut self,
+        input: &mut TtIter,
+        kind: FragmentKind,
+    ) -> ExpandResult<Option<tt::TokenTree>> {
+        let slice = input.inner.as_slice();
+        let (res, consumed) = self
+            .parsed
+            .entry((slice.as_ptr(), slice.len(), kind))
+            .or_insert_with(|| {
+                let mut fork = input.clone();
+                let res = fork.expect_fragment(kind);
+                (res, slice.len() - fork.len())
+            })
+            .clone();
+        input.inner = slice[consumed..].iter();
+        res
+    }
+}
+
+fn leaf_matches(lhs: &tt::Leaf, rhs: &tt::Leaf) -> bool {
+    match (lhs, rhs) {
+        (
+            tt::Leaf::Punct(tt::Punct { char: lhs, .. }),
+            tt::Leaf::Punct(tt::Punct { char: rhs, .. }),
+        ) => lhs == rhs,
+        (
+            tt::Leaf::Ident(tt::Ident { text: lhs, .. }),
+            tt::Leaf::Ident(tt::Ident { text: rhs, .. }),
+        ) => lhs == rhs,
+        (
+            tt::Leaf::Literal(tt::Literal { text: lhs, .. }),
+            tt::Leaf::Literal(tt::Literal { text: rhs, .. }),
+        ) => lhs == rhs,
+        _ => false,
+    }
+}
+
+fn token_id(tt: &tt::TokenTree) -> tt::TokenId {
+    match tt {
+        tt::TokenTree::Leaf(tt::Leaf::Ident(it)) => it.id,
+        tt::TokenTree::Leaf(tt::Leaf::Punct(it)) => it.id,
+        tt::TokenTree::Leaf(tt::Leaf::Literal(it)) => it.id,
+        tt::TokenTree::Subtree(it) => it.delimiter.map_or(tt::TokenId::unspecified(), |it| it.id),
+    }
 }
 
 // General note: These functions have two channels to return errors, a `Result`
@@ -83,16 +292,21 @@ impl Match {
 // sense to try using it. Matching errors are added to the `Match`. It might
 // make sense to make pattern parsing a separate step?
 
-pub(super) fn match_(pattern: &MetaTemplate, src: &tt::Subtree) -> Result<Match, ExpandError> {
+pub(super) fn match_(
+    pattern: &MetaTemplate,
+    input: &tt::Subtree,
+    cache: &mut FragmentCache,
+) -> Result<Match, ExpandError> {
     assert!(pattern.delimiter == None);
 
     let mut res = Match::default();
-    let mut src = TtIter::new(src);
+    let mut src = TtIter::new(input);
 
-    match_subtree(&mut res, pattern, &mut src)?;
+    match_subtree(&mut res, pattern, &mut src, input, cache)?;
 
     if src.len() > 0 {
         res.unmatched_tts += src.len();
+        res.fail(Failure::at(&src, input, None));
         res.add_err(err!("leftover tokens"));
     }
 
@@ -103,50 +317,47 @@ fn match_subtree(
     res: &mut Match,
     pattern: &MetaTemplate,
     src: &mut TtIter,
+    enclosing: &tt::Subtree,
+    cache: &mut FragmentCache,
 ) -> Result<(), ExpandError> {
     for op in pattern.iter() {
+        let err_count = res.err_count;
+        let start = src.clone();
         match op.as_ref().map_err(|err| err.clone())? {
             Op::Leaf(lhs) => {
+                let expected = format!("`{}`", lhs);
                 let rhs = match src.expect_leaf() {
                     Ok(l) => l,
                     Err(()) => {
+                        res.fail(Failure::at(&start, enclosing, Some(expected)));
                         res.add_err(err!("expected leaf: `{}`", lhs));
                         continue;
                     }
                 };
-                match (lhs, rhs) {
-                    (
-                        tt::Leaf::Punct(tt::Punct { char: lhs, .. }),
-                        tt::Leaf::Punct(tt::Punct { char: rhs, .. }),
-                    ) if lhs == rhs => (),
-                    (
-                        tt::Leaf::Ident(tt::Ident { text: lhs, .. }),
-                        tt::Leaf::Ident(tt::Ident { text: rhs, .. }),
-                    ) if lhs == rhs => (),
-                    (
-                        tt::Leaf::Literal(tt::Literal { text: lhs, .. }),
-                        tt::Leaf::Literal(tt::Literal { text: rhs, .. }),
-                    ) if lhs == rhs => (),
-                    _ => {
-                        res.add_err(ExpandError::UnexpectedToken);
-                    }
+                if !leaf_matches(lhs, rhs) {
+                    res.fail(Failure::at(&start, enclosing, Some(expected)));
+                    res.add_err(ExpandError::UnexpectedToken);
                 }
             }
             Op::Subtree(lhs) => {
+                let (open, close) = delimiter_text(lhs.delimiter_kind());
                 let rhs = match src.expect_subtree() {
                     Ok(s) => s,
                     Err(()) => {
+                        res.fail(Failure::at(&start, enclosing, Some(format!("`{}`", open))));
                         res.add_err(err!("expected subtree"));
                         continue;
                     }
                 };
                 if lhs.delimiter_kind() != rhs.delimiter_kind() {
+                    res.fail(Failure::at(&start, enclosing, Some(format!("`{}`", open))));
                     res.add_err(err!("mismatched delimiter"));
                     continue;
                 }
                 let mut src = TtIter::new(rhs);
-                match_subtree(res, lhs, &mut src)?;
+                match_subtree(res, lhs, &mut src, rhs, cache)?;
                 if src.len() > 0 {
+                    res.fail(Failure::at(&src, rhs, Some(format!("`{}`", close))));
                     res.add_err(err!("leftover tokens"));
                 }
             }
@@ -154,12 +365,13 @@ fn match_subtree(
                 let kind = match kind {
                     Some(k) => k,
                     None => {
+                        res.fail(Failure::at(&start, enclosing, None));
                         res.add_err(ExpandError::UnexpectedToken);
                         continue;
                     }
                 };
                 let ExpandResult { value: matched, err: match_err } =
-                    match_meta_var(kind.as_str(), src);
+                    match_meta_var(kind.as_str(), src, cache);
                 match matched {
                     Some(fragment) => {
                         res.bindings.inner.insert(name.clone(), Binding::Fragment(fragment));
@@ -168,13 +380,18 @@ fn match_subtree(
                     _ => {}
                 }
                 if let Some(err) = match_err {
+                    let expected = format!("`${}:{}`", name, kind);
+                    res.fail(Failure::at(&start, enclosing, Some(expected)));
                     res.add_err(err);
                 }
             }
             Op::Repeat { subtree, kind, separator } => {
-                match_repeat(res, subtree, *kind, separator, src)?;
+                match_repeat(res, subtree, *kind, separator, src, enclosing, cache)?;
             }
         }
+        if err_count == 0 && res.err_count > 0 {
+            Failure::merge(&mut res.failure, Failure::at(&start, enclosing, None));
+        }
     }
     Ok(())
 }
@@ -370,12 +587,22 @@ impl<'a> TtIter<'a> {
     }
 }
 
+fn separator_text(separator: &Separator) -> String {
+    match separator {
+        Separator::Ident(it) => it.text.to_string(),
+        Separator::Literal(it) => it.text.to_string(),
+        Separator::Puncts(puncts) => puncts.iter().map(|it| it.char).collect(),
+    }
+}
+
 pub(super) fn match_repeat(
     res: &mut Match,
     pattern: &MetaTemplate,
     kind: RepeatKind,
     separator: &Option<Separator>,
     src: &mut TtIter,
+    enclosing: &tt::Subtree,
+    cache: &mut FragmentCache,
 ) -> Result<(), ExpandError> {
     // Dirty hack to make macro-expansion terminate.
     // This should be replaced by a propper macro-by-example implementation
@@ -387,12 +614,19 @@ pub(super) fn match_repeat(
 
         if let Some(separator) = &separator {
             if i != 0 && !fork.eat_separator(separator) {
+                let expected = format!("`{}`", separator_text(separator));
+                res.fail(Failure::at(&fork, enclosing, Some(expected)));
                 break;
             }
         }
 
         let mut nested = Match::default();
-        match_subtree(&mut nested, pattern, &mut fork)?;
+        match_subtree(&mut nested, pattern, &mut fork, enclosing, cache)?;
+        // Even if this repetition doesn't match, it might have gotten further into the input
+        // than the rest of the rule.
+        if let Some(failure) = nested.failure {
+            res.fail(failure);
+        }
         if nested.err.is_none() {
             limit -= 1;
             if limit == 0 {
@@ -438,7 +672,11 @@ pub(super) fn match_repeat(
     Ok(())
 }
 
-fn match_meta_var(kind: &str, input: &mut TtIter) -> ExpandResult<Option<Fragment>> {
+fn match_meta_var(
+    kind: &str,
+    input: &mut TtIter,
+    cache: &mut FragmentCache,
+) -> ExpandResult<Option<Fragment>> {
     let fragment = match kind {
         "path" => Path,
         "expr" => Expr,
@@ -485,7 +723,7 @@ fn match_meta_var(kind: &str, input: &mut TtIter) -> ExpandResult<Option<Fragmen
             return tt_result.map(|it| it.map(Fragment::Tokens)).into();
         }
     };
-    let result = input.expect_fragment(fragment);
+    let result = cache.expect_fragment(input, fragment);
     result.map(|tt| if kind == "expr" { tt.map(Fragment::Ast) } else { tt.map(Fragment::Tokens) })
 }
 
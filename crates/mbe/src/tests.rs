@@ -1,8 +1,8 @@
-use std::fmt::Write;
+use std::{fmt::Write, fs, time::Instant};
 
 use ::parser::FragmentKind;
 use syntax::{ast, AstNode, NodeOrToken, SyntaxKind::IDENT, SyntaxNode, WalkEvent, T};
-use test_utils::assert_eq_text;
+use test_utils::{assert_eq_text, project_dir};
 
 use super::*;
 
@@ -93,6 +93,26 @@ macro_rules! foobar {
     assert_eq!(get_id(&expansion.token_trees[2]), Some(14));
 }
 
+#[test]
+fn test_token_id_shift_covers_trailing_punct() {
+    let rules = parse_macro(
+        r#"
+macro_rules! foobar {
+    ($e:ident) => { struct $e; }
+}
+"#,
+    );
+    let expansion = rules.expand_tt("foobar!(S);");
+
+    let semi = match expansion.token_trees.last() {
+        Some(tt::TokenTree::Leaf(tt::Leaf::Punct(punct))) => punct.id,
+        _ => panic!("expected a trailing `;`, got {:?}", expansion),
+    };
+    // The `;` is the token with the highest id of the definition, it must not be mistaken for a
+    // token of the call.
+    assert_eq!(rules.rules.map_id_up(semi), (semi, Origin::Def));
+}
+
 #[test]
 fn test_token_map() {
     let expanded = parse_macro(
@@ -166,49 +186,49 @@ SUBTREE $
   IDENT   impl 20
   IDENT   From 21
   PUNCH   < [joint] 22
-  IDENT   Leaf 53
+  IDENT   Leaf 54
   PUNCH   > [alone] 25
   IDENT   for 26
-  IDENT   TokenTree 51
+  IDENT   TokenTree 52
   SUBTREE {} 29
     IDENT   fn 30
     IDENT   from 31
     SUBTREE () 32
       IDENT   it 33
       PUNCH   : [alone] 34
-      IDENT   Leaf 53
+      IDENT   Leaf 54
     PUNCH   - [joint] 37
     PUNCH   > [alone] 38
-    IDENT   TokenTree 51
+    IDENT   TokenTree 52
     SUBTREE {} 41
-      IDENT   TokenTree 51
+      IDENT   TokenTree 52
       PUNCH   : [joint] 44
       PUNCH   : [joint] 45
-      IDENT   Leaf 53
+      IDENT   Leaf 54
       SUBTREE () 48
         IDENT   it 49
   IDENT   impl 20
   IDENT   From 21
   PUNCH   < [joint] 22
-  IDENT   Subtree 55
+  IDENT   Subtree 56
   PUNCH   > [alone] 25
   IDENT   for 26
-  IDENT   TokenTree 51
+  IDENT   TokenTree 52
   SUBTREE {} 29
     IDENT   fn 30
     IDENT   from 31
     SUBTREE () 32
       IDENT   it 33
       PUNCH   : [alone] 34
-      IDENT   Subtree 55
+      IDENT   Subtree 56
     PUNCH   - [joint] 37
     PUNCH   > [alone] 38
-    IDENT   TokenTree 51
+    IDENT   TokenTree 52
     SUBTREE {} 41
-      IDENT   TokenTree 51
+      IDENT   TokenTree 52
       PUNCH   : [joint] 44
       PUNCH   : [joint] 45
-      IDENT   Subtree 55
+      IDENT   Subtree 56
       SUBTREE () 48
         IDENT   it 49
 "#,
@@ -228,16 +248,16 @@ macro_rules! foo {
         r#"foo!(static bar: &'static str = "hello";);"#,
         r#"
 SUBTREE $
-  IDENT   static 17
-  IDENT   bar 18
-  PUNCH   : [alone] 19
-  PUNCH   & [alone] 20
-  PUNCH   ' [joint] 21
-  IDENT   static 22
-  IDENT   str 23
-  PUNCH   = [alone] 24
-  LITERAL "hello" 25
-  PUNCH   ; [joint] 26
+  IDENT   static 18
+  IDENT   bar 19
+  PUNCH   : [alone] 20
+  PUNCH   & [alone] 21
+  PUNCH   ' [joint] 22
+  IDENT   static 23
+  IDENT   str 24
+  PUNCH   = [alone] 25
+  LITERAL "hello" 26
+  PUNCH   ; [joint] 27
 "#,
     );
 }
@@ -1134,9 +1154,9 @@ SUBTREE $
     IDENT   bool 20
   PUNCH   = [alone] 21
   SUBTREE () 22
-    IDENT   true 29
+    IDENT   true 31
     PUNCH   , [joint] 25
-    IDENT   false 31
+    IDENT   false 33
   PUNCH   ; [alone] 28
 "#,
     );
@@ -1661,6 +1681,69 @@ fn expr_interpolation() {
     assert_eq!(expanded.to_string(), "map(x+foo)");
 }
 
+#[test]
+fn test_macro_def_simple() {
+    let expanded = parse_macro_def(
+        r#"
+        macro foo($x:ident) {
+            fn $x() {}
+        }
+        "#,
+    )
+    .expand(&parse_invocation("foo!(bar);"))
+    .value;
+
+    assert_eq!(expanded.to_string(), "fn bar () {}");
+}
+
+#[test]
+fn test_macro_def_rules() {
+    let mac = parse_macro_def(
+        r#"
+        macro foo {
+            ($x:ident) => { struct $x; },
+            ($x:ident, $y:ident) => { struct $x; struct $y; }
+        }
+        "#,
+    );
+
+    assert_eq!(mac.expand(&parse_invocation("foo!(A);")).value.to_string(), "struct A ;");
+    assert_eq!(
+        mac.expand(&parse_invocation("foo!(A, B);")).value.to_string(),
+        "struct A ; struct B ;"
+    );
+}
+
+#[test]
+fn test_macro_def_rules_require_delimiter() {
+    let source_file = ast::SourceFile::parse("macro foo { () => {} () => {} }").ok().unwrap();
+    let macro_definition =
+        source_file.syntax().descendants().find_map(ast::MacroDef::cast).unwrap();
+    let (definition_tt, _) = ast_to_token_tree(&macro_definition.body().unwrap()).unwrap();
+
+    assert_eq!(
+        MacroDef::parse(&definition_tt),
+        Err(ParseError::Expected("expected `;` or `,` to delimit rules".to_string()))
+    );
+}
+
+fn parse_macro_def(ra_fixture: &str) -> MacroDef {
+    let source_file = ast::SourceFile::parse(ra_fixture).ok().unwrap();
+    let macro_definition =
+        source_file.syntax().descendants().find_map(ast::MacroDef::cast).unwrap();
+
+    let (definition_tt, _) = ast_to_token_tree(&macro_definition.body().unwrap()).unwrap();
+    MacroDef::parse(&definition_tt).unwrap()
+}
+
+fn parse_invocation(invocation: &str) -> tt::Subtree {
+    let source_file = ast::SourceFile::parse(invocation).tree();
+    let macro_invocation =
+        source_file.syntax().descendants().find_map(ast::MacroCall::cast).unwrap();
+
+    ast_to_token_tree(&macro_invocation.token_tree().unwrap()).unwrap().0
+}
+
 pub(crate) struct MacroFixture {
     rules: MacroRules,
 }
@@ -1681,8 +1764,8 @@ impl MacroFixture {
         self.rules.expand(&invocation_tt).result()
     }
 
-    fn assert_expand_err(&self, invocation: &str, err: &ExpandError) {
-        assert_eq!(self.try_expand_tt(invocation).as_ref(), Err(err));
+    fn assert_expand_err(&self, invocation: &str, err: &str) {
+        assert_eq!(self.try_expand_tt(invocation).map_err(|it| it.to_string()), Err(err.into()));
     }
 
     fn expand_items(&self, invocation: &str) -> SyntaxNode {
@@ -1978,7 +2061,167 @@ fn test_expand_bad_literal() {
         macro_rules! foo { ($i:literal) => {}; }
     "#,
     )
-    .assert_expand_err(r#"foo!(&k");"#, &ExpandError::BindingError("".into()));
+    // The unterminated string swallows the closing `)`, so the `(` is not a delimiter but the
+    // first token of the input.
+    .assert_expand_err(r#"foo!(&k");"#, "no rules expected the token `(`; expected `$i:literal`");
+}
+
+#[test]
+fn test_matched_arm() {
+    let fixture = parse_macro(
+        r#"
+        macro_rules! foo {
+            (a $i:ident) => {};
+            (b $e:literal) => {};
+        }
+    "#,
+    );
+
+    let invocation = parse_invocation("foo!(a x);");
+    assert_eq!(
+        fixture.rules.matched_arm(&invocation),
+        Some(MatchedArm { index: 0, failed_at: None })
+    );
+
+    let invocation = parse_invocation("foo!(b 1 2);");
+    let leftover = match &invocation.token_trees[2] {
+        tt::TokenTree::Leaf(tt::Leaf::Literal(it)) => it.id,
+        _ => panic!("expected a literal"),
+    };
+    assert_eq!(
+        fixture.rules.matched_arm(&invocation),
+        Some(MatchedArm { index: 1, failed_at: Some(leftover) })
+    );
+}
+
+#[test]
+fn test_trace_expand() {
+    let fixture = parse_macro(
+        r#"
+        macro_rules! foo {
+            (a $i:ident) => { $i };
+            ($($e:literal),*) => { $($e)* };
+        }
+    "#,
+    );
+
+    let trace = fixture.rules.trace_expand(&parse_invocation("foo!(1, 2);"));
+    assert_eq!(trace.chosen, Some(1));
+    assert_eq!(trace.rules.len(), 2);
+    assert_eq!(trace.rules[0].err, Some(ExpandError::UnexpectedToken));
+    assert_eq!(trace.rules[1].err, None);
+    assert_eq!(trace.expansion.err, None);
+
+    let (name, value) = &trace.rules[1].bindings[0];
+    assert_eq!(name, "e");
+    let values: Vec<String> = match value {
+        BoundValue::Nested(it) => it
+            .iter()
+            .map(|it| match it {
+                BoundValue::Fragment(tt) => tt.to_string(),
+                _ => panic!("expected a fragment"),
+            })
+            .collect(),
+        _ => panic!("expected nested bindings"),
+    };
+    assert_eq!(values, ["1", "2"]);
+}
+
+/// Expands macros with many arms, to compare the time it takes before and after changes to rule
+/// matching. Run it with `cargo test -p mbe -- --ignored --nocapture benchmark`.
+#[test]
+#[ignore]
+fn benchmark_expand_heavy_macros() {
+    // `T!` has an arm for each token, all of them starting with a different token.
+    let syntax_kinds =
+        fs::read_to_string(project_dir().join("crates/parser/src/syntax_kind/generated.rs"))
+            .unwrap();
+    let t_macro = parse_macro(&syntax_kinds);
+    // Arms starting with the same fragment, like in `assert_matches!`-style macros.
+    let mut fragment_macro = String::from("macro_rules! m {\n");
+    for i in 0..32 {
+        writeln!(
+            fragment_macro,
+            "    ($e:expr; $p:pat => k{}) => {{ match $e {{ $p => k{} }} }};",
+            i, i
+        )
+        .unwrap();
+    }
+    fragment_macro.push_str("}\n");
+    let fragment_macro = parse_macro(&fragment_macro);
+
+    let t_calls: Vec<_> = [
+        ("T![;]", "SEMICOLON"),
+        ("T![ident]", "IDENT"),
+        ("T![shebang]", "SHEBANG"),
+        ("T![macro_rules]", "MACRO_RULES_KW"),
+    ]
+    .iter()
+    .map(|(call, kind)| (parse_invocation(call), *kind))
+    .collect();
+    let fragment_call = parse_invocation(
+        "m!(foo.bar(1 + 2 * 3, [4, 5, 6]).baz::<u32>()?.0; Some((a, b, ..)) => k31);",
+    );
+
+    let start = Instant::now();
+    for _ in 0..1000 {
+        for (call, kind) in t_calls.iter() {
+            let res = t_macro.rules.expand(call);
+            assert!(res.err.is_none());
+            assert_eq!(res.value.token_trees.last().unwrap().to_string(), *kind);
+        }
+        let res = fragment_macro.rules.expand(&fragment_call);
+        assert!(res.err.is_none());
+        assert!(res.value.to_string().ends_with("k31}"), "{}", res.value);
+    }
+    eprintln!("expanding heavy macros: {:.2?}", start.elapsed());
+}
+
+#[test]
+fn test_no_rule_expected() {
+    let fixture = parse_macro(
+        r#"
+        macro_rules! foo {
+            (a $($i:ident),* ;) => {};
+            (a b c) => {};
+            ([$e:expr]) => {};
+        }
+    "#,
+    );
+    fixture.assert_expand_err(
+        "foo!(a x, y z);",
+        "no rules expected the token `z`; expected `,` or `;`",
+    );
+    fixture.assert_expand_err(
+        "foo!(a b);",
+        "unexpected end of macro invocation; expected `,`, `;` or `c`",
+    );
+    fixture.assert_expand_err("foo!(1);", "no rules expected the token `1`; expected `a` or `[`");
+
+    let invocation = parse_invocation("foo!(a x, y z);");
+    let z = match &invocation.token_trees[4] {
+        tt::TokenTree::Leaf(tt::Leaf::Ident(it)) => it.id,
+        _ => panic!("expected an ident"),
+    };
+    match fixture.rules.expand(&invocation).err {
+        Some(ExpandError::NoRuleExpected { found: UnmatchedInput::Token { id, .. }, .. }) => {
+            assert_eq!(id, z)
+        }
+        err => panic!("unexpected error: {:?}", err),
+    }
+
+    // Running out of input points at the delimiters of the call, which are shifted and unshifted
+    // like the tokens inside them.
+    let invocation = parse_invocation("foo!(a b);");
+    let delimiter = invocation.delimiter.map(|it| it.id);
+    match fixture.rules.expand(&invocation).err {
+        Some(ExpandError::NoRuleExpected {
+            found: UnmatchedInput::End { delimiter: id }, ..
+        }) => {
+            assert_eq!(id, delimiter)
+        }
+        err => panic!("unexpected error: {:?}", err),
+    }
 }
 
 #[test]
@@ -1990,6 +2233,6 @@ fn test_empty_comments() {
     )
     .assert_expand_err(
         r#"one_arg_macro!(/**/)"#,
-        &ExpandError::BindingError("expected Expr".into()),
+        "unexpected end of macro invocation; expected `$fmt:expr`",
     );
 }
@@ -14,15 +14,29 @@ use std::{
     io,
     path::{Path, PathBuf},
     sync::Arc,
+    time::Duration,
 };
 
-use base_db::{Env, ProcMacro};
+use base_db::{Env, ProcMacro, ProcMacroExpansion};
 use tt::{SmolStr, Subtree};
 
 use crate::process::{ProcMacroProcessSrv, ProcMacroProcessThread};
 
 pub use rpc::{ExpansionResult, ExpansionTask, ListMacrosResult, ListMacrosTask, ProcMacroKind};
 
+/// Changes in the state of the proc macro server, reported while expanding macros.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProcMacroServerEvent {
+    /// The server crashed or stopped responding. It is restarted by the next request.
+    Crashed { message: String },
+    /// The server was started again after a crash.
+    Restarted,
+    /// The macro crashed the server too many times and won't be expanded anymore.
+    MacroDisabled { name: String, dylib_path: PathBuf },
+    /// The library of a disabled macro was rebuilt, so the macro is expanded again.
+    MacroEnabled { name: String, dylib_path: PathBuf },
+}
+
 #[derive(Debug, Clone)]
 struct ProcMacroProcessExpander {
     process: Arc<ProcMacroProcessSrv>,
@@ -45,7 +59,7 @@ impl base_db::ProcMacroExpander for ProcMacroProcessExpander {
         subtree: &Subtree,
         attr: Option<&Subtree>,
         env: &Env,
-    ) -> Result<Subtree, tt::ExpansionError> {
+    ) -> Result<ProcMacroExpansion, tt::ExpansionError> {
         let task = ExpansionTask {
             macro_body: subtree.clone(),
             macro_name: self.name.to_string(),
@@ -55,7 +69,12 @@ impl base_db::ProcMacroExpander for ProcMacroProcessExpander {
         };
 
         let result: ExpansionResult = self.process.send_task(msg::Request::ExpansionMacro(task))?;
-        Ok(result.expansion)
+        Ok(ProcMacroExpansion {
+            subtree: result.expansion,
+            diagnostics: result.diagnostics,
+            tracked_env: result.tracked_env,
+            tracked_paths: result.tracked_paths,
+        })
     }
 }
 
@@ -66,11 +85,16 @@ pub struct ProcMacroClient {
 }
 
 impl ProcMacroClient {
+    /// Starts the server. Expansions that take longer than `expansion_timeout` are treated as
+    /// crashes, and `on_event` is called from the server thread whenever its state changes.
     pub fn extern_process(
         process_path: PathBuf,
         args: impl IntoIterator<Item = impl AsRef<OsStr>>,
+        expansion_timeout: Option<Duration>,
+        on_event: Box<dyn Fn(ProcMacroServerEvent) + Send>,
     ) -> io::Result<ProcMacroClient> {
-        let (thread, process) = ProcMacroProcessSrv::run(process_path, args)?;
+        let (thread, process) =
+            ProcMacroProcessSrv::run(process_path, args, expansion_timeout, on_event)?;
         Ok(ProcMacroClient { process: Arc::new(process), thread })
     }
 
@@ -1,7 +1,12 @@
-//! Defines messages for cross-process message passing based on `ndjson` wire protocol
+//! Defines messages for cross-process message passing.
+//!
+//! Messages are sent as `ndjson` until a handshake switches both sides to the [`binary`]
+//! encoding.
+
+mod binary;
 
 use std::{
-    convert::TryFrom,
+    convert::{TryFrom, TryInto},
     io::{self, BufRead, Write},
 };
 
@@ -16,6 +21,7 @@ use crate::{
 pub enum Request {
     ListMacro(ListMacrosTask),
     ExpansionMacro(ExpansionTask),
+    Handshake(HandshakeTask),
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -23,6 +29,7 @@ pub enum Response {
     Error(ResponseError),
     ListMacro(ListMacrosResult),
     ExpansionMacro(ExpansionResult),
+    Handshake(HandshakeResult),
 }
 
 macro_rules! impl_try_from_response {
@@ -52,29 +59,108 @@ pub struct ResponseError {
 pub enum ErrorCode {
     ServerErrorEnd,
     ExpansionError,
+    ExpansionPanic,
+}
+
+/// The version of the [`binary`] encoding. Bump it whenever the encoding changes.
+pub const BINARY_VERSION: u32 = 1;
+
+/// How messages are sent over the wire.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    /// Newline-delimited JSON. Both sides start with it, and fall back to it.
+    Json,
+    /// Length-prefixed binary messages, see [`binary`].
+    Binary,
+}
+
+/// Sent by the client, in JSON, before anything else.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct HandshakeTask {
+    /// The versions of the binary encoding the client supports.
+    pub binary_versions: Vec<u32>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct HandshakeResult {
+    /// The binary encoding both sides switch to after the response, if any.
+    pub binary_version: Option<u32>,
+}
+
+impl Default for HandshakeTask {
+    fn default() -> HandshakeTask {
+        HandshakeTask { binary_versions: vec![BINARY_VERSION] }
+    }
+}
+
+impl HandshakeTask {
+    /// The server's response.
+    pub fn accept(&self) -> HandshakeResult {
+        let binary_version = Some(BINARY_VERSION).filter(|it| self.binary_versions.contains(it));
+        HandshakeResult { binary_version }
+    }
+}
+
+impl HandshakeResult {
+    pub fn encoding(&self) -> Encoding {
+        match self.binary_version {
+            Some(BINARY_VERSION) => Encoding::Binary,
+            _ => Encoding::Json,
+        }
+    }
 }
 
 pub trait Message: Serialize + DeserializeOwned {
-    fn read(inp: &mut impl BufRead) -> io::Result<Option<Self>> {
-        Ok(match read_json(inp)? {
-            None => None,
-            Some(text) => {
-                let mut deserializer = serde_json::Deserializer::from_str(&text);
-                // Note that some proc-macro generate very deep syntax tree
-                // We have to disable the current limit of serde here
-                deserializer.disable_recursion_limit();
-                Some(Self::deserialize(&mut deserializer)?)
-            }
+    fn read(inp: &mut impl BufRead, encoding: Encoding) -> io::Result<Option<Self>> {
+        Ok(match encoding {
+            Encoding::Json => match read_json(inp)? {
+                None => None,
+                Some(text) => {
+                    let mut deserializer = serde_json::Deserializer::from_str(&text);
+                    // Note that some proc-macro generate very deep syntax tree
+                    // We have to disable the current limit of serde here
+                    deserializer.disable_recursion_limit();
+                    Some(Self::deserialize(&mut deserializer)?)
+                }
+            },
+            Encoding::Binary => match read_frame(inp)? {
+                None => None,
+                Some(bytes) => Some(Self::decode(&bytes)?),
+            },
         })
     }
-    fn write(self, out: &mut impl Write) -> io::Result<()> {
-        let text = serde_json::to_string(&self)?;
-        write_json(out, &text)
+    fn write(self, out: &mut impl Write, encoding: Encoding) -> io::Result<()> {
+        match encoding {
+            Encoding::Json => {
+                let text = serde_json::to_string(&self)?;
+                write_json(out, &text)
+            }
+            Encoding::Binary => write_frame(out, &self.encode()?),
+        }
     }
+
+    /// Encodes the message in the binary encoding, without the frame.
+    fn encode(&self) -> io::Result<Vec<u8>>;
+    fn decode(bytes: &[u8]) -> io::Result<Self>;
 }
 
-impl Message for Request {}
-impl Message for Response {}
+impl Message for Request {
+    fn encode(&self) -> io::Result<Vec<u8>> {
+        binary::encode_request(self)
+    }
+    fn decode(bytes: &[u8]) -> io::Result<Self> {
+        binary::decode_request(bytes)
+    }
+}
+
+impl Message for Response {
+    fn encode(&self) -> io::Result<Vec<u8>> {
+        binary::encode_response(self)
+    }
+    fn decode(bytes: &[u8]) -> io::Result<Self> {
+        binary::decode_response(bytes)
+    }
+}
 
 fn read_json(inp: &mut impl BufRead) -> io::Result<Option<String>> {
     let mut buf = String::new();
@@ -93,3 +179,25 @@ fn write_json(out: &mut impl Write, msg: &str) -> io::Result<()> {
     out.flush()?;
     Ok(())
 }
+
+fn read_frame(inp: &mut impl BufRead) -> io::Result<Option<Vec<u8>>> {
+    if inp.fill_buf()?.is_empty() {
+        return Ok(None);
+    }
+    let mut len = [0; 4];
+    inp.read_exact(&mut len)?;
+    let mut buf = vec![0; u32::from_le_bytes(len) as usize];
+    inp.read_exact(&mut buf)?;
+    Ok(Some(buf))
+}
+
+fn write_frame(out: &mut impl Write, msg: &[u8]) -> io::Result<()> {
+    log::debug!("> {} bytes", msg.len());
+    let len: u32 = msg.len().try_into().map_err(|_| {
+        io::Error::new(io::ErrorKind::InvalidData, "message is too large to be framed")
+    })?;
+    out.write_all(&len.to_le_bytes())?;
+    out.write_all(msg)?;
+    out.flush()?;
+    Ok(())
+}
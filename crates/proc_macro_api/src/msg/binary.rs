@@ -0,0 +1,467 @@
+//! The binary encoding of messages.
+//!
+//! Numbers are little-endian `u32`s, strings are their length followed by their UTF-8 bytes, and
+//! sequences are their length followed by their elements. Token trees, which make up most of the
+//! traffic, are flattened into tables by [`FlatTree`], so that decoding them doesn't recurse.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    convert::TryInto,
+    io,
+    path::{Path, PathBuf},
+};
+
+use tt::{
+    Delimiter, DelimiterKind, Diagnostic, DiagnosticLevel, Ident, Leaf, Literal, Punct, SmolStr,
+    Spacing, Subtree, TokenId, TokenTree,
+};
+
+use crate::{
+    msg::{ErrorCode, HandshakeResult, HandshakeTask, Request, Response, ResponseError},
+    rpc::{ExpansionResult, ExpansionTask, ListMacrosResult, ListMacrosTask, ProcMacroKind},
+};
+
+pub(super) fn encode_request(req: &Request) -> io::Result<Vec<u8>> {
+    let mut w = Writer::default();
+    match req {
+        Request::ListMacro(ListMacrosTask { lib }) => {
+            w.u8(0);
+            w.path(lib)?;
+        }
+        Request::ExpansionMacro(ExpansionTask { macro_body, macro_name, attributes, lib, env }) => {
+            w.u8(1);
+            w.subtree(macro_body);
+            w.str(macro_name);
+            w.opt(attributes.as_ref(), Writer::subtree);
+            w.path(lib)?;
+            w.seq(env, |w, (k, v)| {
+                w.str(k);
+                w.str(v);
+            });
+        }
+        Request::Handshake(HandshakeTask { binary_versions }) => {
+            w.u8(2);
+            w.seq(binary_versions, |w, it| w.u32(*it));
+        }
+    }
+    Ok(w.buf)
+}
+
+pub(super) fn decode_request(bytes: &[u8]) -> io::Result<Request> {
+    let mut r = Reader { bytes };
+    let res = match r.u8()? {
+        0 => Request::ListMacro(ListMacrosTask { lib: r.path()? }),
+        1 => Request::ExpansionMacro(ExpansionTask {
+            macro_body: r.subtree()?,
+            macro_name: r.str()?,
+            attributes: r.opt(Reader::subtree)?,
+            lib: r.path()?,
+            env: r.seq(|r| Ok((r.str()?, r.str()?)))?,
+        }),
+        2 => Request::Handshake(HandshakeTask { binary_versions: r.seq(Reader::u32)? }),
+        tag => return Err(invalid_data(format!("unknown request {}", tag))),
+    };
+    r.finish()?;
+    Ok(res)
+}
+
+pub(super) fn encode_response(res: &Response) -> io::Result<Vec<u8>> {
+    let mut w = Writer::default();
+    match res {
+        Response::Error(ResponseError { code, message }) => {
+            w.u8(0);
+            w.u8(match code {
+                ErrorCode::ServerErrorEnd => 0,
+                ErrorCode::ExpansionError => 1,
+                ErrorCode::ExpansionPanic => 2,
+            });
+            w.str(message);
+        }
+        Response::ListMacro(ListMacrosResult { macros }) => {
+            w.u8(1);
+            w.seq(macros, |w, (name, kind)| {
+                w.str(name);
+                w.u8(match kind {
+                    ProcMacroKind::CustomDerive => 0,
+                    ProcMacroKind::FuncLike => 1,
+                    ProcMacroKind::Attr => 2,
+                });
+            });
+        }
+        Response::ExpansionMacro(ExpansionResult {
+            expansion,
+            diagnostics,
+            tracked_env,
+            tracked_paths,
+        }) => {
+            w.u8(2);
+            w.subtree(expansion);
+            w.seq(diagnostics, Writer::diagnostic);
+            w.seq(tracked_env, |w, (k, v)| {
+                w.str(k);
+                w.opt(v.as_ref(), |w, it| w.str(it));
+            });
+            w.u32(tracked_paths.len() as u32);
+            for path in tracked_paths {
+                w.path(path)?;
+            }
+        }
+        Response::Handshake(HandshakeResult { binary_version }) => {
+            w.u8(3);
+            w.opt(binary_version.as_ref(), |w, it| w.u32(*it));
+        }
+    }
+    Ok(w.buf)
+}
+
+pub(super) fn decode_response(bytes: &[u8]) -> io::Result<Response> {
+    let mut r = Reader { bytes };
+    let res = match r.u8()? {
+        0 => {
+            let code = match r.u8()? {
+                0 => ErrorCode::ServerErrorEnd,
+                1 => ErrorCode::ExpansionError,
+                2 => ErrorCode::ExpansionPanic,
+                code => return Err(invalid_data(format!("unknown error code {}", code))),
+            };
+            Response::Error(ResponseError { code, message: r.str()? })
+        }
+        1 => Response::ListMacro(ListMacrosResult {
+            macros: r.seq(|r| {
+                let name = r.str()?;
+                let kind = match r.u8()? {
+                    0 => ProcMacroKind::CustomDerive,
+                    1 => ProcMacroKind::FuncLike,
+                    2 => ProcMacroKind::Attr,
+                    kind => return Err(invalid_data(format!("unknown macro kind {}", kind))),
+                };
+                Ok((name, kind))
+            })?,
+        }),
+        2 => Response::ExpansionMacro(ExpansionResult {
+            expansion: r.subtree()?,
+            diagnostics: r.seq(Reader::diagnostic)?,
+            tracked_env: r.seq(|r| Ok((r.str()?, r.opt(Reader::str)?)))?,
+            tracked_paths: r.seq(Reader::path)?,
+        }),
+        3 => Response::Handshake(HandshakeResult { binary_version: r.opt(Reader::u32)? }),
+        tag => return Err(invalid_data(format!("unknown response {}", tag))),
+    };
+    r.finish()?;
+    Ok(res)
+}
+
+/// A token tree as tables of its subtrees and leaves.
+///
+/// Subtrees are numbered breadth-first, so the children of a subtree are a contiguous range of
+/// `token_tree`, and children always come after their parent.
+#[derive(Default)]
+struct FlatTree {
+    /// `[delimiter kind, delimiter id, start of children, end of children]` for each subtree.
+    subtree: Vec<[u32; 4]>,
+    /// For each child, its index into the table for its kind, tagged with the kind in the lower
+    /// two bits.
+    token_tree: Vec<u32>,
+    /// `[id, text]` for each literal.
+    literal: Vec<[u32; 2]>,
+    /// `[id, char, spacing]` for each punct.
+    punct: Vec<[u32; 3]>,
+    /// `[id, text]` for each ident.
+    ident: Vec<[u32; 2]>,
+    /// The interned text of literals and idents.
+    text: Vec<SmolStr>,
+}
+
+const SUBTREE_TAG: u32 = 0;
+const LITERAL_TAG: u32 = 1;
+const PUNCT_TAG: u32 = 2;
+const IDENT_TAG: u32 = 3;
+
+impl FlatTree {
+    fn new(root: &Subtree) -> FlatTree {
+        let mut res = FlatTree::default();
+        let mut text_ids: HashMap<SmolStr, u32> = HashMap::new();
+        let mut intern = |res: &mut FlatTree, text: &SmolStr| {
+            *text_ids.entry(text.clone()).or_insert_with(|| {
+                res.text.push(text.clone());
+                (res.text.len() - 1) as u32
+            })
+        };
+
+        res.subtree.push([0; 4]);
+        let mut queue = VecDeque::new();
+        queue.push_back((0, root));
+        while let Some((idx, subtree)) = queue.pop_front() {
+            let (kind, id) = match subtree.delimiter {
+                None => (0, TokenId::unspecified()),
+                Some(Delimiter { id, kind: DelimiterKind::Parenthesis }) => (1, id),
+                Some(Delimiter { id, kind: DelimiterKind::Brace }) => (2, id),
+                Some(Delimiter { id, kind: DelimiterKind::Bracket }) => (3, id),
+            };
+            let start = res.token_tree.len() as u32;
+            for child in &subtree.token_trees {
+                let tagged = match child {
+                    TokenTree::Subtree(child) => {
+                        res.subtree.push([0; 4]);
+                        queue.push_back((res.subtree.len() - 1, child));
+                        (res.subtree.len() - 1) as u32 * 4 + SUBTREE_TAG
+                    }
+                    TokenTree::Leaf(Leaf::Literal(lit)) => {
+                        let text = intern(&mut res, &lit.text);
+                        res.literal.push([lit.id.0, text]);
+                        (res.literal.len() - 1) as u32 * 4 + LITERAL_TAG
+                    }
+                    TokenTree::Leaf(Leaf::Punct(punct)) => {
+                        let spacing = match punct.spacing {
+                            Spacing::Alone => 0,
+                            Spacing::Joint => 1,
+                        };
+                        res.punct.push([punct.id.0, punct.char as u32, spacing]);
+                        (res.punct.len() - 1) as u32 * 4 + PUNCT_TAG
+                    }
+                    TokenTree::Leaf(Leaf::Ident(ident)) => {
+                        let text = intern(&mut res, &ident.text);
+                        res.ident.push([ident.id.0, text]);
+                        (res.ident.len() - 1) as u32 * 4 + IDENT_TAG
+                    }
+                };
+                res.token_tree.push(tagged);
+            }
+            res.subtree[idx] = [kind, id.0, start, res.token_tree.len() as u32];
+        }
+        res
+    }
+
+    fn into_subtree(self) -> io::Result<Subtree> {
+        // Children come after their parents, so building the subtrees back to front means
+        // children are always built first.
+        let mut subtrees: Vec<Option<Subtree>> = Vec::new();
+        subtrees.resize_with(self.subtree.len(), || None);
+        for idx in (0..self.subtree.len()).rev() {
+            let [kind, id, start, end] = self.subtree[idx];
+            let delimiter = match kind {
+                0 => None,
+                1 => Some(DelimiterKind::Parenthesis),
+                2 => Some(DelimiterKind::Brace),
+                3 => Some(DelimiterKind::Bracket),
+                _ => return Err(invalid_data("invalid delimiter")),
+            }
+            .map(|kind| Delimiter { id: TokenId(id), kind });
+            let children = self
+                .token_tree
+                .get(start as usize..end as usize)
+                .ok_or_else(|| invalid_data("invalid subtree"))?;
+            let token_trees = children
+                .iter()
+                .map(|&tagged| {
+                    let i = (tagged / 4) as usize;
+                    let tree: TokenTree = match tagged % 4 {
+                        SUBTREE_TAG => {
+                            let child = if i > idx {
+                                subtrees.get_mut(i).and_then(Option::take)
+                            } else {
+                                None
+                            };
+                            child.ok_or_else(|| invalid_data("invalid subtree"))?.into()
+                        }
+                        LITERAL_TAG => {
+                            let [id, text] = *self.literal.get(i).ok_or_else(invalid_leaf)?;
+                            Leaf::from(Literal { text: self.text(text)?, id: TokenId(id) }).into()
+                        }
+                        PUNCT_TAG => {
+                            let [id, char, spacing] =
+                                *self.punct.get(i).ok_or_else(invalid_leaf)?;
+                            let spacing = match spacing {
+                                0 => Spacing::Alone,
+                                1 => Spacing::Joint,
+                                _ => return Err(invalid_leaf()),
+                            };
+                            let char = std::char::from_u32(char).ok_or_else(invalid_leaf)?;
+                            Leaf::from(Punct { char, spacing, id: TokenId(id) }).into()
+                        }
+                        _ => {
+                            let [id, text] = *self.ident.get(i).ok_or_else(invalid_leaf)?;
+                            Leaf::from(Ident { text: self.text(text)?, id: TokenId(id) }).into()
+                        }
+                    };
+                    Ok(tree)
+                })
+                .collect::<io::Result<Vec<TokenTree>>>()?;
+            subtrees[idx] = Some(Subtree { delimiter, token_trees });
+        }
+        subtrees.first_mut().and_then(Option::take).ok_or_else(|| invalid_data("empty token tree"))
+    }
+
+    fn text(&self, idx: u32) -> io::Result<SmolStr> {
+        self.text.get(idx as usize).cloned().ok_or_else(invalid_leaf)
+    }
+
+    fn write(&self, w: &mut Writer) {
+        w.seq(&self.subtree, |w, it| it.iter().for_each(|&it| w.u32(it)));
+        w.seq(&self.token_tree, |w, &it| w.u32(it));
+        w.seq(&self.literal, |w, it| it.iter().for_each(|&it| w.u32(it)));
+        w.seq(&self.punct, |w, it| it.iter().for_each(|&it| w.u32(it)));
+        w.seq(&self.ident, |w, it| it.iter().for_each(|&it| w.u32(it)));
+        w.seq(&self.text, |w, it| w.str(it));
+    }
+
+    fn read(r: &mut Reader) -> io::Result<FlatTree> {
+        Ok(FlatTree {
+            subtree: r.seq(|r| Ok([r.u32()?, r.u32()?, r.u32()?, r.u32()?]))?,
+            token_tree: r.seq(Reader::u32)?,
+            literal: r.seq(|r| Ok([r.u32()?, r.u32()?]))?,
+            punct: r.seq(|r| Ok([r.u32()?, r.u32()?, r.u32()?]))?,
+            ident: r.seq(|r| Ok([r.u32()?, r.u32()?]))?,
+            text: r.seq(|r| Ok(r.str()?.into()))?,
+        })
+    }
+}
+
+#[derive(Default)]
+struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    fn u8(&mut self, value: u8) {
+        self.buf.push(value);
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn str(&mut self, value: &str) {
+        self.u32(value.len() as u32);
+        self.buf.extend_from_slice(value.as_bytes());
+    }
+
+    fn path(&mut self, value: &Path) -> io::Result<()> {
+        self.str(path_str(value)?);
+        Ok(())
+    }
+
+    fn seq<T>(&mut self, items: &[T], mut f: impl FnMut(&mut Writer, &T)) {
+        self.u32(items.len() as u32);
+        for item in items {
+            f(self, item);
+        }
+    }
+
+    fn opt<T>(&mut self, item: Option<&T>, f: impl FnOnce(&mut Writer, &T)) {
+        match item {
+            None => self.u8(0),
+            Some(item) => {
+                self.u8(1);
+                f(self, item);
+            }
+        }
+    }
+
+    fn subtree(&mut self, subtree: &Subtree) {
+        FlatTree::new(subtree).write(self);
+    }
+
+    fn diagnostic(&mut self, diagnostic: &Diagnostic) {
+        self.u8(match diagnostic.level {
+            DiagnosticLevel::Error => 0,
+            DiagnosticLevel::Warning => 1,
+            DiagnosticLevel::Note => 2,
+            DiagnosticLevel::Help => 3,
+        });
+        self.str(&diagnostic.message);
+        self.seq(&diagnostic.spans, |w, it| w.u32(it.0));
+        self.seq(&diagnostic.children, Writer::diagnostic);
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl Reader<'_> {
+    fn take(&mut self, len: usize) -> io::Result<&[u8]> {
+        if self.bytes.len() < len {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "message ended early"));
+        }
+        let (res, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(res)
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn str(&mut self) -> io::Result<String> {
+        let len = self.u32()? as usize;
+        String::from_utf8(self.take(len)?.to_vec()).map_err(invalid_data)
+    }
+
+    fn path(&mut self) -> io::Result<PathBuf> {
+        Ok(self.str()?.into())
+    }
+
+    fn seq<T>(&mut self, mut f: impl FnMut(&mut Self) -> io::Result<T>) -> io::Result<Vec<T>> {
+        let len = self.u32()? as usize;
+        // Don't trust the length for the allocation, every element takes at least a byte.
+        let mut res = Vec::with_capacity(len.min(self.bytes.len()));
+        for _ in 0..len {
+            res.push(f(self)?);
+        }
+        Ok(res)
+    }
+
+    fn opt<T>(&mut self, f: impl FnOnce(&mut Self) -> io::Result<T>) -> io::Result<Option<T>> {
+        match self.u8()? {
+            0 => Ok(None),
+            1 => f(self).map(Some),
+            _ => Err(invalid_data("invalid option")),
+        }
+    }
+
+    fn subtree(&mut self) -> io::Result<Subtree> {
+        FlatTree::read(self)?.into_subtree()
+    }
+
+    fn diagnostic(&mut self) -> io::Result<Diagnostic> {
+        let level = match self.u8()? {
+            0 => DiagnosticLevel::Error,
+            1 => DiagnosticLevel::Warning,
+            2 => DiagnosticLevel::Note,
+            3 => DiagnosticLevel::Help,
+            _ => return Err(invalid_data("invalid diagnostic level")),
+        };
+        Ok(Diagnostic {
+            level,
+            message: self.str()?,
+            spans: self.seq(|r| Ok(TokenId(r.u32()?)))?,
+            children: self.seq(Reader::diagnostic)?,
+        })
+    }
+
+    fn finish(self) -> io::Result<()> {
+        if self.bytes.is_empty() {
+            Ok(())
+        } else {
+            Err(invalid_data("trailing bytes after message"))
+        }
+    }
+}
+
+/// Like JSON, the encoding only supports UTF-8 paths.
+fn path_str(path: &Path) -> io::Result<&str> {
+    path.to_str().ok_or_else(|| invalid_data(format!("path is not UTF-8: {}", path.display())))
+}
+
+fn invalid_leaf() -> io::Error {
+    invalid_data("invalid leaf")
+}
+
+fn invalid_data(err: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
@@ -1,19 +1,26 @@
 //! Handle process life-time and message passing for proc-macro client
 
 use std::{
+    collections::HashMap,
     convert::{TryFrom, TryInto},
     ffi::{OsStr, OsString},
-    io::{self, BufRead, BufReader, Write},
+    fs,
+    io::{self, BufRead, BufReader},
     path::{Path, PathBuf},
-    process::{Child, Command, Stdio},
+    process::{Child, ChildStdin, Command, Stdio},
     sync::{Arc, Weak},
+    thread,
+    time::{Duration, Instant, SystemTime},
 };
 
-use crossbeam_channel::{bounded, Receiver, Sender};
+use crossbeam_channel::{
+    at, bounded, never, select, unbounded, Receiver, RecvTimeoutError, Sender,
+};
 
 use crate::{
-    msg::{ErrorCode, Message, Request, Response, ResponseError},
+    msg::{Encoding, ErrorCode, HandshakeTask, Message, Request, Response, ResponseError},
     rpc::{ListMacrosResult, ListMacrosTask, ProcMacroKind},
+    ProcMacroServerEvent,
 };
 
 #[derive(Debug, Default)]
@@ -32,13 +39,24 @@ impl ProcMacroProcessSrv {
     pub(crate) fn run(
         process_path: PathBuf,
         args: impl IntoIterator<Item = impl AsRef<OsStr>>,
+        expansion_timeout: Option<Duration>,
+        on_event: Box<dyn Fn(ProcMacroServerEvent) + Send>,
     ) -> io::Result<(ProcMacroProcessThread, ProcMacroProcessSrv)> {
-        let process = Process::run(process_path, args)?;
+        let args: Vec<OsString> = args.into_iter().map(|s| s.as_ref().into()).collect();
+        let process = Process::run(&process_path, &args, expansion_timeout)?;
+        let client = Client {
+            path: process_path,
+            args,
+            timeout: expansion_timeout,
+            on_event,
+            process: Some(process),
+            backoff: INITIAL_BACKOFF,
+            restart_at: Instant::now(),
+            crashes: HashMap::default(),
+        };
 
         let (task_tx, task_rx) = bounded(0);
-        let handle = jod_thread::spawn(move || {
-            client_loop(task_rx, process);
-        });
+        let handle = jod_thread::spawn(move || client.run(task_rx));
 
         let task_tx = Arc::new(task_tx);
         let srv = ProcMacroProcessSrv { inner: Arc::downgrade(&task_tx) };
@@ -75,9 +93,10 @@ impl ProcMacroProcessSrv {
             .map_err(|_| tt::ExpansionError::Unknown("proc macro server crashed".into()))?;
 
         match res {
-            Some(Response::Error(err)) => {
-                return Err(tt::ExpansionError::ExpansionError(err.message));
-            }
+            Some(Response::Error(err)) => match err.code {
+                ErrorCode::ExpansionPanic => Err(tt::ExpansionError::Panic(err.message)),
+                _ => Err(tt::ExpansionError::ExpansionError(err.message)),
+            },
             Some(res) => Ok(res.try_into().map_err(|err| {
                 tt::ExpansionError::Unknown(format!("Fail to get response, reason : {:#?} ", err))
             })?),
@@ -86,28 +105,149 @@ impl ProcMacroProcessSrv {
     }
 }
 
-fn client_loop(task_rx: Receiver<Task>, mut process: Process) {
-    let (mut stdin, mut stdout) = process.stdio().expect("couldn't access child stdio");
+/// Number of times an expansion may crash or hang the server before the macro is disabled.
+const MAX_CRASHES: u32 = 3;
+const INITIAL_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_BACKOFF: Duration = Duration::from_secs(5);
+
+/// Owns the server process and restarts it, with exponential backoff, after it crashes or stops
+/// responding. Crashes are attributed to the macro being expanded, so that a single broken macro
+/// is disabled instead of taking the server down over and over again.
+struct Client {
+    path: PathBuf,
+    args: Vec<OsString>,
+    timeout: Option<Duration>,
+    on_event: Box<dyn Fn(ProcMacroServerEvent) + Send>,
+    /// `None` after a crash, until the server is restarted at `restart_at`.
+    process: Option<Process>,
+    backoff: Duration,
+    restart_at: Instant,
+    crashes: HashMap<(PathBuf, String), Crashes>,
+}
+
+/// How often a macro crashed the server, since its library was last modified.
+struct Crashes {
+    count: u32,
+    modified: Option<SystemTime>,
+}
+
+impl Client {
+    fn run(mut self, task_rx: Receiver<Task>) {
+        loop {
+            // Restarting here rather than when the next request comes in means that requests
+            // never wait for the backoff, they fail right away while the server is down.
+            let restart = match self.process {
+                Some(_) => never(),
+                None => at(self.restart_at),
+            };
+            select! {
+                recv(task_rx) -> task => match task {
+                    Ok(Task { req, result_tx }) => {
+                        let res = self.handle(req);
+                        let _ = result_tx.send(Some(res));
+                    }
+                    Err(_) => return,
+                },
+                recv(restart) -> _ => self.restart(),
+            }
+        }
+    }
+
+    fn handle(&mut self, req: Request) -> Response {
+        let key = match &req {
+            Request::ExpansionMacro(task) => Some((task.lib.clone(), task.macro_name.clone())),
+            Request::ListMacro(_) | Request::Handshake(_) => None,
+        };
+        if let Some(key) = &key {
+            if let Some(response) = self.check_disabled(key) {
+                return response;
+            }
+        }
+
+        let timeout = self.timeout;
+        let process = match self.process.as_mut() {
+            Some(it) => it,
+            None => return server_error("proc macro server is restarting after a crash".into()),
+        };
+        let err = match process.send(req, timeout) {
+            Ok(res) => {
+                self.backoff = INITIAL_BACKOFF;
+                return res;
+            }
+            Err(err) => err,
+        };
+
+        let message = match &key {
+            Some((_, name)) => format!("proc macro `{}` crashed the server: {}", name, err),
+            None => format!("proc macro server crashed: {}", err),
+        };
+        log::error!(
+            "{}, server process state: {:?}",
+            message,
+            self.process.as_mut().map(|it| it.child.try_wait())
+        );
+        self.process = None;
+        self.delay_restart();
+        (self.on_event)(ProcMacroServerEvent::Crashed { message: message.clone() });
 
-    for Task { req, result_tx } in task_rx {
-        match send_request(&mut stdin, &mut stdout, req) {
-            Ok(res) => result_tx.send(res).unwrap(),
+        if let Some(key) = key {
+            let modified = modified(&key.0);
+            let crashes = self.crashes.entry(key.clone()).or_insert(Crashes { count: 0, modified });
+            if crashes.modified != modified {
+                *crashes = Crashes { count: 0, modified };
+            }
+            crashes.count += 1;
+            if crashes.count == MAX_CRASHES {
+                let (dylib_path, name) = key;
+                (self.on_event)(ProcMacroServerEvent::MacroDisabled { name, dylib_path });
+            }
+        }
+        server_error(message)
+    }
+
+    /// Refuses to expand a disabled macro, unless its library was rebuilt since it was disabled.
+    fn check_disabled(&mut self, key: &(PathBuf, String)) -> Option<Response> {
+        let crashes = self.crashes.get(key)?;
+        if crashes.count < MAX_CRASHES {
+            return None;
+        }
+        if crashes.modified == modified(&key.0) {
+            return Some(server_error(format!(
+                "proc macro `{}` is disabled after crashing the server {} times",
+                key.1, MAX_CRASHES
+            )));
+        }
+        self.crashes.remove(key);
+        let (dylib_path, name) = key.clone();
+        (self.on_event)(ProcMacroServerEvent::MacroEnabled { name, dylib_path });
+        None
+    }
+
+    fn restart(&mut self) {
+        match Process::run(&self.path, &self.args, self.timeout) {
+            Ok(process) => {
+                self.process = Some(process);
+                (self.on_event)(ProcMacroServerEvent::Restarted);
+            }
             Err(err) => {
-                log::error!(
-                    "proc macro server crashed, server process state: {:?}, server request error: {:?}",
-                    process.child.try_wait(),
-                    err
-                );
-                let res = Response::Error(ResponseError {
-                    code: ErrorCode::ServerErrorEnd,
-                    message: "proc macro server crashed".into(),
-                });
-                result_tx.send(res.into()).unwrap();
-                // Exit the thread.
-                break;
+                log::error!("failed to restart proc macro server: {}", err);
+                self.delay_restart();
             }
         }
     }
+
+    fn delay_restart(&mut self) {
+        self.restart_at = Instant::now() + self.backoff;
+        self.backoff = (self.backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|it| it.modified()).ok()
+}
+
+fn server_error(message: String) -> Response {
+    Response::Error(ResponseError { code: ErrorCode::ServerErrorEnd, message })
 }
 
 struct Task {
@@ -117,30 +257,100 @@ struct Task {
 
 struct Process {
     child: Child,
+    stdin: ChildStdin,
+    encoding: Encoding,
+    /// Responses are read on a separate thread, so that waiting for them can time out.
+    responses: Receiver<io::Result<Option<Response>>>,
 }
 
 impl Drop for Process {
     fn drop(&mut self) {
+        // This also closes the server's stdout, which stops the reader thread.
         let _ = self.child.kill();
+        let _ = self.child.wait();
     }
 }
 
 impl Process {
-    fn run(
-        path: PathBuf,
-        args: impl IntoIterator<Item = impl AsRef<OsStr>>,
-    ) -> io::Result<Process> {
-        let args: Vec<OsString> = args.into_iter().map(|s| s.as_ref().into()).collect();
-        let child = mk_child(&path, &args)?;
-        Ok(Process { child })
+    /// Starts the server and agrees with it on an encoding. Servers that predate the handshake
+    /// exit when they can't parse it, so they are restarted and spoken to in JSON.
+    fn run(path: &Path, args: &[OsString], timeout: Option<Duration>) -> io::Result<Process> {
+        let mut process = Process::spawn(path, args)?;
+        match process.handshake(timeout) {
+            Ok(()) => return Ok(process),
+            Err(err) => log::info!("proc macro server handshake failed, using JSON: {}", err),
+        }
+        drop(process);
+        Process::spawn(path, args)
+    }
+
+    fn spawn(path: &Path, args: &[OsString]) -> io::Result<Process> {
+        let mut child = mk_child(path, args)?;
+        let (stdin, stdout) = match (child.stdin.take(), child.stdout.take()) {
+            (Some(stdin), Some(stdout)) => (stdin, stdout),
+            _ => {
+                let _ = child.kill();
+                return Err(io::Error::new(
+                    io::ErrorKind::BrokenPipe,
+                    "couldn't access child stdio",
+                ));
+            }
+        };
+
+        let (tx, responses) = unbounded();
+        thread::Builder::new()
+            .name("ProcMacroServerReader".to_owned())
+            .spawn(move || read_responses(BufReader::new(stdout), tx))?;
+        Ok(Process { child, stdin, encoding: Encoding::Json, responses })
     }
 
-    fn stdio(&mut self) -> Option<(impl Write, impl BufRead)> {
-        let stdin = self.child.stdin.take()?;
-        let stdout = self.child.stdout.take()?;
-        let read = BufReader::new(stdout);
+    fn handshake(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        // A server that is alive but doesn't understand the request answers with an error, and
+        // keeps talking JSON.
+        if let Response::Handshake(res) =
+            self.send(Request::Handshake(HandshakeTask::default()), timeout)?
+        {
+            self.encoding = res.encoding();
+        }
+        Ok(())
+    }
 
-        Some((stdin, read))
+    fn send(&mut self, req: Request, timeout: Option<Duration>) -> io::Result<Response> {
+        req.write(&mut self.stdin, self.encoding)?;
+        let res = match timeout {
+            Some(timeout) => self.responses.recv_timeout(timeout).map_err(|err| match err {
+                RecvTimeoutError::Timeout => io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("no response after {} seconds", timeout.as_secs_f32()),
+                ),
+                RecvTimeoutError::Disconnected => server_exited(),
+            })?,
+            None => self.responses.recv().map_err(|_| server_exited())?,
+        };
+        res?.ok_or_else(server_exited)
+    }
+}
+
+fn server_exited() -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, "server exited")
+}
+
+fn read_responses(mut stdout: impl BufRead, tx: Sender<io::Result<Option<Response>>>) {
+    let mut encoding = Encoding::Json;
+    loop {
+        let res = Response::read(&mut stdout, encoding);
+        match &res {
+            // Everything after the handshake uses the negotiated encoding.
+            Ok(Some(Response::Handshake(it))) => encoding = it.encoding(),
+            Ok(Some(_)) => (),
+            Ok(None) | Err(_) => {
+                let _ = tx.send(res);
+                return;
+            }
+        }
+        if tx.send(res).is_err() {
+            return;
+        }
     }
 }
 
@@ -153,11 +363,132 @@ fn mk_child(path: &Path, args: impl IntoIterator<Item = impl AsRef<OsStr>>) -> i
         .spawn()
 }
 
-fn send_request(
-    mut writer: &mut impl Write,
-    mut reader: &mut impl BufRead,
-    req: Request,
-) -> io::Result<Option<Response>> {
-    req.write(&mut writer)?;
-    Ok(Response::read(&mut reader)?)
+#[cfg(all(test, unix))]
+mod tests {
+    use crossbeam_channel::Receiver;
+
+    use super::*;
+    use crate::rpc::{ExpansionResult, ExpansionTask};
+
+    /// Starts a fake server, a shell script that answers every request with an empty macro list
+    /// and runs `on_expand` on expansion requests.
+    fn fake_server(
+        on_expand: &str,
+        timeout: Duration,
+    ) -> (ProcMacroProcessThread, ProcMacroProcessSrv, Receiver<ProcMacroServerEvent>) {
+        let script = format!(
+            r#"
+while read -r line; do
+    case "$line" in
+        *Handshake*) echo '{{"Handshake":{{"binary_version":null}}}}' ;;
+        *ExpansionMacro*) {} ;;
+        *) echo '{{"ListMacro":{{"macros":[]}}}}' ;;
+    esac
+done
+"#,
+            on_expand
+        );
+        let (event_tx, event_rx) = unbounded();
+        let (thread, srv) = ProcMacroProcessSrv::run(
+            "sh".into(),
+            &["-c".to_string(), script],
+            Some(timeout),
+            Box::new(move |event| {
+                let _ = event_tx.send(event);
+            }),
+        )
+        .unwrap();
+        (thread, srv, event_rx)
+    }
+
+    fn expand(srv: &ProcMacroProcessSrv, lib: &Path) -> Result<ExpansionResult, String> {
+        let task = ExpansionTask {
+            macro_body: Default::default(),
+            macro_name: "crash".into(),
+            attributes: None,
+            lib: lib.to_path_buf(),
+            env: Vec::new(),
+        };
+        srv.send_task(Request::ExpansionMacro(task)).map_err(|err| err.to_string())
+    }
+
+    fn next_event(events: &Receiver<ProcMacroServerEvent>) -> ProcMacroServerEvent {
+        events.recv_timeout(Duration::from_secs(10)).unwrap()
+    }
+
+    fn fake_dylib(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("ra-{}-{}", std::process::id(), name));
+        fs::write(&path, "v1").unwrap();
+        path
+    }
+
+    fn rebuild(path: &Path) {
+        let before = modified(path);
+        let mut version = 2;
+        while modified(path) == before {
+            thread::sleep(Duration::from_millis(10));
+            fs::write(path, format!("v{}", version)).unwrap();
+            version += 1;
+        }
+    }
+
+    #[test]
+    fn crashing_macro_is_disabled_until_rebuilt() {
+        let (_thread, srv, events) = fake_server("exit 1", Duration::from_secs(10));
+        let lib = fake_dylib("crashing_macro_is_disabled_until_rebuilt");
+
+        for _ in 0..MAX_CRASHES {
+            let err = expand(&srv, &lib).unwrap_err();
+            assert!(err.contains("proc macro `crash` crashed the server"), "{}", err);
+            assert!(matches!(next_event(&events), ProcMacroServerEvent::Crashed { .. }));
+            if let ProcMacroServerEvent::MacroDisabled { name, .. } = next_event(&events) {
+                assert_eq!(name, "crash");
+                assert!(matches!(next_event(&events), ProcMacroServerEvent::Restarted));
+            }
+        }
+
+        let err = expand(&srv, &lib).unwrap_err();
+        assert!(err.contains("proc macro `crash` is disabled"), "{}", err);
+        assert!(events.try_recv().is_err());
+
+        rebuild(&lib);
+        let err = expand(&srv, &lib).unwrap_err();
+        assert!(err.contains("proc macro `crash` crashed the server"), "{}", err);
+        assert!(matches!(next_event(&events), ProcMacroServerEvent::MacroEnabled { .. }));
+        assert!(matches!(next_event(&events), ProcMacroServerEvent::Crashed { .. }));
+
+        let _ = fs::remove_file(&lib);
+    }
+
+    #[test]
+    fn hanging_macro_times_out() {
+        let (_thread, srv, events) = fake_server("exec sleep 10", Duration::from_millis(100));
+        let lib = fake_dylib("hanging_macro_times_out");
+
+        let err = expand(&srv, &lib).unwrap_err();
+        assert!(err.contains("no response after"), "{}", err);
+        assert!(matches!(next_event(&events), ProcMacroServerEvent::Crashed { .. }));
+        assert!(matches!(next_event(&events), ProcMacroServerEvent::Restarted));
+        assert!(srv.find_proc_macros(&lib).unwrap().is_empty());
+
+        let _ = fs::remove_file(&lib);
+    }
+
+    #[test]
+    fn requests_fail_fast_while_restarting() {
+        let (_thread, srv, events) = fake_server("exit 1", Duration::from_secs(10));
+        let lib = fake_dylib("requests_fail_fast_while_restarting");
+
+        expand(&srv, &lib).unwrap_err();
+        assert!(matches!(next_event(&events), ProcMacroServerEvent::Crashed { .. }));
+
+        // The restart is delayed, but the request doesn't wait for it.
+        let err = srv.find_proc_macros(&lib).unwrap_err().to_string();
+        assert!(err.contains("restarting"), "{}", err);
+
+        assert!(matches!(next_event(&events), ProcMacroServerEvent::Restarted));
+        assert!(srv.find_proc_macros(&lib).unwrap().is_empty());
+
+        let _ = fs::remove_file(&lib);
+    }
 }
@@ -10,8 +10,8 @@ use std::path::PathBuf;
 
 use serde::{Deserialize, Serialize};
 use tt::{
-    Delimiter, DelimiterKind, Ident, Leaf, Literal, Punct, SmolStr, Spacing, Subtree, TokenId,
-    TokenTree,
+    Delimiter, DelimiterKind, Diagnostic, DiagnosticLevel, Ident, Leaf, Literal, Punct, SmolStr,
+    Spacing, Subtree, TokenId, TokenTree,
 };
 
 #[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
@@ -60,6 +60,18 @@ pub struct ExpansionTask {
 pub struct ExpansionResult {
     #[serde(with = "SubtreeDef")]
     pub expansion: Subtree,
+
+    /// Diagnostics emitted by the macro via `proc_macro::Diagnostic::emit`.
+    #[serde(default, with = "vec_diagnostic")]
+    pub diagnostics: Vec<Diagnostic>,
+
+    /// Environment variables read via `proc_macro::tracked_env::var`, with the values read.
+    #[serde(default)]
+    pub tracked_env: Vec<(String, Option<String>)>,
+
+    /// Files read by the macro, registered via `proc_macro::tracked_path::path`.
+    #[serde(default)]
+    pub tracked_paths: Vec<PathBuf>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -145,6 +157,27 @@ struct IdentDef {
     id: TokenId,
 }
 
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "Diagnostic")]
+struct DiagnosticDef {
+    #[serde(with = "DiagnosticLevelDef")]
+    level: DiagnosticLevel,
+    message: String,
+    #[serde(with = "vec_token_id")]
+    spans: Vec<TokenId>,
+    #[serde(with = "vec_diagnostic")]
+    children: Vec<Diagnostic>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "DiagnosticLevel")]
+enum DiagnosticLevelDef {
+    Error,
+    Warning,
+    Note,
+    Help,
+}
+
 mod opt_delimiter_def {
     use super::{Delimiter, DelimiterDef};
     use serde::{Deserialize, Deserializer, Serialize, Serializer};
@@ -224,6 +257,54 @@ mod vec_token_tree {
     }
 }
 
+mod vec_token_id {
+    use super::{TokenId, TokenIdDef};
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub(super) fn serialize<S>(value: &Vec<TokenId>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        #[derive(Serialize)]
+        struct Helper<'a>(#[serde(with = "TokenIdDef")] &'a TokenId);
+        serializer.collect_seq(value.iter().map(Helper))
+    }
+
+    pub(super) fn deserialize<'de, D>(deserializer: D) -> Result<Vec<TokenId>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        struct Helper(#[serde(with = "TokenIdDef")] TokenId);
+        let helper = Vec::deserialize(deserializer)?;
+        Ok(helper.into_iter().map(|Helper(external)| external).collect())
+    }
+}
+
+mod vec_diagnostic {
+    use super::{Diagnostic, DiagnosticDef};
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub(super) fn serialize<S>(value: &Vec<Diagnostic>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        #[derive(Serialize)]
+        struct Helper<'a>(#[serde(with = "DiagnosticDef")] &'a Diagnostic);
+        serializer.collect_seq(value.iter().map(Helper))
+    }
+
+    pub(super) fn deserialize<'de, D>(deserializer: D) -> Result<Vec<Diagnostic>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        struct Helper(#[serde(with = "DiagnosticDef")] Diagnostic);
+        let helper = Vec::deserialize(deserializer)?;
+        Ok(helper.into_iter().map(|Helper(external)| external).collect())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -262,10 +343,43 @@ mod tests {
 
         assert_eq!(task.macro_body, back.macro_body);
 
-        let result = ExpansionResult { expansion: tt.clone() };
+        let result = ExpansionResult {
+            expansion: tt.clone(),
+            diagnostics: Vec::new(),
+            tracked_env: vec![("DATABASE_URL".into(), None)],
+            tracked_paths: vec!["queries/user.sql".into()],
+        };
         let json = serde_json::to_string(&result).unwrap();
         let back: ExpansionResult = serde_json::from_str(&json).unwrap();
 
         assert_eq!(result, back);
     }
+
+    #[test]
+    fn test_proc_macro_rpc_diagnostics() {
+        let note = Diagnostic {
+            level: DiagnosticLevel::Note,
+            message: "declared here".into(),
+            spans: vec![TokenId(2)],
+            children: Vec::new(),
+        };
+        let result = ExpansionResult {
+            expansion: fixture_token_tree(),
+            diagnostics: vec![Diagnostic {
+                level: DiagnosticLevel::Error,
+                message: "unsupported field".into(),
+                spans: vec![TokenId(0), TokenId(1)],
+                children: vec![note],
+            }],
+            ..ExpansionResult::default()
+        };
+        let json = serde_json::to_string(&result).unwrap();
+        let back: ExpansionResult = serde_json::from_str(&json).unwrap();
+        assert_eq!(result, back);
+
+        // Servers that predate diagnostics do not send the fields at all.
+        let back: ExpansionResult =
+            serde_json::from_str(r#"{"expansion":{"token_trees":[]}}"#).unwrap();
+        assert_eq!(back, ExpansionResult::default());
+    }
 }
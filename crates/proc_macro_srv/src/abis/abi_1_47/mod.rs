@@ -0,0 +1,114 @@
+//! The `proc_macro` bridge of rustc 1.47.
+
+#[allow(dead_code)]
+#[doc(hidden)]
+mod proc_macro;
+
+#[doc(hidden)]
+pub(crate) mod rustc_server;
+
+use libloading::Library;
+use proc_macro_api::ProcMacroKind;
+
+use super::{ExpansionEffects, PanicMessage};
+use proc_macro::bridge;
+use rustc_server::TokenStream;
+
+impl From<bridge::PanicMessage> for PanicMessage {
+    fn from(msg: bridge::PanicMessage) -> PanicMessage {
+        PanicMessage { message: msg.as_str().map(|it| it.to_string()) }
+    }
+}
+
+pub(crate) struct Abi {
+    exported_macros: Vec<bridge::client::ProcMacro>,
+}
+
+impl Abi {
+    /// Reads the macros exported by `lib` under `symbol_name`.
+    ///
+    /// # Safety
+    ///
+    /// `lib` must have been built against this version of the bridge.
+    pub(crate) unsafe fn from_lib(
+        lib: &Library,
+        symbol_name: &str,
+    ) -> Result<Abi, libloading::Error> {
+        let macros: libloading::Symbol<&&[bridge::client::ProcMacro]> =
+            lib.get(symbol_name.as_bytes())?;
+        Ok(Abi { exported_macros: macros.to_vec() })
+    }
+
+    pub(crate) fn expand(
+        &self,
+        macro_name: &str,
+        macro_body: &tt::Subtree,
+        attributes: Option<&tt::Subtree>,
+    ) -> Result<(tt::Subtree, ExpansionEffects), PanicMessage> {
+        let parsed_body = TokenStream::with_subtree(macro_body.clone());
+        let inputs: Vec<&tt::Subtree> = attributes.into_iter().chain(Some(macro_body)).collect();
+
+        let parsed_attributes =
+            attributes.map_or(TokenStream::new(), |attr| TokenStream::with_subtree(attr.clone()));
+
+        for proc_macro in &self.exported_macros {
+            match proc_macro {
+                bridge::client::ProcMacro::CustomDerive { trait_name, client, .. }
+                    if *trait_name == macro_name =>
+                {
+                    return run(&inputs, |server| {
+                        client.run(&bridge::server::SameThread, server, parsed_body, false)
+                    });
+                }
+                bridge::client::ProcMacro::Bang { name, client } if *name == macro_name => {
+                    return run(&inputs, |server| {
+                        client.run(&bridge::server::SameThread, server, parsed_body, false)
+                    });
+                }
+                bridge::client::ProcMacro::Attr { name, client } if *name == macro_name => {
+                    return run(&inputs, |server| {
+                        client.run(
+                            &bridge::server::SameThread,
+                            server,
+                            parsed_attributes,
+                            parsed_body,
+                            false,
+                        )
+                    });
+                }
+                _ => continue,
+            }
+        }
+
+        Err(bridge::PanicMessage::String("Nothing to expand".to_string()).into())
+    }
+
+    pub(crate) fn list_macros(&self) -> Vec<(String, ProcMacroKind)> {
+        self.exported_macros
+            .iter()
+            .map(|proc_macro| match proc_macro {
+                bridge::client::ProcMacro::CustomDerive { trait_name, .. } => {
+                    (trait_name.to_string(), ProcMacroKind::CustomDerive)
+                }
+                bridge::client::ProcMacro::Bang { name, .. } => {
+                    (name.to_string(), ProcMacroKind::FuncLike)
+                }
+                bridge::client::ProcMacro::Attr { name, .. } => {
+                    (name.to_string(), ProcMacroKind::Attr)
+                }
+            })
+            .collect()
+    }
+}
+
+/// Runs a macro with a server for `inputs`, and collects what it reported besides its output.
+fn run(
+    inputs: &[&tt::Subtree],
+    f: impl FnOnce(rustc_server::Rustc) -> Result<TokenStream, bridge::PanicMessage>,
+) -> Result<(tt::Subtree, ExpansionEffects), PanicMessage> {
+    let server = rustc_server::Rustc::new(inputs);
+    let effects = server.effects();
+    let res = f(server);
+    res.map(|it| (it.subtree, effects.replace(ExpansionEffects::default())))
+        .map_err(PanicMessage::from)
+}
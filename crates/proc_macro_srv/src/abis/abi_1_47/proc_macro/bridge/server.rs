@@ -302,7 +302,7 @@ fn run_server<
     Result::decode(&mut &b[..], &mut dispatcher.handle_store)
 }
 
-impl client::Client<fn(crate::TokenStream) -> crate::TokenStream> {
+impl client::Client<fn(super::client::TokenStream) -> super::client::TokenStream> {
     pub fn run<S: Server>(
         &self,
         strategy: &impl ExecutionStrategy,
@@ -324,7 +324,11 @@ impl client::Client<fn(crate::TokenStream) -> crate::TokenStream> {
     }
 }
 
-impl client::Client<fn(crate::TokenStream, crate::TokenStream) -> crate::TokenStream> {
+impl
+    client::Client<
+        fn(super::client::TokenStream, super::client::TokenStream) -> super::client::TokenStream,
+    >
+{
     pub fn run<S: Server>(
         &self,
         strategy: &impl ExecutionStrategy,
@@ -6,13 +6,17 @@
 //! The original idea from fedochet is using proc-macro2 as backend,
 //! we use tt instead for better intergation with RA.
 //!
-//! FIXME: No span and source file information is implemented yet
+//! Spans are the token ids of the macro input, so that rust-analyzer can map the tokens produced
+//! by a proc macro back to the source. There is no source file or line information.
 
-use crate::proc_macro::bridge::{self, server};
+use super::proc_macro::bridge::{self, server};
+use crate::abis::ExpansionEffects;
 
+use std::cell::RefCell;
 use std::collections::{Bound, HashMap};
 use std::hash::Hash;
 use std::iter::FromIterator;
+use std::rc::Rc;
 use std::str::FromStr;
 use std::{ascii, vec::IntoIter};
 
@@ -21,7 +25,33 @@ type TokenTree = tt::TokenTree;
 type Punct = tt::Punct;
 type Spacing = tt::Spacing;
 type Literal = tt::Literal;
-type Span = tt::TokenId;
+
+/// A span of the macro input, from the token `lo` to the token `hi`.
+///
+/// Tokens only carry a single `tt::TokenId`, so a token created with a span gets `lo` as its id.
+/// Spans that don't point into the input, like `Span::call_site()`, use unspecified ids, and
+/// rust-analyzer maps their tokens to the whole macro call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Span {
+    lo: tt::TokenId,
+    hi: tt::TokenId,
+}
+
+impl Span {
+    fn call_site() -> Span {
+        Span::from(tt::TokenId::unspecified())
+    }
+
+    fn is_call_site(self) -> bool {
+        self.lo == tt::TokenId::unspecified()
+    }
+}
+
+impl From<tt::TokenId> for Span {
+    fn from(id: tt::TokenId) -> Span {
+        Span { lo: id, hi: id }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct TokenStream {
@@ -89,9 +119,9 @@ impl Extend<TokenStream> for TokenStream {
     }
 }
 
-type Level = crate::proc_macro::Level;
-type LineColumn = crate::proc_macro::LineColumn;
-type SourceFile = crate::proc_macro::SourceFile;
+type Level = super::proc_macro::Level;
+type LineColumn = super::proc_macro::LineColumn;
+type SourceFile = super::proc_macro::SourceFile;
 
 /// A structure representing a diagnostic message and associated children
 /// messages.
@@ -108,6 +138,23 @@ impl Diagnostic {
     pub fn new<T: Into<String>>(level: Level, message: T) -> Diagnostic {
         Diagnostic { level, message: message.into(), spans: vec![], children: vec![] }
     }
+
+    /// Converts to the form sent to rust-analyzer. Spans that don't point into the macro input
+    /// are dropped.
+    fn to_tt(&self) -> tt::Diagnostic {
+        let level = match self.level {
+            Level::Error => tt::DiagnosticLevel::Error,
+            Level::Warning => tt::DiagnosticLevel::Warning,
+            Level::Note => tt::DiagnosticLevel::Note,
+            Level::Help => tt::DiagnosticLevel::Help,
+        };
+        tt::Diagnostic {
+            level,
+            message: self.message.clone(),
+            spans: self.spans.iter().filter(|it| !it.is_call_site()).map(|it| it.lo).collect(),
+            children: self.children.iter().map(Diagnostic::to_tt).collect(),
+        }
+    }
 }
 
 // Rustc Server Ident has to be `Copyable`
@@ -249,10 +296,92 @@ pub struct TokenStreamIter {
     trees: IntoIter<TokenTree>,
 }
 
+/// A token of the macro input, used to get the source text of spans.
+struct SourceToken {
+    id: tt::TokenId,
+    text: tt::SmolStr,
+    /// Whether the next token follows without whitespace.
+    glued: bool,
+}
+
 #[derive(Default)]
 pub struct Rustc {
     ident_interner: IdentInterner,
-    // FIXME: store span information here.
+    /// The tokens of the macro input, in order.
+    source: Vec<SourceToken>,
+    /// Shared, because running the macro consumes the server.
+    effects: Rc<RefCell<ExpansionEffects>>,
+}
+
+impl Rustc {
+    /// Creates a server for a proc macro called with `inputs`.
+    pub fn new(inputs: &[&tt::Subtree]) -> Rustc {
+        let mut source = Vec::new();
+        for input in inputs {
+            collect_source_tokens(input, &mut source);
+        }
+        Rustc { source, ..Rustc::default() }
+    }
+
+    /// Returns a handle to the effects of the macro this server runs.
+    pub fn effects(&self) -> Rc<RefCell<ExpansionEffects>> {
+        self.effects.clone()
+    }
+}
+
+fn collect_source_tokens(subtree: &tt::Subtree, acc: &mut Vec<SourceToken>) {
+    let (open, close) = match subtree.delimiter_kind() {
+        None => ("", ""),
+        Some(tt::DelimiterKind::Brace) => ("{", "}"),
+        Some(tt::DelimiterKind::Parenthesis) => ("(", ")"),
+        Some(tt::DelimiterKind::Bracket) => ("[", "]"),
+    };
+    if let Some(delimiter) = subtree.delimiter {
+        acc.push(SourceToken { id: delimiter.id, text: open.into(), glued: true });
+    }
+    for tree in &subtree.token_trees {
+        let token = match tree {
+            tt::TokenTree::Subtree(subtree) => {
+                collect_source_tokens(subtree, acc);
+                continue;
+            }
+            tt::TokenTree::Leaf(tt::Leaf::Ident(ident)) => {
+                SourceToken { id: ident.id, text: ident.text.clone(), glued: false }
+            }
+            tt::TokenTree::Leaf(tt::Leaf::Literal(literal)) => {
+                SourceToken { id: literal.id, text: literal.text.clone(), glued: false }
+            }
+            tt::TokenTree::Leaf(tt::Leaf::Punct(punct)) => SourceToken {
+                id: punct.id,
+                text: punct.char.to_string().into(),
+                glued: punct.spacing == tt::Spacing::Joint,
+            },
+        };
+        acc.push(token);
+    }
+    if let Some(delimiter) = subtree.delimiter {
+        if let Some(last) = acc.last_mut() {
+            last.glued = true;
+        }
+        acc.push(SourceToken { id: delimiter.id, text: close.into(), glued: false });
+    }
+}
+
+/// Tokens parsed from a string by a proc macro don't come from the input.
+fn clear_ids(subtree: &mut tt::Subtree) {
+    if let Some(delimiter) = &mut subtree.delimiter {
+        delimiter.id = tt::TokenId::unspecified();
+    }
+    for tree in &mut subtree.token_trees {
+        match tree {
+            tt::TokenTree::Subtree(subtree) => clear_ids(subtree),
+            tt::TokenTree::Leaf(tt::Leaf::Ident(ident)) => ident.id = tt::TokenId::unspecified(),
+            tt::TokenTree::Leaf(tt::Leaf::Literal(literal)) => {
+                literal.id = tt::TokenId::unspecified()
+            }
+            tt::TokenTree::Leaf(tt::Leaf::Punct(punct)) => punct.id = tt::TokenId::unspecified(),
+        }
+    }
 }
 
 impl server::Types for Rustc {
@@ -271,9 +400,12 @@ impl server::Types for Rustc {
 }
 
 impl server::FreeFunctions for Rustc {
-    fn track_env_var(&mut self, _var: &str, _value: Option<&str>) {
-        // FIXME: track env var accesses
-        // https://github.com/rust-lang/rust/pull/71858
+    fn track_env_var(&mut self, var: &str, value: Option<&str>) {
+        let tracked = (var.to_string(), value.map(str::to_string));
+        let tracked_env = &mut self.effects.borrow_mut().tracked_env;
+        if !tracked_env.contains(&tracked) {
+            tracked_env.push(tracked);
+        }
     }
 }
 
@@ -286,7 +418,9 @@ impl server::TokenStream for Rustc {
         stream.is_empty()
     }
     fn from_str(&mut self, src: &str) -> Self::TokenStream {
-        Self::TokenStream::from_str(src).expect("cannot parse string")
+        let mut stream = Self::TokenStream::from_str(src).expect("cannot parse string");
+        clear_ids(&mut stream.subtree);
+        stream
     }
     fn to_string(&mut self, stream: &Self::TokenStream) -> String {
         stream.to_string()
@@ -409,22 +543,22 @@ impl server::Group for Rustc {
     }
 
     fn span(&mut self, group: &Self::Group) -> Self::Span {
-        group.delimiter.map(|it| it.id).unwrap_or_else(|| tt::TokenId::unspecified())
+        group.delimiter.map_or_else(Span::call_site, |it| Span::from(it.id))
     }
 
-    fn set_span(&mut self, _group: &mut Self::Group, _span: Self::Span) {
-        // FIXME handle span
+    fn set_span(&mut self, group: &mut Self::Group, span: Self::Span) {
+        if let Some(delimiter) = &mut group.delimiter {
+            delimiter.id = span.lo;
+        }
     }
 
-    fn span_open(&mut self, _group: &Self::Group) -> Self::Span {
-        // FIXME handle span
-        // MySpan(self.span_interner.intern(&MySpanData(group.span_open())))
-        tt::TokenId::unspecified()
+    // Both delimiters of a group share its id, the token map of the input tells them apart.
+    fn span_open(&mut self, group: &Self::Group) -> Self::Span {
+        self.span(group)
     }
 
-    fn span_close(&mut self, _group: &Self::Group) -> Self::Span {
-        // FIXME handle span
-        tt::TokenId::unspecified()
+    fn span_close(&mut self, group: &Self::Group) -> Self::Span {
+        self.span(group)
     }
 }
 
@@ -442,33 +576,27 @@ impl server::Punct for Rustc {
     fn spacing(&mut self, punct: Self::Punct) -> bridge::Spacing {
         spacing_to_external(punct.spacing)
     }
-    fn span(&mut self, _punct: Self::Punct) -> Self::Span {
-        // FIXME handle span
-        tt::TokenId::unspecified()
+    fn span(&mut self, punct: Self::Punct) -> Self::Span {
+        Span::from(punct.id)
     }
-    fn with_span(&mut self, punct: Self::Punct, _span: Self::Span) -> Self::Punct {
-        // FIXME handle span
-        punct
+    fn with_span(&mut self, punct: Self::Punct, span: Self::Span) -> Self::Punct {
+        tt::Punct { id: span.lo, ..punct }
     }
 }
 
 impl server::Ident for Rustc {
-    fn new(&mut self, string: &str, _span: Self::Span, _is_raw: bool) -> Self::Ident {
+    fn new(&mut self, string: &str, span: Self::Span, _is_raw: bool) -> Self::Ident {
         IdentId(
-            self.ident_interner.intern(&IdentData(tt::Ident {
-                text: string.into(),
-                id: tt::TokenId::unspecified(),
-            })),
+            self.ident_interner.intern(&IdentData(tt::Ident { text: string.into(), id: span.lo })),
         )
     }
 
-    fn span(&mut self, _ident: Self::Ident) -> Self::Span {
-        // FIXME handle span
-        tt::TokenId::unspecified()
+    fn span(&mut self, ident: Self::Ident) -> Self::Span {
+        Span::from(self.ident_interner.get(ident.0).0.id)
     }
-    fn with_span(&mut self, ident: Self::Ident, _span: Self::Span) -> Self::Ident {
-        // FIXME handle span
-        ident
+    fn with_span(&mut self, ident: Self::Ident, span: Self::Span) -> Self::Ident {
+        let IdentData(ident) = self.ident_interner.get(ident.0).clone();
+        IdentId(self.ident_interner.intern(&IdentData(tt::Ident { id: span.lo, ..ident })))
     }
 }
 
@@ -556,11 +684,11 @@ impl server::Literal for Rustc {
     }
 
     fn span(&mut self, literal: &Self::Literal) -> Self::Span {
-        literal.id
+        Span::from(literal.id)
     }
 
-    fn set_span(&mut self, _literal: &mut Self::Literal, _span: Self::Span) {
-        // FIXME handle span
+    fn set_span(&mut self, literal: &mut Self::Literal, span: Self::Span) {
+        literal.id = span.lo;
     }
 
     fn subspan(
@@ -569,7 +697,7 @@ impl server::Literal for Rustc {
         _start: Bound<usize>,
         _end: Bound<usize>,
     ) -> Option<Self::Span> {
-        // FIXME handle span
+        // Spans can't point into a token.
         None
     }
 }
@@ -597,34 +725,35 @@ impl server::Diagnostic for Rustc {
 
     fn sub(
         &mut self,
-        _diag: &mut Self::Diagnostic,
-        _level: Level,
-        _msg: &str,
-        _spans: Self::MultiSpan,
+        diag: &mut Self::Diagnostic,
+        level: Level,
+        msg: &str,
+        spans: Self::MultiSpan,
     ) {
-        // FIXME handle diagnostic
-        //
+        let mut child = Diagnostic::new(level, msg);
+        child.spans = spans;
+        diag.children.push(child);
     }
 
-    fn emit(&mut self, _diag: Self::Diagnostic) {
-        // FIXME handle diagnostic
-        // diag.emit()
+    fn emit(&mut self, diag: Self::Diagnostic) {
+        self.effects.borrow_mut().diagnostics.push(diag.to_tt());
     }
 }
 
 impl server::Span for Rustc {
     fn debug(&mut self, span: Self::Span) -> String {
-        format!("{:?}", span.0)
+        if span.lo == span.hi {
+            format!("{:?}", span.lo.0)
+        } else {
+            format!("{:?}..{:?}", span.lo.0, span.hi.0)
+        }
     }
+    // There is no hygiene for proc macros, so all three sites are the same.
     fn def_site(&mut self) -> Self::Span {
-        // MySpan(self.span_interner.intern(&MySpanData(Span::def_site())))
-        // FIXME handle span
-        tt::TokenId::unspecified()
+        Span::call_site()
     }
     fn call_site(&mut self) -> Self::Span {
-        // MySpan(self.span_interner.intern(&MySpanData(Span::call_site())))
-        // FIXME handle span
-        tt::TokenId::unspecified()
+        Span::call_site()
     }
     fn source_file(&mut self, _span: Self::Span) -> Self::SourceFile {
         // let MySpanData(span) = self.span_interner.get(span.0);
@@ -635,8 +764,23 @@ impl server::Span for Rustc {
     ///
     /// See PR:
     /// https://github.com/rust-lang/rust/pull/55780
-    fn source_text(&mut self, _span: Self::Span) -> Option<String> {
-        None
+    ///
+    /// The input has no whitespace information, so tokens are separated by single spaces.
+    fn source_text(&mut self, span: Self::Span) -> Option<String> {
+        if span.is_call_site() {
+            return None;
+        }
+        let start = self.source.iter().position(|it| it.id == span.lo)?;
+        let end = self.source.iter().rposition(|it| it.id == span.hi)?;
+        let tokens = self.source.get(start..=end)?;
+        let mut text = String::new();
+        for token in tokens {
+            text.push_str(&token.text);
+            if !token.glued {
+                text.push(' ');
+            }
+        }
+        Some(text.trim_end().to_string())
     }
 
     fn parent(&mut self, _span: Self::Span) -> Option<Self::Span> {
@@ -644,7 +788,6 @@ impl server::Span for Rustc {
         None
     }
     fn source(&mut self, span: Self::Span) -> Self::Span {
-        // FIXME handle span
         span
     }
     fn start(&mut self, _span: Self::Span) -> LineColumn {
@@ -655,17 +798,22 @@ impl server::Span for Rustc {
         // FIXME handle span
         LineColumn { line: 0, column: 0 }
     }
-    fn join(&mut self, _first: Self::Span, _second: Self::Span) -> Option<Self::Span> {
-        None
+    fn join(&mut self, first: Self::Span, second: Self::Span) -> Option<Self::Span> {
+        if first.is_call_site() || second.is_call_site() {
+            return None;
+        }
+        // Token ids are allocated in source order.
+        let lo = tt::TokenId(first.lo.0.min(second.lo.0));
+        let hi = tt::TokenId(first.hi.0.max(second.hi.0));
+        Some(Span { lo, hi })
     }
-    fn resolved_at(&mut self, _span: Self::Span, _at: Self::Span) -> Self::Span {
-        // FIXME handle span
-        tt::TokenId::unspecified()
+    fn resolved_at(&mut self, span: Self::Span, _at: Self::Span) -> Self::Span {
+        // There is no hygiene to take from `at`.
+        span
     }
 
     fn mixed_site(&mut self) -> Self::Span {
-        // FIXME handle span
-        tt::TokenId::unspecified()
+        Span::call_site()
     }
 }
 
@@ -683,12 +831,12 @@ impl server::MultiSpan for Rustc {
 
 #[cfg(test)]
 mod tests {
+    use super::server::Literal;
     use super::*;
-    use crate::proc_macro::bridge::server::Literal;
 
     #[test]
     fn test_rustc_server_literals() {
-        let mut srv = Rustc { ident_interner: IdentInterner::default() };
+        let mut srv = Rustc::default();
         assert_eq!(srv.integer("1234").text, "1234");
 
         assert_eq!(srv.typed_integer("12", "u8").text, "12u8");
@@ -712,6 +860,81 @@ mod tests {
         assert_eq!(srv.byte_string(b"1234586\x88").text, "b\"1234586\\x88\"");
     }
 
+    #[test]
+    fn test_rustc_server_spans() {
+        let (input, _) = mbe::parse_to_token_tree("struct Foo { a: u32 }").unwrap();
+        let mut srv = Rustc::new(&[&input]);
+        let foo = Span::from(tt::TokenId(1));
+        let brace = Span::from(tt::TokenId(2));
+
+        assert_eq!(server::Span::source_text(&mut srv, foo).as_deref(), Some("Foo"));
+        assert_eq!(server::Span::source_text(&mut srv, brace).as_deref(), Some("{a : u32}"));
+        let joined = server::Span::join(&mut srv, brace, foo).unwrap();
+        assert_eq!(server::Span::source_text(&mut srv, joined).as_deref(), Some("Foo {a : u32}"));
+
+        let call_site = server::Span::call_site(&mut srv);
+        assert_eq!(server::Span::join(&mut srv, foo, call_site), None);
+        assert_eq!(server::Span::source_text(&mut srv, call_site), None);
+
+        let ident = server::Ident::new(&mut srv, "Bar", joined, false);
+        assert_eq!(server::Ident::span(&mut srv, ident), foo);
+        let punct = server::Punct::new(&mut srv, ';', bridge::Spacing::Alone);
+        let punct = server::Punct::with_span(&mut srv, punct, brace);
+        assert_eq!(punct.id, tt::TokenId(2));
+
+        let parsed = server::TokenStream::from_str(&mut srv, "a;");
+        assert!(parsed.into_iter().all(|it| match it {
+            tt::TokenTree::Leaf(tt::Leaf::Ident(it)) => it.id == tt::TokenId::unspecified(),
+            tt::TokenTree::Leaf(tt::Leaf::Punct(it)) => it.id == tt::TokenId::unspecified(),
+            _ => false,
+        }));
+    }
+
+    #[test]
+    fn test_rustc_server_diagnostics() {
+        let mut srv = Rustc::default();
+        let effects = srv.effects();
+        let foo = Span::from(tt::TokenId(1));
+        let call_site = server::Span::call_site(&mut srv);
+
+        let mut diag = server::Diagnostic::new(&mut srv, Level::Error, "bad", vec![foo]);
+        server::Diagnostic::sub(&mut srv, &mut diag, Level::Help, "try this", vec![call_site]);
+        assert!(effects.borrow().diagnostics.is_empty());
+        server::Diagnostic::emit(&mut srv, diag);
+
+        assert_eq!(
+            effects.borrow().diagnostics,
+            vec![tt::Diagnostic {
+                level: tt::DiagnosticLevel::Error,
+                message: "bad".into(),
+                spans: vec![tt::TokenId(1)],
+                children: vec![tt::Diagnostic {
+                    level: tt::DiagnosticLevel::Help,
+                    message: "try this".into(),
+                    spans: vec![],
+                    children: vec![],
+                }],
+            }]
+        );
+    }
+
+    #[test]
+    fn test_rustc_server_tracked_env() {
+        let mut srv = Rustc::default();
+        let effects = srv.effects();
+        server::FreeFunctions::track_env_var(&mut srv, "DATABASE_URL", Some("sqlite::memory:"));
+        server::FreeFunctions::track_env_var(&mut srv, "UNSET", None);
+        server::FreeFunctions::track_env_var(&mut srv, "DATABASE_URL", Some("sqlite::memory:"));
+
+        assert_eq!(
+            effects.borrow().tracked_env,
+            vec![
+                ("DATABASE_URL".to_string(), Some("sqlite::memory:".to_string())),
+                ("UNSET".to_string(), None),
+            ]
+        );
+    }
+
     #[test]
     fn test_rustc_server_to_string() {
         let s = TokenStream {
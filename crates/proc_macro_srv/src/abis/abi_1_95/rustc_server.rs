@@ -0,0 +1,949 @@
+//! Rustc proc-macro server implementation with tt
+//!
+//! Based on idea from https://github.com/fedochet/rust-proc-macro-expander
+//! The lib-proc-macro server backend is `TokenStream`-agnostic, such that
+//! we could provide any TokenStream implementation.
+//! The original idea from fedochet is using proc-macro2 as backend,
+//! we use tt instead for better intergation with RA.
+//!
+//! Spans are the token ids of the macro input, so that rust-analyzer can map the tokens produced
+//! by a proc macro back to the source. There is no source file or line information.
+//!
+//! Unlike in 1.47, only token streams and spans are passed through handles, token trees are sent
+//! over as plain data with their strings interned as symbols.
+
+use super::proc_macro;
+use super::proc_macro::bridge::{self, server};
+use crate::abis::ExpansionEffects;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::iter::FromIterator;
+use std::ops::{Bound, Range};
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::str::FromStr;
+use std::vec::IntoIter;
+
+type TokenTree = tt::TokenTree;
+type Spacing = tt::Spacing;
+type Level = super::proc_macro::Level;
+
+/// A span of the macro input, from the token `lo` to the token `hi`.
+///
+/// Tokens only carry a single `tt::TokenId`, so a token created with a span gets `lo` as its id.
+/// Spans that don't point into the input, like `Span::call_site()`, use unspecified ids, and
+/// rust-analyzer maps their tokens to the whole macro call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Span {
+    lo: tt::TokenId,
+    hi: tt::TokenId,
+}
+
+impl Span {
+    fn call_site() -> Span {
+        Span::from(tt::TokenId::unspecified())
+    }
+
+    fn is_call_site(self) -> bool {
+        self.lo == tt::TokenId::unspecified()
+    }
+}
+
+impl From<tt::TokenId> for Span {
+    fn from(id: tt::TokenId) -> Span {
+        Span { lo: id, hi: id }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TokenStream {
+    pub subtree: tt::Subtree,
+}
+
+impl TokenStream {
+    pub fn new() -> Self {
+        TokenStream { subtree: Default::default() }
+    }
+
+    pub fn with_subtree(subtree: tt::Subtree) -> Self {
+        TokenStream { subtree }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.subtree.token_trees.is_empty()
+    }
+}
+
+/// Creates a token stream containing a single token tree.
+impl From<TokenTree> for TokenStream {
+    fn from(tree: TokenTree) -> TokenStream {
+        TokenStream { subtree: tt::Subtree { delimiter: None, token_trees: vec![tree] } }
+    }
+}
+
+/// Collects a number of token trees into a single stream.
+impl FromIterator<TokenTree> for TokenStream {
+    fn from_iter<I: IntoIterator<Item = TokenTree>>(trees: I) -> Self {
+        let mut res = TokenStream::new();
+        res.extend(trees);
+        res
+    }
+}
+
+impl Extend<TokenTree> for TokenStream {
+    fn extend<I: IntoIterator<Item = TokenTree>>(&mut self, trees: I) {
+        self.extend(trees.into_iter().map(TokenStream::from));
+    }
+}
+
+impl Extend<TokenStream> for TokenStream {
+    fn extend<I: IntoIterator<Item = TokenStream>>(&mut self, streams: I) {
+        for item in streams {
+            for tkn in item {
+                match tkn {
+                    tt::TokenTree::Subtree(subtree) if subtree.delimiter.is_none() => {
+                        self.subtree.token_trees.extend(subtree.token_trees);
+                    }
+                    _ => {
+                        self.subtree.token_trees.push(tkn);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl IntoIterator for TokenStream {
+    type Item = TokenTree;
+    type IntoIter = IntoIter<TokenTree>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.subtree.token_trees.into_iter()
+    }
+}
+
+impl FromStr for TokenStream {
+    type Err = String;
+
+    fn from_str(src: &str) -> Result<TokenStream, String> {
+        let (subtree, _token_map) =
+            mbe::parse_to_token_tree(src).ok_or("Failed to parse from mbe")?;
+
+        Ok(TokenStream { subtree })
+    }
+}
+
+impl ToString for TokenStream {
+    fn to_string(&self) -> String {
+        let tt = self.subtree.clone().into();
+        to_text(&tt)
+    }
+}
+
+fn to_text(tkn: &tt::TokenTree) -> String {
+    match tkn {
+        tt::TokenTree::Leaf(tt::Leaf::Ident(ident)) => ident.text.clone().into(),
+        tt::TokenTree::Leaf(tt::Leaf::Literal(literal)) => literal.text.clone().into(),
+        tt::TokenTree::Leaf(tt::Leaf::Punct(punct)) => format!("{}", punct.char),
+        tt::TokenTree::Subtree(subtree) => {
+            let content = subtree
+                .token_trees
+                .iter()
+                .fold((String::new(), true), |(last, last_to_joint), tkn| {
+                    let s = [last, to_text(tkn)].join(if last_to_joint { "" } else { " " });
+                    let mut is_joint = false;
+                    if let tt::TokenTree::Leaf(tt::Leaf::Punct(punct)) = tkn {
+                        if punct.spacing == tt::Spacing::Joint {
+                            is_joint = true;
+                        }
+                    }
+                    (s, is_joint)
+                })
+                .0;
+
+            let (open, close) = match subtree.delimiter.map(|it| it.kind) {
+                None => ("", ""),
+                Some(tt::DelimiterKind::Brace) => ("{", "}"),
+                Some(tt::DelimiterKind::Parenthesis) => ("(", ")"),
+                Some(tt::DelimiterKind::Bracket) => ("[", "]"),
+            };
+            format!("{}{}{}", open, content, close)
+        }
+    }
+}
+
+/// An interned string, like the name of an identifier or the contents of a literal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Symbol(u32);
+
+#[derive(Default)]
+struct SymbolInterner {
+    idents: HashMap<Box<str>, u32>,
+    strings: Vec<Box<str>>,
+}
+
+impl SymbolInterner {
+    fn intern(&mut self, data: &str) -> Symbol {
+        if let Some(index) = self.idents.get(data) {
+            return Symbol(*index);
+        }
+
+        let index = self.strings.len() as u32;
+        self.strings.push(data.into());
+        self.idents.insert(data.into(), index);
+        Symbol(index)
+    }
+
+    fn get(&self, sym: Symbol) -> &str {
+        &self.strings[sym.0 as usize]
+    }
+}
+
+thread_local! {
+    // The bridge interns symbols without a server at hand.
+    static SYMBOL_INTERNER: RefCell<SymbolInterner> = Default::default();
+}
+
+impl Symbol {
+    fn intern(data: &str) -> Symbol {
+        SYMBOL_INTERNER.with(|it| it.borrow_mut().intern(data))
+    }
+
+    fn text(self) -> String {
+        SYMBOL_INTERNER.with(|it| it.borrow().get(self).to_string())
+    }
+}
+
+/// A token of the macro input, used to get the source text of spans.
+struct SourceToken {
+    id: tt::TokenId,
+    text: tt::SmolStr,
+    /// Whether the next token follows without whitespace.
+    glued: bool,
+}
+
+#[derive(Default)]
+pub struct Rustc {
+    /// The tokens of the macro input, in order.
+    source: Vec<SourceToken>,
+    /// Spans saved by `Span::save_span`, which is used by rustc's incremental compilation.
+    saved_spans: Vec<Span>,
+    /// Shared, because running the macro consumes the server.
+    effects: Rc<RefCell<ExpansionEffects>>,
+}
+
+impl Rustc {
+    /// Creates a server for a proc macro called with `inputs`.
+    pub fn new(inputs: &[&tt::Subtree]) -> Rustc {
+        let mut source = Vec::new();
+        for input in inputs {
+            collect_source_tokens(input, &mut source);
+        }
+        Rustc { source, ..Rustc::default() }
+    }
+
+    /// Returns a handle to the effects of the macro this server runs.
+    pub fn effects(&self) -> Rc<RefCell<ExpansionEffects>> {
+        self.effects.clone()
+    }
+
+    fn token_tree_from_bridge(
+        &self,
+        tree: bridge::TokenTree<TokenStream, Span, Symbol>,
+    ) -> TokenTree {
+        match tree {
+            bridge::TokenTree::Group(group) => {
+                let delimiter = delim_to_internal(group.delimiter, group.span.entire);
+                let token_trees = group.stream.map_or_else(Vec::new, |it| it.subtree.token_trees);
+                tt::Subtree { delimiter, token_trees }.into()
+            }
+            bridge::TokenTree::Ident(ident) => {
+                let text = ident.sym.text();
+                let text = if ident.is_raw { format!("r#{}", text) } else { text };
+                tt::Leaf::from(tt::Ident { text: text.into(), id: ident.span.lo }).into()
+            }
+            bridge::TokenTree::Literal(literal) => {
+                let text = literal_text(
+                    literal.kind,
+                    &literal.symbol.text(),
+                    literal.suffix.map(Symbol::text).as_deref(),
+                );
+                tt::Leaf::from(tt::Literal { text: text.into(), id: literal.span.lo }).into()
+            }
+            bridge::TokenTree::Punct(punct) => {
+                let spacing = if punct.joint { Spacing::Joint } else { Spacing::Alone };
+                tt::Leaf::from(tt::Punct { char: punct.ch as char, spacing, id: punct.span.lo })
+                    .into()
+            }
+        }
+    }
+
+    fn token_tree_to_bridge(
+        &self,
+        tree: TokenTree,
+    ) -> bridge::TokenTree<TokenStream, Span, Symbol> {
+        match tree {
+            TokenTree::Subtree(subtree) => {
+                let span = subtree.delimiter.map_or_else(Span::call_site, |it| Span::from(it.id));
+                let stream = if subtree.token_trees.is_empty() {
+                    None
+                } else {
+                    Some(TokenStream {
+                        subtree: tt::Subtree { delimiter: None, token_trees: subtree.token_trees },
+                    })
+                };
+                bridge::TokenTree::Group(bridge::Group {
+                    delimiter: delim_to_external(subtree.delimiter),
+                    stream,
+                    span: bridge::DelimSpan::from_single(span),
+                })
+            }
+            TokenTree::Leaf(tt::Leaf::Ident(ident)) => {
+                let (text, is_raw) = match ident.text.strip_prefix("r#") {
+                    Some(text) => (text, true),
+                    None => (ident.text.as_str(), false),
+                };
+                bridge::TokenTree::Ident(bridge::Ident {
+                    sym: Symbol::intern(text),
+                    is_raw,
+                    span: Span::from(ident.id),
+                })
+            }
+            TokenTree::Leaf(tt::Leaf::Literal(literal)) => {
+                let (kind, symbol, suffix) = split_literal(&literal.text);
+                bridge::TokenTree::Literal(bridge::Literal {
+                    kind,
+                    symbol: Symbol::intern(symbol),
+                    suffix: suffix.map(Symbol::intern),
+                    span: Span::from(literal.id),
+                })
+            }
+            TokenTree::Leaf(tt::Leaf::Punct(punct)) => bridge::TokenTree::Punct(bridge::Punct {
+                ch: punct.char as u8,
+                joint: punct.spacing == Spacing::Joint,
+                span: Span::from(punct.id),
+            }),
+        }
+    }
+}
+
+fn collect_source_tokens(subtree: &tt::Subtree, acc: &mut Vec<SourceToken>) {
+    let (open, close) = match subtree.delimiter_kind() {
+        None => ("", ""),
+        Some(tt::DelimiterKind::Brace) => ("{", "}"),
+        Some(tt::DelimiterKind::Parenthesis) => ("(", ")"),
+        Some(tt::DelimiterKind::Bracket) => ("[", "]"),
+    };
+    if let Some(delimiter) = subtree.delimiter {
+        acc.push(SourceToken { id: delimiter.id, text: open.into(), glued: true });
+    }
+    for tree in &subtree.token_trees {
+        let token = match tree {
+            tt::TokenTree::Subtree(subtree) => {
+                collect_source_tokens(subtree, acc);
+                continue;
+            }
+            tt::TokenTree::Leaf(tt::Leaf::Ident(ident)) => {
+                SourceToken { id: ident.id, text: ident.text.clone(), glued: false }
+            }
+            tt::TokenTree::Leaf(tt::Leaf::Literal(literal)) => {
+                SourceToken { id: literal.id, text: literal.text.clone(), glued: false }
+            }
+            tt::TokenTree::Leaf(tt::Leaf::Punct(punct)) => SourceToken {
+                id: punct.id,
+                text: punct.char.to_string().into(),
+                glued: punct.spacing == tt::Spacing::Joint,
+            },
+        };
+        acc.push(token);
+    }
+    if let Some(delimiter) = subtree.delimiter {
+        if let Some(last) = acc.last_mut() {
+            last.glued = true;
+        }
+        acc.push(SourceToken { id: delimiter.id, text: close.into(), glued: false });
+    }
+}
+
+/// Tokens parsed from a string by a proc macro don't come from the input.
+fn clear_ids(subtree: &mut tt::Subtree) {
+    if let Some(delimiter) = &mut subtree.delimiter {
+        delimiter.id = tt::TokenId::unspecified();
+    }
+    for tree in &mut subtree.token_trees {
+        match tree {
+            tt::TokenTree::Subtree(subtree) => clear_ids(subtree),
+            tt::TokenTree::Leaf(tt::Leaf::Ident(ident)) => ident.id = tt::TokenId::unspecified(),
+            tt::TokenTree::Leaf(tt::Leaf::Literal(literal)) => {
+                literal.id = tt::TokenId::unspecified()
+            }
+            tt::TokenTree::Leaf(tt::Leaf::Punct(punct)) => punct.id = tt::TokenId::unspecified(),
+        }
+    }
+}
+
+fn delim_to_internal(d: proc_macro::Delimiter, span: Span) -> Option<tt::Delimiter> {
+    let kind = match d {
+        proc_macro::Delimiter::Parenthesis => tt::DelimiterKind::Parenthesis,
+        proc_macro::Delimiter::Brace => tt::DelimiterKind::Brace,
+        proc_macro::Delimiter::Bracket => tt::DelimiterKind::Bracket,
+        proc_macro::Delimiter::None => return None,
+    };
+    Some(tt::Delimiter { id: span.lo, kind })
+}
+
+fn delim_to_external(d: Option<tt::Delimiter>) -> proc_macro::Delimiter {
+    match d.map(|it| it.kind) {
+        Some(tt::DelimiterKind::Parenthesis) => proc_macro::Delimiter::Parenthesis,
+        Some(tt::DelimiterKind::Brace) => proc_macro::Delimiter::Brace,
+        Some(tt::DelimiterKind::Bracket) => proc_macro::Delimiter::Bracket,
+        None => proc_macro::Delimiter::None,
+    }
+}
+
+/// Splits the text of a literal into its kind, its symbol and its suffix. The symbol is the text
+/// without quotes and prefixes, so `br#"a"#u8` has the symbol `a` and the suffix `u8`.
+fn split_literal(text: &str) -> (bridge::LitKind, &str, Option<&str>) {
+    let (kind, rest) = if let Some(rest) = text.strip_prefix("b'") {
+        (bridge::LitKind::Byte, Some(("'", rest)))
+    } else if let Some(rest) = text.strip_prefix('\'') {
+        (bridge::LitKind::Char, Some(("'", rest)))
+    } else if let Some(rest) = text.strip_prefix("b\"") {
+        (bridge::LitKind::ByteStr, Some(("\"", rest)))
+    } else if let Some(rest) = text.strip_prefix("c\"") {
+        (bridge::LitKind::CStr, Some(("\"", rest)))
+    } else if let Some(rest) = text.strip_prefix('"') {
+        (bridge::LitKind::Str, Some(("\"", rest)))
+    } else if let Some((kind, rest)) = raw_string_prefix(text) {
+        let hashes = rest.len() - rest.trim_start_matches('#').len();
+        let rest = &rest[hashes..];
+        let rest = rest.strip_prefix('"').unwrap_or(rest);
+        let close = format!("\"{}", "#".repeat(hashes));
+        let kind = match kind {
+            bridge::LitKind::ByteStr => bridge::LitKind::ByteStrRaw(hashes as u8),
+            bridge::LitKind::CStr => bridge::LitKind::CStrRaw(hashes as u8),
+            _ => bridge::LitKind::StrRaw(hashes as u8),
+        };
+        return match rest.rfind(&close) {
+            Some(end) => (kind, &rest[..end], non_empty(&rest[end + close.len()..])),
+            None => (kind, rest, None),
+        };
+    } else {
+        (bridge::LitKind::Integer, None)
+    };
+
+    match rest {
+        Some((close, rest)) => match rest.rfind(close) {
+            Some(end) => (kind, &rest[..end], non_empty(&rest[end + 1..])),
+            None => (kind, rest, None),
+        },
+        None => {
+            let end = number_len(text);
+            let suffix = non_empty(&text[end..]);
+            let is_float = text[..end].contains(&['.', 'e', 'E'][..]) && !text.starts_with("0x")
+                || suffix.map_or(false, |it| it.starts_with('f'));
+            let kind = if is_float { bridge::LitKind::Float } else { bridge::LitKind::Integer };
+            (kind, &text[..end], suffix)
+        }
+    }
+}
+
+fn raw_string_prefix(text: &str) -> Option<(bridge::LitKind, &str)> {
+    let (kind, rest) = if let Some(rest) = text.strip_prefix("br") {
+        (bridge::LitKind::ByteStr, rest)
+    } else if let Some(rest) = text.strip_prefix("cr") {
+        (bridge::LitKind::CStr, rest)
+    } else {
+        (bridge::LitKind::Str, text.strip_prefix('r')?)
+    };
+    if rest.starts_with('#') || rest.starts_with('"') {
+        Some((kind, rest))
+    } else {
+        None
+    }
+}
+
+/// Returns the length of the number at the start of `text`, without its suffix.
+fn number_len(text: &str) -> usize {
+    let bytes = text.as_bytes();
+    let is_digit: fn(u8) -> bool = if text.starts_with("0x") {
+        |b| b.is_ascii_hexdigit() || b == b'_'
+    } else {
+        |b| b.is_ascii_digit() || b == b'_'
+    };
+    let mut len = if text.starts_with("0x") || text.starts_with("0o") || text.starts_with("0b") {
+        2
+    } else {
+        0
+    };
+    while len < bytes.len() && is_digit(bytes[len]) {
+        len += 1;
+    }
+    if text.starts_with("0x") || text.starts_with("0o") || text.starts_with("0b") {
+        return len;
+    }
+    // A fractional part, `1.` is a float but `1.foo` is a field access.
+    if bytes.get(len) == Some(&b'.')
+        && !bytes.get(len + 1).map_or(false, |&b| b == b'.' || b == b'_' || b.is_ascii_alphabetic())
+    {
+        len += 1;
+        while len < bytes.len() && (bytes[len].is_ascii_digit() || bytes[len] == b'_') {
+            len += 1;
+        }
+    }
+    // An exponent.
+    if matches!(bytes.get(len), Some(b'e') | Some(b'E')) {
+        let mut end = len + 1;
+        if matches!(bytes.get(end), Some(b'+') | Some(b'-')) {
+            end += 1;
+        }
+        if bytes.get(end).map_or(false, |b| b.is_ascii_digit() || *b == b'_') {
+            len = end;
+            while len < bytes.len() && (bytes[len].is_ascii_digit() || bytes[len] == b'_') {
+                len += 1;
+            }
+        }
+    }
+    len
+}
+
+fn non_empty(text: &str) -> Option<&str> {
+    if text.is_empty() {
+        None
+    } else {
+        Some(text)
+    }
+}
+
+/// The text of a literal, the reverse of [`split_literal`].
+fn literal_text(kind: bridge::LitKind, symbol: &str, suffix: Option<&str>) -> String {
+    let raw = |prefix: &str, hashes: u8| {
+        let hashes = "#".repeat(hashes as usize);
+        format!("{}{}\"{}\"{}", prefix, hashes, symbol, hashes)
+    };
+    let text = match kind {
+        bridge::LitKind::Byte => format!("b'{}'", symbol),
+        bridge::LitKind::Char => format!("'{}'", symbol),
+        bridge::LitKind::Integer | bridge::LitKind::Float | bridge::LitKind::ErrWithGuar => {
+            symbol.to_string()
+        }
+        bridge::LitKind::Str => format!("\"{}\"", symbol),
+        bridge::LitKind::StrRaw(n) => raw("r", n),
+        bridge::LitKind::ByteStr => format!("b\"{}\"", symbol),
+        bridge::LitKind::ByteStrRaw(n) => raw("br", n),
+        bridge::LitKind::CStr => format!("c\"{}\"", symbol),
+        bridge::LitKind::CStrRaw(n) => raw("cr", n),
+    };
+    text + suffix.unwrap_or("")
+}
+
+/// Converts to the form sent to rust-analyzer. Spans that don't point into the macro input are
+/// dropped.
+fn diagnostic_to_tt(diag: bridge::Diagnostic<Span>) -> tt::Diagnostic {
+    let level = match diag.level {
+        Level::Error => tt::DiagnosticLevel::Error,
+        Level::Warning => tt::DiagnosticLevel::Warning,
+        Level::Note => tt::DiagnosticLevel::Note,
+        Level::Help => tt::DiagnosticLevel::Help,
+    };
+    tt::Diagnostic {
+        level,
+        message: diag.message,
+        spans: diag.spans.iter().filter(|it| !it.is_call_site()).map(|it| it.lo).collect(),
+        children: diag.children.into_iter().map(diagnostic_to_tt).collect(),
+    }
+}
+
+impl server::Server for Rustc {
+    type TokenStream = TokenStream;
+    type Span = Span;
+    type Symbol = Symbol;
+
+    // There is no hygiene for proc macros, so all three sites are the same.
+    fn globals(&mut self) -> bridge::ExpnGlobals<Self::Span> {
+        bridge::ExpnGlobals {
+            def_site: Span::call_site(),
+            call_site: Span::call_site(),
+            mixed_site: Span::call_site(),
+        }
+    }
+
+    fn intern_symbol(ident: &str) -> Self::Symbol {
+        Symbol::intern(ident)
+    }
+
+    fn with_symbol_string(symbol: &Self::Symbol, f: impl FnOnce(&str)) {
+        f(&symbol.text())
+    }
+
+    fn injected_env_var(&mut self, _var: &str) -> Option<String> {
+        // The environment of the crate is set as the environment of this process.
+        None
+    }
+
+    fn track_env_var(&mut self, var: &str, value: Option<&str>) {
+        let tracked = (var.to_string(), value.map(str::to_string));
+        let tracked_env = &mut self.effects.borrow_mut().tracked_env;
+        if !tracked_env.contains(&tracked) {
+            tracked_env.push(tracked);
+        }
+    }
+
+    fn track_path(&mut self, path: &str) {
+        let path = PathBuf::from(path);
+        let tracked_paths = &mut self.effects.borrow_mut().tracked_paths;
+        if !tracked_paths.contains(&path) {
+            tracked_paths.push(path);
+        }
+    }
+
+    fn literal_from_str(
+        &mut self,
+        s: &str,
+    ) -> Result<bridge::Literal<Self::Span, Self::Symbol>, String> {
+        let stream = TokenStream::from_str(s)?;
+        let (negative, literal) = match stream.subtree.token_trees.as_slice() {
+            [tt::TokenTree::Leaf(tt::Leaf::Literal(literal))] => (false, literal),
+            [tt::TokenTree::Leaf(tt::Leaf::Punct(tt::Punct { char: '-', .. })), tt::TokenTree::Leaf(tt::Leaf::Literal(literal))] => {
+                (true, literal)
+            }
+            _ => return Err(format!("`{}` is not a literal", s)),
+        };
+        let (kind, symbol, suffix) = split_literal(&literal.text);
+        let symbol = if negative { format!("-{}", symbol) } else { symbol.to_string() };
+        Ok(bridge::Literal {
+            kind,
+            symbol: Symbol::intern(&symbol),
+            suffix: suffix.map(Symbol::intern),
+            span: Span::call_site(),
+        })
+    }
+
+    fn emit_diagnostic(&mut self, diagnostic: bridge::Diagnostic<Self::Span>) {
+        self.effects.borrow_mut().diagnostics.push(diagnostic_to_tt(diagnostic));
+    }
+
+    fn ts_drop(&mut self, stream: Self::TokenStream) {
+        drop(stream);
+    }
+
+    fn ts_clone(&mut self, stream: &Self::TokenStream) -> Self::TokenStream {
+        stream.clone()
+    }
+
+    fn ts_is_empty(&mut self, stream: &Self::TokenStream) -> bool {
+        stream.is_empty()
+    }
+
+    fn ts_expand_expr(&mut self, _stream: &Self::TokenStream) -> Result<Self::TokenStream, ()> {
+        // Expanding macros in the input needs name resolution.
+        Err(())
+    }
+
+    fn ts_from_str(&mut self, src: &str) -> Result<Self::TokenStream, String> {
+        let mut stream = TokenStream::from_str(src)?;
+        clear_ids(&mut stream.subtree);
+        Ok(stream)
+    }
+
+    fn ts_to_string(&mut self, stream: &Self::TokenStream) -> String {
+        stream.to_string()
+    }
+
+    fn ts_from_token_tree(
+        &mut self,
+        tree: bridge::TokenTree<Self::TokenStream, Self::Span, Self::Symbol>,
+    ) -> Self::TokenStream {
+        TokenStream::from(self.token_tree_from_bridge(tree))
+    }
+
+    fn ts_concat_trees(
+        &mut self,
+        base: Option<Self::TokenStream>,
+        trees: Vec<bridge::TokenTree<Self::TokenStream, Self::Span, Self::Symbol>>,
+    ) -> Self::TokenStream {
+        let mut stream = base.unwrap_or_default();
+        stream.extend(trees.into_iter().map(|tree| self.token_tree_from_bridge(tree)));
+        stream
+    }
+
+    fn ts_concat_streams(
+        &mut self,
+        base: Option<Self::TokenStream>,
+        streams: Vec<Self::TokenStream>,
+    ) -> Self::TokenStream {
+        let mut stream = base.unwrap_or_default();
+        stream.extend(streams);
+        stream
+    }
+
+    fn ts_into_trees(
+        &mut self,
+        stream: Self::TokenStream,
+    ) -> Vec<bridge::TokenTree<Self::TokenStream, Self::Span, Self::Symbol>> {
+        stream.into_iter().map(|tree| self.token_tree_to_bridge(tree)).collect()
+    }
+
+    fn span_debug(&mut self, span: Self::Span) -> String {
+        if span.lo == span.hi {
+            format!("{:?}", span.lo.0)
+        } else {
+            format!("{:?}..{:?}", span.lo.0, span.hi.0)
+        }
+    }
+
+    fn span_parent(&mut self, _span: Self::Span) -> Option<Self::Span> {
+        None
+    }
+
+    fn span_source(&mut self, span: Self::Span) -> Self::Span {
+        span
+    }
+
+    fn span_byte_range(&mut self, _span: Self::Span) -> Range<usize> {
+        // FIXME handle span
+        0..0
+    }
+
+    fn span_start(&mut self, span: Self::Span) -> Self::Span {
+        Span::from(span.lo)
+    }
+
+    fn span_end(&mut self, span: Self::Span) -> Self::Span {
+        Span::from(span.hi)
+    }
+
+    fn span_line(&mut self, _span: Self::Span) -> usize {
+        // FIXME handle span
+        1
+    }
+
+    fn span_column(&mut self, _span: Self::Span) -> usize {
+        // FIXME handle span
+        1
+    }
+
+    fn span_file(&mut self, _span: Self::Span) -> String {
+        // FIXME handle span
+        String::new()
+    }
+
+    fn span_local_file(&mut self, _span: Self::Span) -> Option<String> {
+        // FIXME handle span
+        None
+    }
+
+    fn span_join(&mut self, first: Self::Span, second: Self::Span) -> Option<Self::Span> {
+        if first.is_call_site() || second.is_call_site() {
+            return None;
+        }
+        // Token ids are allocated in source order.
+        let lo = tt::TokenId(first.lo.0.min(second.lo.0));
+        let hi = tt::TokenId(first.hi.0.max(second.hi.0));
+        Some(Span { lo, hi })
+    }
+
+    fn span_subspan(
+        &mut self,
+        _span: Self::Span,
+        _start: Bound<usize>,
+        _end: Bound<usize>,
+    ) -> Option<Self::Span> {
+        // Spans can't point into a token.
+        None
+    }
+
+    fn span_resolved_at(&mut self, span: Self::Span, _at: Self::Span) -> Self::Span {
+        // There is no hygiene to take from `at`.
+        span
+    }
+
+    /// The input has no whitespace information, so tokens are separated by single spaces.
+    fn span_source_text(&mut self, span: Self::Span) -> Option<String> {
+        if span.is_call_site() {
+            return None;
+        }
+        let start = self.source.iter().position(|it| it.id == span.lo)?;
+        let end = self.source.iter().rposition(|it| it.id == span.hi)?;
+        let tokens = self.source.get(start..=end)?;
+        let mut text = String::new();
+        for token in tokens {
+            text.push_str(&token.text);
+            if !token.glued {
+                text.push(' ');
+            }
+        }
+        Some(text.trim_end().to_string())
+    }
+
+    fn span_save_span(&mut self, span: Self::Span) -> usize {
+        self.saved_spans.push(span);
+        self.saved_spans.len() - 1
+    }
+
+    fn span_recover_proc_macro_span(&mut self, id: usize) -> Self::Span {
+        self.saved_spans.get(id).copied().unwrap_or_else(Span::call_site)
+    }
+
+    fn symbol_normalize_and_validate_ident(&mut self, string: &str) -> Result<Self::Symbol, ()> {
+        let mut chars = string.chars();
+        let first = chars.next().ok_or(())?;
+        if !(first.is_alphabetic() || first == '_')
+            || !chars.all(|c| c.is_alphanumeric() || c == '_')
+        {
+            return Err(());
+        }
+        Ok(Symbol::intern(string))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::server::Server;
+    use super::*;
+
+    fn check_literal(text: &str, kind: bridge::LitKind, symbol: &str, suffix: Option<&str>) {
+        assert_eq!(split_literal(text), (kind, symbol, suffix), "{}", text);
+        assert_eq!(literal_text(kind, symbol, suffix), text);
+    }
+
+    #[test]
+    fn test_rustc_server_literals() {
+        use bridge::LitKind::*;
+
+        check_literal("1234", Integer, "1234", None);
+        check_literal("12u8", Integer, "12", Some("u8"));
+        check_literal("0xffi32", Integer, "0xff", Some("i32"));
+        check_literal("0b1_0usize", Integer, "0b1_0", Some("usize"));
+        check_literal("1.5", Float, "1.5", None);
+        check_literal("1.", Float, "1.", None);
+        check_literal("1e-3f64", Float, "1e-3", Some("f64"));
+        check_literal("2f32", Float, "2", Some("f32"));
+        check_literal("'c'", Char, "c", None);
+        check_literal("b'\\n'", Byte, "\\n", None);
+        check_literal("\"a\\\"b\"", Str, "a\\\"b", None);
+        check_literal("\"sql\"suffix", Str, "sql", Some("suffix"));
+        check_literal("b\"bytes\"", ByteStr, "bytes", None);
+        check_literal("c\"c\"", CStr, "c", None);
+        check_literal("r\"raw\"", StrRaw(0), "raw", None);
+        check_literal("r##\"a\"#b\"##", StrRaw(2), "a\"#b", None);
+        check_literal("br#\"raw\"#", ByteStrRaw(1), "raw", None);
+        check_literal("cr\"raw\"", CStrRaw(0), "raw", None);
+
+        let mut srv = Rustc::default();
+        let literal = srv.literal_from_str("-1.5f32").unwrap();
+        assert_eq!(literal.kind, Float);
+        assert_eq!(literal.symbol.text(), "-1.5");
+        assert_eq!(literal.suffix.map(Symbol::text).as_deref(), Some("f32"));
+        assert!(srv.literal_from_str("a").is_err());
+    }
+
+    #[test]
+    fn test_rustc_server_token_trees() {
+        let mut srv = Rustc::default();
+        let (input, _) = mbe::parse_to_token_tree("struct r#Foo { a: [u8; 2] } 'x'").unwrap();
+        let trees = srv.ts_into_trees(TokenStream::with_subtree(input.clone()));
+        assert!(matches!(&trees[1], bridge::TokenTree::Ident(ident) if ident.is_raw));
+
+        let stream = srv.ts_concat_trees(None, trees);
+        assert_eq!(stream.subtree, input);
+        assert_eq!(srv.ts_to_string(&stream), "struct r#Foo {a : [u8 ; 2]} 'x'");
+    }
+
+    #[test]
+    fn test_rustc_server_spans() {
+        let (input, _) = mbe::parse_to_token_tree("struct Foo { a: u32 }").unwrap();
+        let mut srv = Rustc::new(&[&input]);
+        let foo = Span::from(tt::TokenId(1));
+        let brace = Span::from(tt::TokenId(2));
+
+        assert_eq!(srv.span_source_text(foo).as_deref(), Some("Foo"));
+        assert_eq!(srv.span_source_text(brace).as_deref(), Some("{a : u32}"));
+        let joined = srv.span_join(brace, foo).unwrap();
+        assert_eq!(srv.span_source_text(joined).as_deref(), Some("Foo {a : u32}"));
+
+        let call_site = srv.globals().call_site;
+        assert_eq!(srv.span_join(foo, call_site), None);
+        assert_eq!(srv.span_source_text(call_site), None);
+
+        let saved = srv.span_save_span(joined);
+        assert_eq!(srv.span_recover_proc_macro_span(saved), joined);
+
+        let parsed = srv.ts_from_str("a;").unwrap();
+        assert!(parsed.into_iter().all(|it| match it {
+            tt::TokenTree::Leaf(tt::Leaf::Ident(it)) => it.id == tt::TokenId::unspecified(),
+            tt::TokenTree::Leaf(tt::Leaf::Punct(it)) => it.id == tt::TokenId::unspecified(),
+            _ => false,
+        }));
+    }
+
+    #[test]
+    fn test_rustc_server_diagnostics() {
+        let mut srv = Rustc::default();
+        let effects = srv.effects();
+        let foo = Span::from(tt::TokenId(1));
+        let call_site = srv.globals().call_site;
+
+        srv.emit_diagnostic(bridge::Diagnostic {
+            level: Level::Error,
+            message: "bad".into(),
+            spans: vec![foo],
+            children: vec![bridge::Diagnostic {
+                level: Level::Help,
+                message: "try this".into(),
+                spans: vec![call_site],
+                children: vec![],
+            }],
+        });
+
+        assert_eq!(
+            effects.borrow().diagnostics,
+            vec![tt::Diagnostic {
+                level: tt::DiagnosticLevel::Error,
+                message: "bad".into(),
+                spans: vec![tt::TokenId(1)],
+                children: vec![tt::Diagnostic {
+                    level: tt::DiagnosticLevel::Help,
+                    message: "try this".into(),
+                    spans: vec![],
+                    children: vec![],
+                }],
+            }]
+        );
+    }
+
+    #[test]
+    fn test_rustc_server_tracked_env() {
+        let mut srv = Rustc::default();
+        let effects = srv.effects();
+        srv.track_env_var("DATABASE_URL", Some("sqlite::memory:"));
+        srv.track_env_var("UNSET", None);
+        srv.track_env_var("DATABASE_URL", Some("sqlite::memory:"));
+
+        assert_eq!(
+            effects.borrow().tracked_env,
+            vec![
+                ("DATABASE_URL".to_string(), Some("sqlite::memory:".to_string())),
+                ("UNSET".to_string(), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rustc_server_tracked_paths() {
+        let mut srv = Rustc::default();
+        let effects = srv.effects();
+        srv.track_path("/project/queries/user.sql");
+        srv.track_path("/project/queries/post.sql");
+        srv.track_path("/project/queries/user.sql");
+
+        assert_eq!(
+            effects.borrow().tracked_paths,
+            vec![
+                PathBuf::from("/project/queries/user.sql"),
+                PathBuf::from("/project/queries/post.sql"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,97 @@
+//! Proc macro ABI.
+//!
+//! The `proc_macro` bridge between rustc and proc macros is not stable, so a proc macro library
+//! can only be driven by a server built against the `proc_macro` of the rustc that compiled it.
+//! Each `abi_x_y` module vendors a copy of `proc_macro`, together with the `rustc_server`
+//! implementing it, which works with libraries built by rustc `x.y` and the later versions that
+//! kept the same bridge. Libraries built by other versions are rejected rather than risking a
+//! mismatched bridge.
+//!
+//! Since 1.98 the type of the macro declarations a library exports is defined by rustc itself
+//! rather than by `proc_macro`, so newer bridges need the sources of the compiler to vendor.
+//!
+//! To support another rustc version, copy `library/proc_macro` from its sources into a new
+//! module, port `rustc_server` to it and add a variant to [`Abi`].
+
+mod abi_1_47;
+mod abi_1_95;
+
+use std::{io, path::PathBuf};
+
+use libloading::Library;
+use proc_macro_api::ProcMacroKind;
+
+use crate::dylib::{invalid_data_err, RustcVersion};
+
+#[cfg(test)]
+pub(crate) use abi_1_47::rustc_server::TokenStream;
+
+/// What a macro reports through the bridge besides the tokens it produces.
+#[derive(Default, Debug)]
+pub struct ExpansionEffects {
+    pub diagnostics: Vec<tt::Diagnostic>,
+    /// Environment variables read with `proc_macro::tracked_env::var`, and the values read.
+    pub tracked_env: Vec<(String, Option<String>)>,
+    /// Files registered with `proc_macro::tracked_path::path`.
+    pub tracked_paths: Vec<PathBuf>,
+}
+
+/// The payload of a panic in a proc macro.
+#[derive(Debug)]
+pub struct PanicMessage {
+    message: Option<String>,
+}
+
+impl PanicMessage {
+    pub fn as_str(&self) -> Option<&str> {
+        self.message.as_deref()
+    }
+}
+
+pub(crate) enum Abi {
+    Abi1_47(abi_1_47::Abi),
+    Abi1_95(abi_1_95::Abi),
+}
+
+impl Abi {
+    /// Reads the macros exported by `lib` under `symbol_name`, using the bridge that matches the
+    /// rustc `version` the library was built with.
+    pub(crate) fn from_lib(
+        lib: &Library,
+        symbol_name: &str,
+        version: &RustcVersion,
+    ) -> io::Result<Abi> {
+        let abi = match (version.major, version.minor) {
+            // SAFETY: the bridge is the one used by this version of rustc.
+            (1, 47) => unsafe { abi_1_47::Abi::from_lib(lib, symbol_name) }.map(Abi::Abi1_47),
+            (1, 95..=97) => unsafe { abi_1_95::Abi::from_lib(lib, symbol_name) }.map(Abi::Abi1_95),
+            _ => {
+                return Err(invalid_data_err(format!(
+                    "unsupported rustc version: the library was built with rustc {}.{}, but only \
+                     libraries built with rustc 1.47 or 1.95 to 1.97 can be loaded",
+                    version.major, version.minor
+                )))
+            }
+        };
+        abi.map_err(invalid_data_err)
+    }
+
+    pub(crate) fn expand(
+        &self,
+        macro_name: &str,
+        macro_body: &tt::Subtree,
+        attributes: Option<&tt::Subtree>,
+    ) -> Result<(tt::Subtree, ExpansionEffects), PanicMessage> {
+        match self {
+            Abi::Abi1_47(abi) => abi.expand(macro_name, macro_body, attributes),
+            Abi::Abi1_95(abi) => abi.expand(macro_name, macro_body, attributes),
+        }
+    }
+
+    pub(crate) fn list_macros(&self) -> Vec<(String, ProcMacroKind)> {
+        match self {
+            Abi::Abi1_47(abi) => abi.list_macros(),
+            Abi::Abi1_95(abi) => abi.list_macros(),
+        }
+    }
+}
@@ -6,34 +6,42 @@ use std::io;
 
 pub fn run() -> io::Result<()> {
     let mut srv = ProcMacroSrv::default();
+    let mut encoding = msg::Encoding::Json;
 
-    while let Some(req) = read_request()? {
+    while let Some(req) = read_request(encoding)? {
+        // The response to a handshake is sent in the old encoding.
+        let mut next_encoding = encoding;
         let res = match req {
-            msg::Request::ListMacro(task) => srv.list_macros(&task).map(msg::Response::ListMacro),
+            msg::Request::ListMacro(task) => {
+                srv.list_macros(&task).map(msg::Response::ListMacro).map_err(|message| {
+                    msg::ResponseError { code: msg::ErrorCode::ExpansionError, message }
+                })
+            }
             msg::Request::ExpansionMacro(task) => {
                 srv.expand(&task).map(msg::Response::ExpansionMacro)
             }
+            msg::Request::Handshake(task) => {
+                let res = task.accept();
+                next_encoding = res.encoding();
+                Ok(msg::Response::Handshake(res))
+            }
         };
 
-        let msg = res.unwrap_or_else(|err| {
-            msg::Response::Error(msg::ResponseError {
-                code: msg::ErrorCode::ExpansionError,
-                message: err,
-            })
-        });
+        let msg = res.unwrap_or_else(msg::Response::Error);
 
-        if let Err(err) = write_response(msg) {
+        if let Err(err) = write_response(msg, encoding) {
             eprintln!("Write message error: {}", err);
         }
+        encoding = next_encoding;
     }
 
     Ok(())
 }
 
-fn read_request() -> io::Result<Option<msg::Request>> {
-    msg::Request::read(&mut io::stdin().lock())
+fn read_request(encoding: msg::Encoding) -> io::Result<Option<msg::Request>> {
+    msg::Request::read(&mut io::stdin().lock(), encoding)
 }
 
-fn write_response(msg: msg::Response) -> io::Result<()> {
-    msg.write(&mut io::stdout().lock())
+fn write_response(msg: msg::Response, encoding: msg::Encoding) -> io::Result<()> {
+    msg.write(&mut io::stdout().lock(), encoding)
 }
@@ -1,21 +1,26 @@
 //! Handles dynamic library loading for proc macro
 
 use std::{
+    convert::TryInto,
+    fmt,
     fs::File,
-    io,
+    io::{self, Read},
     path::{Path, PathBuf},
 };
 
 use libloading::Library;
 use memmap::Mmap;
-use object::Object;
+use object::{Object, ObjectSection};
 use proc_macro_api::ProcMacroKind;
+use snap::read::FrameDecoder;
 
-use crate::{proc_macro::bridge, rustc_server::TokenStream};
+use crate::abis::{Abi, ExpansionEffects, PanicMessage};
 
 const NEW_REGISTRAR_SYMBOL: &str = "_rustc_proc_macro_decls_";
 
-fn invalid_data_err(e: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+pub(crate) fn invalid_data_err(
+    e: impl Into<Box<dyn std::error::Error + Send + Sync>>,
+) -> io::Error {
     io::Error::new(io::ErrorKind::InvalidData, e)
 }
 
@@ -23,12 +28,8 @@ fn is_derive_registrar_symbol(symbol: &str) -> bool {
     symbol.contains(NEW_REGISTRAR_SYMBOL)
 }
 
-fn find_registrar_symbol(file: &Path) -> io::Result<Option<String>> {
-    let file = File::open(file)?;
-    let buffer = unsafe { Mmap::map(&file)? };
-
-    Ok(object::File::parse(&buffer)
-        .map_err(invalid_data_err)?
+fn find_registrar_symbol(file: &object::File) -> io::Result<Option<String>> {
+    Ok(file
         .exports()
         .map_err(invalid_data_err)?
         .into_iter()
@@ -74,26 +75,128 @@ fn load_library(file: &Path) -> Result<Library, libloading::Error> {
     UnixLibrary::open(Some(file), RTLD_NOW | RTLD_DEEPBIND).map(|lib| lib.into())
 }
 
+/// The version of rustc that built a library, e.g. `1.48.0` or `1.50.0-nightly`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RustcVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+    /// `nightly`, `beta.3` and so on, `None` for stable releases.
+    pub pre: Option<String>,
+}
+
+impl fmt::Display for RustcVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)?;
+        if let Some(pre) = &self.pre {
+            write!(f, "-{}", pre)?;
+        }
+        Ok(())
+    }
+}
+
+impl RustcVersion {
+    /// Parses the output of `rustc --version`, e.g. `rustc 1.50.0-nightly (1c389ffef 2020-11-24)`.
+    pub fn parse(text: &str) -> Option<RustcVersion> {
+        let version = text.strip_prefix("rustc ")?.split_whitespace().next()?;
+        let (numbers, pre) = match version.find('-') {
+            Some(idx) => (&version[..idx], Some(version[idx + 1..].to_string())),
+            None => (version, None),
+        };
+        let mut numbers = numbers.split('.').map(|it| it.parse::<u32>().ok());
+        let res = RustcVersion {
+            major: numbers.next()??,
+            minor: numbers.next()??,
+            patch: numbers.next()??,
+            pre,
+        };
+        if numbers.next().is_some() {
+            return None;
+        }
+        Some(res)
+    }
+}
+
+/// Reads the rustc version from the metadata of a proc macro library.
+pub(crate) fn read_rustc_version(file: &object::File) -> io::Result<RustcVersion> {
+    let section = file
+        .section_by_name(".rustc")
+        .ok_or_else(|| invalid_data_err("cannot find the .rustc section"))?;
+    let data = section.data().map_err(invalid_data_err)?;
+    read_metadata_version(data)
+}
+
+/// Reads the rustc version from the `.rustc` section of a proc macro library.
+///
+/// The section starts with `rust`, and the version of the metadata format as a big endian `u32`.
+/// Formats 7 and later follow that with the length of the metadata, which is compressed in the
+/// [snappy framing format] unless it starts with the same header again, as it does since format
+/// 10. After the header, the metadata holds the position of the crate root (four bytes before
+/// format 9, eight after) and the length-prefixed rustc version string.
+///
+/// [snappy framing format]: https://github.com/google/snappy/blob/master/framing_format.txt
+fn read_metadata_version(data: &[u8]) -> io::Result<RustcVersion> {
+    if data.len() < 8 || &data[..4] != b"rust" {
+        return Err(invalid_data_err("the .rustc section is not rustc metadata"));
+    }
+    let format = u32::from_be_bytes([data[4], data[5], data[6], data[7]]);
+    let truncated = || invalid_data_err("truncated metadata");
+    let metadata = match format {
+        5 | 6 => &data[8..],
+        7 | 8 => {
+            let len = data.get(8..12).ok_or_else(truncated)?;
+            let len = u32::from_be_bytes(len.try_into().unwrap()) as usize;
+            data.get(12..12 + len).ok_or_else(truncated)?
+        }
+        9 | 10 => {
+            let len = data.get(8..16).ok_or_else(truncated)?;
+            let len = u64::from_le_bytes(len.try_into().unwrap()) as usize;
+            data.get(16..16 + len).ok_or_else(truncated)?
+        }
+        _ => {
+            return Err(invalid_data_err(format!(
+                "the library was built with an unsupported version of rustc (metadata version {})",
+                format
+            )))
+        }
+    };
+
+    let mut metadata: Box<dyn Read> = if metadata.starts_with(b"rust") {
+        Box::new(metadata)
+    } else {
+        Box::new(FrameDecoder::new(metadata))
+    };
+    let root_position_len = if format < 9 { 4 } else { 8 };
+    let mut header = vec![0; 8 + root_position_len + 1];
+    metadata.read_exact(&mut header)?;
+    let mut version = vec![0; header[header.len() - 1] as usize];
+    metadata.read_exact(&mut version)?;
+    let version = String::from_utf8(version).map_err(invalid_data_err)?;
+    RustcVersion::parse(&version)
+        .ok_or_else(|| invalid_data_err(format!("cannot parse rustc version `{}`", version)))
+}
+
 struct ProcMacroLibraryLibloading {
     // Hold the dylib to prevent it from unloading
     _lib: Library,
-    exported_macros: Vec<bridge::client::ProcMacro>,
+    abi: Abi,
 }
 
 impl ProcMacroLibraryLibloading {
     fn open(file: &Path) -> io::Result<Self> {
-        let symbol_name = find_registrar_symbol(file)?.ok_or_else(|| {
+        let (symbol_name, version) = {
+            let file = File::open(file)?;
+            let buffer = unsafe { Mmap::map(&file)? };
+            let object = object::File::parse(&buffer).map_err(invalid_data_err)?;
+            (find_registrar_symbol(&object)?, read_rustc_version(&object)?)
+        };
+        let symbol_name = symbol_name.ok_or_else(|| {
             invalid_data_err(format!("Cannot find registrar symbol in file {}", file.display()))
         })?;
 
         let lib = load_library(file).map_err(invalid_data_err)?;
-        let exported_macros = {
-            let macros: libloading::Symbol<&&[bridge::client::ProcMacro]> =
-                unsafe { lib.get(symbol_name.as_bytes()) }.map_err(invalid_data_err)?;
-            macros.to_vec()
-        };
-
-        Ok(ProcMacroLibraryLibloading { _lib: lib, exported_macros })
+        let abi = Abi::from_lib(&lib, &symbol_name, &version)?;
+        Ok(ProcMacroLibraryLibloading { _lib: lib, abi })
     }
 }
 
@@ -119,69 +222,12 @@ impl Expander {
         macro_name: &str,
         macro_body: &tt::Subtree,
         attributes: Option<&tt::Subtree>,
-    ) -> Result<tt::Subtree, bridge::PanicMessage> {
-        let parsed_body = TokenStream::with_subtree(macro_body.clone());
-
-        let parsed_attributes = attributes
-            .map_or(crate::rustc_server::TokenStream::new(), |attr| {
-                TokenStream::with_subtree(attr.clone())
-            });
-
-        for proc_macro in &self.inner.exported_macros {
-            match proc_macro {
-                bridge::client::ProcMacro::CustomDerive { trait_name, client, .. }
-                    if *trait_name == macro_name =>
-                {
-                    let res = client.run(
-                        &crate::proc_macro::bridge::server::SameThread,
-                        crate::rustc_server::Rustc::default(),
-                        parsed_body,
-                        false,
-                    );
-                    return res.map(|it| it.subtree);
-                }
-                bridge::client::ProcMacro::Bang { name, client } if *name == macro_name => {
-                    let res = client.run(
-                        &crate::proc_macro::bridge::server::SameThread,
-                        crate::rustc_server::Rustc::default(),
-                        parsed_body,
-                        false,
-                    );
-                    return res.map(|it| it.subtree);
-                }
-                bridge::client::ProcMacro::Attr { name, client } if *name == macro_name => {
-                    let res = client.run(
-                        &crate::proc_macro::bridge::server::SameThread,
-                        crate::rustc_server::Rustc::default(),
-                        parsed_attributes,
-                        parsed_body,
-                        false,
-                    );
-                    return res.map(|it| it.subtree);
-                }
-                _ => continue,
-            }
-        }
-
-        Err(bridge::PanicMessage::String("Nothing to expand".to_string()))
+    ) -> Result<(tt::Subtree, ExpansionEffects), PanicMessage> {
+        self.inner.abi.expand(macro_name, macro_body, attributes)
     }
 
     pub fn list_macros(&self) -> Vec<(String, ProcMacroKind)> {
-        self.inner
-            .exported_macros
-            .iter()
-            .map(|proc_macro| match proc_macro {
-                bridge::client::ProcMacro::CustomDerive { trait_name, .. } => {
-                    (trait_name.to_string(), ProcMacroKind::CustomDerive)
-                }
-                bridge::client::ProcMacro::Bang { name, .. } => {
-                    (name.to_string(), ProcMacroKind::FuncLike)
-                }
-                bridge::client::ProcMacro::Attr { name, .. } => {
-                    (name.to_string(), ProcMacroKind::Attr)
-                }
-            })
-            .collect()
+        self.inner.abi.list_macros()
     }
 }
 
@@ -214,3 +260,62 @@ fn ensure_file_with_lock_free_access(path: &Path) -> io::Result<PathBuf> {
 fn ensure_file_with_lock_free_access(path: &Path) -> io::Result<PathBuf> {
     Ok(path.to_path_buf())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_rustc_version() {
+        let version = RustcVersion::parse("rustc 1.50.0-nightly (1c389ffef 2020-11-24)").unwrap();
+        assert_eq!(version.to_string(), "1.50.0-nightly");
+        assert_eq!((version.major, version.minor, version.patch), (1, 50, 0));
+
+        let version = RustcVersion::parse("rustc 1.49.0 (e1884a8e3 2020-12-29)").unwrap();
+        assert_eq!(version, RustcVersion { major: 1, minor: 49, patch: 0, pre: None });
+
+        assert_eq!(RustcVersion::parse("rustc 1.49"), None);
+        assert_eq!(RustcVersion::parse("cargo 1.49.0"), None);
+    }
+
+    fn metadata_section(format: u8, metadata: &[u8]) -> Vec<u8> {
+        let mut data = b"rust\0\0\0".to_vec();
+        data.push(format);
+        match format {
+            7 | 8 => data.extend_from_slice(&(metadata.len() as u32).to_be_bytes()),
+            9 | 10 => data.extend_from_slice(&(metadata.len() as u64).to_le_bytes()),
+            _ => (),
+        }
+        data.extend_from_slice(metadata);
+        data
+    }
+
+    #[test]
+    fn test_read_metadata_version() {
+        let version = b"rustc 1.47.0 (18bf6b4f0 2020-10-07)";
+        let mut metadata = b"rust\0\0\0\x05\0\0\0\0".to_vec();
+        metadata.push(version.len() as u8);
+        metadata.extend_from_slice(version);
+        metadata.extend_from_slice(&[0; 100]);
+        let mut compressed = Vec::new();
+        snap::write::FrameEncoder::new(&mut compressed).write_all(&metadata).unwrap();
+
+        let read = read_metadata_version(&metadata_section(5, &compressed)).unwrap();
+        assert_eq!(read, RustcVersion { major: 1, minor: 47, patch: 0, pre: None });
+        let read = read_metadata_version(&metadata_section(8, &compressed)).unwrap();
+        assert_eq!(read.minor, 47);
+
+        let version = b"rustc 1.96.0-nightly (4d91de4e4 2026-03-02)";
+        let mut metadata = b"rust\0\0\0\x0a\0\0\0\0\0\0\0\0".to_vec();
+        metadata.push(version.len() as u8);
+        metadata.extend_from_slice(version);
+        let read = read_metadata_version(&metadata_section(10, &metadata)).unwrap();
+        assert_eq!(read.to_string(), "1.96.0-nightly");
+
+        let err = read_metadata_version(&metadata_section(11, &metadata)).unwrap_err();
+        assert!(err.to_string().contains("metadata version 11"));
+        assert!(read_metadata_version(&metadata_section(10, &metadata)[..30]).is_err());
+    }
+}
@@ -8,20 +8,18 @@
 //! * We use `tt` for proc-macro `TokenStream` server, it is easier to manipulate and interact with
 //!   RA than `proc-macro2` token stream.
 //! * By **copying** the whole rustc `lib_proc_macro` code, we are able to build this with `stable`
-//!   rustc rather than `unstable`. (Although in general ABI compatibility is still an issue)…
+//!   rustc rather than `unstable`. The ABI of `lib_proc_macro` is not stable though, so we keep a
+//!   copy for each supported range of rustc versions, and pick one based on the rustc version
+//!   recorded in the proc macro library.
 #![allow(unreachable_pub)]
 
-#[allow(dead_code)]
-#[doc(hidden)]
-mod proc_macro;
-
-#[doc(hidden)]
-mod rustc_server;
-
+mod abis;
 mod dylib;
 
-use proc_macro::bridge::client::TokenStream;
-use proc_macro_api::{ExpansionResult, ExpansionTask, ListMacrosResult, ListMacrosTask};
+use proc_macro_api::{
+    msg::{ErrorCode, ResponseError},
+    ExpansionResult, ExpansionTask, ListMacrosResult, ListMacrosTask,
+};
 use std::{
     collections::{hash_map::Entry, HashMap},
     env, fs,
@@ -35,8 +33,10 @@ pub(crate) struct ProcMacroSrv {
 }
 
 impl ProcMacroSrv {
-    pub fn expand(&mut self, task: &ExpansionTask) -> Result<ExpansionResult, String> {
-        let expander = self.expander(&task.lib)?;
+    pub fn expand(&mut self, task: &ExpansionTask) -> Result<ExpansionResult, ResponseError> {
+        let expander = self
+            .expander(&task.lib)
+            .map_err(|message| ResponseError { code: ErrorCode::ExpansionError, message })?;
 
         let mut prev_env = HashMap::new();
         for (k, v) in &task.env {
@@ -54,10 +54,15 @@ impl ProcMacroSrv {
         }
 
         match result {
-            Ok(expansion) => Ok(ExpansionResult { expansion }),
+            Ok((expansion, effects)) => Ok(ExpansionResult {
+                expansion,
+                diagnostics: effects.diagnostics,
+                tracked_env: effects.tracked_env,
+                tracked_paths: effects.tracked_paths,
+            }),
             Err(msg) => {
-                let msg = msg.as_str().unwrap_or("<unknown error>");
-                Err(format!("proc-macro panicked: {}", msg))
+                let message = msg.as_str().unwrap_or("<unknown error>").to_string();
+                Err(ResponseError { code: ErrorCode::ExpansionPanic, message })
             }
         }
     }
@@ -74,7 +79,7 @@ impl ProcMacroSrv {
 
         Ok(match self.expanders.entry((path.to_path_buf(), time)) {
             Entry::Vacant(v) => v.insert(dylib::Expander::new(path).map_err(|err| {
-                format!("Cannot create expander for {}: {:?}", path.display(), err)
+                format!("Cannot create expander for {}: {}", path.display(), err)
             })?),
             Entry::Occupied(e) => e.into_mut(),
         })
@@ -2,6 +2,7 @@
 
 #[macro_use]
 mod utils;
+mod wire;
 use test_utils::assert_eq_text;
 use utils::*;
 
@@ -25,10 +26,10 @@ fn test_derive_serialize_proc_macro_failed() {
         r"struct {}",
         r##"
 SUBTREE $
-  IDENT   compile_error 4294967295
-  PUNCH   ! [alone] 4294967295
-  SUBTREE {} 4294967295
-    LITERAL "expected identifier" 4294967295
+  IDENT   compile_error 1
+  PUNCH   ! [alone] 1
+  SUBTREE {} 1
+    LITERAL "expected identifier" 1
 "##,
     );
 }
@@ -56,3 +57,13 @@ attribute_macro [Attr]
 DummyTrait [CustomDerive]"#
     );
 }
+
+/// Reads the rustc version from the metadata of a library built by the current toolchain.
+#[test]
+fn test_read_rustc_version() {
+    let rustc = std::process::Command::new(toolchain::rustc()).arg("--version").output().unwrap();
+    let rustc = String::from_utf8(rustc.stdout).unwrap();
+    let expected = crate::dylib::RustcVersion::parse(rustc.trim()).unwrap();
+
+    assert_eq!(read_version("proc_macro_test", "0.0.0"), expected);
+}
@@ -22,8 +22,13 @@ mod fixtures {
             match message.unwrap() {
                 Message::CompilerArtifact(artifact) => {
                     if artifact.target.kind.contains(&"proc-macro".to_string()) {
-                        let repr = format!("{} {}", crate_name, version);
-                        if artifact.package_id.repr.starts_with(&repr) {
+                        // `name version (source)`, or `source#name@version` and
+                        // `source/name#version` since cargo 1.77.
+                        let repr = &artifact.package_id.repr;
+                        if repr.starts_with(&format!("{} {}", crate_name, version))
+                            || repr.contains(&format!("#{}@{}", crate_name, version))
+                            || repr.contains(&format!("/{}#{}", crate_name, version))
+                        {
                             return artifact.filenames[0].clone();
                         }
                     }
@@ -36,8 +41,8 @@ mod fixtures {
     }
 }
 
-fn parse_string(code: &str) -> Option<crate::rustc_server::TokenStream> {
-    Some(crate::rustc_server::TokenStream::from_str(code).unwrap())
+fn parse_string(code: &str) -> Option<crate::abis::TokenStream> {
+    Some(crate::abis::TokenStream::from_str(code).unwrap())
 }
 
 pub fn assert_expand(
@@ -51,10 +56,18 @@ pub fn assert_expand(
     let expander = dylib::Expander::new(&path).unwrap();
     let fixture = parse_string(ra_fixture).unwrap();
 
-    let res = expander.expand(macro_name, &fixture.subtree, None).unwrap();
+    let (res, _) = expander.expand(macro_name, &fixture.subtree, None).unwrap();
     assert_eq_text!(&format!("{:?}", res), &expect.trim());
 }
 
+pub fn read_version(crate_name: &str, version: &str) -> dylib::RustcVersion {
+    let path = fixtures::dylib_path(crate_name, version);
+    let file = std::fs::File::open(&path).unwrap();
+    let buffer = unsafe { memmap::Mmap::map(&file).unwrap() };
+    let object = object::File::parse(&buffer).unwrap();
+    dylib::read_rustc_version(&object).unwrap()
+}
+
 pub fn list(crate_name: &str, version: &str) -> Vec<String> {
     let path = fixtures::dylib_path(crate_name, version);
     let task = ListMacrosTask { lib: path };
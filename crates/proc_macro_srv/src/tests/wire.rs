@@ -0,0 +1,176 @@
+//! Round-trip tests for the encodings of the proc-macro protocol
+
+use proc_macro_api::{
+    msg::{
+        Encoding, ErrorCode, HandshakeResult, HandshakeTask, Message, Request, Response,
+        ResponseError, BINARY_VERSION,
+    },
+    ExpansionResult, ExpansionTask, ListMacrosResult, ListMacrosTask, ProcMacroKind,
+};
+
+fn parse(text: &str) -> tt::Subtree {
+    mbe::parse_to_token_tree(text).unwrap().0
+}
+
+fn roundtrip<M: Message>(msg: M, encoding: Encoding) -> M {
+    let mut buf = Vec::new();
+    msg.write(&mut buf, encoding).unwrap();
+    let mut inp = buf.as_slice();
+    let res = M::read(&mut inp, encoding).unwrap().unwrap();
+    assert!(M::read(&mut inp, encoding).unwrap().is_none());
+    res
+}
+
+fn expansion_task() -> ExpansionTask {
+    // A large derive input, like the enums generated from protobuf definitions.
+    let variants: Vec<String> =
+        (0..200).map(|i| format!("V{}(Vec<u{}>, &'static str) = {}", i, 8 << (i % 4), i)).collect();
+    let body = format!("#[repr(u8)] pub enum Big {{ {} }}", variants.join(", "));
+    ExpansionTask {
+        macro_body: parse(&body),
+        macro_name: "Serialize".into(),
+        attributes: Some(parse(r#"(rename = "big", x += 1)"#)),
+        lib: "/target/debug/deps/libserde_derive.so".into(),
+        env: vec![("CARGO_PKG_NAME".into(), "big".into())],
+    }
+}
+
+fn expansion_result() -> ExpansionResult {
+    ExpansionResult {
+        expansion: parse(r#"impl Foo for Bar { fn foo(&self) -> &str { "foo" } }"#),
+        diagnostics: vec![tt::Diagnostic {
+            level: tt::DiagnosticLevel::Error,
+            message: "unsupported attribute".into(),
+            spans: vec![tt::TokenId(3), tt::TokenId::unspecified()],
+            children: vec![tt::Diagnostic {
+                level: tt::DiagnosticLevel::Help,
+                message: "remove it".into(),
+                spans: vec![],
+                children: vec![],
+            }],
+        }],
+        tracked_env: vec![("DATABASE_URL".into(), None), ("OUT_DIR".into(), Some("/out".into()))],
+        tracked_paths: vec!["/project/queries/user.sql".into()],
+    }
+}
+
+#[test]
+fn expansion_task_roundtrips() {
+    for &encoding in &[Encoding::Json, Encoding::Binary] {
+        let task = expansion_task();
+        match roundtrip(Request::ExpansionMacro(task.clone()), encoding) {
+            Request::ExpansionMacro(back) => assert_eq!(back, task),
+            back => panic!("unexpected request: {:?}", back),
+        }
+    }
+}
+
+#[test]
+fn expansion_result_roundtrips() {
+    for &encoding in &[Encoding::Json, Encoding::Binary] {
+        let result = expansion_result();
+        match roundtrip(Response::ExpansionMacro(result.clone()), encoding) {
+            Response::ExpansionMacro(back) => assert_eq!(back, result),
+            back => panic!("unexpected response: {:?}", back),
+        }
+    }
+}
+
+#[test]
+fn other_messages_roundtrip() {
+    let task = ListMacrosTask { lib: "/libfoo.so".into() };
+    match roundtrip(Request::ListMacro(task.clone()), Encoding::Binary) {
+        Request::ListMacro(back) => assert_eq!(back, task),
+        back => panic!("unexpected request: {:?}", back),
+    }
+
+    let result = ListMacrosResult {
+        macros: vec![("foo".into(), ProcMacroKind::FuncLike), ("Bar".into(), ProcMacroKind::Attr)],
+    };
+    match roundtrip(Response::ListMacro(result.clone()), Encoding::Binary) {
+        Response::ListMacro(back) => assert_eq!(back, result),
+        back => panic!("unexpected response: {:?}", back),
+    }
+
+    let err = ResponseError { code: ErrorCode::ExpansionPanic, message: "boom".into() };
+    match roundtrip(Response::Error(err), Encoding::Binary) {
+        Response::Error(ResponseError { code: ErrorCode::ExpansionPanic, message }) => {
+            assert_eq!(message, "boom")
+        }
+        back => panic!("unexpected response: {:?}", back),
+    }
+}
+
+#[test]
+fn empty_and_nested_subtrees_roundtrip() {
+    let mut subtree = parse("a (b [c {}]) () {{}} d");
+    subtree.delimiter = None;
+    let result = ExpansionResult { expansion: subtree, ..ExpansionResult::default() };
+    match roundtrip(Response::ExpansionMacro(result.clone()), Encoding::Binary) {
+        Response::ExpansionMacro(back) => assert_eq!(back, result),
+        back => panic!("unexpected response: {:?}", back),
+    }
+}
+
+#[test]
+fn several_messages_in_one_stream() {
+    let mut buf = Vec::new();
+    Request::ListMacro(ListMacrosTask { lib: "/a.so".into() })
+        .write(&mut buf, Encoding::Binary)
+        .unwrap();
+    Request::ExpansionMacro(expansion_task()).write(&mut buf, Encoding::Binary).unwrap();
+
+    let mut inp = buf.as_slice();
+    assert!(matches!(Request::read(&mut inp, Encoding::Binary), Ok(Some(Request::ListMacro(_)))));
+    assert!(matches!(
+        Request::read(&mut inp, Encoding::Binary),
+        Ok(Some(Request::ExpansionMacro(_)))
+    ));
+    assert!(matches!(Request::read(&mut inp, Encoding::Binary), Ok(None)));
+}
+
+#[test]
+fn binary_is_smaller_than_json() {
+    let mut json = Vec::new();
+    Request::ExpansionMacro(expansion_task()).write(&mut json, Encoding::Json).unwrap();
+    let mut binary = Vec::new();
+    Request::ExpansionMacro(expansion_task()).write(&mut binary, Encoding::Binary).unwrap();
+    assert!(binary.len() * 2 < json.len(), "binary: {}, json: {}", binary.len(), json.len());
+}
+
+#[test]
+fn truncated_and_corrupt_messages_are_errors() {
+    let bytes = Request::ExpansionMacro(expansion_task()).encode().unwrap();
+    for len in [0, 1, 5, bytes.len() / 2, bytes.len() - 1].iter() {
+        assert!(Request::decode(&bytes[..*len]).is_err());
+    }
+
+    let mut trailing = bytes.clone();
+    trailing.push(0);
+    assert!(Request::decode(&trailing).is_err());
+
+    let mut unknown = bytes;
+    unknown[0] = 42;
+    assert!(Request::decode(&unknown).is_err());
+}
+
+#[test]
+fn handshake_negotiates_encoding() {
+    let res = HandshakeTask::default().accept();
+    assert_eq!(res, HandshakeResult { binary_version: Some(BINARY_VERSION) });
+    assert_eq!(res.encoding(), Encoding::Binary);
+
+    // A client that only knows other versions of the binary encoding falls back to JSON.
+    let res = HandshakeTask { binary_versions: vec![BINARY_VERSION + 1] }.accept();
+    assert_eq!(res, HandshakeResult { binary_version: None });
+    assert_eq!(res.encoding(), Encoding::Json);
+    assert_eq!(
+        HandshakeResult { binary_version: Some(BINARY_VERSION + 1) }.encoding(),
+        Encoding::Json
+    );
+
+    match roundtrip(Request::Handshake(HandshakeTask::default()), Encoding::Json) {
+        Request::Handshake(task) => assert_eq!(task, HandshakeTask::default()),
+        back => panic!("unexpected request: {:?}", back),
+    }
+}
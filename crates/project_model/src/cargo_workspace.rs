@@ -2,6 +2,7 @@
 
 use std::{
     convert::TryInto,
+    env::consts::EXE_SUFFIX,
     ffi::OsStr,
     ops,
     path::{Path, PathBuf},
@@ -10,7 +11,7 @@ use std::{
 
 use anyhow::{Context, Result};
 use arena::{Arena, Idx};
-use base_db::Edition;
+use base_db::{CrateKind, Edition};
 use cargo_metadata::{BuildScript, CargoOpt, Message, MetadataCommand, PackageId};
 use itertools::Itertools;
 use paths::{AbsPath, AbsPathBuf};
@@ -34,6 +35,8 @@ pub struct CargoWorkspace {
     packages: Arena<PackageData>,
     targets: Arena<TargetData>,
     workspace_root: AbsPathBuf,
+    /// Directory where `cargo build` puts the binaries of the workspace.
+    bin_dir: AbsPathBuf,
 }
 
 impl ops::Index<Package> for CargoWorkspace {
@@ -87,6 +90,9 @@ pub struct PackageData {
     pub manifest: AbsPathBuf,
     pub targets: Vec<Target>,
     pub is_member: bool,
+    /// Whether `cargo build` in the workspace builds this package by default. Cargo sets
+    /// `CARGO_PRIMARY_PACKAGE` for those.
+    pub is_primary: bool,
     pub dependencies: Vec<PackageDependency>,
     pub edition: Edition,
     pub features: Vec<String>,
@@ -111,6 +117,23 @@ pub struct TargetData {
     pub is_proc_macro: bool,
 }
 
+impl TargetData {
+    /// The kind of the crate rust-analyzer creates for this target.
+    pub fn crate_kind(&self) -> CrateKind {
+        if self.is_proc_macro {
+            return CrateKind::ProcMacro;
+        }
+        match self.kind {
+            TargetKind::Bin => CrateKind::Bin,
+            TargetKind::Lib => CrateKind::Lib,
+            TargetKind::Example => CrateKind::Example,
+            TargetKind::Test => CrateKind::Test,
+            TargetKind::Bench => CrateKind::Bench,
+            TargetKind::Other => CrateKind::Other,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TargetKind {
     Bin,
@@ -198,7 +221,7 @@ impl CargoWorkspace {
             meta.other_options(vec![String::from("--filter-platform"), target]);
         }
 
-        let mut meta = meta.exec().with_context(|| {
+        let meta = meta.exec().with_context(|| {
             let cwd: Option<AbsPathBuf> =
                 std::env::current_dir().ok().and_then(|p| p.try_into().ok());
 
@@ -216,32 +239,53 @@ impl CargoWorkspace {
             )
         })?;
 
-        let mut out_dir_by_id = FxHashMap::default();
-        let mut cfgs = FxHashMap::default();
-        let mut envs = FxHashMap::default();
-        let mut proc_macro_dylib_paths = FxHashMap::default();
-        if config.load_out_dirs_from_check {
-            let resources = load_extern_resources(cargo_toml, config)?;
-            out_dir_by_id = resources.out_dirs;
-            cfgs = resources.cfgs;
-            envs = resources.env;
-            proc_macro_dylib_paths = resources.proc_dylib_paths;
-        }
+        let resources = if config.load_out_dirs_from_check {
+            load_extern_resources(cargo_toml, config)?
+        } else {
+            ExternResources::default()
+        };
+
+        let cargo = toolchain::cargo();
+        // Cargo sets `CARGO` to the absolute path of its executable.
+        let cargo = toolchain::absolute_path(&cargo).unwrap_or(cargo);
+        CargoWorkspace::new(meta, resources, config.target.as_deref(), &cargo)
+    }
+
+    pub(crate) fn new(
+        mut meta: cargo_metadata::Metadata,
+        resources: ExternResources,
+        target: Option<&str>,
+        cargo: &Path,
+    ) -> Result<CargoWorkspace> {
+        let ExternResources {
+            out_dirs: out_dir_by_id,
+            proc_dylib_paths: proc_macro_dylib_paths,
+            cfgs,
+            env: mut envs,
+        } = resources;
 
         let mut pkg_by_id = FxHashMap::default();
         let mut packages = Arena::default();
         let mut targets = Arena::default();
 
         let ws_members = &meta.workspace_members;
+        // Without arguments, `cargo build` builds the package of the manifest it is run for, or
+        // the members of a virtual workspace. `default-members` isn't reported by the version of
+        // `cargo metadata` we use, so all members are assumed to be selected.
+        let root_package = meta.root_package().map(|it| it.id.clone());
 
         meta.packages.sort_by(|a, b| a.id.cmp(&b.id));
         for meta_pkg in meta.packages {
             let id = meta_pkg.id.clone();
-            inject_cargo_env(&meta_pkg, envs.entry(id).or_default());
+            inject_cargo_env(cargo, &meta_pkg, envs.entry(id).or_default());
 
             let cargo_metadata::Package { id, edition, name, manifest_path, version, .. } =
                 meta_pkg;
             let is_member = ws_members.contains(&id);
+            let is_primary = match &root_package {
+                Some(root) => *root == id,
+                None => is_member,
+            };
             let edition = edition
                 .parse::<Edition>()
                 .with_context(|| format!("Failed to parse edition {}", edition))?;
@@ -251,6 +295,7 @@ impl CargoWorkspace {
                 manifest: AbsPathBuf::assert(manifest_path),
                 targets: Vec::new(),
                 is_member,
+                is_primary,
                 edition,
                 dependencies: Vec::new(),
                 features: Vec::new(),
@@ -304,7 +349,13 @@ impl CargoWorkspace {
         }
 
         let workspace_root = AbsPathBuf::assert(meta.workspace_root);
-        Ok(CargoWorkspace { packages, targets, workspace_root: workspace_root })
+        let mut bin_dir = meta.target_directory;
+        if let Some(target) = target {
+            bin_dir.push(target);
+        }
+        bin_dir.push("debug");
+        let bin_dir = AbsPathBuf::assert(bin_dir);
+        Ok(CargoWorkspace { packages, targets, workspace_root: workspace_root, bin_dir })
     }
 
     pub fn packages<'a>(&'a self) -> impl Iterator<Item = Package> + ExactSizeIterator + 'a {
@@ -322,6 +373,32 @@ impl CargoWorkspace {
         &self.workspace_root
     }
 
+    /// Environment variables Cargo sets for a single target, in addition to the
+    /// package-wide ones from [`PackageData::envs`].
+    pub fn target_env(&self, tgt: Target) -> Vec<(String, String)> {
+        let target = &self[tgt];
+        let package = &self[target.package];
+        let mut env = Vec::new();
+
+        env.push(("CARGO_CRATE_NAME".into(), target.name.replace('-', "_")));
+        if package.is_primary {
+            env.push(("CARGO_PRIMARY_PACKAGE".into(), "1".into()));
+        }
+        match target.kind {
+            TargetKind::Bin => env.push(("CARGO_BIN_NAME".into(), target.name.clone())),
+            // Integration tests and benchmarks get the paths of the package's binaries.
+            TargetKind::Test | TargetKind::Bench => {
+                for &bin in package.targets.iter().filter(|&&it| self[it].kind == TargetKind::Bin) {
+                    let name = &self[bin].name;
+                    let exe = self.bin_dir.join(format!("{}{}", name, EXE_SUFFIX));
+                    env.push((format!("CARGO_BIN_EXE_{}", name), exe.display().to_string()));
+                }
+            }
+            TargetKind::Lib | TargetKind::Example | TargetKind::Other => (),
+        }
+        env
+    }
+
     pub fn package_flag(&self, package: &PackageData) -> String {
         if self.is_unique(&*package.name) {
             package.name.clone()
@@ -441,9 +518,18 @@ fn is_dylib(path: &Path) -> bool {
 /// Recreates the compile-time environment variables that Cargo sets.
 ///
 /// Should be synced with <https://doc.rust-lang.org/cargo/reference/environment-variables.html#environment-variables-cargo-sets-for-crates>
-fn inject_cargo_env(package: &cargo_metadata::Package, env: &mut Vec<(String, String)>) {
+fn inject_cargo_env(
+    cargo: &Path,
+    package: &cargo_metadata::Package,
+    env: &mut Vec<(String, String)>,
+) {
+    // Target-specific variables are set by `CargoWorkspace::target_env`.
     // FIXME: Missing variables:
-    // CARGO, CARGO_PKG_HOMEPAGE, CARGO_CRATE_NAME, CARGO_BIN_NAME, CARGO_BIN_EXE_<name>
+    // CARGO_PKG_HOMEPAGE (needs a newer `cargo_metadata`)
+
+    if let Some(cargo) = cargo.to_str() {
+        env.push(("CARGO".into(), cargo.into()));
+    }
 
     let mut manifest_dir = package.manifest_path.clone();
     manifest_dir.pop();
@@ -472,3 +558,163 @@ fn inject_cargo_env(package: &cargo_metadata::Package, env: &mut Vec<(String, St
         package.license_file.as_ref().map(|buf| buf.display().to_string()).unwrap_or_default();
     env.push(("CARGO_PKG_LICENSE_FILE".into(), license_file));
 }
+
+#[cfg(test)]
+mod tests {
+    use expect_test::{expect, Expect};
+
+    use super::*;
+
+    fn check_env(metadata: &str, expect: Expect) {
+        let meta: cargo_metadata::Metadata = serde_json::from_str(metadata).unwrap();
+        let cargo = Path::new("/usr/bin/cargo");
+        let ws = CargoWorkspace::new(meta, ExternResources::default(), None, cargo).unwrap();
+        let mut actual = String::new();
+        for pkg in ws.packages() {
+            for &tgt in &ws[pkg].targets {
+                actual.push_str(&format!("{} {:?}\n", ws[tgt].name, ws[tgt].kind));
+                for (key, value) in ws[pkg].envs.iter().chain(&ws.target_env(tgt)) {
+                    actual.push_str(&format!("  {}={}\n", key, value));
+                }
+            }
+        }
+        expect.assert_eq(&actual);
+    }
+
+    #[test]
+    fn target_env() {
+        check_env(
+            r#"{
+    "version": 1,
+    "workspace_root": "/ws",
+    "target_directory": "/ws/target",
+    "workspace_members": ["foo 0.1.0 (path+file:///ws)", "bar 1.2.3-beta.1 (path+file:///ws/bar)"],
+    "packages": [
+        {
+            "name": "foo",
+            "version": "0.1.0",
+            "id": "foo 0.1.0 (path+file:///ws)",
+            "authors": ["A", "B"],
+            "dependencies": [],
+            "features": {},
+            "manifest_path": "/ws/Cargo.toml",
+            "targets": [
+                { "name": "foo-cli", "kind": ["bin"], "src_path": "/ws/src/main.rs" },
+                { "name": "it", "kind": ["test"], "src_path": "/ws/tests/it.rs" }
+            ]
+        },
+        {
+            "name": "bar",
+            "version": "1.2.3-beta.1",
+            "id": "bar 1.2.3-beta.1 (path+file:///ws/bar)",
+            "description": "The bar crate",
+            "license": "MIT",
+            "dependencies": [],
+            "features": {},
+            "manifest_path": "/ws/bar/Cargo.toml",
+            "targets": [
+                { "name": "bar", "kind": ["lib"], "src_path": "/ws/bar/src/lib.rs" }
+            ]
+        }
+    ],
+    "resolve": {
+        "root": "foo 0.1.0 (path+file:///ws)",
+        "nodes": [
+            { "id": "foo 0.1.0 (path+file:///ws)", "dependencies": [] },
+            { "id": "bar 1.2.3-beta.1 (path+file:///ws/bar)", "dependencies": [] }
+        ]
+    }
+}"#,
+            expect![[r#"
+                bar Lib
+                  CARGO=/usr/bin/cargo
+                  CARGO_MANIFEST_DIR=/ws/bar
+                  CARGO_PKG_VERSION=1.2.3-beta.1
+                  CARGO_PKG_VERSION_MAJOR=1
+                  CARGO_PKG_VERSION_MINOR=2
+                  CARGO_PKG_VERSION_PATCH=3
+                  CARGO_PKG_VERSION_PRE=beta.1
+                  CARGO_PKG_AUTHORS=
+                  CARGO_PKG_NAME=bar
+                  CARGO_PKG_DESCRIPTION=The bar crate
+                  CARGO_PKG_REPOSITORY=
+                  CARGO_PKG_LICENSE=MIT
+                  CARGO_PKG_LICENSE_FILE=
+                  CARGO_CRATE_NAME=bar
+                foo-cli Bin
+                  CARGO=/usr/bin/cargo
+                  CARGO_MANIFEST_DIR=/ws
+                  CARGO_PKG_VERSION=0.1.0
+                  CARGO_PKG_VERSION_MAJOR=0
+                  CARGO_PKG_VERSION_MINOR=1
+                  CARGO_PKG_VERSION_PATCH=0
+                  CARGO_PKG_VERSION_PRE=
+                  CARGO_PKG_AUTHORS=A;B
+                  CARGO_PKG_NAME=foo
+                  CARGO_PKG_DESCRIPTION=
+                  CARGO_PKG_REPOSITORY=
+                  CARGO_PKG_LICENSE=
+                  CARGO_PKG_LICENSE_FILE=
+                  CARGO_CRATE_NAME=foo_cli
+                  CARGO_PRIMARY_PACKAGE=1
+                  CARGO_BIN_NAME=foo-cli
+                it Test
+                  CARGO=/usr/bin/cargo
+                  CARGO_MANIFEST_DIR=/ws
+                  CARGO_PKG_VERSION=0.1.0
+                  CARGO_PKG_VERSION_MAJOR=0
+                  CARGO_PKG_VERSION_MINOR=1
+                  CARGO_PKG_VERSION_PATCH=0
+                  CARGO_PKG_VERSION_PRE=
+                  CARGO_PKG_AUTHORS=A;B
+                  CARGO_PKG_NAME=foo
+                  CARGO_PKG_DESCRIPTION=
+                  CARGO_PKG_REPOSITORY=
+                  CARGO_PKG_LICENSE=
+                  CARGO_PKG_LICENSE_FILE=
+                  CARGO_CRATE_NAME=it
+                  CARGO_PRIMARY_PACKAGE=1
+                  CARGO_BIN_EXE_foo-cli=/ws/target/debug/foo-cli
+            "#]],
+        );
+    }
+
+    #[test]
+    fn virtual_workspace_members_are_primary() {
+        let meta: cargo_metadata::Metadata = serde_json::from_str(
+            r#"{
+    "version": 1,
+    "workspace_root": "/ws",
+    "target_directory": "/ws/target",
+    "workspace_members": ["a 0.1.0 (path+file:///ws/a)"],
+    "packages": [
+        {
+            "name": "a",
+            "version": "0.1.0",
+            "id": "a 0.1.0 (path+file:///ws/a)",
+            "dependencies": [],
+            "features": {},
+            "manifest_path": "/ws/a/Cargo.toml",
+            "targets": []
+        },
+        {
+            "name": "dep",
+            "version": "1.0.0",
+            "id": "dep 1.0.0 (registry+https://github.com/rust-lang/crates.io-index)",
+            "dependencies": [],
+            "features": {},
+            "manifest_path": "/registry/dep/Cargo.toml",
+            "targets": []
+        }
+    ],
+    "resolve": { "root": null, "nodes": [] }
+}"#,
+        )
+        .unwrap();
+        let cargo = Path::new("/usr/bin/cargo");
+        let ws = CargoWorkspace::new(meta, ExternResources::default(), None, cargo).unwrap();
+        let primary: Vec<_> =
+            ws.packages().filter(|&pkg| ws[pkg].is_primary).map(|pkg| &ws[pkg].name).collect();
+        assert_eq!(primary, ["a"]);
+    }
+}
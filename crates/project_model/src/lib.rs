@@ -23,7 +23,7 @@ pub use crate::{
     workspace::{PackageRoot, ProjectWorkspace},
 };
 
-pub use proc_macro_api::ProcMacroClient;
+pub use proc_macro_api::{ProcMacroClient, ProcMacroServerEvent};
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Ord, PartialOrd)]
 pub enum ProjectManifest {
@@ -2,7 +2,7 @@
 
 use std::path::PathBuf;
 
-use base_db::{CrateDisplayName, CrateId, CrateName, Dependency, Edition};
+use base_db::{CrateDisplayName, CrateId, CrateKind, CrateName, Dependency, Edition};
 use paths::{AbsPath, AbsPathBuf};
 use rustc_hash::FxHashMap;
 use serde::{de, Deserialize};
@@ -24,6 +24,7 @@ pub struct Crate {
     pub(crate) display_name: Option<CrateDisplayName>,
     pub(crate) root_module: AbsPathBuf,
     pub(crate) edition: Edition,
+    pub(crate) kind: CrateKind,
     pub(crate) deps: Vec<Dependency>,
     pub(crate) cfg: Vec<CfgFlag>,
     pub(crate) target: Option<String>,
@@ -55,6 +56,11 @@ impl ProjectJson {
                             && !crate_data.root_module.starts_with("..")
                             || crate_data.root_module.starts_with(base)
                     });
+                    let kind = match crate_data.kind {
+                        Some(kind) => kind.into(),
+                        None if crate_data.proc_macro_dylib_path.is_some() => CrateKind::ProcMacro,
+                        None => CrateKind::Lib,
+                    };
                     let root_module = base.join(crate_data.root_module).normalize();
                     let (include, exclude) = match crate_data.source {
                         Some(src) => {
@@ -74,6 +80,7 @@ impl ProjectJson {
                             .map(CrateDisplayName::from_canonical_name),
                         root_module,
                         edition: crate_data.edition.into(),
+                        kind,
                         deps: crate_data
                             .deps
                             .into_iter()
@@ -121,6 +128,7 @@ struct CrateData {
     display_name: Option<String>,
     root_module: PathBuf,
     edition: EditionData,
+    kind: Option<CrateKindData>,
     deps: Vec<DepData>,
     #[serde(default)]
     cfg: Vec<CfgFlag>,
@@ -150,6 +158,32 @@ impl From<EditionData> for Edition {
     }
 }
 
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum CrateKindData {
+    Lib,
+    ProcMacro,
+    Bin,
+    Example,
+    Test,
+    Bench,
+    Other,
+}
+
+impl From<CrateKindData> for CrateKind {
+    fn from(data: CrateKindData) -> Self {
+        match data {
+            CrateKindData::Lib => CrateKind::Lib,
+            CrateKindData::ProcMacro => CrateKind::ProcMacro,
+            CrateKindData::Bin => CrateKind::Bin,
+            CrateKindData::Example => CrateKind::Example,
+            CrateKindData::Test => CrateKind::Test,
+            CrateKindData::Bench => CrateKind::Bench,
+            CrateKindData::Other => CrateKind::Other,
+        }
+    }
+}
+
 #[derive(Deserialize)]
 struct DepData {
     /// Identifies a crate by position in the crates array.
@@ -9,15 +9,17 @@ use std::{
 };
 
 use anyhow::{Context, Result};
-use base_db::{CrateDisplayName, CrateGraph, CrateId, CrateName, Edition, Env, FileId, ProcMacro};
+use base_db::{
+    CrateDisplayName, CrateGraph, CrateId, CrateKind, CrateName, Edition, Env, FileId, ProcMacro,
+};
 use cfg::CfgOptions;
 use paths::{AbsPath, AbsPathBuf};
 use proc_macro_api::ProcMacroClient;
 use rustc_hash::{FxHashMap, FxHashSet};
 
 use crate::{
-    cargo_workspace, cfg_flag::CfgFlag, sysroot::SysrootCrate, utf8_stdout, CargoConfig,
-    CargoWorkspace, ProjectJson, ProjectManifest, Sysroot, TargetKind,
+    cfg_flag::CfgFlag, sysroot::SysrootCrate, utf8_stdout, CargoConfig, CargoWorkspace,
+    ProjectJson, ProjectManifest, Sysroot, Target, TargetKind,
 };
 
 /// `PackageRoot` describes a package root folder.
@@ -258,6 +260,7 @@ fn project_json_to_crate_graph(
                 crate_graph.add_crate_root(
                     file_id,
                     krate.edition,
+                    krate.kind,
                     krate.display_name.clone(),
                     cfg_options,
                     env,
@@ -315,7 +318,8 @@ fn cargo_to_crate_graph(
             if let Some(file_id) = load(&cargo[tgt].root) {
                 let crate_id = add_target_crate_root(
                     &mut crate_graph,
-                    &cargo[pkg],
+                    cargo,
+                    tgt,
                     &cfg_options,
                     proc_macro_loader,
                     file_id,
@@ -391,7 +395,8 @@ fn cargo_to_crate_graph(
                 if let Some(file_id) = load(&rustc_workspace[tgt].root) {
                     let crate_id = add_target_crate_root(
                         &mut crate_graph,
-                        &rustc_workspace[pkg],
+                        rustc_workspace,
+                        tgt,
                         &cfg_options,
                         proc_macro_loader,
                         file_id,
@@ -439,11 +444,14 @@ fn cargo_to_crate_graph(
 
 fn add_target_crate_root(
     crate_graph: &mut CrateGraph,
-    pkg: &cargo_workspace::PackageData,
+    cargo: &CargoWorkspace,
+    tgt: Target,
     cfg_options: &CfgOptions,
     proc_macro_loader: &dyn Fn(&Path) -> Vec<ProcMacro>,
     file_id: FileId,
 ) -> CrateId {
+    let pkg = &cargo[cargo[tgt].package];
+    let kind = cargo[tgt].crate_kind();
     let edition = pkg.edition;
     let cfg_options = {
         let mut opts = cfg_options.clone();
@@ -455,7 +463,7 @@ fn add_target_crate_root(
     };
 
     let mut env = Env::default();
-    for (k, v) in &pkg.envs {
+    for (k, v) in pkg.envs.iter().chain(&cargo.target_env(tgt)) {
         env.set(k, v.clone());
     }
     if let Some(out_dir) = &pkg.out_dir {
@@ -465,13 +473,20 @@ fn add_target_crate_root(
         }
     }
 
-    let proc_macro =
-        pkg.proc_macro_dylib_path.as_ref().map(|it| proc_macro_loader(&it)).unwrap_or_default();
+    // Only the library target of a proc-macro package can define procedural macros, its tests
+    // and examples are ordinary crates.
+    let proc_macro = match (&pkg.proc_macro_dylib_path, kind) {
+        (Some(dylib_path), CrateKind::ProcMacro) => proc_macro_loader(&dylib_path),
+        _ => Vec::new(),
+    };
 
-    let display_name = CrateDisplayName::from_canonical_name(pkg.name.clone());
+    // Use the target's name, which is what rustc knows the crate as. It differs from the
+    // package's name for renamed targets and for binaries, tests and examples.
+    let display_name = CrateDisplayName::from_canonical_name(cargo[tgt].name.clone());
     let crate_id = crate_graph.add_crate_root(
         file_id,
         edition,
+        kind,
         Some(display_name),
         cfg_options,
         env,
@@ -500,6 +515,7 @@ fn sysroot_to_crate_graph(
             let crate_id = crate_graph.add_crate_root(
                 file_id,
                 Edition::Edition2018,
+                CrateKind::Lib,
                 Some(display_name),
                 cfg_options.clone(),
                 env,
@@ -560,3 +576,61 @@ fn add_dep(graph: &mut CrateGraph, from: CrateId, name: CrateName, to: CrateId)
         log::error!("{}", err)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use expect_test::expect;
+    use itertools::Itertools;
+
+    use crate::cargo_workspace::ExternResources;
+
+    use super::*;
+
+    #[test]
+    fn crate_names_of_renamed_targets() {
+        let meta: cargo_metadata::Metadata = serde_json::from_str(
+            r#"{
+    "version": 1,
+    "workspace_root": "/ws",
+    "target_directory": "/ws/target",
+    "workspace_members": ["foo-bar 0.1.0 (path+file:///ws)"],
+    "packages": [
+        {
+            "name": "foo-bar",
+            "version": "0.1.0",
+            "id": "foo-bar 0.1.0 (path+file:///ws)",
+            "dependencies": [],
+            "features": {},
+            "manifest_path": "/ws/Cargo.toml",
+            "targets": [
+                { "name": "foo_core", "kind": ["lib"], "src_path": "/ws/src/lib.rs" },
+                { "name": "foo-cli", "kind": ["bin"], "src_path": "/ws/src/main.rs" }
+            ]
+        }
+    ],
+    "resolve": { "root": null, "nodes": [] }
+}"#,
+        )
+        .unwrap();
+        let cargo = Path::new("/usr/bin/cargo");
+        let ws = CargoWorkspace::new(meta, ExternResources::default(), None, cargo).unwrap();
+        let mut next_file = 0;
+        let crate_graph = cargo_to_crate_graph(
+            None,
+            &|_| Vec::new(),
+            &mut |_| {
+                next_file += 1;
+                Some(FileId(next_file))
+            },
+            &ws,
+            &Sysroot::default(),
+            &None,
+        );
+        let names = crate_graph
+            .iter()
+            .map(|krate| crate_graph[krate].display_name.as_ref().unwrap().to_string())
+            .sorted()
+            .collect::<Vec<_>>();
+        expect![[r#"["foo_cli", "foo_core"]"#]].assert_eq(&format!("{:?}", names));
+    }
+}
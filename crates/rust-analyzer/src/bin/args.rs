@@ -18,14 +18,34 @@ pub(crate) struct Args {
 }
 
 pub(crate) enum Command {
-    Parse { no_dump: bool },
+    Parse {
+        no_dump: bool,
+    },
     Symbols,
-    Highlight { rainbow: bool },
+    Highlight {
+        rainbow: bool,
+    },
+    DefMap {
+        path: Option<PathBuf>,
+        item_tree: bool,
+        json: bool,
+        load_output_dirs: bool,
+        with_proc_macro: bool,
+    },
     AnalysisStats(AnalysisStatsCmd),
     Bench(BenchCmd),
-    Diagnostics { path: PathBuf, load_output_dirs: bool, with_proc_macro: bool },
-    Ssr { rules: Vec<SsrRule> },
-    StructuredSearch { debug_snippet: Option<String>, patterns: Vec<SsrPattern> },
+    Diagnostics {
+        path: PathBuf,
+        load_output_dirs: bool,
+        with_proc_macro: bool,
+    },
+    Ssr {
+        rules: Vec<SsrRule>,
+    },
+    StructuredSearch {
+        debug_snippet: Option<String>,
+        patterns: Vec<SsrPattern>,
+    },
     ProcMacro,
     RunServer,
     PrintConfigSchema,
@@ -65,6 +85,15 @@ symbols < main.rs     Parse input an print the list of symbols
 highlight < main.rs   Highlight input as html
     --rainbow         Enable rainbow highlighting of identifiers
 
+def-map [PATH]        Print modules, names, imports and macros resolved in the crates
+                      of a project, or in input if no path is given
+    <PATH>            Directory with Cargo.toml
+    --item-tree       Print the item trees of the files instead
+    --json            Print as JSON
+    --load-output-dirs
+                      Load OUT_DIR values by running `cargo check` before analysis
+    --with-proc-macro Use proc-macro-srv for proc-macro expanding
+
 analysis-stats <PATH> Batch typecheck project and print summary statistics
     <PATH>            Directory with Cargo.toml
     --randomize       Randomize order in which crates, modules, and items are processed
@@ -151,6 +180,13 @@ impl Args {
             "parse" => Command::Parse { no_dump: matches.contains("--no-dump") },
             "symbols" => Command::Symbols,
             "highlight" => Command::Highlight { rainbow: matches.contains("--rainbow") },
+            "def-map" => Command::DefMap {
+                item_tree: matches.contains("--item-tree"),
+                json: matches.contains("--json"),
+                load_output_dirs: matches.contains("--load-output-dirs"),
+                with_proc_macro: matches.contains("--with-proc-macro"),
+                path: matches.free_from_str()?,
+            },
             "analysis-stats" => Command::AnalysisStats(AnalysisStatsCmd {
                 randomize: matches.contains("--randomize"),
                 parallel: matches.contains("--parallel"),
@@ -39,6 +39,9 @@ fn try_main() -> Result<()> {
         args::Command::Parse { no_dump } => cli::parse(no_dump)?,
         args::Command::Symbols => cli::symbols()?,
         args::Command::Highlight { rainbow } => cli::highlight(rainbow)?,
+        args::Command::DefMap { path, item_tree, json, load_output_dirs, with_proc_macro } => {
+            cli::def_map(path.as_deref(), item_tree, json, load_output_dirs, with_proc_macro)?
+        }
         args::Command::AnalysisStats(cmd) => cmd.run(args.verbosity)?,
         args::Command::Bench(cmd) => cmd.run(args.verbosity)?,
         args::Command::Diagnostics { path, load_output_dirs, with_proc_macro } => {
@@ -3,6 +3,7 @@
 mod load_cargo;
 mod analysis_stats;
 mod analysis_bench;
+mod def_map;
 mod diagnostics;
 mod progress_report;
 mod ssr;
@@ -17,6 +18,7 @@ use vfs::Vfs;
 pub use self::{
     analysis_bench::{BenchCmd, BenchWhat, Position},
     analysis_stats::AnalysisStatsCmd,
+    def_map::def_map,
     diagnostics::diagnostics,
     load_cargo::load_cargo,
     ssr::{apply_ssr_rules, search_for_patterns},
@@ -0,0 +1,125 @@
+//! Dumps the def maps or item trees of a project, or of a single file read from stdin.
+
+use std::path::Path;
+
+use anyhow::format_err;
+use hir::Crate;
+use ide::Analysis;
+use ide_db::{
+    base_db::{FileId, SourceDatabaseExt},
+    RootDatabase,
+};
+use rustc_hash::FxHashSet;
+use serde_json::json;
+
+use crate::{
+    cli::{load_cargo::load_cargo, read_stdin, Result},
+    to_proto,
+};
+
+pub fn def_map(
+    path: Option<&Path>,
+    item_tree: bool,
+    json: bool,
+    load_output_dirs: bool,
+    with_proc_macro: bool,
+) -> Result<()> {
+    let path = match path {
+        Some(it) => it,
+        None => {
+            let (analysis, file_id) = Analysis::from_single_file(read_stdin()?);
+            if json {
+                let dump = json_dump(&analysis, file_id, item_tree)?;
+                println!("{}", serde_json::to_string_pretty(&dump)?);
+            } else {
+                print!("{}", text_dump(&analysis, file_id, item_tree)?);
+            }
+            return Ok(());
+        }
+    };
+
+    let (host, vfs) = load_cargo(path, load_output_dirs, with_proc_macro)?;
+    let db = host.raw_database();
+    let analysis = host.analysis();
+
+    let files = if item_tree { local_files(db) } else { local_crate_roots(db) };
+    let mut text = String::new();
+    let mut values = Vec::new();
+    for (name, file_id) in files {
+        let file = vfs.file_path(file_id).to_string();
+        if json {
+            let dump = json_dump(&analysis, file_id, item_tree)?;
+            values.push(json!({ "crate": name, "file": file, "dump": dump }));
+        } else {
+            if !text.is_empty() {
+                text.push('\n');
+            }
+            let dump = text_dump(&analysis, file_id, item_tree)?;
+            text.push_str(&format!("// {} ({})\n{}", name, file, dump));
+        }
+    }
+    if json {
+        println!("{}", serde_json::to_string_pretty(&values)?);
+    } else {
+        print!("{}", text);
+    }
+    Ok(())
+}
+
+fn text_dump(analysis: &Analysis, file_id: FileId, item_tree: bool) -> Result<String> {
+    let res = if item_tree {
+        analysis.view_item_tree(file_id)?.to_string()
+    } else {
+        analysis.view_def_map(file_id)?.ok_or_else(|| format_err!("file has no crate"))?.to_string()
+    };
+    Ok(res)
+}
+
+fn json_dump(analysis: &Analysis, file_id: FileId, item_tree: bool) -> Result<serde_json::Value> {
+    let res = if item_tree {
+        serde_json::to_value(to_proto::item_tree_dump(&analysis.view_item_tree(file_id)?))?
+    } else {
+        serde_json::to_value(to_proto::def_map_dump(
+            &analysis.view_def_map(file_id)?.ok_or_else(|| format_err!("file has no crate"))?,
+        ))?
+    };
+    Ok(res)
+}
+
+/// Returns the name and the root file of every crate of the workspace itself.
+fn local_crate_roots(db: &RootDatabase) -> Vec<(String, FileId)> {
+    Crate::all(db)
+        .into_iter()
+        .filter(|krate| !is_library(db, krate.root_file(db)))
+        .map(|krate| (crate_name(db, krate), krate.root_file(db)))
+        .collect()
+}
+
+/// Returns every file containing a module of a crate of the workspace itself, with the name of
+/// the crate.
+fn local_files(db: &RootDatabase) -> Vec<(String, FileId)> {
+    let mut visited = FxHashSet::default();
+    let mut res = Vec::new();
+    for krate in Crate::all(db) {
+        if is_library(db, krate.root_file(db)) {
+            continue;
+        }
+        let mut worklist = vec![krate.root_module(db)];
+        while let Some(module) = worklist.pop() {
+            let file_id = module.definition_source(db).file_id.original_file(db);
+            if visited.insert(file_id) {
+                res.push((crate_name(db, krate), file_id));
+            }
+            worklist.extend(module.children(db));
+        }
+    }
+    res
+}
+
+fn is_library(db: &RootDatabase, file_id: FileId) -> bool {
+    db.source_root(db.file_source_root(file_id)).is_library
+}
+
+fn crate_name(db: &RootDatabase, krate: Crate) -> String {
+    krate.display_name(db).as_deref().unwrap_or("unknown").to_string()
+}
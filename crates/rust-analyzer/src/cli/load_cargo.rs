@@ -33,7 +33,9 @@ pub fn load_cargo(
 
     let proc_macro_client = if with_proc_macro {
         let path = std::env::current_exe()?;
-        Some(ProcMacroClient::extern_process(path, &["proc-macro"]).unwrap())
+        Some(
+            ProcMacroClient::extern_process(path, &["proc-macro"], None, Box::new(|_| ())).unwrap(),
+        )
     } else {
         None
     };
@@ -45,7 +47,7 @@ pub fn load_cargo(
         vfs.file_id(&path)
     });
 
-    let project_folders = ProjectFolders::new(&[ws]);
+    let project_folders = ProjectFolders::new(&[ws], &[]);
     loader.set_config(vfs::loader::Config { load: project_folders.load, watch: vec![] });
 
     log::debug!("crate graph: {:?}", crate_graph);
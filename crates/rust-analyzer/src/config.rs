@@ -7,7 +7,7 @@
 //! configure the server itself, feature flags are passed into analysis, and
 //! tweak things like automatic insertion of `()` in completions.
 
-use std::{convert::TryFrom, ffi::OsString, path::PathBuf};
+use std::{convert::TryFrom, ffi::OsString, path::PathBuf, time::Duration};
 
 use flycheck::FlycheckConfig;
 use hir::PrefixKind;
@@ -40,7 +40,7 @@ config_data! {
         /// List of features to activate.
         cargo_features: Vec<String>      = "[]",
         /// Run `cargo check` on startup to get the correct value for package
-        /// OUT_DIRs.
+        /// OUT_DIRs and the environment variables set by build scripts.
         cargo_loadOutDirsFromCheck: bool = "false",
         /// Do not activate the `default` feature.
         cargo_noDefaultFeatures: bool    = "false",
@@ -101,6 +101,9 @@ config_data! {
 
         /// Controls file watching implementation.
         files_watcher: String = "\"client\"",
+        /// Extensions of non-Rust files to load up front. Other files read by
+        /// `include_str!` and `include_bytes!` are loaded when they are first read.
+        files_extraExtensions: Vec<String> = "[]",
 
         /// Whether to show `Debug` action. Only applies when
         /// `#rust-analyzer.hoverActions.enable#` is set.
@@ -155,6 +158,9 @@ config_data! {
         /// Enable Proc macro support, `#rust-analyzer.cargo.loadOutDirsFromCheck#` must be
         /// enabled.
         procMacro_enable: bool                     = "false",
+        /// Number of seconds to wait for a proc macro expansion before restarting the
+        /// proc macro server, or `null` to wait indefinitely.
+        procMacro_expansionTimeout: Option<usize>  = "10",
 
         /// Command to be executed instead of 'cargo' for runnables.
         runnables_overrideCargo: Option<String> = "null",
@@ -182,6 +188,7 @@ pub struct Config {
     pub diagnostics_map: DiagnosticsMapConfig,
     pub lru_capacity: Option<usize>,
     pub proc_macro_srv: Option<(PathBuf, Vec<OsString>)>,
+    pub proc_macro_expansion_timeout: Option<Duration>,
     pub files: FilesConfig,
     pub notifications: NotificationsConfig,
 
@@ -258,6 +265,7 @@ impl LensConfig {
 pub struct FilesConfig {
     pub watcher: FilesWatcher,
     pub exclude: Vec<String>,
+    pub extra_extensions: Vec<String>,
 }
 
 #[derive(Debug, Clone)]
@@ -312,7 +320,12 @@ impl Config {
             diagnostics_map: DiagnosticsMapConfig::default(),
             lru_capacity: None,
             proc_macro_srv: None,
-            files: FilesConfig { watcher: FilesWatcher::Notify, exclude: Vec::new() },
+            proc_macro_expansion_timeout: Some(Duration::from_secs(10)),
+            files: FilesConfig {
+                watcher: FilesWatcher::Notify,
+                exclude: Vec::new(),
+                extra_extensions: Vec::new(),
+            },
             notifications: NotificationsConfig { cargo_toml_not_found: false },
 
             cargo_autoreload: false,
@@ -373,6 +386,7 @@ impl Config {
             "notify" => FilesWatcher::Notify,
             "client" | _ => FilesWatcher::Client,
         };
+        self.files.extra_extensions = data.files_extraExtensions;
         self.notifications =
             NotificationsConfig { cargo_toml_not_found: data.notifications_cargoTomlNotFound };
         self.cargo_autoreload = data.cargo_autoreload;
@@ -407,6 +421,8 @@ impl Config {
         } else {
             None
         };
+        self.proc_macro_expansion_timeout =
+            data.procMacro_expansionTimeout.map(|secs| Duration::from_secs(secs as u64));
 
         self.rustfmt = match data.rustfmt_overrideCommand {
             Some(mut args) if !args.is_empty() => {
@@ -3,7 +3,7 @@
 //!
 //! Each tick provides an immutable snapshot of the state as `WorldSnapshot`.
 
-use std::{sync::Arc, time::Instant};
+use std::{path::PathBuf, sync::Arc, time::Instant};
 
 use crossbeam_channel::{unbounded, Receiver, Sender};
 use flycheck::FlycheckHandle;
@@ -11,9 +11,11 @@ use ide::{Analysis, AnalysisHost, Change, FileId};
 use ide_db::base_db::{CrateId, VfsPath};
 use lsp_types::{SemanticTokens, Url};
 use parking_lot::{Mutex, RwLock};
-use project_model::{CargoWorkspace, ProcMacroClient, ProjectWorkspace, Target};
-use rustc_hash::FxHashMap;
-use vfs::AnchoredPathBuf;
+use project_model::{
+    CargoWorkspace, ProcMacroClient, ProcMacroServerEvent, ProjectWorkspace, Target,
+};
+use rustc_hash::{FxHashMap, FxHashSet};
+use vfs::{AbsPathBuf, AnchoredPathBuf};
 
 use crate::{
     config::Config,
@@ -77,6 +79,15 @@ pub(crate) struct GlobalState {
     pub(crate) status: Status,
     pub(crate) source_root_config: SourceRootConfig,
     pub(crate) proc_macro_client: Option<ProcMacroClient>,
+    pub(crate) proc_macro_sender: Sender<ProcMacroServerEvent>,
+    pub(crate) proc_macro_receiver: Receiver<ProcMacroServerEvent>,
+    /// Why the proc macro server is being restarted, if it is.
+    pub(crate) proc_macro_crash: Option<String>,
+    /// Macros the server stopped expanding, with the libraries they come from.
+    pub(crate) disabled_proc_macros: Vec<(PathBuf, String)>,
+    /// Files that weren't loaded with the project folders, but that analysis asked for, see
+    /// `FileLoader::request_file`.
+    pub(crate) requested_files: FxHashSet<AbsPathBuf>,
     pub(crate) workspaces: Arc<Vec<ProjectWorkspace>>,
     latest_requests: Arc<RwLock<LatestRequests>>,
 }
@@ -111,6 +122,7 @@ impl GlobalState {
 
         let analysis_host = AnalysisHost::new(config.lru_capacity);
         let (flycheck_sender, flycheck_receiver) = unbounded();
+        let (proc_macro_sender, proc_macro_receiver) = unbounded();
         GlobalState {
             sender,
             req_queue: ReqQueue::default(),
@@ -129,6 +141,11 @@ impl GlobalState {
             status: Status::default(),
             source_root_config: SourceRootConfig::default(),
             proc_macro_client: None,
+            proc_macro_sender,
+            proc_macro_receiver,
+            proc_macro_crash: None,
+            disabled_proc_macros: Vec::new(),
+            requested_files: FxHashSet::default(),
             workspaces: Arc::new(Vec::new()),
             latest_requests: Default::default(),
         }
@@ -155,6 +172,18 @@ impl GlobalState {
                     }
                 }
 
+                if !is_rust_file(&vfs.file_path(file.file_id)) {
+                    // Non-Rust files are only read by `include_bytes!` and friends, which want
+                    // them byte for byte.
+                    let bytes = if file.exists() {
+                        Some(Arc::new(vfs.file_contents(file.file_id).to_vec()))
+                    } else {
+                        None
+                    };
+                    change.change_binary_file(file.file_id, bytes);
+                    continue;
+                }
+
                 let text = if file.exists() {
                     let bytes = vfs.file_contents(file.file_id).to_vec();
                     match String::from_utf8(bytes).ok() {
@@ -304,3 +333,12 @@ pub(crate) fn url_to_file_id(vfs: &vfs::Vfs, url: &Url) -> Result<FileId> {
     let res = vfs.file_id(&path).ok_or_else(|| format!("file not found: {}", path))?;
     Ok(res)
 }
+
+/// Whether the file should be handed to the analysis as Rust source code, as
+/// opposed to raw bytes for `include_bytes!` and friends.
+fn is_rust_file(path: &VfsPath) -> bool {
+    match path.name_and_extension() {
+        Some((_, extension)) => extension == Some("rs"),
+        None => true,
+    }
+}
@@ -105,6 +105,39 @@ pub(crate) fn handle_syntax_tree(
     Ok(res)
 }
 
+pub(crate) fn handle_view_def_map(
+    snap: GlobalStateSnapshot,
+    params: lsp_ext::ViewDumpParams,
+) -> Result<Option<String>> {
+    let _p = profile::span("handle_view_def_map");
+    let file_id = from_proto::file_id(&snap, &params.text_document.uri)?;
+    let dump = match snap.analysis.view_def_map(file_id)? {
+        Some(it) => it,
+        None => return Ok(None),
+    };
+    let res = match params.format {
+        lsp_ext::DumpFormat::Text => dump.to_string(),
+        lsp_ext::DumpFormat::Json => serde_json::to_string_pretty(&to_proto::def_map_dump(&dump))?,
+    };
+    Ok(Some(res))
+}
+
+pub(crate) fn handle_view_item_tree(
+    snap: GlobalStateSnapshot,
+    params: lsp_ext::ViewDumpParams,
+) -> Result<String> {
+    let _p = profile::span("handle_view_item_tree");
+    let file_id = from_proto::file_id(&snap, &params.text_document.uri)?;
+    let dump = snap.analysis.view_item_tree(file_id)?;
+    let res = match params.format {
+        lsp_ext::DumpFormat::Text => dump.to_string(),
+        lsp_ext::DumpFormat::Json => {
+            serde_json::to_string_pretty(&to_proto::item_tree_dump(&dump))?
+        }
+    };
+    Ok(res)
+}
+
 pub(crate) fn handle_expand_macro(
     snap: GlobalStateSnapshot,
     params: lsp_ext::ExpandMacroParams,
@@ -118,6 +151,47 @@ pub(crate) fn handle_expand_macro(
     Ok(res.map(|it| lsp_ext::ExpandedMacro { name: it.name, expansion: it.expansion }))
 }
 
+pub(crate) fn handle_debug_macro(
+    snap: GlobalStateSnapshot,
+    params: lsp_ext::ExpandMacroParams,
+) -> Result<Option<lsp_ext::DebuggedMacro>> {
+    let _p = profile::span("handle_debug_macro");
+    let file_id = from_proto::file_id(&snap, &params.text_document.uri)?;
+    let line_index = snap.analysis.file_line_index(file_id)?;
+    let offset = from_proto::offset(&line_index, params.position);
+
+    let res = match snap.analysis.debug_macro(FilePosition { file_id, offset })? {
+        Some(it) => it,
+        None => return Ok(None),
+    };
+    let rules = res
+        .rules
+        .into_iter()
+        .map(|rule| lsp_ext::MacroRuleAttempt {
+            index: rule.index,
+            error: rule.error,
+            failed_at: rule
+                .failed_at
+                .filter(|it| it.file_id == file_id)
+                .map(|it| to_proto::range(&line_index, it.range)),
+            bindings: rule
+                .bindings
+                .into_iter()
+                .map(|(name, value)| lsp_ext::MacroBinding {
+                    name,
+                    value: to_proto::macro_binding_value(value),
+                })
+                .collect(),
+        })
+        .collect();
+    Ok(Some(lsp_ext::DebuggedMacro {
+        name: res.name,
+        rules,
+        chosen_rule: res.chosen,
+        expansion: res.expansion,
+    }))
+}
+
 pub(crate) fn handle_selection_range(
     snap: GlobalStateSnapshot,
     params: lsp_types::SelectionRangeParams,
@@ -1269,7 +1343,15 @@ pub(crate) fn publish_diagnostics(
             }),
             source: Some("rust-analyzer".to_string()),
             message: d.message,
-            related_information: None,
+            related_information: if d.related.is_empty() {
+                None
+            } else {
+                d.related
+                    .into_iter()
+                    .map(|it| to_proto::diagnostic_related_information(snap, it))
+                    .collect::<Result<Vec<_>>>()
+                    .ok()
+            },
             tags: if d.unused { Some(vec![DiagnosticTag::Unnecessary]) } else { None },
             data: None,
         })
@@ -53,6 +53,125 @@ pub struct SyntaxTreeParams {
     pub range: Option<Range>,
 }
 
+pub enum ViewDefMap {}
+
+impl Request for ViewDefMap {
+    type Params = ViewDumpParams;
+    type Result = Option<String>;
+    const METHOD: &'static str = "rust-analyzer/viewDefMap";
+}
+
+pub enum ViewItemTree {}
+
+impl Request for ViewItemTree {
+    type Params = ViewDumpParams;
+    type Result = String;
+    const METHOD: &'static str = "rust-analyzer/viewItemTree";
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ViewDumpParams {
+    pub text_document: TextDocumentIdentifier,
+    #[serde(default)]
+    pub format: DumpFormat,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum DumpFormat {
+    Text,
+    Json,
+}
+
+impl Default for DumpFormat {
+    fn default() -> DumpFormat {
+        DumpFormat::Text
+    }
+}
+
+/// JSON form of a def map dump.
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct DefMapDump {
+    pub modules: Vec<ModuleDump>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ModuleDump {
+    pub path: String,
+    pub origin: String,
+    pub items: Vec<ScopeItemDump>,
+    pub imports: Vec<ImportDump>,
+    pub textual_macros: Vec<TextualMacroDump>,
+    pub impls: usize,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ScopeItemDump {
+    pub name: String,
+    pub namespace: Option<DumpNamespace>,
+    pub kind: String,
+    pub visibility: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum DumpNamespace {
+    Types,
+    Values,
+    Macros,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportDump {
+    pub path: String,
+    pub alias: Option<String>,
+    pub visibility: String,
+    pub is_glob: bool,
+    pub is_extern_crate: bool,
+    pub status: ImportStatus,
+    pub namespaces: Vec<DumpNamespace>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum ImportStatus {
+    Resolved,
+    Unresolved,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct TextualMacroDump {
+    pub name: String,
+    pub kind: String,
+    pub visible_from: Option<u32>,
+}
+
+/// JSON form of an item tree dump.
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ItemTreeDump {
+    pub attrs: Vec<String>,
+    pub items: Vec<ItemTreeItemDump>,
+    pub blocks: Vec<Vec<ItemTreeItemDump>>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ItemTreeItemDump {
+    pub kind: String,
+    pub name: Option<String>,
+    pub visibility: Option<String>,
+    pub attrs: Vec<String>,
+    pub detail: Option<String>,
+    pub children: Vec<ItemTreeItemDump>,
+}
+
 pub enum ExpandMacro {}
 
 impl Request for ExpandMacro {
@@ -75,6 +194,47 @@ pub struct ExpandedMacro {
     pub expansion: String,
 }
 
+pub enum DebugMacro {}
+
+impl Request for DebugMacro {
+    type Params = ExpandMacroParams;
+    type Result = Option<DebuggedMacro>;
+    const METHOD: &'static str = "rust-analyzer/debugMacro";
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct DebuggedMacro {
+    pub name: String,
+    pub rules: Vec<MacroRuleAttempt>,
+    pub chosen_rule: Option<usize>,
+    pub expansion: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct MacroRuleAttempt {
+    pub index: usize,
+    pub error: Option<String>,
+    pub failed_at: Option<Range>,
+    pub bindings: Vec<MacroBinding>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct MacroBinding {
+    pub name: String,
+    pub value: MacroBindingValue,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(untagged)]
+pub enum MacroBindingValue {
+    Fragment(String),
+    Repetition(Vec<MacroBindingValue>),
+    Empty,
+}
+
 pub enum MatchingBrace {}
 
 impl Request for MatchingBrace {
@@ -234,6 +394,8 @@ pub enum Status {
 #[derive(Deserialize, Serialize)]
 pub struct StatusParams {
     pub status: Status,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
 }
 
 impl Notification for StatusNotification {
@@ -11,7 +11,7 @@ use ide::{Canceled, FileId};
 use ide_db::base_db::VfsPath;
 use lsp_server::{Connection, Notification, Request, Response};
 use lsp_types::notification::Notification as _;
-use project_model::ProjectWorkspace;
+use project_model::{ProcMacroServerEvent, ProjectWorkspace};
 use vfs::ChangeKind;
 
 use crate::{
@@ -55,6 +55,7 @@ enum Event {
     Task(Task),
     Vfs(vfs::loader::Message),
     Flycheck(flycheck::Message),
+    ProcMacro(ProcMacroServerEvent),
 }
 
 #[derive(Debug)]
@@ -93,6 +94,7 @@ impl fmt::Debug for Event {
             Event::Task(it) => fmt::Debug::fmt(it, f),
             Event::Vfs(it) => fmt::Debug::fmt(it, f),
             Event::Flycheck(it) => fmt::Debug::fmt(it, f),
+            Event::ProcMacro(it) => fmt::Debug::fmt(it, f),
         }
     }
 }
@@ -167,6 +169,9 @@ impl GlobalState {
 
             recv(self.flycheck_receiver) -> task =>
                 Some(Event::Flycheck(task.unwrap())),
+
+            recv(self.proc_macro_receiver) -> event =>
+                Some(Event::ProcMacro(event.unwrap())),
         }
     }
 
@@ -352,8 +357,45 @@ impl GlobalState {
                     }
                 }
             }
+            Event::ProcMacro(mut event) => {
+                let _p = profile::span("GlobalState::handle_event/proc_macro");
+                let mut invalidate = false;
+                loop {
+                    match event {
+                        ProcMacroServerEvent::Crashed { message } => {
+                            self.proc_macro_crash = Some(message)
+                        }
+                        ProcMacroServerEvent::Restarted => {
+                            self.proc_macro_crash = None;
+                            invalidate = true;
+                        }
+                        ProcMacroServerEvent::MacroDisabled { name, dylib_path } => {
+                            log::warn!(
+                                "disabled proc macro `{}` from {}",
+                                name,
+                                dylib_path.display()
+                            );
+                            self.disabled_proc_macros.push((dylib_path, name));
+                        }
+                        ProcMacroServerEvent::MacroEnabled { name, dylib_path } => {
+                            self.disabled_proc_macros
+                                .retain(|(path, it)| *path != dylib_path || *it != name);
+                            invalidate = true;
+                        }
+                    }
+                    event = match self.proc_macro_receiver.try_recv() {
+                        Ok(event) => event,
+                        Err(_) => break,
+                    }
+                }
+                if invalidate {
+                    self.invalidate_proc_macro_expansions();
+                }
+                self.transition(self.status);
+            }
         }
 
+        self.load_requested_files();
         let state_changed = self.process_changes();
         if prev_status == Status::Loading && self.status == Status::Ready {
             for flycheck in &self.flycheck {
@@ -444,6 +486,9 @@ impl GlobalState {
             .on::<lsp_ext::AnalyzerStatus>(handlers::handle_analyzer_status)
             .on::<lsp_ext::SyntaxTree>(handlers::handle_syntax_tree)
             .on::<lsp_ext::ExpandMacro>(handlers::handle_expand_macro)
+            .on::<lsp_ext::DebugMacro>(handlers::handle_debug_macro)
+            .on::<lsp_ext::ViewDefMap>(handlers::handle_view_def_map)
+            .on::<lsp_ext::ViewItemTree>(handlers::handle_view_item_tree)
             .on::<lsp_ext::ParentModule>(handlers::handle_parent_module)
             .on::<lsp_ext::Runnables>(handlers::handle_runnables)
             .on::<lsp_ext::InlayHints>(handlers::handle_inlay_hints)
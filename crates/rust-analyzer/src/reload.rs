@@ -1,9 +1,12 @@
 //! Project loading & configuration updates
-use std::{mem, sync::Arc};
+use std::{iter, mem, sync::Arc};
 
 use flycheck::{FlycheckConfig, FlycheckHandle};
 use ide::Change;
-use ide_db::base_db::{CrateGraph, SourceRoot, VfsPath};
+use ide_db::base_db::{
+    AnchoredPathBuf, CrateGraph, Env, SourceDatabase, SourceDatabaseExt, SourceRoot, VfsPath,
+};
+use itertools::Itertools;
 use project_model::{ProcMacroClient, ProjectWorkspace};
 use vfs::{file_set::FileSetConfig, AbsPath, AbsPathBuf, ChangeKind};
 
@@ -88,9 +91,35 @@ impl GlobalState {
             };
             self.send_notification::<lsp_ext::StatusNotification>(StatusParams {
                 status: lsp_status,
+                message: self.proc_macro_message(),
             });
         }
     }
+    fn proc_macro_message(&self) -> Option<String> {
+        let mut lines = Vec::new();
+        if let Some(crash) = &self.proc_macro_crash {
+            lines.push(format!("{}, restarting the proc macro server", crash));
+        }
+        if !self.disabled_proc_macros.is_empty() {
+            let names = self.disabled_proc_macros.iter().map(|(_, name)| format!("`{}`", name));
+            lines.push(format!("Disabled crashing proc macros: {}", names.format(", ")));
+        }
+        if lines.is_empty() {
+            None
+        } else {
+            Some(lines.join("\n"))
+        }
+    }
+
+    /// Expands proc macros again after the server restarted or a macro was enabled, as the
+    /// failed expansions are cached like any other.
+    pub(crate) fn invalidate_proc_macro_expansions(&mut self) {
+        let crate_graph = self.analysis_host.raw_database().crate_graph();
+        let mut change = Change::new();
+        change.set_crate_graph((*crate_graph).clone());
+        self.analysis_host.apply_change(change);
+    }
+
     pub(crate) fn fetch_workspaces(&mut self) {
         log::info!("will fetch workspaces");
         self.task_pool.handle.spawn({
@@ -144,13 +173,19 @@ impl GlobalState {
         }
 
         if let FilesWatcher::Client = self.config.files.watcher {
+            let extensions = iter::once("rs")
+                .chain(self.config.files.extra_extensions.iter().map(String::as_str))
+                .collect::<Vec<_>>()
+                .join(",");
             let registration_options = lsp_types::DidChangeWatchedFilesRegistrationOptions {
                 watchers: workspaces
                     .iter()
                     .flat_map(ProjectWorkspace::to_roots)
                     .filter(|it| it.is_member)
                     .flat_map(|root| {
-                        root.include.into_iter().map(|it| format!("{}/**/*.rs", it.display()))
+                        root.include
+                            .into_iter()
+                            .map(|it| format!("{}/**/*.{{{}}}", it.display(), extensions))
                     })
                     .map(|glob_pattern| lsp_types::FileSystemWatcher { glob_pattern, kind: None })
                     .collect(),
@@ -168,11 +203,19 @@ impl GlobalState {
 
         let mut change = Change::new();
 
-        let project_folders = ProjectFolders::new(&workspaces);
+        let project_folders = ProjectFolders::new(&workspaces, &self.config.files.extra_extensions);
 
+        self.proc_macro_crash = None;
+        self.disabled_proc_macros.clear();
+        let sender = self.proc_macro_sender.clone();
         self.proc_macro_client = match &self.config.proc_macro_srv {
             None => None,
-            Some((path, args)) => match ProcMacroClient::extern_process(path.into(), args) {
+            Some((path, args)) => match ProcMacroClient::extern_process(
+                path.into(),
+                args,
+                self.config.proc_macro_expansion_timeout,
+                Box::new(move |event| sender.send(event).unwrap()),
+            ) {
                 Ok(it) => Some(it),
                 Err(err) => {
                     log::error!(
@@ -184,12 +227,32 @@ impl GlobalState {
                 }
             },
         };
+        if self.proc_macro_client.is_some() {
+            // The server inherits our environment, macros read what the crate doesn't set from it.
+            let mut env = Env::default();
+            for (var, value) in std::env::vars_os() {
+                if let (Ok(var), Ok(value)) = (var.into_string(), value.into_string()) {
+                    env.set(&var, value);
+                }
+            }
+            change.set_proc_macro_env(env);
+        }
 
+        let mut load = project_folders.load;
         let watch = match self.config.files.watcher {
             FilesWatcher::Client => vec![],
-            FilesWatcher::Notify => project_folders.watch,
+            FilesWatcher::Notify => {
+                let mut watch = project_folders.watch;
+                if !self.requested_files.is_empty() {
+                    watch.push(load.len());
+                    load.push(vfs::loader::Entry::Files(
+                        self.requested_files.iter().cloned().collect(),
+                    ));
+                }
+                watch
+            }
         };
-        self.loader.handle.set_config(vfs::loader::Config { load: project_folders.load, watch });
+        self.loader.handle.set_config(vfs::loader::Config { load, watch });
 
         // Create crate graph from all the workspaces
         let crate_graph = {
@@ -268,6 +331,67 @@ impl GlobalState {
             })
             .collect();
     }
+
+    /// Loads the files analysis asked for with `FileLoader::request_file`, and starts watching
+    /// them.
+    pub(crate) fn load_requested_files(&mut self) {
+        let requested = self.analysis_host.raw_database().take_requested_files();
+        if requested.is_empty() {
+            return;
+        }
+        let mut new_files = Vec::new();
+        {
+            let db = self.analysis_host.raw_database();
+            let vfs = &self.vfs.read().0;
+            for AnchoredPathBuf { anchor, path } in requested {
+                // Libraries can't change, everything they need is loaded already.
+                if db.source_root(db.file_source_root(anchor)).is_library {
+                    continue;
+                }
+                let mut base = vfs.file_path(anchor);
+                base.pop();
+                let path =
+                    match base.join(&path).and_then(|it| it.as_path().map(AbsPath::to_path_buf)) {
+                        Some(it) => it,
+                        None => continue,
+                    };
+                if self.requested_files.insert(path.clone()) {
+                    new_files.push(path);
+                }
+            }
+        }
+        if new_files.is_empty() {
+            return;
+        }
+
+        match self.config.files.watcher {
+            FilesWatcher::Client => {
+                let registration_options = lsp_types::DidChangeWatchedFilesRegistrationOptions {
+                    watchers: new_files
+                        .iter()
+                        .map(|path| lsp_types::FileSystemWatcher {
+                            glob_pattern: path.display().to_string(),
+                            kind: None,
+                        })
+                        .collect(),
+                };
+                let registration = lsp_types::Registration {
+                    id: format!("workspace/didChangeWatchedFiles/{}", self.requested_files.len()),
+                    method: "workspace/didChangeWatchedFiles".to_string(),
+                    register_options: Some(serde_json::to_value(registration_options).unwrap()),
+                };
+                self.send_request::<lsp_types::request::RegisterCapability>(
+                    lsp_types::RegistrationParams { registrations: vec![registration] },
+                    |_, _| (),
+                );
+            }
+            // The loader only starts watching them once the workspace is reloaded.
+            FilesWatcher::Notify => (),
+        }
+        for path in new_files {
+            self.loader.handle.invalidate(path);
+        }
+    }
 }
 
 #[derive(Default)]
@@ -278,7 +402,10 @@ pub(crate) struct ProjectFolders {
 }
 
 impl ProjectFolders {
-    pub(crate) fn new(workspaces: &[ProjectWorkspace]) -> ProjectFolders {
+    pub(crate) fn new(
+        workspaces: &[ProjectWorkspace],
+        extra_extensions: &[String],
+    ) -> ProjectFolders {
         let mut res = ProjectFolders::default();
         let mut fsc = FileSetConfig::builder();
         let mut local_filesets = vec![];
@@ -290,6 +417,11 @@ impl ProjectFolders {
             let entry = {
                 let mut dirs = vfs::loader::Directories::default();
                 dirs.extensions.push("rs".into());
+                // Only workspace members can `include_str!` non-Rust files, dependencies don't
+                // get analyzed deeply enough for their contents to matter.
+                if root.is_member {
+                    dirs.extensions.extend(extra_extensions.iter().cloned());
+                }
                 dirs.include.extend(root.include);
                 dirs.exclude.extend(root.exclude);
                 vfs::loader::Entry::Directories(dirs)
@@ -5,11 +5,12 @@ use std::{
 };
 
 use ide::{
+    dump::{DefMapDump, ImportStatus, ItemTreeDump, ItemTreeItemDump, Namespace},
     Assist, AssistKind, CallInfo, CompletionItem, CompletionItemKind, Documentation, FileId,
     FileRange, FileSystemEdit, Fold, FoldKind, Highlight, HighlightModifier, HighlightTag,
-    HighlightedRange, Indel, InlayHint, InlayKind, InsertTextFormat, LineIndex, Markup,
-    NavigationTarget, ReferenceAccess, Runnable, Severity, SourceChange, SourceFileEdit,
-    SymbolKind, TextEdit, TextRange, TextSize,
+    HighlightedRange, Indel, InlayHint, InlayKind, InsertTextFormat, LineIndex, MacroBinding,
+    Markup, NavigationTarget, ReferenceAccess, RelatedInformation, Runnable, Severity,
+    SourceChange, SourceFileEdit, SymbolKind, TextEdit, TextRange, TextSize,
 };
 use itertools::Itertools;
 
@@ -69,6 +70,24 @@ pub(crate) fn diagnostic_severity(severity: Severity) -> lsp_types::DiagnosticSe
     }
 }
 
+pub(crate) fn macro_binding_value(value: MacroBinding) -> lsp_ext::MacroBindingValue {
+    match value {
+        MacroBinding::Fragment(it) => lsp_ext::MacroBindingValue::Fragment(it),
+        MacroBinding::Nested(it) => lsp_ext::MacroBindingValue::Repetition(
+            it.into_iter().map(macro_binding_value).collect(),
+        ),
+        MacroBinding::Empty => lsp_ext::MacroBindingValue::Empty,
+    }
+}
+
+pub(crate) fn diagnostic_related_information(
+    snap: &GlobalStateSnapshot,
+    related: RelatedInformation,
+) -> Result<lsp_types::DiagnosticRelatedInformation> {
+    let location = location(snap, related.range)?;
+    Ok(lsp_types::DiagnosticRelatedInformation { location, message: related.message })
+}
+
 pub(crate) fn documentation(documentation: Documentation) -> lsp_types::Documentation {
     let value = crate::markdown::format_docs(documentation.as_str());
     let markup_content = lsp_types::MarkupContent { kind: lsp_types::MarkupKind::Markdown, value };
@@ -847,6 +866,82 @@ pub(crate) fn markup_content(markup: Markup) -> lsp_types::MarkupContent {
     lsp_types::MarkupContent { kind: lsp_types::MarkupKind::Markdown, value }
 }
 
+pub(crate) fn def_map_dump(dump: &DefMapDump) -> lsp_ext::DefMapDump {
+    let modules = dump
+        .modules
+        .iter()
+        .map(|module| lsp_ext::ModuleDump {
+            path: module.path.clone(),
+            origin: module.origin.clone(),
+            items: module
+                .items
+                .iter()
+                .map(|it| lsp_ext::ScopeItemDump {
+                    name: it.name.clone(),
+                    namespace: it.namespace.map(dump_namespace),
+                    kind: it.kind.clone(),
+                    visibility: it.visibility.clone(),
+                })
+                .collect(),
+            imports: module
+                .imports
+                .iter()
+                .map(|it| lsp_ext::ImportDump {
+                    path: it.path.clone(),
+                    alias: it.alias.clone(),
+                    visibility: it.visibility.clone(),
+                    is_glob: it.is_glob,
+                    is_extern_crate: it.is_extern_crate,
+                    status: match it.status {
+                        ImportStatus::Resolved => lsp_ext::ImportStatus::Resolved,
+                        ImportStatus::Unresolved => lsp_ext::ImportStatus::Unresolved,
+                    },
+                    namespaces: it.namespaces.iter().copied().map(dump_namespace).collect(),
+                })
+                .collect(),
+            textual_macros: module
+                .textual_macros
+                .iter()
+                .map(|it| lsp_ext::TextualMacroDump {
+                    name: it.name.clone(),
+                    kind: it.kind.clone(),
+                    visible_from: it.visible_from,
+                })
+                .collect(),
+            impls: module.impls,
+        })
+        .collect();
+    lsp_ext::DefMapDump { modules }
+}
+
+fn dump_namespace(ns: Namespace) -> lsp_ext::DumpNamespace {
+    match ns {
+        Namespace::Types => lsp_ext::DumpNamespace::Types,
+        Namespace::Values => lsp_ext::DumpNamespace::Values,
+        Namespace::Macros => lsp_ext::DumpNamespace::Macros,
+    }
+}
+
+pub(crate) fn item_tree_dump(dump: &ItemTreeDump) -> lsp_ext::ItemTreeDump {
+    let items = |items: &[ItemTreeItemDump]| items.iter().map(item_tree_item_dump).collect();
+    lsp_ext::ItemTreeDump {
+        attrs: dump.attrs.clone(),
+        items: items(&dump.items),
+        blocks: dump.blocks.iter().map(|block| items(block)).collect(),
+    }
+}
+
+fn item_tree_item_dump(it: &ItemTreeItemDump) -> lsp_ext::ItemTreeItemDump {
+    lsp_ext::ItemTreeItemDump {
+        kind: it.kind.clone(),
+        name: it.name.clone(),
+        visibility: it.visibility.clone(),
+        attrs: it.attrs.clone(),
+        detail: it.detail.clone(),
+        children: it.children.iter().map(item_tree_item_dump).collect(),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use ide::Analysis;
@@ -895,6 +990,136 @@ mod tests {
         .assert_debug_eq(&completions);
     }
 
+    #[test]
+    fn def_map_dump_as_json() {
+        let (analysis, file_id) = Analysis::from_single_file(
+            r#"
+use missing::Thing;
+pub struct S;
+macro_rules! m { () => {} }
+"#
+            .to_string(),
+        );
+        let dump = def_map_dump(&analysis.view_def_map(file_id).unwrap().unwrap());
+        expect_test::expect![[r#"
+            {
+              "modules": [
+                {
+                  "path": "crate",
+                  "origin": "crate root",
+                  "items": [
+                    {
+                      "name": "S",
+                      "namespace": "types",
+                      "kind": "struct",
+                      "visibility": "pub"
+                    },
+                    {
+                      "name": "S",
+                      "namespace": "values",
+                      "kind": "struct",
+                      "visibility": "pub"
+                    },
+                    {
+                      "name": "Thing",
+                      "namespace": null,
+                      "kind": "unresolved",
+                      "visibility": null
+                    }
+                  ],
+                  "imports": [
+                    {
+                      "path": "missing::Thing",
+                      "alias": null,
+                      "visibility": "pub(self)",
+                      "isGlob": false,
+                      "isExternCrate": false,
+                      "status": "unresolved",
+                      "namespaces": []
+                    }
+                  ],
+                  "textualMacros": [
+                    {
+                      "name": "m",
+                      "kind": "macro_rules",
+                      "visibleFrom": 35
+                    }
+                  ],
+                  "impls": 0
+                }
+              ]
+            }"#]]
+        .assert_eq(&serde_json::to_string_pretty(&dump).unwrap());
+    }
+
+    #[test]
+    fn item_tree_dump_as_json() {
+        let (analysis, file_id) = Analysis::from_single_file(
+            r#"
+#![no_std]
+pub enum E { A(u8) }
+fn f() { struct Inner; }
+"#
+            .to_string(),
+        );
+        let dump = item_tree_dump(&analysis.view_item_tree(file_id).unwrap());
+        expect_test::expect![[r##"
+            {
+              "attrs": [
+                "#![no_std]"
+              ],
+              "items": [
+                {
+                  "kind": "enum",
+                  "name": "E",
+                  "visibility": "pub",
+                  "attrs": [],
+                  "detail": null,
+                  "children": [
+                    {
+                      "kind": "variant",
+                      "name": "A",
+                      "visibility": null,
+                      "attrs": [],
+                      "detail": null,
+                      "children": [
+                        {
+                          "kind": "field",
+                          "name": "0",
+                          "visibility": "pub",
+                          "attrs": [],
+                          "detail": null,
+                          "children": []
+                        }
+                      ]
+                    }
+                  ]
+                },
+                {
+                  "kind": "fn",
+                  "name": "f",
+                  "visibility": "pub(self)",
+                  "attrs": [],
+                  "detail": null,
+                  "children": []
+                }
+              ],
+              "blocks": [
+                [
+                  {
+                    "kind": "struct",
+                    "name": "Inner",
+                    "visibility": "pub(self)",
+                    "attrs": [],
+                    "detail": "unit",
+                    "children": []
+                  }
+                ]
+              ]
+            }"##]]
+        .assert_eq(&serde_json::to_string_pretty(&dump).unwrap());
+    }
+
     #[test]
     fn conv_fold_line_folding_only_fixup() {
         let text = r#"mod a;
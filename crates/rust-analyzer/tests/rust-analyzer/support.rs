@@ -92,7 +92,11 @@ impl<'a> Project<'a> {
             },
             cargo: CargoConfig { no_sysroot: !self.with_sysroot, ..Default::default() },
             linked_projects,
-            files: FilesConfig { watcher: FilesWatcher::Client, exclude: Vec::new() },
+            files: FilesConfig {
+                watcher: FilesWatcher::Client,
+                exclude: Vec::new(),
+                extra_extensions: Vec::new(),
+            },
             ..Config::new(tmp_dir_path)
         };
         if let Some(f) = &self.config {
@@ -13,6 +13,7 @@ pub struct Fixture {
     pub cfg_atoms: Vec<String>,
     pub cfg_key_values: Vec<(String, String)>,
     pub edition: Option<String>,
+    pub kind: Option<String>,
     pub env: FxHashMap<String, String>,
 }
 
@@ -55,7 +56,7 @@ impl Fixture {
         res
     }
 
-    //- /lib.rs crate:foo deps:bar,baz cfg:foo=a,bar=b env:OUTDIR=path/to,OTHER=foo
+    //- /lib.rs crate:foo deps:bar,baz cfg:foo=a,bar=b env:OUTDIR=path/to,OTHER=foo kind:lib
     fn parse_meta_line(meta: &str) -> Fixture {
         assert!(meta.starts_with("//-"));
         let meta = meta["//-".len()..].trim();
@@ -67,6 +68,7 @@ impl Fixture {
         let mut krate = None;
         let mut deps = Vec::new();
         let mut edition = None;
+        let mut kind = None;
         let mut cfg_atoms = Vec::new();
         let mut cfg_key_values = Vec::new();
         let mut env = FxHashMap::default();
@@ -76,6 +78,7 @@ impl Fixture {
                 "crate" => krate = Some(value.to_string()),
                 "deps" => deps = value.split(',').map(|it| it.to_string()).collect(),
                 "edition" => edition = Some(value.to_string()),
+                "kind" => kind = Some(value.to_string()),
                 "cfg" => {
                     for entry in value.split(',') {
                         match split_once(entry, '=') {
@@ -103,6 +106,7 @@ impl Fixture {
             cfg_atoms,
             cfg_key_values,
             edition,
+            kind,
             env,
         }
     }
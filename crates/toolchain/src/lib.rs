@@ -1,5 +1,8 @@
 //! Discovery of `cargo` & `rustc` executables.
-use std::{env, iter, path::PathBuf};
+use std::{
+    env, iter,
+    path::{Path, PathBuf},
+};
 
 pub fn cargo() -> PathBuf {
     get_path_for_executable("cargo")
@@ -52,9 +55,25 @@ fn get_path_for_executable(executable_name: &'static str) -> PathBuf {
     executable_name.into()
 }
 
+/// Returns the absolute path of an executable returned by one of the functions above, looking
+/// bare executable names up in `$PATH`.
+pub fn absolute_path(path: &Path) -> Option<PathBuf> {
+    if path.is_absolute() {
+        return Some(path.to_path_buf());
+    }
+    if path.components().count() == 1 {
+        return find_in_path(path.to_str()?);
+    }
+    Some(env::current_dir().ok()?.join(path))
+}
+
 fn lookup_in_path(exec: &str) -> bool {
+    find_in_path(exec).is_some()
+}
+
+fn find_in_path(exec: &str) -> Option<PathBuf> {
     let paths = env::var_os("PATH").unwrap_or_default();
-    env::split_paths(&paths).map(|path| path.join(exec)).find_map(probe).is_some()
+    env::split_paths(&paths).map(|path| path.join(exec)).find_map(probe)
 }
 
 fn probe(path: PathBuf) -> Option<PathBuf> {
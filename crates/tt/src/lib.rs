@@ -229,12 +229,17 @@ impl Subtree {
 
 pub mod buffer;
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
 pub enum ExpansionError {
     IOError(String),
     JsonError(String),
     Unknown(String),
     ExpansionError(String),
+    /// The proc macro panicked with this message.
+    Panic(String),
+    /// The proc macro emitted diagnostics. It still produced an expansion, which is returned
+    /// alongside this.
+    Diagnostics(Vec<Diagnostic>),
 }
 
 impl fmt::Display for ExpansionError {
@@ -244,6 +249,58 @@ impl fmt::Display for ExpansionError {
             ExpansionError::JsonError(e) => write!(f, "JSON decoding error: {}", e),
             ExpansionError::Unknown(e) => e.fmt(f),
             ExpansionError::ExpansionError(e) => write!(f, "proc macro returned error: {}", e),
+            ExpansionError::Panic(e) => write!(f, "proc macro panicked: {}", e),
+            ExpansionError::Diagnostics(diagnostics) => {
+                for (i, diagnostic) in diagnostics.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str("\n")?;
+                    }
+                    write!(f, "{}: {}", diagnostic.level, diagnostic)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// A diagnostic emitted by a proc macro, mirroring `proc_macro::Diagnostic`.
+///
+/// Displays as its message followed by its children, which are prefixed with their level.
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+pub struct Diagnostic {
+    pub level: DiagnosticLevel,
+    pub message: String,
+    /// The tokens of the macro input the diagnostic points at.
+    pub spans: Vec<TokenId>,
+    pub children: Vec<Diagnostic>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum DiagnosticLevel {
+    Error,
+    Warning,
+    Note,
+    Help,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)?;
+        for child in &self.children {
+            write!(f, "\n{}: {}", child.level, child.message)?;
         }
+        Ok(())
+    }
+}
+
+impl fmt::Display for DiagnosticLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text = match self {
+            DiagnosticLevel::Error => "error",
+            DiagnosticLevel::Warning => "warning",
+            DiagnosticLevel::Note => "note",
+            DiagnosticLevel::Help => "help",
+        };
+        f.write_str(text)
     }
 }
@@ -26,7 +26,7 @@
 //! from the anchor than.
 use crate::FileId;
 
-#[derive(Clone, PartialEq, Eq, Debug)]
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
 pub struct AnchoredPathBuf {
     pub anchor: FileId,
     pub path: String,
@@ -276,6 +276,10 @@ impl VirtualPath {
         true
     }
     fn join(&self, mut path: &str) -> Option<VirtualPath> {
+        // Like `Path::join`, an absolute path replaces the base entirely.
+        if path.starts_with('/') {
+            return Some(VirtualPath(path.to_string()));
+        }
         let mut res = self.clone();
         while path.starts_with("../") {
             if !res.pop() {
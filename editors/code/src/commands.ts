@@ -344,6 +344,48 @@ export function syntaxTree(ctx: Ctx): Cmd {
 // Opens the virtual file that will show the syntax tree
 //
 // The contents of the file come from the `TextDocumentContentProvider`
+export function viewDefMap(ctx: Ctx): Cmd {
+    return viewDump(ctx, 'rust-analyzer-def-map', async (textDocument) => {
+        const dump = await ctx.client.sendRequest(ra.viewDefMap, { textDocument });
+        return dump ?? 'Not available';
+    });
+}
+
+export function viewItemTree(ctx: Ctx): Cmd {
+    return viewDump(ctx, 'rust-analyzer-item-tree', (textDocument) =>
+        ctx.client.sendRequest(ra.viewItemTree, { textDocument })
+    );
+}
+
+function viewDump(
+    ctx: Ctx,
+    scheme: string,
+    request: (textDocument: lc.TextDocumentIdentifier) => Promise<string>,
+): Cmd {
+    const tdcp = new class implements vscode.TextDocumentContentProvider {
+        readonly uri = vscode.Uri.parse(`${scheme}://dump`);
+        readonly eventEmitter = new vscode.EventEmitter<vscode.Uri>();
+
+        provideTextDocumentContent(_uri: vscode.Uri): vscode.ProviderResult<string> {
+            const doc = ctx.activeRustEditor?.document;
+            if (!doc) return '';
+            return request(ctx.client.code2ProtocolConverter.asTextDocumentIdentifier(doc));
+        }
+
+        get onDidChange(): vscode.Event<vscode.Uri> {
+            return this.eventEmitter.event;
+        }
+    }();
+
+    ctx.pushCleanup(vscode.workspace.registerTextDocumentContentProvider(scheme, tdcp));
+
+    return async () => {
+        const document = await vscode.workspace.openTextDocument(tdcp.uri);
+        tdcp.eventEmitter.fire(tdcp.uri);
+        return vscode.window.showTextDocument(document, vscode.ViewColumn.Two, true);
+    };
+}
+
 export function expandMacro(ctx: Ctx): Cmd {
     function codeFormat(expanded: ra.ExpandedMacro): string {
         let result = `// Recursive expansion of ${expanded.name}! macro\n`;
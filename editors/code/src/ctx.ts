@@ -36,7 +36,7 @@ export class Ctx {
 
         res.pushCleanup(client.start());
         await client.onReady();
-        client.onNotification(ra.status, (params) => res.setStatus(params.status));
+        client.onNotification(ra.status, (params) => res.setStatus(params.status, params.message));
         return res;
     }
 
@@ -66,7 +66,7 @@ export class Ctx {
         return this.extCtx.subscriptions;
     }
 
-    setStatus(status: Status) {
+    setStatus(status: Status, message?: string) {
         switch (status) {
             case "loading":
                 this.statusBar.text = "$(sync~spin) rust-analyzer";
@@ -93,6 +93,13 @@ export class Ctx {
                 this.statusBar.color = new vscode.ThemeColor("notificationsWarningIcon.foreground");
                 break;
         }
+        if (message) {
+            this.statusBar.tooltip = `${this.statusBar.tooltip}\n\n${message}`;
+            if (status === "ready") {
+                this.statusBar.text = "$(warning) rust-analyzer";
+                this.statusBar.color = new vscode.ThemeColor("notificationsWarningIcon.foreground");
+            }
+        }
     }
 
     pushCleanup(d: Disposable) {
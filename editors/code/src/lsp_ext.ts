@@ -13,6 +13,7 @@ export const memoryUsage = new lc.RequestType0<string, void>("rust-analyzer/memo
 export type Status = "loading" | "ready" | "invalid" | "needsReload";
 export interface StatusParams {
     status: Status;
+    message?: string;
 }
 export const status = new lc.NotificationType<StatusParams>("rust-analyzer/status");
 
@@ -25,6 +26,13 @@ export interface SyntaxTreeParams {
 export const syntaxTree = new lc.RequestType<SyntaxTreeParams, string, void>("rust-analyzer/syntaxTree");
 
 
+export interface ViewDumpParams {
+    textDocument: lc.TextDocumentIdentifier;
+    format?: "text" | "json";
+}
+export const viewDefMap = new lc.RequestType<ViewDumpParams, string | null, void>("rust-analyzer/viewDefMap");
+export const viewItemTree = new lc.RequestType<ViewDumpParams, string, void>("rust-analyzer/viewItemTree");
+
 export interface ExpandMacroParams {
     textDocument: lc.TextDocumentIdentifier;
     position: lc.Position;